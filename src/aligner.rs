@@ -0,0 +1,138 @@
+/// The MAPQ ceiling of bwa mem, the scale the default
+/// `--min-mapping-quality` was chosen for.
+pub const REFERENCE_MAX_MAPQ: u32 = 60;
+
+/// An aligner's MAPQ convention, recognised from its `@PG` line.
+pub struct Aligner {
+    pub name: &'static str,
+    /// `PN` of the `@PG` line, or `ID` when there is no `PN`.
+    program: &'static str,
+    /// Subcommands that must follow the program in `CL`; empty matches any.
+    commands: &'static [&'static str],
+    pub max_mapq: u32,
+}
+
+/// Known aligners. To add one, give its program name, the subcommands that
+/// tell it apart from others sharing that name, and its highest MAPQ.
+const ALIGNERS: &[Aligner] = &[
+    Aligner { name: "bwa mem", program: "bwa", commands: &["mem"], max_mapq: 60 },
+    Aligner { name: "bwa aln", program: "bwa", commands: &["aln", "samse", "sampe"], max_mapq: 37 },
+    Aligner { name: "bwa-mem2", program: "bwa-mem2", commands: &[], max_mapq: 60 },
+    Aligner { name: "minimap2", program: "minimap2", commands: &[], max_mapq: 60 },
+    Aligner { name: "bowtie2", program: "bowtie2", commands: &[], max_mapq: 42 },
+    Aligner { name: "GSNAP", program: "gsnap", commands: &[], max_mapq: 40 },
+];
+
+impl Aligner {
+    /// Whether MAPQ from this aligner is on a different scale from bwa mem.
+    pub fn differs(&self) -> bool {
+        self.max_mapq != REFERENCE_MAX_MAPQ
+    }
+
+    /// `min_mq` rescaled from bwa mem's MAPQ range to this aligner's.
+    pub fn suggested_min_mq(&self, min_mq: u32) -> u32 {
+        min_mq * self.max_mapq / REFERENCE_MAX_MAPQ
+    }
+
+    fn matches(&self, program: &str, command_line: Option<&str>) -> bool {
+        if !program.eq_ignore_ascii_case(self.program) {
+            return false;
+        }
+        if self.commands.is_empty() {
+            return true;
+        }
+        command_line
+            .and_then(|command_line| command_line.split_whitespace().nth(1))
+            .is_some_and(|command| self.commands.contains(&command))
+    }
+}
+
+/// The aligner named by a single `@PG` header line, if it is a known one.
+pub fn identify(pg_line: &str) -> Option<&'static Aligner> {
+    let fields = pg_line.strip_prefix("@PG\t")?.split('\t');
+    let field = |tag: &str| fields.clone().find_map(|field| field.strip_prefix(tag));
+    let program = field("PN:").or_else(|| field("ID:"))?;
+    let command_line = field("CL:");
+    ALIGNERS
+        .iter()
+        .find(|aligner| aligner.matches(program, command_line))
+}
+
+/// The first known aligner among the `@PG` lines of a SAM header. Later
+/// programs such as samtools or Picard are not aligners and are skipped.
+pub fn detect(header: &str) -> Option<&'static Aligner> {
+    header
+        .lines()
+        .filter(|line| line.starts_with("@PG\t"))
+        .find_map(identify)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(pg_line: &str) -> Option<&'static str> {
+        identify(pg_line).map(|aligner| aligner.name)
+    }
+
+    #[test]
+    fn identifies_aligners_from_pg_lines() {
+        assert_eq!(
+            name("@PG\tID:bwa\tPN:bwa\tVN:0.7.17-r1188\tCL:bwa mem -t 8 -R @RG\\tID:x\\tSM:x ref.fa r1.fq r2.fq"),
+            Some("bwa mem")
+        );
+        assert_eq!(
+            name("@PG\tID:bwa\tPN:bwa\tVN:0.7.12\tCL:/opt/bwa/bwa sampe ref.fa r1.sai r2.sai r1.fq r2.fq"),
+            Some("bwa aln")
+        );
+        assert_eq!(
+            name("@PG\tID:bwa-mem2\tPN:bwa-mem2\tVN:2.2.1\tCL:bwa-mem2 mem -t 8 ref.fa r1.fq r2.fq"),
+            Some("bwa-mem2")
+        );
+        assert_eq!(
+            name("@PG\tID:minimap2\tPN:minimap2\tVN:2.24-r1122\tCL:minimap2 -ax sr ref.fa r1.fq r2.fq"),
+            Some("minimap2")
+        );
+        assert_eq!(
+            name("@PG\tID:bowtie2\tPN:bowtie2\tVN:2.4.1\tCL:\"/usr/bin/bowtie2-align-s --wrapper basic-0 -x idx -1 r1.fq -2 r2.fq\""),
+            Some("bowtie2")
+        );
+        // No PN, so the ID names the program
+        assert_eq!(name("@PG\tID:GSNAP\tVN:2020-12-16\tCL:gsnap -d GRCh38 r1.fq"), Some("GSNAP"));
+    }
+
+    #[test]
+    fn other_programs_are_not_aligners() {
+        assert_eq!(name("@PG\tID:samtools\tPN:samtools\tPP:bwa\tVN:1.16\tCL:samtools sort -o a.bam"), None);
+        assert_eq!(name("@PG\tID:MarkDuplicates\tPN:MarkDuplicates\tVN:2.27.4"), None);
+        // bwa without a subcommand that aligns
+        assert_eq!(name("@PG\tID:bwa\tPN:bwa\tVN:0.7.17\tCL:bwa index ref.fa"), None);
+        assert_eq!(name("@PG\tID:bwa\tPN:bwa\tVN:0.7.17"), None);
+        assert_eq!(name("@RG\tID:bwa\tPN:bwa"), None);
+    }
+
+    #[test]
+    fn detects_the_first_aligner_in_a_header() {
+        let header = "@HD\tVN:1.6\tSO:coordinate\n\
+                      @SQ\tSN:MT\tLN:16569\n\
+                      @PG\tID:bowtie2\tPN:bowtie2\tVN:2.4.1\tCL:bowtie2 -x idx\n\
+                      @PG\tID:samtools\tPN:samtools\tPP:bowtie2\tVN:1.16\tCL:samtools sort\n\
+                      @PG\tID:minimap2\tPN:minimap2\tVN:2.24\tCL:minimap2 -ax sr\n";
+        assert_eq!(detect(header).map(|aligner| aligner.name), Some("bowtie2"));
+        assert!(detect("@HD\tVN:1.6\n@PG\tID:samtools\tPN:samtools\n").is_none());
+    }
+
+    #[test]
+    fn rescales_min_mq_to_the_aligner() {
+        let aligner = |pg_line| identify(pg_line).unwrap();
+        let bwa_mem = aligner("@PG\tID:bwa\tPN:bwa\tCL:bwa mem ref.fa r.fq");
+        assert!(!bwa_mem.differs());
+        assert_eq!(bwa_mem.suggested_min_mq(30), 30);
+        let bowtie2 = aligner("@PG\tID:bowtie2\tPN:bowtie2");
+        assert!(bowtie2.differs());
+        assert_eq!(bowtie2.suggested_min_mq(30), 21);
+        let bwa_aln = aligner("@PG\tID:bwa\tPN:bwa\tCL:bwa samse ref.fa r.sai r.fq");
+        assert_eq!(bwa_aln.suggested_min_mq(30), 18);
+        assert_eq!(bwa_aln.suggested_min_mq(0), 0);
+    }
+}
@@ -0,0 +1,817 @@
+use log::{debug, info};
+use noodles::bgzf;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::checksum;
+use crate::error::MityError;
+use crate::haplogroup::Classifier;
+use crate::mity_util::header::VcfHeader;
+use crate::mity_util::{self, Interval, Tool};
+use crate::pedigree::{self, Inheritance, InheritanceClassifier};
+use crate::vcf_record::{VariantType, VcfRecord};
+
+/// Forward/reverse read counts of the reference and alternate alleles, as
+/// written by freebayes.
+const STRAND_FIELDS: [&str; 4] = ["SRF", "SRR", "SAF", "SAR"];
+
+const INHERITANCE_HEADER: &str = "##FORMAT=<ID=inheritance,Number=1,Type=String,Description=\"Inheritance of the variant in a proband from the PED file: maternal, de_novo, indeterminate (low-level in the mother) or unknown (mother not in the VCF or without depth)\">";
+
+/// Runs mity's annotation layer over an arbitrary mitochondrial VCF.
+pub struct Annotate {
+    debug: bool,
+    vcf: PathBuf,
+    output_dir: PathBuf,
+    prefix: String,
+    with_report: bool,
+    split_by_type: bool,
+    annotation_beds: Vec<(String, PathBuf)>,
+    pedigree: Option<PathBuf>,
+    maternal_min_vaf: f64,
+    strand_table: bool,
+    vcfanno_config: Option<PathBuf>,
+    annotation_vcfs: Vec<AnnotationVcf>,
+    keep: bool,
+
+    annotated_vcf_path: PathBuf,
+    report_path: PathBuf,
+    strand_table_path: PathBuf,
+    resolved_config_path: PathBuf,
+}
+
+impl Annotate {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+        with_report: bool,
+        split_by_type: bool,
+        annotation_beds: Vec<(String, PathBuf)>,
+        pedigree: Option<PathBuf>,
+        maternal_min_vaf: f64,
+        strand_table: bool,
+        vcfanno_config: Option<PathBuf>,
+        annotation_vcfs: Vec<AnnotationVcf>,
+        keep: bool,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&vcf));
+        Annotate {
+            debug,
+            annotated_vcf_path: output_dir.join(format!("{}.mity.annotated.vcf.gz", prefix)),
+            report_path: output_dir.join(format!("{}.mity.annotated.tsv", prefix)),
+            strand_table_path: output_dir.join(format!("{}.mity.strand_counts.tsv", prefix)),
+            resolved_config_path: output_dir.join(format!("{}.mity.vcfanno.toml", prefix)),
+            vcf,
+            output_dir,
+            prefix,
+            with_report,
+            split_by_type,
+            annotation_beds,
+            pedigree,
+            maternal_min_vaf,
+            strand_table,
+            vcfanno_config,
+            annotation_vcfs,
+            keep,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+        debug!(
+            "Annotating {} into {} with prefix {}",
+            self.vcf.display(),
+            self.output_dir.display(),
+            self.prefix
+        );
+
+        self.run_vcfanno()?;
+        mity_util::tabix(&self.annotated_vcf_path)?;
+        info!("Wrote {}", self.annotated_vcf_path.display());
+
+        if self.with_report {
+            self.write_report()?;
+        }
+        if self.strand_table {
+            self.write_strand_table()?;
+        }
+        Ok(())
+    }
+
+    fn run_vcfanno(&self) -> Result<(), Box<dyn Error>> {
+        let config = match &self.vcfanno_config {
+            Some(config) => config.clone(),
+            None => vcfanno_config(&mt_contig(&self.vcf)?)?,
+        };
+        if !config.exists() {
+            return Err(MityError::MissingFile(config).into());
+        }
+        // vcfanno resolves relative paths against its working directory, so
+        // hand it a copy of the config with every file made absolute
+        let mut resolved = resolve_vcfanno_config(&config)?;
+        resolved.push_str(&self.annotation_vcf_blocks(&resolved)?);
+        fs::write(&self.resolved_config_path, resolved)?;
+        let threads = mity_util::thread_budget().total();
+
+        let mut command = mity_util::tool_command(Tool::Vcfanno)?;
+        command
+            .arg("-p")
+            .arg(threads.to_string())
+            .arg(&self.resolved_config_path)
+            .arg(&self.vcf)
+            .stdout(Stdio::piped());
+        debug!("{:?}", command);
+
+        let trios = match &self.pedigree {
+            Some(ped) => Some(pedigree::read_ped(ped)?),
+            None => None,
+        };
+        let mut classifier: Option<InheritanceClassifier> = None;
+        let mut counts: BTreeMap<Inheritance, usize> = BTreeMap::new();
+
+        let _permit = mity_util::thread_budget().acquire("vcfanno", threads);
+        let mut child = command.spawn()?;
+        let stdout = child.stdout.take().expect("vcfanno stdout is piped");
+        let mut writer = bgzf::Writer::new(checksum::OutputFile::create(&self.annotated_vcf_path)?);
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                for source in &self.annotation_vcfs {
+                    writeln!(writer, "{}", source.provenance())?;
+                }
+            }
+            if let (Some(trios), true) = (&trios, line.starts_with("#CHROM")) {
+                let samples: Vec<String> = line.split('\t').skip(9).map(String::from).collect();
+                classifier = Some(InheritanceClassifier::new(trios, &samples, self.maternal_min_vaf));
+                writeln!(writer, "{}", INHERITANCE_HEADER)?;
+            }
+            match &classifier {
+                Some(classifier) if !line.starts_with('#') && !line.is_empty() => {
+                    let mut record = VcfRecord::parse(&line)?;
+                    for (proband, status) in classifier.classify(&record) {
+                        record.set_sample(proband, "inheritance", status.as_str().to_string());
+                        *counts.entry(status).or_default() += 1;
+                    }
+                    writeln!(writer, "{}", record)?;
+                }
+                _ => writeln!(writer, "{}", line)?,
+            }
+        }
+        writer.finish()?.finish()?;
+
+        let status = child.wait()?;
+        if !self.keep {
+            fs::remove_file(&self.resolved_config_path)?;
+        }
+        if !status.success() {
+            return Err(MityError::tool("vcfanno", status.code(), b"").into());
+        }
+
+        if let Some(classifier) = &classifier {
+            println!("Inheritance of proband variants ({} probands)", classifier.probands());
+            for status in [
+                Inheritance::Maternal,
+                Inheritance::DeNovo,
+                Inheritance::Indeterminate,
+                Inheritance::Unknown,
+            ] {
+                println!("  {:<14} {}", status.as_str(), counts.get(&status).copied().unwrap_or(0));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `[[annotation]]` blocks of the `--annotation-vcf` sources. Each
+    /// must be indexed and declare its fields, and the INFO names they are
+    /// written as must not clash with the input's INFO fields, the names
+    /// `config` annotates, or each other.
+    fn annotation_vcf_blocks(&self, config: &str) -> Result<String, Box<dyn Error>> {
+        let mut taken: Vec<(String, String)> = Vec::new();
+        let mut input = mity_util::open_vcf(&self.vcf)?.lines();
+        for line in VcfHeader::read(&mut input)?.lines() {
+            if let Some(id) = info_id(line) {
+                taken.push((id.to_string(), self.vcf.display().to_string()));
+            }
+        }
+        for name in config_annotation_names(config) {
+            taken.push((name, "the vcfanno config".to_string()));
+        }
+
+        let mut blocks = String::new();
+        for source in &self.annotation_vcfs {
+            if !source.path.exists() {
+                return Err(MityError::MissingFile(source.path.clone()).into());
+            }
+            if mity_util::vcf_index(&source.path).is_none() {
+                return Err(format!(
+                    "{} has no .tbi or .csi index; bgzip it and run tabix -p vcf on it",
+                    source.path.display()
+                )
+                .into());
+            }
+            let mut lines = mity_util::open_vcf(&source.path)?.lines();
+            let header = VcfHeader::read(&mut lines)?;
+            let declared: Vec<&str> = header.lines().filter_map(info_id).collect();
+            if let Some(field) = source.fields.iter().find(|field| !declared.contains(&field.as_str())) {
+                return Err(format!(
+                    "{} has no INFO/{}; its INFO fields are {}",
+                    source.path.display(),
+                    field,
+                    declared.join(", ")
+                )
+                .into());
+            }
+            for name in source.names() {
+                if let Some((_, owner)) = taken.iter().find(|(taken, _)| *taken == name) {
+                    return Err(format!(
+                        "INFO/{} from {} is already annotated by {}; give the source a prefix, as FILE:FIELDS:PREFIX",
+                        name,
+                        source.path.display(),
+                        owner
+                    )
+                    .into());
+                }
+                taken.push((name, source.path.display().to_string()));
+            }
+            blocks.push_str(&source.config_block(&fs::canonicalize(&source.path)?));
+            info!("Annotating INFO/{} from {}", source.names().join(", INFO/"), source.path.display());
+        }
+        Ok(blocks)
+    }
+
+    /// One row per variant and sample with every annotation as a column.
+    /// VAF, its interval, tier and inheritance are only included when
+    /// declared, so VCFs from other callers still produce a report.
+    ///
+    /// With `split_by_type`, SNVs and the other variant types also go to
+    /// their own tables, and a summary table counts each type. Each
+    /// annotation BED adds a column of the variants it overlaps.
+    /// Haplogroup-defining PhyloTree variants are flagged in the
+    /// `phylotree_polymorphism` column so they can be de-prioritised.
+    ///
+    /// Reference blocks, from `mity call --report-all-sites`, are not
+    /// variants: they go to a table of their own, one row per block and
+    /// sample with its minimum depth, showing the positions covered and
+    /// reference.
+    fn write_report(&self) -> Result<(), Box<dyn Error>> {
+        let phylotree = Classifier::bundled()?;
+        let beds = self
+            .annotation_beds
+            .iter()
+            .map(|(name, path)| AnnotationBed::read(name, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let reader = mity_util::open_vcf(&self.annotated_vcf_path)?;
+        let mut writer = BufWriter::new(checksum::OutputFile::create(&self.report_path)?);
+        let mut split_writers = if self.split_by_type {
+            Some([
+                BufWriter::new(checksum::OutputFile::create(&self.report_table_path("snv"))?),
+                BufWriter::new(checksum::OutputFile::create(&self.report_table_path("indel"))?),
+            ])
+        } else {
+            None
+        };
+        let mut reference_writer: Option<BufWriter<checksum::OutputFile>> = None;
+        let (mut reference_blocks, mut reference_positions) = (0, 0);
+        let mut type_counts: BTreeMap<VariantType, usize> = BTreeMap::new();
+        let (mut variants, mut polymorphisms) = (0, 0);
+
+        let mut info_fields: Vec<String> = Vec::new();
+        let mut format_fields: Vec<String> = Vec::new();
+        let mut samples: Vec<String> = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(id) = info_id(&line) {
+                info_fields.push(id.to_string());
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("##FORMAT=<ID=") {
+                let id = rest.split([',', '>']).next().unwrap_or_default();
+                if matches!(id, "VAF" | "VAF_LO" | "VAF_HI" | "tier" | "inheritance") {
+                    format_fields.push(id.to_string());
+                }
+                continue;
+            }
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(String::from).collect();
+                let mut columns: Vec<String> = ["SAMPLE", "CHROM", "POS", "REF", "ALT", "variant_type", "phylotree_polymorphism", "QUAL", "FILTER"]
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect();
+                columns.extend(format_fields.iter().cloned());
+                columns.extend(info_fields.iter().cloned());
+                columns.extend(beds.iter().map(|bed| bed.name.clone()));
+                writeln!(writer, "{}", columns.join("\t"))?;
+                for split_writer in split_writers.iter_mut().flatten() {
+                    writeln!(split_writer, "{}", columns.join("\t"))?;
+                }
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let record = VcfRecord::parse(&line)?;
+            if record.is_reference_block() {
+                let writer = match &mut reference_writer {
+                    Some(writer) => writer,
+                    None => {
+                        let mut writer =
+                            BufWriter::new(checksum::OutputFile::create(&self.report_table_path("reference_blocks"))?);
+                        writeln!(writer, "SAMPLE\tCHROM\tSTART\tEND\tMIN_DP")?;
+                        reference_writer.insert(writer)
+                    }
+                };
+                let sample_names = if samples.is_empty() {
+                    vec![String::new()]
+                } else {
+                    samples.clone()
+                };
+                for (index, sample) in sample_names.iter().enumerate() {
+                    let min_dp = record
+                        .sample(index, "MIN_DP")
+                        .or_else(|| record.sample(index, "DP"))
+                        .or_else(|| record.info("MIN_DP"))
+                        .unwrap_or_default();
+                    writeln!(writer, "{}\t{}\t{}\t{}\t{}", sample, record.chrom, record.pos, record.end(), min_dp)?;
+                }
+                reference_blocks += 1;
+                reference_positions += record.end() + 1 - record.pos;
+                continue;
+            }
+            let variant_type = record.variant_type();
+            *type_counts.entry(variant_type).or_default() += 1;
+            let polymorphism = record
+                .alt
+                .split(',')
+                .any(|alt| phylotree.is_defining(&(record.pos, record.reference.clone(), alt.to_string())));
+            variants += 1;
+            if polymorphism {
+                polymorphisms += 1;
+            }
+            let fixed = [
+                record.chrom.clone(),
+                record.pos.to_string(),
+                record.reference.clone(),
+                record.alt.clone(),
+                variant_type.as_str().to_string(),
+                polymorphism.to_string(),
+                record.qual.clone(),
+                record.filter.clone(),
+            ];
+            let mut annotations = annotation_values(&record, &info_fields);
+            annotations.extend(beds.iter().map(|bed| bed.annotate(&record)));
+
+            // Sites-only VCFs get a single row with an empty sample
+            let sample_names = if samples.is_empty() {
+                vec![String::new()]
+            } else {
+                samples.clone()
+            };
+            for (index, sample) in sample_names.iter().enumerate() {
+                let mut row = vec![sample.clone()];
+                row.extend(fixed.iter().cloned());
+                row.extend(
+                    format_fields
+                        .iter()
+                        .map(|key| record.sample(index, key).unwrap_or_default().to_string()),
+                );
+                row.extend(annotations.iter().cloned());
+                writeln!(writer, "{}", row.join("\t"))?;
+                if let Some([snv_writer, indel_writer]) = &mut split_writers {
+                    let split_writer = if variant_type == VariantType::Snv {
+                        snv_writer
+                    } else {
+                        indel_writer
+                    };
+                    writeln!(split_writer, "{}", row.join("\t"))?;
+                }
+            }
+        }
+        checksum::finish_buffered(writer)?;
+        info!("{} of {} variants are common PhyloTree polymorphisms", polymorphisms, variants);
+        info!("Wrote {}", self.report_path.display());
+        if let Some(reference_writer) = reference_writer {
+            checksum::finish_buffered(reference_writer)?;
+            info!(
+                "Wrote {} reference blocks covering {} positions to {}",
+                reference_blocks,
+                reference_positions,
+                self.report_table_path("reference_blocks").display()
+            );
+        }
+
+        if let Some(split_writers) = split_writers {
+            for split_writer in split_writers {
+                checksum::finish_buffered(split_writer)?;
+            }
+            let summary_path = self.report_table_path("summary");
+            let mut summary = BufWriter::new(checksum::OutputFile::create(&summary_path)?);
+            writeln!(summary, "category\tvariants")?;
+            for variant_type in VariantType::ALL {
+                let count = type_counts.get(&variant_type).copied().unwrap_or(0);
+                writeln!(summary, "{}\t{}", variant_type.as_str(), count)?;
+                info!("  {:<8} {}", variant_type.as_str(), count);
+            }
+            writeln!(summary, "phylotree_polymorphism\t{}", polymorphisms)?;
+            checksum::finish_buffered(summary)?;
+            info!(
+                "Wrote {}, {} and {}",
+                self.report_table_path("snv").display(),
+                self.report_table_path("indel").display(),
+                summary_path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// One row per variant and sample with the forward and reverse read
+    /// counts of the reference (SRF, SRR) and alternate alleles (SAF, SAR),
+    /// the strand biases SBR and SBA derived from them, and the FILTER
+    /// outcome, for reviewing borderline calls.
+    ///
+    /// A sample's own FORMAT counts are used when the caller wrote them,
+    /// otherwise the site's INFO counts; the `counts` column says which.
+    /// Missing counts and the biases that need them are left blank.
+    fn write_strand_table(&self) -> Result<(), Box<dyn Error>> {
+        let reader = mity_util::open_vcf(&self.annotated_vcf_path)?;
+        let mut writer = BufWriter::new(checksum::OutputFile::create(&self.strand_table_path)?);
+        writeln!(writer, "SAMPLE\tCHROM\tPOS\tREF\tALT\tcounts\tSRF\tSRR\tSAF\tSAR\tSBR\tSBA\tFILTER")?;
+
+        let mut samples: Vec<String> = Vec::new();
+        let mut rows = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(String::from).collect();
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let record = VcfRecord::parse(&line)?;
+            if record.is_reference_block() {
+                continue;
+            }
+            let sample_names = if samples.is_empty() {
+                vec![String::new()]
+            } else {
+                samples.clone()
+            };
+            for (index, sample) in sample_names.iter().enumerate() {
+                let per_sample = STRAND_FIELDS.iter().any(|key| record.sample(index, key).is_some());
+                let [srf, srr, saf, sar] = STRAND_FIELDS.map(|key| {
+                    let value = if per_sample { record.sample(index, key) } else { record.info(key) };
+                    value.unwrap_or_default().to_string()
+                });
+                let sbr = strand_bias(&srf, &srr);
+                let sba = if saf.is_empty() || sar.is_empty() {
+                    String::new()
+                } else {
+                    saf.split(',')
+                        .zip(sar.split(','))
+                        .map(|(saf, sar)| strand_bias(saf, sar))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                };
+                let row = [
+                    sample.clone(),
+                    record.chrom.clone(),
+                    record.pos.to_string(),
+                    record.reference.clone(),
+                    record.alt.clone(),
+                    if per_sample { "sample" } else { "site" }.to_string(),
+                    srf,
+                    srr,
+                    saf,
+                    sar,
+                    sbr,
+                    sba,
+                    record.filter.clone(),
+                ];
+                writeln!(writer, "{}", row.join("\t"))?;
+                rows += 1;
+            }
+        }
+        checksum::finish_buffered(writer)?;
+        info!("Wrote {} rows to {}", rows, self.strand_table_path.display());
+        Ok(())
+    }
+
+    /// Path of one of the tables written with `split_by_type`.
+    fn report_table_path(&self, table: &str) -> PathBuf {
+        self.output_dir
+            .join(format!("{}.mity.annotated.{}.tsv", self.prefix, table))
+    }
+}
+
+/// `forward / (forward + reverse)` to four decimals, or blank when either
+/// count is missing or both are zero.
+fn strand_bias(forward: &str, reverse: &str) -> String {
+    match (forward.parse::<f64>(), reverse.parse::<f64>()) {
+        (Ok(forward), Ok(reverse)) if forward + reverse > 0.0 => format!("{:.4}", forward / (forward + reverse)),
+        _ => String::new(),
+    }
+}
+
+/// A user BED whose overlap with each variant becomes a report column.
+struct AnnotationBed {
+    name: String,
+    intervals: Vec<(Interval, Option<String>)>,
+}
+
+impl AnnotationBed {
+    fn read(name: &str, path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(AnnotationBed {
+            name: name.to_string(),
+            intervals: mity_util::read_named_bed(path)?,
+        })
+    }
+
+    /// The names of the intervals overlapping the record, or `true`/`false`
+    /// for BEDs without a name column. MT and chrM match each other.
+    fn annotate(&self, record: &VcfRecord) -> String {
+        let start = record.pos as usize - 1;
+        let end = start + record.reference.len().max(1);
+        let same_contig = |contig: &str| {
+            contig == record.chrom || (mity_util::is_mt_contig(contig) && mity_util::is_mt_contig(&record.chrom))
+        };
+        let overlapping: Vec<&(Interval, Option<String>)> = self
+            .intervals
+            .iter()
+            .filter(|(interval, _)| same_contig(&interval.contig) && interval.start < end && start < interval.end)
+            .collect();
+        if self.intervals.iter().any(|(_, name)| name.is_some()) {
+            let mut names: Vec<&str> = overlapping.iter().filter_map(|(_, name)| name.as_deref()).collect();
+            names.dedup();
+            names.join(",")
+        } else {
+            (!overlapping.is_empty()).to_string()
+        }
+    }
+}
+
+/// Parse `--annotation-bed` as `NAME=FILE.bed`.
+pub fn parse_annotation_bed(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+            Ok((name.trim().to_string(), PathBuf::from(path.trim())))
+        }
+        _ => Err(format!("Expected NAME=FILE.bed, got {}", value)),
+    }
+}
+
+/// A tabixed VCF whose INFO fields are added by vcfanno, from
+/// `--annotation-vcf FILE:FIELDS[:PREFIX]`.
+#[derive(Clone, Debug)]
+pub struct AnnotationVcf {
+    pub path: PathBuf,
+    pub fields: Vec<String>,
+    /// Prepended to the field names, as `PREFIX_FIELD`, to avoid clashes.
+    pub prefix: Option<String>,
+}
+
+impl AnnotationVcf {
+    /// The INFO names the fields are written as.
+    fn names(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .map(|field| match &self.prefix {
+                Some(prefix) => format!("{}_{}", prefix, field),
+                None => field.clone(),
+            })
+            .collect()
+    }
+
+    fn config_block(&self, path: &Path) -> String {
+        let quoted = |values: &[String]| {
+            let values: Vec<String> = values.iter().map(|value| format!("\"{}\"", value)).collect();
+            format!("[{}]", values.join(", "))
+        };
+        let ops = vec!["self".to_string(); self.fields.len()];
+        format!(
+            "\n[[annotation]]\nfile=\"{}\"\nfields={}\nnames={}\nops={}\n",
+            path.display().to_string().replace('\\', "\\\\").replace('"', "\\\""),
+            quoted(&self.fields),
+            quoted(&self.names()),
+            quoted(&ops)
+        )
+    }
+
+    /// Header line recording the source and the fields taken from it.
+    fn provenance(&self) -> String {
+        format!(
+            "##mityAnnotationVcf=\"{} fields={} names={}\"",
+            self.path.display(),
+            self.fields.join(","),
+            self.names().join(",")
+        )
+    }
+}
+
+/// Parse `--annotation-vcf` as `FILE:FIELD1,FIELD2[:PREFIX]`.
+pub fn parse_annotation_vcf(value: &str) -> Result<AnnotationVcf, String> {
+    let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+    let (path, fields, prefix) = match parts.as_slice() {
+        [path, fields] => (path, fields, None),
+        [path, fields, prefix] if !prefix.is_empty() => (path, fields, Some(prefix.to_string())),
+        _ => return Err(format!("Expected FILE:FIELD1,FIELD2[:PREFIX], got {}", value)),
+    };
+    let fields: Vec<String> = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(String::from)
+        .collect();
+    if path.is_empty() || fields.is_empty() {
+        return Err(format!("Expected FILE:FIELD1,FIELD2[:PREFIX], got {}", value));
+    }
+    Ok(AnnotationVcf {
+        path: PathBuf::from(path),
+        fields,
+        prefix,
+    })
+}
+
+/// The INFO names a vcfanno config annotates: each block's `names`, or its
+/// `fields` when it has no `names`.
+fn config_annotation_names(config: &str) -> Vec<String> {
+    let mut all = Vec::new();
+    let mut block: (Option<Vec<String>>, Option<Vec<String>>) = (None, None);
+    for line in config.lines().chain(std::iter::once("[[end]]")) {
+        let line = line.trim();
+        if line.starts_with("[[") {
+            let (names, fields) = std::mem::take(&mut block);
+            all.extend(names.or(fields).unwrap_or_default());
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let strings = || value.split('"').skip(1).step_by(2).map(String::from).collect();
+        match key.trim() {
+            "names" => block.0 = Some(strings()),
+            "fields" => block.1 = Some(strings()),
+            _ => {}
+        }
+    }
+    all
+}
+
+/// The ID of an `##INFO` header line.
+pub fn info_id(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("##INFO=<ID=")?;
+    rest.split([',', '>']).next()
+}
+
+/// INFO values of a record as report columns: flags are `true` and missing
+/// fields empty.
+pub fn annotation_values(record: &VcfRecord, fields: &[String]) -> Vec<String> {
+    fields
+        .iter()
+        .map(|key| match record.info.iter().find(|(k, _)| k == key) {
+            Some((_, Some(value))) => value.clone(),
+            Some((_, None)) => "true".to_string(),
+            None => String::new(),
+        })
+        .collect()
+}
+
+/// The mitochondrial contig name of a VCF, from its header or, for VCFs
+/// without `##contig` lines, its first record.
+fn mt_contig(vcf: &Path) -> Result<String, Box<dyn Error>> {
+    if let Ok((contig, _)) = mity_util::vcf_get_mt_contig(vcf) {
+        return Ok(contig);
+    }
+    let reader = mity_util::open_vcf(vcf)?;
+    for line in reader.lines() {
+        let line = line?;
+        if !line.starts_with('#') && !line.is_empty() {
+            return Ok(line.split('\t').next().unwrap_or_default().to_string());
+        }
+    }
+    Err(format!("Could not determine the mitochondrial contig of {}", vcf.display()).into())
+}
+
+/// The bundled vcfanno config matching the contig naming of the input.
+fn vcfanno_config(contig: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let name = match contig {
+        "MT" => "vcfanno-config-mt.toml",
+        "chrM" => "vcfanno-config-chrm.toml",
+        _ => return Err(format!("Unsupported mitochondrial contig name: {}", contig).into()),
+    };
+    Ok(mity_util::get_mity_dir()?.join("config").join(name))
+}
+
+/// A vcfanno config with each annotation `file` made absolute. Relative
+/// files are looked up next to the config, then in the mity directory and
+/// its `annot` directory. Files found in none of them are reported together.
+fn resolve_vcfanno_config(config: &Path) -> Result<String, Box<dyn Error>> {
+    let text = fs::read_to_string(config)?;
+    let config_dir = config.parent().unwrap_or(Path::new("."));
+    let mity_dir = mity_util::get_mity_dir()?;
+    let mut resolved = String::new();
+    let mut missing = Vec::new();
+    for line in text.lines() {
+        let Some(file) = config_file_entry(line) else {
+            resolved.push_str(line);
+            resolved.push('\n');
+            continue;
+        };
+        let path = [config_dir.join(file), mity_dir.join(file)]
+            .into_iter()
+            .find(|path| path.exists())
+            .or_else(|| mity_util::get_annot_file(file).ok());
+        match path {
+            Some(path) => {
+                let path = fs::canonicalize(&path)?.display().to_string();
+                resolved.push_str(&format!("file=\"{}\"\n", path.replace('\\', "\\\\").replace('"', "\\\"")));
+            }
+            None => {
+                missing.push(file.to_string());
+                resolved.push_str(line);
+                resolved.push('\n');
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "Annotation files of {} not found next to it or in {}:\n  {}",
+            config.display(),
+            mity_dir.display(),
+            missing.join("\n  ")
+        )
+        .into());
+    }
+    Ok(resolved)
+}
+
+/// The value of a `file="..."` line of a vcfanno config.
+fn config_file_entry(line: &str) -> Option<&str> {
+    let (key, value) = line.split_once('=')?;
+    if key.trim() != "file" {
+        return None;
+    }
+    value.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> VcfRecord {
+        VcfRecord::parse(line).unwrap()
+    }
+
+    #[test]
+    fn bed_columns_name_the_regions_a_variant_overlaps() {
+        let temp = tempfile::tempdir().unwrap();
+        let named = temp.path().join("genes.bed");
+        fs::write(&named, "chrM\t3306\t4262\tMT-ND1\nchrM\t4261\t4331\tMT-TI\nchrM\t4261\t4331\tMT-TI\n").unwrap();
+        let genes = AnnotationBed::read("genes", &named).unwrap();
+        let unnamed = temp.path().join("hypervariable.bed");
+        fs::write(&unnamed, "MT\t15999\t16569\n").unwrap();
+        let hypervariable = AnnotationBed::read("hv", &unnamed).unwrap();
+
+        // MT matches chrM; a deletion reaches into the next gene
+        assert_eq!(genes.annotate(&record("MT\t3307\t.\tA\tG\t.\t.\t.")), "MT-ND1");
+        assert_eq!(genes.annotate(&record("MT\t4261\t.\tAGA\tA\t.\t.\t.")), "MT-ND1,MT-TI");
+        assert_eq!(genes.annotate(&record("MT\t3306\t.\tC\tT\t.\t.\t.")), "");
+        assert_eq!(hypervariable.annotate(&record("MT\t16000\t.\tC\tT\t.\t.\t.")), "true");
+        assert_eq!(hypervariable.annotate(&record("MT\t15999\t.\tC\tT\t.\t.\t.")), "false");
+    }
+
+    #[test]
+    fn annotation_vcfs_become_vcfanno_blocks_and_report_columns() {
+        let gnomad = parse_annotation_vcf("gnomad.vcf.gz:AF_hom,AF_het:gnomad").unwrap();
+        assert_eq!(gnomad.names(), ["gnomad_AF_hom", "gnomad_AF_het"]);
+        let block = gnomad.config_block(Path::new("/data/gnomad.vcf.gz"));
+        assert!(block.contains("fields=[\"AF_hom\", \"AF_het\"]\nnames=[\"gnomad_AF_hom\", \"gnomad_AF_het\"]\nops=[\"self\", \"self\"]"), "{}", block);
+        assert_eq!(parse_annotation_vcf("clinvar.vcf.gz:CLNSIG").unwrap().names(), ["CLNSIG"]);
+        for invalid in ["clinvar.vcf.gz", "clinvar.vcf.gz::", ":CLNSIG", "a:b:c:d"] {
+            assert!(parse_annotation_vcf(invalid).is_err(), "{}", invalid);
+        }
+
+        let config = format!("[[annotation]]\nfile=\"a.vcf.gz\"\nfields=[\"X\"]\n{}", block);
+        assert_eq!(config_annotation_names(&config), ["X", "gnomad_AF_hom", "gnomad_AF_het"]);
+
+        let annotated = record("MT\t73\t.\tA\tG\t.\tPASS\tgnomad_AF_hom=0.8;COMMON");
+        let fields = ["gnomad_AF_hom", "COMMON", "gnomad_AF_het"].map(String::from);
+        assert_eq!(annotation_values(&annotated, &fields), ["0.8", "true", ""]);
+        assert_eq!(strand_bias("3", "1"), "0.7500");
+        assert_eq!(strand_bias("0", "0"), "");
+        assert_eq!(strand_bias("", "4"), "");
+    }
+}
@@ -1,19 +1,245 @@
-use log::{debug, error, info, LevelFilter};
-use noodles::bam;
-use simple_logger;
+use log::{debug, error, info, warn};
+use noodles::bgzf;
+use noodles::core::Region;
+use std::collections::HashSet;
 use std::error::Error;
-use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
 
-use crate::mity_util;
+use crate::aligner;
+use crate::check;
+use crate::checksum;
+use crate::circular::{self, Rotation};
+use crate::error::MityError;
+use crate::mity_util::{self, Interval, Tool, ToolVersion};
+use crate::mity_util::header::VcfHeader;
 use crate::normalise;
+use crate::pileup::{self, DepthSummary};
+use crate::preset::Preset;
+use crate::progress;
+use crate::read_filter::{self, NumtFilter, ReadFilter};
+use crate::rg_stats;
+use crate::sample_map::SampleMap;
+use crate::stats::{RunSummary, Timer};
+use crate::vcf_record::VcfRecord;
+
+/// The empty block that ends every complete BGZF file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// The empty container that ends every complete CRAM 3 file.
+const CRAM3_EOF: [u8; 38] = [
+    0x0f, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x0f, 0xe0, 0x45, 0x4f, 0x46, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x05, 0xbd, 0xd9, 0x4f, 0x00, 0x01, 0x00, 0x06, 0x06, 0x01, 0x00, 0x01, 0x00,
+    0x01, 0x00, 0xee, 0x63, 0x01, 0x4b,
+];
+
+/// Records and genotypes changed while the freebayes VCF is streamed.
+#[derive(Default)]
+struct StreamCounts {
+    /// Records removed by `--adaptive-min-ac`.
+    adaptive_removed: usize,
+    /// Sample genotypes reset by `--per-sample-filter`.
+    genotype_resets: usize,
+    /// Records left with no supporting sample by `--per-sample-filter`.
+    per_sample_removed: usize,
+    /// Windows freebayes failed on twice, left without calls.
+    uncalled: Vec<Interval>,
+}
+
+impl StreamCounts {
+    fn add(&mut self, other: StreamCounts) {
+        self.adaptive_removed += other.adaptive_removed;
+        self.genotype_resets += other.genotype_resets;
+        self.per_sample_removed += other.per_sample_removed;
+        self.uncalled.extend(other.uncalled);
+    }
+}
+
+/// How the call VCF is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Uncompressed text, not indexed.
+    Vcf,
+    /// BGZF with a tabix index.
+    #[default]
+    VcfGz,
+    /// Binary, not indexed.
+    Bcf,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 3] = ["vcf", "vcf.gz", "bcf"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "vcf" => Some(OutputFormat::Vcf),
+            "vcf.gz" => Some(OutputFormat::VcfGz),
+            "bcf" => Some(OutputFormat::Bcf),
+            _ => None,
+        }
+    }
+
+    /// The file extension, which is also the name of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Vcf => "vcf",
+            OutputFormat::VcfGz => "vcf.gz",
+            OutputFormat::Bcf => "bcf",
+        }
+    }
+}
+
+/// What a call run produced, for callers that go on to use its outputs.
+#[derive(Clone, Debug)]
+pub struct CallResult {
+    pub call_vcf: PathBuf,
+    pub format: OutputFormat,
+    /// Written with `--normalise`.
+    pub normalised_vcf: Option<PathBuf>,
+    /// The region called, as given to freebayes.
+    pub region: Option<String>,
+    pub prefix: String,
+    /// The `SM` tags of the inputs, in input order, before `--sample-map`.
+    pub samples: Vec<String>,
+    /// Variant records in the call VCF.
+    pub records: usize,
+}
+
+/// Options of a call run beyond its inputs and outputs. The thresholds
+/// left as `None` take their values from `preset`.
+#[derive(Clone, Debug)]
+pub struct CallSettings {
+    /// The build a `--custom-reference-fasta` stands in for, whose MT
+    /// length it is checked against.
+    pub custom_reference_build: Option<String>,
+    pub min_mq: Option<u32>,
+    pub min_bq: Option<u32>,
+    pub min_af: Option<f32>,
+    pub min_ac: Option<u32>,
+    pub p: Option<f32>,
+    pub preset: Preset,
+    pub normalise: bool,
+    pub region: Option<String>,
+    pub exclude_regions: Vec<String>,
+    pub exclude_bed: Option<PathBuf>,
+    pub region_bed: Option<PathBuf>,
+    /// The single input lists the BAMs and CRAMs to call.
+    pub bam_list: bool,
+    pub keep: bool,
+    pub sort_inputs: bool,
+    pub sample_map_path: Option<PathBuf>,
+    pub rg_stats: bool,
+    pub adaptive_min_ac: bool,
+    pub auto_mq: bool,
+    pub per_sample_filter: bool,
+    pub create_index: bool,
+    pub force: bool,
+    pub split_samples: bool,
+    pub progress: bool,
+    /// Per-sample depth freebayes downsamples each position to.
+    pub max_depth: Option<u32>,
+    /// The oldest freebayes calling is allowed with.
+    pub min_freebayes_version: ToolVersion,
+    pub ploidy: u32,
+    pub read_filter: ReadFilter,
+    /// Call inputs whose MT contig is not the reference's length.
+    pub skip_reference_check: bool,
+    /// Write reference blocks between the variants, with freebayes --gvcf.
+    pub report_all_sites: bool,
+    /// Call the MT junction again against a rotated mitochondrion.
+    pub circular: bool,
+    /// NUMTs whose reads are left out of calling.
+    pub numt_bed: Option<PathBuf>,
+    /// Write the per-base MT depth of each input as a bedgraph.
+    pub coverage_output: bool,
+    /// Fail on inputs without MT reads instead of warning.
+    pub error_on_empty: bool,
+    pub output_format: OutputFormat,
+    /// Fail on the first window freebayes fails on, without a retry.
+    pub strict_windows: bool,
+}
+
+impl Default for CallSettings {
+    fn default() -> Self {
+        CallSettings {
+            custom_reference_build: None,
+            min_mq: None,
+            min_bq: None,
+            min_af: None,
+            min_ac: None,
+            p: None,
+            preset: Preset::default(),
+            normalise: false,
+            region: None,
+            exclude_regions: Vec::new(),
+            exclude_bed: None,
+            region_bed: None,
+            bam_list: false,
+            keep: false,
+            sort_inputs: false,
+            sample_map_path: None,
+            rg_stats: false,
+            adaptive_min_ac: false,
+            auto_mq: false,
+            per_sample_filter: false,
+            create_index: false,
+            force: false,
+            split_samples: false,
+            progress: true,
+            max_depth: None,
+            min_freebayes_version: ToolVersion { major: 1, minor: 3, patch: 6 },
+            ploidy: 2,
+            read_filter: ReadFilter {
+                duplicates: true,
+                secondary: false,
+                supplementary: true,
+                end_exclusion: 0,
+            },
+            skip_reference_check: false,
+            report_all_sites: false,
+            circular: false,
+            numt_bed: None,
+            coverage_output: false,
+            error_on_empty: false,
+            output_format: OutputFormat::default(),
+            strict_windows: false,
+        }
+    }
+}
+
+/// Windows shorter than this are not worth a freebayes process of their own.
+const MIN_WINDOW_LEN: usize = 1000;
+
+/// Memory planned for each freebayes window under `--max-memory`, about
+/// what one takes on a deeply covered mitochondrion.
+const FREEBAYES_WINDOW_MEMORY: u64 = 512 << 20;
+
+/// Lines of freebayes' log given in the error when it fails.
+const FREEBAYES_LOG_TAIL: usize = 20;
+
+/// Definitions of the gVCF fields of `--report-all-sites`, added when
+/// freebayes does not declare them.
+const GVCF_HEADER: [(&str, &str, &str); 3] = [
+    ("ALT", "*", "##ALT=<ID=*,Description=\"Any allele other than REF, the ALT of a reference block\">"),
+    ("INFO", "END", "##INFO=<ID=END,Number=1,Type=Integer,Description=\"Last position of a reference block\">"),
+    ("INFO", "MIN_DP", "##INFO=<ID=MIN_DP,Number=1,Type=Integer,Description=\"Minimum depth over a reference block\">"),
+];
 
 pub struct Call {
     debug: bool,
-    files: Vec<String>,
-    reference: String,
-    genome: Option<String>,
+    files: Vec<PathBuf>,
+    reference: PathBuf,
+    /// The build a `--custom-reference-fasta` stands in for, whose MT
+    /// length it is checked against.
+    custom_reference_build: Option<String>,
+    genome: Option<PathBuf>,
     prefix: Option<String>,
     min_mq: u32,
     min_bq: u32,
@@ -21,53 +247,140 @@ pub struct Call {
     min_ac: u32,
     p: f32,
     normalise: bool,
-    output_dir: String,
+    output_dir: PathBuf,
     region: Option<String>,
+    exclude_regions: Vec<String>,
+    exclude_bed: Option<PathBuf>,
+    region_bed: Option<PathBuf>,
     bam_list: bool,
     keep: bool,
+    sort_inputs: bool,
+    sample_map_path: Option<PathBuf>,
+    rg_stats: bool,
+    adaptive_min_ac: bool,
+    auto_mq: bool,
+    per_sample_filter: bool,
+    create_index: bool,
+    force: bool,
+    split_samples: bool,
+    progress: bool,
+    /// Per-sample depth freebayes downsamples each position to.
+    max_depth: Option<u32>,
+    /// The oldest freebayes calling is allowed with.
+    min_freebayes_version: ToolVersion,
+    ploidy: u32,
+    read_filter: ReadFilter,
+    /// Call inputs whose MT contig is not the reference's length.
+    skip_reference_check: bool,
+    /// Write reference blocks between the variants, with freebayes --gvcf.
+    report_all_sites: bool,
+    /// Call the MT junction again against a rotated mitochondrion.
+    circular: bool,
+    /// The defaults the thresholds not given were taken from.
+    preset: Preset,
+    /// NUMTs whose reads are left out of calling.
+    numt_bed: Option<PathBuf>,
+    /// Write the per-base MT depth of each input as a bedgraph.
+    coverage_output: bool,
+    /// Fail on inputs without MT reads instead of warning.
+    error_on_empty: bool,
+    output_format: OutputFormat,
+    /// Fail on the first window freebayes fails on, without a retry.
+    strict_windows: bool,
 
     // Internal fields
-    file_string: String,
-    normalised_vcf_path: String,
-    call_vcf_path: String,
-    mity_cmd: String,
-    sed_cmd: String,
+    sample_map: SampleMap,
+    numt_filter: Option<NumtFilter>,
+    /// The `SM` tags of the inputs, in input order.
+    samples: Vec<String>,
+    /// The BAMs freebayes reads: the inputs, or their filtered copies.
+    calling_files: Vec<PathBuf>,
+    exclusions: Vec<Interval>,
+    /// Sub-regions left after the exclusions; empty when there are none.
+    regions: Vec<Interval>,
+    /// Windows freebayes runs on at once; empty for a single run.
+    windows: Vec<Interval>,
+    /// Indexes made by `--create-index`, removed after calling.
+    created_indexes: Vec<PathBuf>,
+    /// The per-sample VCF of each input with `--split-samples`, in input
+    /// order.
+    split_vcf_paths: Vec<PathBuf>,
+    /// The depth bedgraph of each input with `--coverage-output`, in input
+    /// order.
+    coverage_paths: Vec<PathBuf>,
+    /// The freebayes version, when it could be read.
+    freebayes_version: Option<ToolVersion>,
+    /// How `--auto-mq` changed `min_mq`, for the provenance header.
+    auto_mq_note: Option<String>,
+    targets_bed_path: PathBuf,
+    normalised_vcf_path: PathBuf,
+    /// The BGZF VCF calling writes, and the output for `vcf.gz`.
+    call_vcf_path: PathBuf,
+    /// The call VCF in `output_format`.
+    output_vcf_path: PathBuf,
+    rg_stats_path: PathBuf,
+    provenance: Vec<String>,
 }
 
 impl Call {
-    const MIN_MQ: u32 = 30;
-    const MIN_BQ: u32 = 24;
-    const MIN_AF: f32 = 0.01;
-    const MIN_AC: u32 = 4;
-    const P_VAL: f32 = 0.002;
-
     pub fn new(
         debug: bool,
-        files: Vec<String>,
-        reference: String,
-        genome: Option<String>,
+        files: Vec<PathBuf>,
+        reference: PathBuf,
+        genome: Option<PathBuf>,
+        output_dir: PathBuf,
         prefix: Option<String>,
-        min_mq: Option<u32>,
-        min_bq: Option<u32>,
-        min_af: Option<f32>,
-        min_ac: Option<u32>,
-        p: Option<f32>,
-        normalise: bool,
-        output_dir: String,
-        region: Option<String>,
-        bam_list: bool,
-        keep: bool,
+        settings: CallSettings,
     ) -> Self {
-        let min_mq = min_mq.unwrap_or(Self::MIN_MQ);
-        let min_bq = min_bq.unwrap_or(Self::MIN_BQ);
-        let min_af = min_af.unwrap_or(Self::MIN_AF);
-        let min_ac = min_ac.unwrap_or(Self::MIN_AC);
-        let p = p.unwrap_or(Self::P_VAL);
+        let CallSettings {
+            custom_reference_build,
+            min_mq,
+            min_bq,
+            min_af,
+            min_ac,
+            p,
+            preset,
+            normalise,
+            region,
+            exclude_regions,
+            exclude_bed,
+            region_bed,
+            bam_list,
+            keep,
+            sort_inputs,
+            sample_map_path,
+            rg_stats,
+            adaptive_min_ac,
+            auto_mq,
+            per_sample_filter,
+            create_index,
+            force,
+            split_samples,
+            progress,
+            max_depth,
+            min_freebayes_version,
+            ploidy,
+            read_filter,
+            skip_reference_check,
+            report_all_sites,
+            circular,
+            numt_bed,
+            coverage_output,
+            error_on_empty,
+            output_format,
+            strict_windows,
+        } = settings;
+        let min_mq = min_mq.unwrap_or(preset.min_mq());
+        let min_bq = min_bq.unwrap_or(preset.min_bq());
+        let min_af = min_af.unwrap_or(preset.min_af());
+        let min_ac = min_ac.unwrap_or(preset.min_ac());
+        let p = p.unwrap_or(preset.p());
 
         Call {
             debug,
             files,
             reference,
+            custom_reference_build,
             genome,
             prefix,
             min_mq,
@@ -78,283 +391,2980 @@ impl Call {
             normalise,
             output_dir,
             region,
+            exclude_regions,
+            exclude_bed,
+            region_bed,
             bam_list,
             keep,
-            file_string: String::new(),
-            normalised_vcf_path: String::new(),
-            call_vcf_path: String::new(),
-            mity_cmd: String::new(),
-            sed_cmd: String::new(),
+            sort_inputs,
+            sample_map_path,
+            rg_stats,
+            adaptive_min_ac,
+            auto_mq,
+            per_sample_filter,
+            create_index,
+            force,
+            split_samples,
+            progress,
+            max_depth,
+            min_freebayes_version,
+            ploidy,
+            read_filter,
+            skip_reference_check,
+            report_all_sites,
+            circular,
+            preset,
+            numt_bed,
+            coverage_output,
+            error_on_empty,
+            output_format,
+            strict_windows,
+            sample_map: SampleMap::default(),
+            numt_filter: None,
+            samples: Vec::new(),
+            calling_files: Vec::new(),
+            exclusions: Vec::new(),
+            regions: Vec::new(),
+            windows: Vec::new(),
+            created_indexes: Vec::new(),
+            split_vcf_paths: Vec::new(),
+            coverage_paths: Vec::new(),
+            freebayes_version: None,
+            auto_mq_note: None,
+            targets_bed_path: PathBuf::new(),
+            normalised_vcf_path: PathBuf::new(),
+            call_vcf_path: PathBuf::new(),
+            output_vcf_path: PathBuf::new(),
+            rg_stats_path: PathBuf::new(),
+            provenance: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn run(&mut self) -> Result<CallResult, Box<dyn Error>> {
         if self.debug {
-            simple_logger::SimpleLogger::new()
-                .with_level(LevelFilter::Debug)
-                .init()?;
             debug!("Entered debug mode.");
-        } else {
-            simple_logger::SimpleLogger::new()
-                .with_level(LevelFilter::Info)
-                .init()?;
         }
 
+        let mut summary = RunSummary::new("mity call");
+        let _progress = progress::start(self.progress);
+
+        progress::stage("checks");
+        let timer = Timer::start();
         if self.bam_list {
             self.get_files_from_list()?;
         }
+        // Everything downstream (prefix, region detection and the VCF sample
+        // columns) follows this one canonical order.
+        if self.sort_inputs {
+            self.files.sort();
+        }
         self.run_checks()?;
         self.set_strings();
+        if self.split_samples {
+            self.set_split_vcf_paths()?;
+        }
+        if self.coverage_output {
+            self.set_coverage_paths()?;
+        }
+        let mut outputs = vec![self.output_vcf_path.as_path()];
+        if self.normalise {
+            outputs.push(&self.normalised_vcf_path);
+        }
+        outputs.extend(self.split_vcf_paths.iter().map(PathBuf::as_path));
+        outputs.extend(self.coverage_paths.iter().map(PathBuf::as_path));
+        mity_util::check_overwrite(&outputs, self.force).map_err(crate::error::boxed)?;
         self.set_region()?;
+        self.set_windows()?;
         self.set_mity_cmd();
+        summary.record("checks", timer);
+        summary.set_metric("input_files", self.files.len());
+        if !self.created_indexes.is_empty() {
+            summary.set_metric(
+                "created_indexes",
+                self.created_indexes.iter().map(|path| path.display().to_string()).collect::<Vec<_>>(),
+            );
+        }
+        if self.regions.is_empty() {
+            summary.set_metric("region", self.region.clone());
+        } else {
+            summary.set_metric("region", self.regions.iter().map(Interval::to_region).collect::<Vec<_>>());
+        }
+        if !self.exclusions.is_empty() {
+            summary.set_metric("excluded", self.exclusions.iter().map(Interval::to_region).collect::<Vec<_>>());
+        }
+        if !self.windows.is_empty() {
+            summary.set_metric("freebayes_windows", self.windows.len());
+        }
+        if self.split_samples {
+            summary.set_metric("split_samples", self.split_vcf_paths.len());
+        }
 
-        self.run_freebayes()?;
+        if self.needs_filtered_copies() {
+            progress::stage("read filtering");
+            let timer = Timer::start();
+            let removed = self.write_filtered_inputs()?;
+            summary.record("read filtering", timer);
+            if let Some(removed) = removed {
+                summary.set_metric("read_filter_removed", removed.flagged);
+                if self.numt_filter.is_some() {
+                    summary.set_metric("numt_reads_removed", removed.numt);
+                }
+            }
+        }
 
-        if self.normalise {
-            self.run_normalise()?;
-        } else {
-            mity_util::tabix(&self.call_vcf_path)?;
+        if self.rg_stats {
+            progress::stage("read group stats");
+            let timer = Timer::start();
+            summary.set_metric("read_groups", self.write_rg_stats()?);
+            summary.record("read group stats", timer);
+            summary.add_output(&self.rg_stats_path);
         }
 
-        Ok(())
-    }
+        if self.coverage_output {
+            progress::stage("coverage");
+            let timer = Timer::start();
+            summary.set_metric("coverage", self.write_coverage()?);
+            summary.record("coverage", timer);
+            for (file, path) in self.files.iter().zip(&self.coverage_paths) {
+                if !mity_util::is_cram(file) {
+                    summary.add_output(path);
+                }
+            }
+        }
 
-    fn run_freebayes(&self) -> Result<(), Box<dyn Error>> {
-        let freebayes_call = format!(
-            "set -o pipefail && freebayes -f {} {} --min-mapping-quality {} \
-            --min-base-quality {} --min-alternate-fraction {} --min-alternate-count {} \
-            --ploidy 2 --region {} | sed 's/##source/##freebayesSource/' | sed \
-            's/##commandline/##freebayesCommandline/' | bgzip > {}",
-            self.reference,
-            self.file_string,
-            self.min_mq,
-            self.min_bq,
-            self.min_af,
-            self.min_ac,
-            self.region.as_deref().unwrap_or(""),
-            self.call_vcf_path,
-        );
+        progress::stage("freebayes + bgzip");
+        let timer = Timer::start();
+        let mut counts = self.run_freebayes()?;
+        summary.record("freebayes + bgzip", timer);
+        if self.keep && !self.windows.is_empty() {
+            let chunks: Vec<String> = self
+                .freebayes_vcfs()
+                .iter()
+                .flat_map(|vcf| self.chunk_paths(vcf))
+                .map(|path| path.display().to_string())
+                .collect();
+            summary.set_metric("freebayes_chunks", chunks);
+        }
+        if self.circular {
+            progress::stage("circular pass");
+            let timer = Timer::start();
+            let (calls, circular_counts) = self.run_circular_pass()?;
+            counts.add(circular_counts);
+            summary.record("circular pass", timer);
+            summary.set_metric("circular_pass_calls", calls);
+        }
+        if !self.keep {
+            for path in self
+                .created_indexes
+                .iter()
+                .chain(&self.freebayes_logs())
+                .chain(&self.filtered_copies())
+                .chain(&self.circular_files())
+            {
+                fs::remove_file(path)?;
+            }
+        }
+        counts.uncalled.sort_by_key(|window| window.start);
+        let mut uncalled: Vec<String> = counts.uncalled.iter().map(Interval::to_region).collect();
+        uncalled.dedup();
+        if !uncalled.is_empty() {
+            summary.set_metric("uncalled_intervals", uncalled.clone());
+        }
+        if self.adaptive_min_ac {
+            summary.set_metric("adaptive_min_ac_removed", counts.adaptive_removed);
+        }
+        if self.per_sample_filter {
+            summary.set_metric("per_sample_genotype_resets", counts.genotype_resets);
+            summary.set_metric("per_sample_filter_removed", counts.per_sample_removed);
+        }
 
-        info!("Running FreeBayes in sensitive mode");
-        debug!("{}", freebayes_call);
+        if !self.sample_map.is_empty() {
+            progress::stage("sample renaming");
+            let timer = Timer::start();
+            self.sample_map.rewrite_vcf(&self.call_vcf_path)?;
+            for path in &self.split_vcf_paths {
+                self.sample_map.rewrite_vcf(path)?;
+            }
+            summary.record("sample renaming", timer);
+        }
+        if !self.split_vcf_paths.is_empty() {
+            progress::stage("per-sample tabix");
+            let timer = Timer::start();
+            for path in &self.split_vcf_paths {
+                mity_util::tabix(path)?;
+                summary.add_output(path);
+            }
+            summary.record("per-sample tabix", timer);
+        }
+
+        let records = mity_util::count_vcf_records(&self.call_vcf_path).map_err(crate::error::boxed)?;
+        if self.output_format != OutputFormat::VcfGz {
+            progress::stage("output format");
+            let timer = Timer::start();
+            self.write_output_format()?;
+            summary.record("output format", timer);
+        }
+        summary.set_metric("output_format", self.output_format.extension());
+        summary.add_output(&self.output_vcf_path);
 
-        let output = Command::new("/bin/bash")
-            .arg("-c")
-            .arg(freebayes_call)
-            .output()?;
+        let mut normalised_vcf = None;
+        if self.normalise {
+            progress::stage("normalise");
+            let timer = Timer::start();
+            normalised_vcf = self.run_normalise()?.normalised_vcf;
+            summary.record("normalise", timer);
+            summary.add_output(&self.normalised_vcf_path);
+        } else if self.output_format == OutputFormat::VcfGz {
+            progress::stage("tabix");
+            let timer = Timer::start();
+            mity_util::tabix(&self.call_vcf_path)?;
+            summary.record("tabix", timer);
+        }
 
-        if !output.status.success() {
-            error!(
-                "FreeBayes failed: {:?}",
-                String::from_utf8_lossy(&output.stderr)
+        mity_util::log_intermediate_files(self.keep, &self.intermediate_files());
+        summary.log();
+        summary.write_json(&self.output_dir.join(format!(
+            "{}.mity.call.stats.json",
+            self.prefix.as_ref().unwrap()
+        )))?;
+        if !uncalled.is_empty() {
+            warn!(
+                "NOT CALLED: FreeBayes failed twice on {}, which have no calls. The call VCF notes them in its mityUncalled header lines; --strict-windows makes this an error",
+                uncalled.join(", ")
             );
-            return Err(format!("FreeBayes failed with code {:?}", output.status.code()).into());
         }
 
-        debug!("Finished running FreeBayes");
-        Ok(())
+        Ok(CallResult {
+            records,
+            call_vcf: self.output_vcf_path.clone(),
+            format: self.output_format,
+            normalised_vcf,
+            region: self.region.clone(),
+            prefix: self.prefix.clone().unwrap_or_default(),
+            samples: self.samples.clone(),
+        })
     }
 
-    fn set_region(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.region.is_none() {
-            self.region = Some(self.bam_get_mt_contig(&self.files[0])?);
+    /// Write the BGZF call VCF as plain VCF or BCF, then remove it unless
+    /// `--keep` is given.
+    fn write_output_format(&self) -> Result<(), Box<dyn Error>> {
+        let mut output = checksum::OutputFile::create(&self.output_vcf_path)?;
+        if self.output_format == OutputFormat::Bcf {
+            output = mity_util::write_bcf(&self.call_vcf_path, output).map_err(crate::error::boxed)?;
+        } else {
+            io::copy(&mut mity_util::open_vcf(&self.call_vcf_path).map_err(crate::error::boxed)?, &mut output)?;
+        }
+        output.finish().map_err(crate::error::boxed)?;
+        info!("Wrote {} as {}", self.output_vcf_path.display(), self.output_format.extension());
+        if !self.keep {
+            fs::remove_file(&self.call_vcf_path)?;
+            if let Some(algorithm) = checksum::algorithm() {
+                let mut sidecar = self.call_vcf_path.clone().into_os_string();
+                sidecar.push(format!(".{}", algorithm.name()));
+                let _ = fs::remove_file(sidecar);
+            }
         }
         Ok(())
     }
 
-    fn set_strings(&mut self) {
-        if self.prefix.is_none() {
-            self.prefix = Some(self.make_prefix(&self.files[0]));
+    /// Run freebayes and write the BGZF call VCF, returning what
+    /// `--adaptive-min-ac` and `--per-sample-filter` changed.
+    fn run_freebayes(&self) -> Result<StreamCounts, Box<dyn Error>> {
+        let freebayes = mity_util::tool_path(Tool::Freebayes)?;
+        info!("Running FreeBayes in sensitive mode");
+        let region_args = self.region_args()?;
+        let runs = if self.split_samples { self.files.len() } else { 1 };
+        let chunks = runs * self.windows.len().max(1);
+        if chunks > 1 {
+            progress::chunks(chunks as u64);
         }
+        let counts = if self.split_samples {
+            self.run_freebayes_split(&freebayes, &region_args)
+        } else {
+            self.run_freebayes_on(&freebayes, &region_args, &bam_args(&self.calling_files), &self.call_vcf_path)
+        };
+        if !self.regions.is_empty() && !self.keep {
+            fs::remove_file(&self.targets_bed_path)?;
+        }
+        let counts = counts?;
 
-        self.file_string = self
-            .files
-            .iter()
-            .rev()
-            .map(|file| format!("-b {}", file))
-            .collect::<Vec<_>>()
-            .join(" ");
-
-        self.normalised_vcf_path = format!(
-            "{}/{}.mity.normalise.vcf.gz",
-            self.output_dir,
-            self.prefix.as_ref().unwrap()
-        );
-        self.call_vcf_path = format!(
-            "{}/{}.mity.call.vcf.gz",
-            self.output_dir,
-            self.prefix.as_ref().unwrap()
-        );
+        if self.adaptive_min_ac {
+            info!("Adaptive minimum alternate count removed {} records", counts.adaptive_removed);
+        }
+        if self.per_sample_filter {
+            info!(
+                "Per-sample filter reset {} genotypes and removed {} records",
+                counts.genotype_resets, counts.per_sample_removed
+            );
+        }
+        debug!("Finished running FreeBayes");
+        Ok(counts)
     }
 
-    fn make_prefix(&self, file_name: &str) -> String {
-        let path = Path::new(file_name);
-        path.file_stem()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string()
+    /// Call each input on its own, at once within the thread budget, into
+    /// its per-sample VCF, then merge those into the call VCF so its sample
+    /// columns are theirs, in input order.
+    fn run_freebayes_split(&self, freebayes: &Path, region_args: &[OsString]) -> Result<StreamCounts, Box<dyn Error>> {
+        let results = thread::scope(|scope| {
+            let runs: Vec<_> = self
+                .files
+                .iter()
+                .zip(&self.calling_files)
+                .zip(&self.split_vcf_paths)
+                .map(|((file, calling_file), vcf)| {
+                    let file_args = bam_args(std::slice::from_ref(calling_file));
+                    scope.spawn(move || {
+                        let counts = self
+                            .run_freebayes_on(freebayes, region_args, &file_args, vcf)
+                            .map_err(|e| e.to_string());
+                        if self.windows.is_empty() {
+                            progress::chunk_done(&file.display().to_string());
+                        }
+                        counts
+                    })
+                })
+                .collect();
+            runs.into_iter()
+                .map(|run| run.join().expect("freebayes sample run panicked"))
+                .collect::<Vec<_>>()
+        });
+        let mut counts = StreamCounts::default();
+        for result in results {
+            counts.add(result?);
+        }
+
+        info!("Merging {} per-sample VCFs", self.split_vcf_paths.len());
+        if let [single] = self.split_vcf_paths.as_slice() {
+            fs::copy(single, &self.call_vcf_path)?;
+            return Ok(counts);
+        }
+        for path in &self.split_vcf_paths {
+            mity_util::tabix(path)?;
+        }
+        let mut command = mity_util::tool_command(Tool::Bcftools)?;
+        command
+            .arg("merge");
+        if self.report_all_sites {
+            // Merge overlapping reference blocks rather than stacking them
+            command.arg("--gvcf").arg(&self.reference);
+        }
+        command
+            .arg("-O")
+            .arg("z")
+            .arg("-o")
+            .arg(&self.call_vcf_path)
+            .args(&self.split_vcf_paths);
+        debug!("{:?}", command);
+        let output = command.output()?;
+        if !output.status.success() {
+            error!("bcftools merge failed: {:?}", String::from_utf8_lossy(&output.stderr));
+            return Err(MityError::tool("bcftools merge", output.status.code(), &output.stderr).into());
+        }
+        Ok(counts)
     }
 
-    fn get_files_from_list(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.files.len() > 1 {
-            return Err("--bam-file-list argument expects only 1 file to be provided.".into());
+    /// Call the inputs in `file_args` into `vcf`, in windows if there are
+    /// any.
+    fn run_freebayes_on(
+        &self,
+        freebayes: &Path,
+        region_args: &[OsString],
+        file_args: &[OsString],
+        vcf: &Path,
+    ) -> Result<StreamCounts, Box<dyn Error>> {
+        if self.windows.is_empty() {
+            self.run_freebayes_once(freebayes, region_args, file_args, vcf)
+        } else {
+            self.run_freebayes_windows(freebayes, file_args, vcf)
         }
+    }
 
-        let file_content = fs::read_to_string(&self.files[0])?;
-        self.files = file_content.lines().map(String::from).collect();
-        Ok(())
+    /// Run freebayes over the whole call region, streaming its VCF.
+    fn run_freebayes_once(
+        &self,
+        freebayes: &Path,
+        region_args: &[OsString],
+        file_args: &[OsString],
+        vcf: &Path,
+    ) -> Result<StreamCounts, Box<dyn Error>> {
+        // freebayes is spawned directly so paths reach it byte-for-byte,
+        // whatever they contain. The VCF comes back on stdout, where its
+        // header is rewritten and it is compressed, so the adaptive filter
+        // and `--checksums` see it as it is written.
+        let args = self.freebayes_args(&self.reference, region_args, file_args);
+        debug!("{} {:?} > {}", freebayes.display(), args, vcf.display());
+
+        let _permit = mity_util::thread_budget().acquire("freebayes", 1);
+        let mut child = Command::new(freebayes)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Drain stderr into the log alongside stdout so neither pipe fills
+        // up and stalls
+        let log_path = call_vcf_sibling(vcf, "freebayes.log");
+        let mut log = BufWriter::new(File::create(&log_path)?);
+        let stderr = child.stderr.take().expect("freebayes stderr is piped");
+        let stderr = thread::spawn(move || -> io::Result<Vec<String>> {
+            let mut tail = Vec::new();
+            for line in BufReader::new(stderr).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                debug!("freebayes: {}", line);
+                writeln!(log, "{}", line)?;
+                tail.push(line);
+                if tail.len() > FREEBAYES_LOG_TAIL {
+                    tail.remove(0);
+                }
+            }
+            log.flush()?;
+            Ok(tail)
+        });
+        let stdout = child.stdout.take().expect("freebayes stdout is piped");
+        // Its header echoes the reference path, which need not be UTF-8
+        let lines = BufReader::new(stdout)
+            .split(b'\n')
+            .map(|line| line.map(|line| String::from_utf8_lossy(&line).into_owned()));
+        let counts = self.write_call_vcf(vcf, lines)?;
+        let status = child.wait()?;
+        let tail = stderr.join().expect("stderr reader panicked")?;
+
+        if !status.success() {
+            return Err(freebayes_failure(status.code(), &tail, &log_path));
+        }
+        Ok(counts)
     }
 
-    fn run_checks(&self) -> Result<(), Box<dyn Error>> {
-        if self.files.len() > 1 && self.prefix.is_none() {
-            return Err("If there is more than one BAM/CRAM file, --prefix must be set".into());
+    /// Run freebayes on every window at once, within the thread budget, and
+    /// write their records in window order. A record reported by two
+    /// windows, as can happen at a boundary, is kept once.
+    ///
+    /// A window freebayes fails on is run once more; if that fails too, the
+    /// window is left without calls, noted in the header and returned in the
+    /// counts, and the others are written. With `--strict-windows` the first
+    /// failure is the error instead.
+    fn run_freebayes_windows(
+        &self,
+        freebayes: &Path,
+        file_args: &[OsString],
+        vcf: &Path,
+    ) -> Result<StreamCounts, Box<dyn Error>> {
+        let attempts = thread::scope(|scope| {
+            let runs: Vec<_> = self
+                .windows
+                .iter()
+                .map(|window| {
+                    let args = self.freebayes_args(
+                        &self.reference,
+                        &[OsString::from("--region"), OsString::from(window.to_freebayes_region())],
+                        file_args,
+                    );
+                    scope.spawn(move || -> io::Result<Vec<Output>> {
+                        let run = || {
+                            let _permit = mity_util::thread_budget().acquire("freebayes", 1);
+                            debug!("{} {:?}", freebayes.display(), args);
+                            Command::new(freebayes).args(&args).output()
+                        };
+                        let mut attempts = vec![run()?];
+                        if !self.strict_windows && !attempts[0].status.success() {
+                            warn!("FreeBayes failed on {}; running it again", window.to_region());
+                            attempts.push(run()?);
+                        }
+                        progress::chunk_done(&format!("window {}", window.to_region()));
+                        Ok(attempts)
+                    })
+                })
+                .collect();
+            runs.into_iter()
+                .map(|run| run.join().expect("freebayes window panicked"))
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+
+        // Every window's messages go in the log, in window order, before
+        // any failure is reported
+        let log_path = call_vcf_sibling(vcf, "freebayes.log");
+        let mut log = BufWriter::new(File::create(&log_path)?);
+        for (window, attempts) in self.windows.iter().zip(&attempts) {
+            for (attempt, output) in attempts.iter().enumerate() {
+                if attempt == 0 {
+                    writeln!(log, "# {}", window.to_region())?;
+                } else {
+                    writeln!(log, "# {} (retry)", window.to_region())?;
+                }
+                for line in String::from_utf8_lossy(&output.stderr).lines() {
+                    debug!("freebayes {}: {}", window.to_region(), line);
+                    writeln!(log, "{}", line)?;
+                }
+            }
+        }
+        log.flush()?;
+        let outputs: Vec<Output> = attempts
+            .into_iter()
+            .map(|attempts| attempts.into_iter().last().expect("every window is run"))
+            .collect();
+        if self.keep {
+            fs::create_dir_all(self.output_dir.join("chunks"))?;
+            for (path, output) in self.chunk_paths(vcf).iter().zip(&outputs) {
+                let mut writer = bgzf::Writer::new(File::create(path)?);
+                writer.write_all(&output.stdout)?;
+                writer.finish()?;
+            }
         }
 
-        if self.normalise && self.genome.is_none() {
-            return Err("A genome file should be supplied if mity call normalise=True".into());
+        let failure = |window: &Interval, output: &Output| {
+            error!("FreeBayes failed on {}", window.to_region());
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let lines: Vec<String> = stderr.lines().map(String::from).collect();
+            let tail = &lines[lines.len().saturating_sub(FREEBAYES_LOG_TAIL)..];
+            freebayes_failure(output.status.code(), tail, &log_path)
+        };
+        let uncalled: Vec<Interval> = self
+            .windows
+            .iter()
+            .zip(&outputs)
+            .filter(|(_, output)| !output.status.success())
+            .map(|(window, _)| window.clone())
+            .collect();
+        if self.strict_windows || uncalled.len() == self.windows.len() {
+            if let Some((window, output)) = self.windows.iter().zip(&outputs).find(|(_, output)| !output.status.success()) {
+                return Err(failure(window, output));
+            }
         }
 
-        for file in &self.files {
-            if !Path::new(file).exists() {
-                return Err(format!("Missing file: {}", file).into());
+        // The header is the first called window's, noting the windows
+        // left without calls
+        let mut lines = Vec::new();
+        let mut seen = HashSet::new();
+        let mut has_header = false;
+        for output in outputs.iter().filter(|output| output.status.success()) {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                if line.starts_with("#CHROM") && !has_header {
+                    lines.extend(uncalled.iter().map(uncalled_header));
+                    lines.push(line.to_string());
+                } else if line.starts_with('#') {
+                    if !has_header {
+                        lines.push(line.to_string());
+                    }
+                } else if seen.insert(line.splitn(6, '\t').take(5).collect::<Vec<_>>().join("\t")) {
+                    lines.push(line.to_string());
+                }
             }
+            has_header = true;
+        }
+        for window in &uncalled {
+            warn!("{} has no calls in {}: FreeBayes failed on it twice", vcf.display(), window.to_region());
+        }
+        let mut counts = self.write_call_vcf(vcf, lines.into_iter().map(Ok))?;
+        counts.uncalled = uncalled;
+        Ok(counts)
+    }
+
+    /// Call the MT junction again against the mitochondrion rotated by
+    /// [`circular::SHIFT`], where it is in the middle of the contig rather
+    /// than split between its ends, and put those calls in place of the
+    /// first pass's within [`circular::JUNCTION_WINDOW`] of it. Returns the
+    /// number of calls from this pass and what the streaming filters changed.
+    fn run_circular_pass(&self) -> Result<(usize, StreamCounts), Box<dyn Error>> {
+        let (contig, length) = mity_util::bam_mt_contig(&self.files[0])?;
+        let rotation = Rotation::new(contig, length);
+        let (reference_contig, _) = check::reference_mt_contig(&self.reference, self.genome.as_deref())
+            .map_err(crate::error::boxed)?
+            .ok_or_else(|| format!("{} has no MT or chrM contig to rotate", self.reference.display()))?;
+        let shifted_fasta = self.shifted_fasta_path();
+        circular::write_shifted_fasta(&self.reference, &reference_contig, &rotation, &shifted_fasta)
+            .map_err(crate::error::boxed)?;
+        for (file, shifted) in self.calling_files.iter().zip(self.shifted_bam_paths()) {
+            let copied = circular::write_shifted_bam(file, &rotation, &shifted).map_err(crate::error::boxed)?;
+            debug!("{}: {} alignments around the junction", file.display(), copied);
         }
 
-        let invalid_files: Vec<String> = self
-            .files
-            .iter()
-            .filter_map(|file| {
-                if self.bam_has_rg(file).is_err() {
-                    Some(file.clone()) // Add the file to the list if it doesn't pass
-                } else {
-                    None // Skip files that pass the check
+        let freebayes = mity_util::tool_path(Tool::Freebayes)?;
+        let region_args = [OsString::from("--region"), OsString::from(rotation.shifted_region())];
+        let args = self.freebayes_args(&shifted_fasta, &region_args, &bam_args(&self.shifted_bam_paths()));
+        debug!("{} {:?}", freebayes.display(), args);
+        let output = {
+            let _permit = mity_util::thread_budget().acquire("freebayes", 1);
+            Command::new(&freebayes).args(&args).output()?
+        };
+        let log_path = call_vcf_sibling(&self.call_vcf_path, "freebayes.shifted.log");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        fs::write(&log_path, stderr.as_bytes())?;
+        if !output.status.success() {
+            let lines: Vec<String> = stderr.lines().map(String::from).collect();
+            let tail = &lines[lines.len().saturating_sub(FREEBAYES_LOG_TAIL)..];
+            return Err(freebayes_failure(output.status.code(), tail, &log_path));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if self.keep {
+            let raw = call_vcf_sibling(&self.call_vcf_path, "freebayes.shifted.raw.vcf.gz");
+            let mut writer = bgzf::Writer::new(File::create(raw)?);
+            writer.write_all(stdout.as_bytes())?;
+            writer.finish()?;
+        }
+
+        // The junction calls go through the same filters as the first pass,
+        // under the first pass's header
+        let mut primary = mity_util::open_vcf(&self.call_vcf_path)?.lines();
+        let header = VcfHeader::read(&mut primary)?;
+        let mut header_text = Vec::new();
+        header.write(&mut header_text)?;
+        let mut lines: Vec<String> = String::from_utf8(header_text)?.lines().map(String::from).collect();
+        for line in stdout.lines().filter(|line| !line.starts_with('#') && !line.is_empty()) {
+            if let Some(record) = circular::unshift_record(line, &rotation).map_err(crate::error::boxed)? {
+                if !record.is_reference_block() {
+                    lines.push(record.to_string());
                 }
-            })
-            .collect();
+            }
+        }
+        let mut filtered = Vec::new();
+        let counts = self.filter_vcf_lines(&mut filtered, lines.into_iter().map(Ok))?;
+        let junction_records: Vec<VcfRecord> = String::from_utf8(filtered)?
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(VcfRecord::parse)
+            .collect::<anyhow::Result<_>>()
+            .map_err(crate::error::boxed)?;
 
-        if invalid_files.len() != 0 {
-            let invalid_files_string = invalid_files.join(", ");
-            return Err(format!(
-                "The BAM/CRAM files: {} lack an @RG header",
-                invalid_files_string
-            )
-            .into());
+        let mut records = Vec::new();
+        for line in primary {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line).map_err(crate::error::boxed)?;
+            if record.is_reference_block() || !rotation.near_junction(record.pos as usize) {
+                records.push(record);
+            }
         }
+        let calls = junction_records.len();
+        records.extend(junction_records);
+        records.sort_by_key(|record| record.pos);
 
-        Ok(())
+        let mut writer = bgzf::Writer::new(checksum::OutputFile::create(&self.call_vcf_path)?);
+        header.write(&mut writer)?;
+        for record in &records {
+            writeln!(writer, "{}", record)?;
+        }
+        writer.finish()?.finish()?;
+        info!(
+            "{} calls within {} bp of the {}:{}/1 junction are from the rotated reference",
+            calls,
+            circular::JUNCTION_WINDOW,
+            rotation.contig,
+            rotation.length
+        );
+        Ok((calls, counts))
     }
 
-    fn bam_has_rg(&self, bam: &str) -> Result<(), Box<dyn Error>> {
-        // Create a reader for the BAM file
-        let mut reader = bam::io::reader::Builder::default().build_from_path(bam)?;
+    /// The MT FASTA rotated for `--circular`.
+    fn shifted_fasta_path(&self) -> PathBuf {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        self.output_dir.join(format!("{}.mity.call.shifted.fa", prefix))
+    }
 
-        // Retrieve the read groups from the BAM file header
-        let header = reader.read_header().unwrap();
-        let read_groups = header.read_groups();
+    /// The rotated copies of the inputs around the junction for `--circular`.
+    fn shifted_bam_paths(&self) -> Vec<PathBuf> {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        (1..=self.files.len())
+            .map(|n| self.output_dir.join(format!("{}.mity.call.shifted{}.bam", prefix, n)))
+            .collect()
+    }
 
-        // Check if there are any read groups
-        if read_groups.is_empty() {
-            // Return an error if no read groups are found
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "No read groups found in BAM file",
-            )))
+    /// The files of the `--circular` pass, with their indexes and log.
+    fn circular_files(&self) -> Vec<PathBuf> {
+        if !self.circular {
+            return Vec::new();
+        }
+        let with_index = |path: PathBuf, extension: &str| {
+            let mut index = path.clone().into_os_string();
+            index.push(extension);
+            [path, PathBuf::from(index)]
+        };
+        let mut files: Vec<PathBuf> = with_index(self.shifted_fasta_path(), ".fai").into();
+        files.extend(self.shifted_bam_paths().into_iter().flat_map(|bam| with_index(bam, ".bai")));
+        files.push(call_vcf_sibling(&self.call_vcf_path, "freebayes.shifted.log"));
+        files
+    }
+
+    /// The freebayes arguments for the given reference, region and input
+    /// arguments.
+    fn freebayes_args(&self, reference: &Path, region_args: &[OsString], file_args: &[OsString]) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![OsString::from("-f"), reference.as_os_str().to_owned()];
+        args.extend(region_args.iter().cloned());
+        args.extend(file_args.iter().cloned());
+        for (flag, value) in [
+            ("--min-mapping-quality", self.min_mq.to_string()),
+            ("--min-base-quality", self.min_bq.to_string()),
+            ("--min-alternate-fraction", self.min_af.to_string()),
+            ("--min-alternate-count", self.min_ac.to_string()),
+            ("--ploidy", self.ploidy.to_string()),
+        ] {
+            args.push(OsString::from(flag));
+            args.push(OsString::from(value));
+        }
+        args.extend(self.preset.freebayes_args().iter().map(OsString::from));
+        if let Some(max_depth) = self.max_depth {
+            args.push(OsString::from("--limit-coverage"));
+            args.push(OsString::from(max_depth.to_string()));
+        }
+        if !self.read_filter.duplicates {
+            args.push(OsString::from("--use-duplicate-reads"));
+        }
+        if self.report_all_sites {
+            args.push(OsString::from("--gvcf"));
+        }
+        args
+    }
+
+    /// Copy the call region of each input, without the alignments the read
+    /// filter leaves out, for freebayes to read instead. Returns the number
+    /// left out, unless a CRAM's could not be counted.
+    fn write_filtered_inputs(&mut self) -> Result<Option<read_filter::Removed>, Box<dyn Error>> {
+        let intervals = if self.regions.is_empty() {
+            vec![Interval::parse_region(self.region.as_deref().unwrap_or_default())?]
         } else {
-            // Return Ok if read groups are found
-            Ok(())
+            self.regions.clone()
+        };
+        let copies = self.filtered_copy_paths();
+        let mut removed = Some(read_filter::Removed::default());
+        for (file, copy) in self.files.iter().zip(&copies) {
+            info!("Filtering reads of {} into {}", file.display(), copy.display());
+            let count = read_filter::write_filtered_bam(
+                file,
+                &intervals,
+                self.read_filter,
+                self.numt_filter.as_ref(),
+                &self.reference,
+                copy,
+            )?;
+            if let Some(count) = count.filter(|_| self.numt_filter.is_some()) {
+                info!("Left out {} NUMT-like reads of {}", count.numt, file.display());
+            }
+            removed = removed.zip(count).map(|(total, count)| read_filter::Removed {
+                flagged: total.flagged + count.flagged,
+                numt: total.numt + count.numt,
+            });
         }
+        self.calling_files = copies;
+        if let (Some(numts), Some(removed)) = (&self.numt_filter, removed) {
+            self.provenance.push(numts.header_line(removed.numt));
+        }
+        Ok(removed)
     }
 
-    fn bam_get_mt_contig(&self, bam: &str) -> Result<String, Box<dyn Error>> {
-        let mut reader = bam::io::reader::Builder::default().build_from_path(bam)?;
+    /// Where the filtered copy of each input goes.
+    fn filtered_copy_paths(&self) -> Vec<PathBuf> {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        (1..=self.files.len())
+            .map(|n| self.output_dir.join(format!("{}.mity.call.input{}.bam", prefix, n)))
+            .collect()
+    }
 
-        // Get the list of chromosomes (SQ records)
-        let header = reader.read_header().unwrap();
-        let header_referece_sequences = header.reference_sequences();
-        let chroms: Vec<String> = header_referece_sequences
-            .iter()
-            .map(|seq| seq.0.to_string())
-            .collect();
+    /// Whether freebayes reads filtered copies of the inputs: for flags it
+    /// has no option for, or for NUMT filtering.
+    fn needs_filtered_copies(&self) -> bool {
+        self.read_filter.needs_copies() || self.numt_filter.is_some()
+    }
 
-        // Find intersection with mitochondrial contigs
-        let mito_contig: Vec<_> = chroms
-            .iter()
-            .filter(|&&ref seq| seq == "MT" || seq == "chrM")
-            .collect();
+    /// The filtered copies of the inputs and their indexes, if made.
+    fn filtered_copies(&self) -> Vec<PathBuf> {
+        if !self.needs_filtered_copies() {
+            return Vec::new();
+        }
+        self.filtered_copy_paths()
+            .into_iter()
+            .flat_map(|copy| {
+                let mut index = copy.clone().into_os_string();
+                index.push(".bai");
+                [copy, PathBuf::from(index)]
+            })
+            .collect()
+    }
 
-        // Ensure exactly one mitochondrial contig is found
-        if mito_contig.len() != 1 {
-            return Err(
-                "Mitochondrial contig not found or multiple mitochondrial contigs found.".into(),
-            );
+    /// Rewrite the header of freebayes' VCF, apply the streaming filters
+    /// and write it compressed to `vcf`. With `--keep`, freebayes' VCF is
+    /// also written as it came, to debug freebayes with.
+    fn write_call_vcf<I>(&self, vcf: &Path, lines: I) -> Result<StreamCounts, Box<dyn Error>>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let mut raw = if self.keep {
+            Some(bgzf::Writer::new(File::create(call_vcf_sibling(vcf, "freebayes.raw.vcf.gz"))?))
+        } else {
+            None
+        };
+        let mut lines = lines.map(|line| {
+            let line = line?;
+            if let Some(raw) = raw.as_mut() {
+                writeln!(raw, "{}", line)?;
+            }
+            Ok(line)
+        });
+        let counts = self.write_filtered_vcf(vcf, &mut lines)?;
+        drop(lines);
+        if let Some(raw) = raw {
+            raw.finish()?;
         }
+        Ok(counts)
+    }
 
-        // Extract the mitochondrial contig name and length
-        let mito_contig_name = mito_contig[0].clone();
-        let mut res: Option<(String, usize)> = None;
+    fn write_filtered_vcf<I>(&self, vcf: &Path, lines: I) -> Result<StreamCounts, Box<dyn Error>>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let mut writer = bgzf::Writer::new(checksum::OutputFile::create(vcf)?);
+        let counts = self.filter_vcf_lines(&mut writer, lines)?;
+        writer.finish()?.finish()?;
+        Ok(counts)
+    }
 
-        // Find the corresponding sequence record for the mitochondrial contig
-        for seq in header_referece_sequences {
-            if seq.0.to_string() == mito_contig_name {
-                res = Some((seq.0.to_string(), seq.1.length().get()));
-                break;
+    /// Rewrite the header of freebayes' VCF lines and apply the streaming
+    /// filters to its records, writing them to `writer`.
+    fn filter_vcf_lines<I>(&self, writer: &mut dyn Write, mut lines: I) -> Result<StreamCounts, Box<dyn Error>>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let mut header = VcfHeader::read(&mut lines)?;
+        header.rename_key("source", "freebayesSource");
+        header.rename_key("commandline", "freebayesCommandline");
+        header.remove("##phasing=none");
+        for line in &self.provenance {
+            header.append_once(line.as_str());
+        }
+        if self.adaptive_min_ac {
+            header.append_once(format!(
+                "##mityAdaptiveMinAC=\"Calls need an alternate count of at least max({}, ceil({} x DP)) in some sample\"",
+                self.min_ac, self.p
+            ));
+        }
+        if self.per_sample_filter {
+            header.append_once(format!(
+                "##mityPerSampleFilter=\"Sample GT reset to reference (0/0 at ploidy 2), or missing without reference reads, when no alternate allele has AO >= {} and AO / DP >= {}; AO and DP are kept. Records no sample supports are removed\"",
+                self.min_ac, self.min_af
+            ));
+        }
+        if self.report_all_sites {
+            for (key, id, line) in GVCF_HEADER {
+                if !header.contains(key, id) {
+                    header.append_once(line);
+                }
+            }
+        }
+        if self.circular {
+            header.upsert(circular::JUNCTION_HEADER);
+        }
+        header.write(writer)?;
+        let mut counts = StreamCounts::default();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() || !(self.adaptive_min_ac || self.per_sample_filter) {
+                writeln!(writer, "{}", line)?;
+                continue;
+            }
+            let mut record = VcfRecord::parse(&line)?;
+            // Reference blocks carry no alternate counts to filter on
+            if record.is_reference_block() {
+                writeln!(writer, "{}", line)?;
+                continue;
+            }
+            if self.adaptive_min_ac && !self.passes_adaptive_min_ac(&record) {
+                counts.adaptive_removed += 1;
+                continue;
             }
+            if self.per_sample_filter {
+                match self.reset_unsupported_genotypes(&mut record) {
+                    None => {
+                        counts.per_sample_removed += 1;
+                        continue;
+                    }
+                    Some(0) => {}
+                    Some(resets) => {
+                        counts.genotype_resets += resets;
+                        writeln!(writer, "{}", record)?;
+                        continue;
+                    }
+                }
+            }
+            writeln!(writer, "{}", line)?;
         }
+        Ok(counts)
+    }
 
-        // Return the result as a string if `as_string` is true
-        if let Some((name, length)) = res {
-            let result = format!("{}:1-{}", name, length);
-            return Ok(result);
+    /// Reset the GT of each sample with no alternate allele at or above
+    /// `min_ac` reads and `min_af` of DP, since freebayes keeps a site that
+    /// any one sample supports. AO and DP are left as they are. Returns the
+    /// number of genotypes reset, or `None` when no sample supports the
+    /// site. Samples without AO or DP count as supporting it.
+    fn reset_unsupported_genotypes(&self, record: &mut VcfRecord) -> Option<usize> {
+        if record.samples.is_empty() {
+            return Some(0);
+        }
+        let min_ac = f64::from(self.min_ac);
+        let min_af = f64::from(self.min_af);
+        let mut resets = 0;
+        let mut supported = false;
+        for sample in 0..record.samples.len() {
+            let (Some(ao), Some(dp)) = (record.sample(sample, "AO"), record.sample_f64(sample, "DP")) else {
+                supported = true;
+                continue;
+            };
+            let passes = ao
+                .split(',')
+                .filter_map(|ao| ao.parse::<f64>().ok())
+                .any(|ao| ao >= min_ac && dp > 0.0 && ao / dp >= min_af);
+            if passes {
+                supported = true;
+                continue;
+            }
+            let Some(gt) = record.sample(sample, "GT") else {
+                continue;
+            };
+            if gt.split(['/', '|']).all(|allele| allele == "0" || allele == ".") {
+                continue;
+            }
+            let allele = if record.sample_f64(sample, "RO").is_some_and(|ro| ro > 0.0) {
+                "0"
+            } else {
+                "."
+            };
+            record.set_sample(sample, "GT", vec![allele; self.ploidy as usize].join("/"));
+            resets += 1;
         }
+        supported.then_some(resets)
+    }
 
-        return Err(
-            "Mitochondrial contig not found or multiple mitochondrial contigs found.".into(),
-        );
+    /// Whether some sample has an alternate allele with AO at or above
+    /// max(min_ac, ceil(p x DP)): `p` is the noise rate, so this is the
+    /// count noise alone would reach at that depth. Sites-only records use
+    /// the INFO AO and DP.
+    fn passes_adaptive_min_ac(&self, record: &VcfRecord) -> bool {
+        let passes = |ao: Option<&str>, dp: Option<&str>| {
+            let (Some(ao), Some(dp)) = (ao, dp.and_then(|dp| dp.parse::<f64>().ok())) else {
+                return true;
+            };
+            let min_ac = f64::from(self.min_ac).max((f64::from(self.p) * dp).ceil());
+            ao.split(',')
+                .filter_map(|ao| ao.parse::<f64>().ok())
+                .any(|ao| ao >= min_ac)
+        };
+        if record.samples.is_empty() {
+            return passes(record.info("AO"), record.info("DP"));
+        }
+        (0..record.samples.len()).any(|sample| passes(record.sample(sample, "AO"), record.sample(sample, "DP")))
     }
 
-    fn run_normalise(&self) -> Result<(), Box<dyn Error>> {
-        let normalise_runner = normalise::Normalise::new(
-            self.debug,
-            self.call_vcf_path.clone(),
-            self.reference.clone(),
-            self.genome.as_ref().unwrap().clone(),
-            self.output_dir.clone(),
-            self.prefix.clone(),
-            false,
-            self.keep,
-            self.p
-        );
-        match normalise_runner.run() {
-            Ok(()) => {
-                println!("Normalisation command completed successfully.");
+    /// The detected or supplied regions, merged where they overlap, minus
+    /// any `--exclude-region` and `--exclude-bed` intervals. Several regions
+    /// are left in `regions`; a single one only in `region`.
+    fn set_region(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut intervals = Vec::new();
+        if let Some(regions) = &self.region {
+            for region in regions.split(',').map(str::trim).filter(|region| !region.is_empty()) {
+                intervals.push(self.parse_call_region("--region", region)?);
             }
-            Err(e) => {
-                eprintln!("Error executing normalisation command: {}", e);
-                std::process::exit(1);
+        }
+        if let Some(bed) = &self.region_bed {
+            for interval in mity_util::read_bed(bed)? {
+                intervals.push(self.parse_call_region("--region-bed", &interval.to_region())?);
+            }
+        }
+        if intervals.is_empty() {
+            let (contig, length) = mity_util::bam_mt_contig(&self.files[0])?;
+            intervals.push(Interval {
+                contig,
+                start: 0,
+                end: length,
+            });
+        }
+        let intervals = merge_intervals(intervals);
+        self.region = Some(intervals.iter().map(Interval::to_region).collect::<Vec<_>>().join(","));
+
+        for region in &self.exclude_regions {
+            self.exclusions.push(Interval::parse_region(region)?);
+        }
+        if let Some(bed) = &self.exclude_bed {
+            self.exclusions.extend(mity_util::read_bed(bed)?);
+        }
+        if intervals.len() == 1 && self.exclusions.is_empty() {
+            return Ok(());
+        }
+
+        for exclusion in &self.exclusions {
+            if !intervals.iter().any(|interval| interval.overlaps(exclusion)) {
+                warn!(
+                    "Excluded region {} is outside the call region {}; ignoring it",
+                    exclusion.to_region(),
+                    self.region.as_deref().unwrap_or_default()
+                );
             }
         }
+
+        self.regions = intervals
+            .iter()
+            .flat_map(|interval| interval.subtract(&self.exclusions))
+            .collect();
+        if self.regions.is_empty() {
+            return Err(format!(
+                "The excluded regions cover all of {}",
+                self.region.as_deref().unwrap_or_default()
+            )
+            .into());
+        }
+        info!(
+            "Calling in {}",
+            self.regions.iter().map(Interval::to_region).collect::<Vec<_>>().join(", ")
+        );
         Ok(())
     }
 
-    fn set_mity_cmd(&mut self) {
-        // TODO: this feels like a dumb way to do things
-        let mut mity_cmd = format!(
-            r#"##mityCommandline="mity call --reference {} --prefix {} --min-mapping-quality {} \
---min-base-quality {} --min-alternate-fraction {} --min-alternate-count {} --out-folder-path {}"#,
-            self.reference,
-            self.prefix.as_ref().unwrap(),
-            self.min_mq,
-            self.min_bq,
-            self.min_af,
-            self.min_ac,
-            self.output_dir,
-        );
+    /// freebayes takes one `--region`, so sub-regions go in a targets BED.
+    /// Both are in BED coordinates, unlike the 1-based `region`.
+    fn region_args(&self) -> Result<Vec<OsString>, Box<dyn Error>> {
+        if self.regions.is_empty() {
+            let region = Interval::parse_region(self.region.as_deref().unwrap_or_default())?;
+            return Ok(vec![OsString::from("--region"), OsString::from(region.to_freebayes_region())]);
+        }
+        let mut writer = BufWriter::new(File::create(&self.targets_bed_path)?);
+        for region in &self.regions {
+            writeln!(writer, "{}\t{}\t{}", region.contig, region.start, region.end)?;
+        }
+        writer.flush()?;
+        Ok(vec![
+            OsString::from("--targets"),
+            self.targets_bed_path.clone().into_os_string(),
+        ])
+    }
 
-        // Finalize the command
-        mity_cmd.push('"');
-        self.mity_cmd = mity_cmd.replace("/", "\\/");
+    /// Parse a region given with `option`, expanding a bare contig to its
+    /// whole length, and check that it lies on a contig of the first input,
+    /// so freebayes is never given a region it would fail on or find nothing
+    /// in.
+    fn parse_call_region(&self, option: &str, region: &str) -> Result<Interval, Box<dyn Error>> {
+        let bam = &self.files[0];
+        let header = mity_util::bam_header_text(bam).map_err(crate::error::boxed)?;
+        let contigs: Vec<(&str, usize)> = header
+            .lines()
+            .filter(|line| line.starts_with("@SQ\t"))
+            .filter_map(|line| {
+                let field = |tag: &str| line.split('\t').find_map(|field| field.strip_prefix(tag));
+                Some((field("SN:")?, field("LN:")?.parse().ok()?))
+            })
+            .collect();
+        let length_of = |contig: &str| contigs.iter().find(|(name, _)| *name == contig).map(|(_, length)| *length);
+        let missing = |contig: &str| {
+            let hint = mity_util::bam_mt_contig(bam)
+                .map(|(mt, _)| format!("; its mitochondrial contig is {}", mt))
+                .unwrap_or_default();
+            format!("Invalid {} {}: {} has no contig {}{}", option, region, bam.display(), contig, hint)
+        };
 
-        // Create the sed_cmd
-        self.sed_cmd = format!("sed 's/^##phasing=none/{}/g'", self.mity_cmd);
-    }
+        if let Some(length) = length_of(region) {
+            return Ok(Interval {
+                contig: region.to_string(),
+                start: 0,
+                end: length,
+            });
+        }
+        if !region.contains(':') {
+            return Err(missing(region).into());
+        }
+        let interval = Interval::parse_region(region).map_err(|e| format!("{}: {:#}", option, e))?;
+        let Some(length) = length_of(&interval.contig) else {
+            return Err(missing(&interval.contig).into());
+        };
+        if interval.end > length {
+            return Err(format!(
+                "Invalid {} {}: {} is only {} bp long",
+                option, region, interval.contig, length
+            )
+            .into());
+        }
+        Ok(interval)
+    }
+
+    /// Split the call region into one window per thread of the budget, so
+    /// freebayes can call them at once. Leaves no windows, for a single run,
+    /// with one thread or a region too short to split.
+    fn set_windows(&mut self) -> Result<(), Box<dyn Error>> {
+        let threads = mity_util::thread_budget().total();
+        if threads == 1 {
+            return Ok(());
+        }
+        let intervals = if self.regions.is_empty() {
+            vec![Interval::parse_region(self.region.as_deref().unwrap_or_default())?]
+        } else {
+            self.regions.clone()
+        };
+        let total: usize = intervals.iter().map(Interval::len).sum();
+        let memory = mity_util::memory_budget();
+        let count = window_count(threads, memory, total);
+        if let Some(memory) = memory {
+            info!(
+                "--max-memory of {} MB leaves room for {} freebayes window(s) at once",
+                memory >> 20,
+                count.max(1)
+            );
+        }
+        if count < 2 {
+            return Ok(());
+        }
+        let window_len = total.div_ceil(count);
+        for interval in intervals {
+            let mut start = interval.start;
+            while start < interval.end {
+                let end = (start + window_len).min(interval.end);
+                self.windows.push(Interval {
+                    contig: interval.contig.clone(),
+                    start,
+                    end,
+                });
+                start = end;
+            }
+        }
+        info!("Calling {} windows in parallel", self.windows.len());
+        Ok(())
+    }
+
+    fn set_strings(&mut self) {
+        if self.prefix.is_none() {
+            self.prefix = Some(self.make_prefix(&self.files[0]));
+        }
+
+        self.calling_files = self.files.clone();
+
+        let prefix = self.prefix.as_ref().unwrap();
+        self.normalised_vcf_path = self
+            .output_dir
+            .join(format!("{}.mity.normalise.vcf.gz", prefix));
+        self.call_vcf_path = self.output_dir.join(format!("{}.mity.call.vcf.gz", prefix));
+        self.output_vcf_path = self
+            .output_dir
+            .join(format!("{}.mity.call.{}", prefix, self.output_format.extension()));
+        self.targets_bed_path = self
+            .output_dir
+            .join(format!("{}.mity.call.targets.bed", prefix));
+        self.rg_stats_path = self.output_dir.join(format!("{}.mity.rg_stats.tsv", prefix));
+    }
+
+    /// Name each input's per-sample VCF after its one `SM` tag, which must
+    /// differ between inputs so no VCF is written twice.
+    fn set_split_vcf_paths(&mut self) -> Result<(), Box<dyn Error>> {
+        let prefix = self.prefix.as_ref().unwrap();
+        let mut samples: Vec<String> = Vec::new();
+        for file in &self.files {
+            let names = mity_util::bam_sample_names(file).map_err(crate::error::boxed)?;
+            let [sample] = names.as_slice() else {
+                return Err(format!(
+                    "--split-samples needs one sample per input, but {} has {}: {}",
+                    file.display(),
+                    names.len(),
+                    names.join(", ")
+                )
+                .into());
+            };
+            if sample.contains(std::path::is_separator) {
+                return Err(format!("--split-samples: sample {} of {} cannot name a file", sample, file.display()).into());
+            }
+            if samples.contains(sample) {
+                return Err(format!("--split-samples: sample {} is in more than one input", sample).into());
+            }
+            self.split_vcf_paths
+                .push(self.output_dir.join(format!("{}.{}.mity.call.vcf.gz", prefix, sample)));
+            samples.push(sample.clone());
+        }
+        Ok(())
+    }
+
+    /// Name the depth bedgraph `PREFIX.mity.coverage.bedgraph.gz` for one
+    /// input, or after each input's one `SM` tag for several, as
+    /// `--split-samples` names its VCFs.
+    fn set_coverage_paths(&mut self) -> Result<(), Box<dyn Error>> {
+        let prefix = self.prefix.as_ref().unwrap();
+        if let [_] = self.files.as_slice() {
+            self.coverage_paths = vec![self.output_dir.join(format!("{}.mity.coverage.bedgraph.gz", prefix))];
+            return Ok(());
+        }
+        for file in &self.files {
+            let names = mity_util::bam_sample_names(file).map_err(crate::error::boxed)?;
+            let [sample] = names.as_slice() else {
+                return Err(format!(
+                    "--coverage-output with several inputs needs one sample per input, but {} has {}: {}",
+                    file.display(),
+                    names.len(),
+                    names.join(", ")
+                )
+                .into());
+            };
+            if sample.contains(std::path::is_separator) {
+                return Err(format!("--coverage-output: sample {} of {} cannot name a file", sample, file.display()).into());
+            }
+            self.coverage_paths
+                .push(self.output_dir.join(format!("{}.{}.mity.coverage.bedgraph.gz", prefix, sample)));
+        }
+        Ok(())
+    }
+
+    /// Write the per-base depth over the MT contig of each input, counted
+    /// with the calling `min_mq` and `min_bq`, as a bedgraph of runs of
+    /// equal depth, returning a depth summary of each for the run summary.
+    fn write_coverage(&self) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+        let mut metrics = Vec::new();
+        for (file, path) in self.files.iter().zip(&self.coverage_paths) {
+            if mity_util::is_cram(file) {
+                warn!("--coverage-output only reads BAMs; skipping {}", file.display());
+                continue;
+            }
+            let (contig, _) = mity_util::bam_mt_contig(file)?;
+            let depth = pileup::contig_depth(file, &contig, self.min_mq, self.min_bq)?;
+            let mut writer = bgzf::Writer::new(File::create(path)?);
+            for (start, end, d) in pileup::depth_runs(&depth) {
+                writeln!(writer, "{}	{}	{}	{}", contig, start, end, d)?;
+            }
+            writer.finish()?;
+
+            let summary = DepthSummary::new(&depth);
+            info!(
+                "Wrote {}: mean depth {:.1}, median {:.1}, {:.1}% at 500x or more",
+                path.display(),
+                summary.mean,
+                summary.median,
+                summary.fraction_500x * 100.0
+            );
+            metrics.push(serde_json::json!({
+                "file": file.display().to_string(),
+                "bedgraph": path.display().to_string(),
+                "mean": summary.mean,
+                "median": summary.median,
+                "fraction_500x": summary.fraction_500x,
+            }));
+        }
+        Ok(metrics)
+    }
+
+    /// Write per-read-group QC statistics over the MT contig of each input,
+    /// returning them for the run summary.
+    fn write_rg_stats(&self) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(&self.rg_stats_path)?);
+        writeln!(
+            writer,
+            "file\tread_group\tsample\treads\tduplicate_fraction\tmean_mapq\tmean_base_quality\tmedian_insert_size"
+        )?;
+        let mut metrics = Vec::new();
+        for file in &self.files {
+            if mity_util::is_cram(file) {
+                warn!("--rg-stats only reads BAMs; skipping {}", file.display());
+                continue;
+            }
+            let (contig, _) = mity_util::bam_mt_contig(file)?;
+            for (read_group, stats) in rg_stats::read_group_stats(file, &contig)? {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{:.4}\t{:.2}\t{:.2}\t{}",
+                    file.display(),
+                    read_group,
+                    stats.sample.as_deref().unwrap_or("."),
+                    stats.reads,
+                    stats.duplicate_fraction(),
+                    stats.mean_mapq(),
+                    stats.mean_base_quality(),
+                    stats
+                        .median_insert_size()
+                        .map_or_else(|| ".".to_string(), |size| size.to_string()),
+                )?;
+                metrics.push(stats.to_json(file, &read_group));
+            }
+        }
+        writer.flush()?;
+        info!("Wrote {}", self.rg_stats_path.display());
+        Ok(metrics)
+    }
+
+    fn make_prefix(&self, path: &Path) -> String {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn get_files_from_list(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.files.len() > 1 {
+            return Err("--bam-file-list argument expects only 1 file to be provided.".into());
+        }
+
+        self.files = mity_util::read_file_list(&self.files[0])?;
+        Ok(())
+    }
+
+    fn run_checks(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.files.len() > 1 && self.prefix.is_none() {
+            return Err("If there is more than one BAM/CRAM file, --prefix must be set".into());
+        }
+
+        if self.normalise && self.genome.is_none() {
+            return Err("A genome file should be supplied if mity call normalise=True".into());
+        }
+
+        if let Some(build) = &self.custom_reference_build {
+            match check::check_mt_length(&self.reference, build) {
+                Ok(check) => {
+                    if let Some(problem) = check.problem() {
+                        warn!("The custom reference may not be {}: {}", build, problem);
+                    }
+                }
+                Err(e) => warn!("Could not check the custom reference's MT contig: {:#}", e),
+            }
+        }
+
+        if let Some(genome) = &self.genome {
+            match check::compare_reference_files(&self.reference, genome) {
+                Ok(comparison) if !comparison.mismatches.is_empty() => {
+                    return Err(format!(
+                        "The reference and genome files disagree:\n  {}",
+                        comparison.mismatches.join("\n  ")
+                    )
+                    .into());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Could not compare the reference and genome files: {:#}", e),
+            }
+        }
+
+        if self.circular && self.files.iter().any(|file| mity_util::is_cram(file)) {
+            return Err("--circular reads BAM inputs only; convert CRAM with samtools view -b".into());
+        }
+        if self.read_filter.end_exclusion > 0 && self.files.iter().any(|file| mity_util::is_cram(file)) {
+            return Err("--read-end-exclusion reads BAM inputs only; convert CRAM with samtools view -b".into());
+        }
+        if let Some(bed) = &self.numt_bed {
+            if self.files.iter().any(|file| mity_util::is_cram(file)) {
+                return Err("--filter-numts reads BAM inputs only; convert CRAM with samtools view -b".into());
+            }
+            self.numt_filter = Some(NumtFilter::from_bed(bed).map_err(crate::error::boxed)?);
+        }
+
+        let mut tools = vec![Tool::Freebayes, Tool::Tabix];
+        if self.split_samples && self.files.len() > 1 {
+            tools.push(Tool::Bcftools);
+        }
+        // CRAM headers are read with samtools; freebayes decodes the reads
+        // against the `-f` reference
+        if self.files.iter().any(|file| mity_util::is_cram(file)) {
+            tools.push(Tool::Samtools);
+        }
+        for tool in tools {
+            let path = mity_util::tool_path(tool)?;
+            info!("Using {}: {}", tool.name(), path.display());
+        }
+        match mity_util::freebayes_version() {
+            Ok(version) if version < self.min_freebayes_version => {
+                return Err(format!(
+                    "freebayes {} is older than {}, the oldest mity supports; install a newer freebayes or lower --min-freebayes-version",
+                    version, self.min_freebayes_version
+                )
+                .into());
+            }
+            Ok(version) => {
+                info!("freebayes version: {}", version);
+                self.freebayes_version = Some(version);
+            }
+            Err(e) => warn!("Could not check the freebayes version: {:#}", e),
+        }
+
+        if let Some(path) = &self.sample_map_path {
+            self.sample_map = SampleMap::load(path)?;
+        }
+
+        for file in &self.files {
+            if !file.exists() {
+                return Err(MityError::MissingFile(file.clone()).into());
+            }
+        }
+
+        // freebayes fails late and cryptically on these, so catch them first
+        let mut problems = Vec::new();
+        for file in &self.files {
+            for problem in self.bam_problems(file)? {
+                problems.push(format!("  {}: {}", file.display(), problem));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(format!("Unusable BAM/CRAM input:\n{}", problems.join("\n")).into());
+        }
+        if self.create_index {
+            for file in &self.files {
+                if mity_util::bam_index(file).is_some() {
+                    continue;
+                }
+                let index = if mity_util::is_cram(file) {
+                    mity_util::index_cram(file)
+                } else {
+                    mity_util::index_bam(file)
+                }
+                .map_err(crate::error::boxed)?;
+                info!("Created {}", index.display());
+                self.created_indexes.push(index);
+            }
+        }
+
+        let mut invalid_files: Vec<String> = Vec::new();
+        let mut samples: Vec<(String, Vec<String>)> = Vec::new();
+        for file in &self.files {
+            let Ok(names) = self.bam_has_rg(file) else {
+                invalid_files.push(file.display().to_string());
+                continue;
+            };
+            if names.len() > 1 {
+                warn!(
+                    "{} has read groups for several samples ({}); freebayes will give each its own column",
+                    file.display(),
+                    names.join(", ")
+                );
+            }
+            for name in names {
+                self.samples.push(name.clone());
+                let file = file.display().to_string();
+                match samples.iter_mut().find(|(sample, _)| *sample == name) {
+                    Some((_, files)) if !files.contains(&file) => files.push(file),
+                    Some(_) => {}
+                    None => samples.push((name, vec![file])),
+                }
+            }
+        }
+
+        if !invalid_files.is_empty() {
+            let invalid_files_string = invalid_files.join(", ");
+            return Err(format!(
+                "The BAM/CRAM files: {} lack an @RG header",
+                invalid_files_string
+            )
+            .into());
+        }
+
+        // freebayes merges the reads of inputs with the same SM into one column
+        let shared: Vec<String> = samples
+            .iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|(sample, files)| format!("  SM:{}: {}", sample, files.join(", ")))
+            .collect();
+        if !shared.is_empty() {
+            return Err(format!(
+                "Several BAM/CRAM inputs have the same @RG SM tag, so freebayes would call them as one sample:\n{}\nGive each input its own SM, e.g. with samtools addreplacerg",
+                shared.join("\n")
+            )
+            .into());
+        }
+
+        self.check_mt_contigs()?;
+        self.check_reference_mt_length()?;
+        self.check_mapq_scale()?;
+        self.check_mt_reads()?;
+
+        Ok(())
+    }
+
+    /// An input with no alignments on the MT contig, such as an exome BAM
+    /// without off-target reads, has no calls. The run goes on to a VCF with
+    /// a header and no records, unless `--error-on-empty` is given. CRAM
+    /// inputs are not checked.
+    fn check_mt_reads(&self) -> Result<(), Box<dyn Error>> {
+        let mut empty = Vec::new();
+        for file in &self.files {
+            if mity_util::is_cram(file) {
+                continue;
+            }
+            let (contig, _) = mity_util::bam_mt_contig(file).map_err(crate::error::boxed)?;
+            if !bam_has_reads_on(file, &contig)? {
+                empty.push(format!("  {}: no reads on {}", file.display(), contig));
+            }
+        }
+        if empty.is_empty() {
+            return Ok(());
+        }
+        if self.error_on_empty {
+            return Err(format!("BAM inputs without mitochondrial reads:\n{}", empty.join("\n")).into());
+        }
+        for line in &empty {
+            warn!("{}; its sample will have no calls", line.trim_start());
+        }
+        if empty.len() == self.files.len() {
+            warn!("No input has mitochondrial reads, so the call VCF will have a header and no records");
+        }
+        Ok(())
+    }
+
+    /// freebayes gets one region for all inputs, so they must all name the
+    /// mitochondrial contig the same way. Files that name it differently, or
+    /// have none, would be silently left out of the calls.
+    fn check_mt_contigs(&self) -> Result<(), Box<dyn Error>> {
+        let mut by_contig: Vec<(String, Vec<String>)> = Vec::new();
+        let mut unresolved: Vec<String> = Vec::new();
+        for file in &self.files {
+            match mity_util::bam_mt_contig(file) {
+                Ok((contig, _)) => {
+                    debug!("{}: mitochondrial contig {}", file.display(), contig);
+                    match by_contig.iter_mut().find(|(name, _)| *name == contig) {
+                        Some((_, files)) => files.push(file.display().to_string()),
+                        None => by_contig.push((contig, vec![file.display().to_string()])),
+                    }
+                }
+                Err(e) => unresolved.push(format!("  {}: {:#}", file.display(), e)),
+            }
+        }
+        if by_contig.len() <= 1 && unresolved.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines: Vec<String> = by_contig
+            .iter()
+            .map(|(contig, files)| format!("  {}: {}", contig, files.join(", ")))
+            .collect();
+        lines.extend(unresolved);
+        let hint = match mity_util::mito_contig_override() {
+            Some(contig) => format!("Every input needs the --mito-contig contig {}", contig),
+            None => "Re-align or rename them to one naming, or name the contig with --mito-contig".to_string(),
+        };
+        let problem = if by_contig.len() > 1 {
+            "The BAM/CRAM inputs do not agree on the mitochondrial contig"
+        } else {
+            "The mitochondrial contig of the BAM/CRAM inputs could not be found"
+        };
+        Err(format!("{}:\n{}\n{}", problem, lines.join("\n"), hint).into())
+    }
+
+    /// The inputs must be aligned to the reference's mitochondrion: against
+    /// hg19's 16571 bp chrM, reads aligned to the 16569 bp rCRS give shifted
+    /// or missing calls.
+    fn check_reference_mt_length(&self) -> Result<(), Box<dyn Error>> {
+        if self.skip_reference_check {
+            return Ok(());
+        }
+        let reference = match check::reference_mt_contig(&self.reference, self.genome.as_deref()) {
+            Ok(Some((_, length))) => length,
+            Ok(None) => {
+                warn!("{} has no MT or chrM contig to check the inputs against", self.reference.display());
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("Could not read the reference's MT contig length: {:#}", e);
+                return Ok(());
+            }
+        };
+
+        let mut mismatches = Vec::new();
+        let mut lengths = Vec::new();
+        for file in &self.files {
+            let (contig, length) = mity_util::bam_mt_contig(file)?;
+            let length = length as u64;
+            if length != reference {
+                mismatches.push(format!("  {}: {} is {} bp", file.display(), contig, length));
+                if !lengths.contains(&length) {
+                    lengths.push(length);
+                }
+            }
+        }
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+
+        let builds: Vec<String> = lengths
+            .iter()
+            .flat_map(|length| check::builds_with_mt_length(*length))
+            .map(|build| format!("--reference {}", build))
+            .collect();
+        let hint = if builds.is_empty() {
+            "No mity reference matches; give the FASTA they were aligned to with --custom-reference-fasta".to_string()
+        } else {
+            format!("They look aligned to another build; try {}", builds.join(" or "))
+        };
+        Err(format!(
+            "The mitochondrial contig of the reference {} is {} bp, but in the BAM/CRAM inputs:\n{}\n{}, or pass --skip-reference-check for an unusual reference",
+            self.reference.display(),
+            reference,
+            mismatches.join("\n"),
+            hint
+        )
+        .into())
+    }
+
+    /// Warn about inputs from aligners whose MAPQ is not on bwa mem's 0-60
+    /// scale, where `--min-mapping-quality` would drop most reads. With
+    /// `--auto-mq`, lower `min_mq` to the lowest rescaled threshold instead.
+    fn check_mapq_scale(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut adjusted: Option<(u32, &aligner::Aligner)> = None;
+        for file in &self.files {
+            let header = mity_util::bam_header_text(file).map_err(crate::error::boxed)?;
+            let Some(aligner) = aligner::detect(&header) else {
+                debug!("{}: no known aligner in the @PG lines", file.display());
+                continue;
+            };
+            debug!("{}: aligned with {}", file.display(), aligner.name);
+            let suggested = aligner.suggested_min_mq(self.min_mq);
+            if !aligner.differs() || suggested >= self.min_mq {
+                continue;
+            }
+            warn!(
+                "{} was aligned with {}, whose MAPQ only goes up to {} (bwa mem: {}); \
+                --min-mapping-quality {} may exclude most reads, consider --min-mapping-quality {}{}",
+                file.display(),
+                aligner.name,
+                aligner.max_mapq,
+                aligner::REFERENCE_MAX_MAPQ,
+                self.min_mq,
+                suggested,
+                if self.auto_mq { "" } else { " or --auto-mq" }
+            );
+            if adjusted.is_none_or(|(min_mq, _)| suggested < min_mq) {
+                adjusted = Some((suggested, aligner));
+            }
+        }
+
+        if let Some((min_mq, aligner)) = adjusted.filter(|_| self.auto_mq) {
+            info!(
+                "--auto-mq: using --min-mapping-quality {} instead of {} for {}",
+                min_mq, self.min_mq, aligner.name
+            );
+            self.auto_mq_note = Some(format!(
+                "--min-mapping-quality {} lowered to {} for {} (MAPQ up to {})",
+                self.min_mq, min_mq, aligner.name, aligner.max_mapq
+            ));
+            self.min_mq = min_mq;
+        }
+        Ok(())
+    }
+
+    /// Problems with a BAM or CRAM that would stop freebayes, each with its
+    /// fix. Missing sort order information and stale indexes are only warned
+    /// about.
+    fn bam_problems(&self, bam: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut problems = Vec::new();
+        let cram = mity_util::is_cram(bam);
+
+        let mut file = File::open(bam)?;
+        let eof: Option<&[u8]> = if cram {
+            // Byte 4 is the major version; only CRAM 3 has this EOF container
+            let mut definition = [0u8; 5];
+            file.read_exact(&mut definition)?;
+            (definition[4] == 3).then_some(&CRAM3_EOF[..])
+        } else {
+            Some(&BGZF_EOF[..])
+        };
+        if let Some(eof) = eof {
+            let mut tail = vec![0u8; eof.len()];
+            let truncated = file.seek(SeekFrom::End(-(eof.len() as i64))).is_err()
+                || file.read_exact(&mut tail).is_err()
+                || tail != eof;
+            if truncated {
+                problems.push(format!(
+                    "missing the {} EOF marker, so the file is truncated or corrupt; re-create or re-copy it",
+                    if cram { "CRAM" } else { "BGZF" }
+                ));
+                return Ok(problems);
+            }
+        }
+
+        let header = mity_util::bam_header_text(bam).map_err(crate::error::boxed)?;
+        let sort_order = header
+            .lines()
+            .find(|line| line.starts_with("@HD\t"))
+            .and_then(|line| line.split('\t').find_map(|field| field.strip_prefix("SO:")));
+        match sort_order {
+            Some("coordinate") => {}
+            Some(order @ ("queryname" | "unsorted")) => problems.push(format!(
+                "not coordinate-sorted (@HD SO:{}); run samtools sort -o sorted.{} {} and index the result",
+                order,
+                if cram { "cram" } else { "bam" },
+                bam.display()
+            )),
+            Some(order) => warn!(
+                "{} has @HD SO:{}; freebayes needs it coordinate-sorted (samtools sort)",
+                bam.display(),
+                order
+            ),
+            None => warn!(
+                "{} does not declare a sort order (@HD SO); freebayes needs it coordinate-sorted (samtools sort)",
+                bam.display()
+            ),
+        }
+
+        match mity_util::bam_index(bam) {
+            None if self.create_index => {}
+            None => problems.push(format!(
+                "no index; run samtools index {} or pass --create-index",
+                bam.display()
+            )),
+            Some(index) => {
+                let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+                if let (Ok(bam_time), Ok(index_time)) = (modified(bam), modified(&index)) {
+                    if index_time < bam_time {
+                        warn!(
+                            "{} is older than {}, so it may be stale; run samtools index {}",
+                            index.display(),
+                            bam.display(),
+                            bam.display()
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// The distinct `SM` tags of the read groups of `bam`, which must have at
+    /// least one `@RG` line.
+    fn bam_has_rg(&self, bam: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+        // Read the header text, which also works for CRAM
+        let header = mity_util::bam_header_text(bam).map_err(crate::error::boxed)?;
+
+        // Check if there are any read groups
+        if !header.lines().any(|line| line.starts_with("@RG\t")) {
+            // Return an error if no read groups are found
+            Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "No read groups found in BAM/CRAM file",
+            )))
+        } else {
+            // Return the samples if read groups are found
+            Ok(mity_util::bam_sample_names(bam).map_err(crate::error::boxed)?)
+        }
+    }
+
+    fn run_normalise(&self) -> Result<normalise::NormaliseResult, Box<dyn Error>> {
+        let settings = normalise::NormaliseSettings {
+            keep: self.keep,
+            p: self.p,
+            force: self.force,
+            ..normalise::NormaliseSettings::default()
+        };
+        let normalise_runner = normalise::Normalise::new(
+            self.debug,
+            self.output_vcf_path.clone(),
+            self.reference.clone(),
+            self.genome.as_ref().unwrap().clone(),
+            self.output_dir.clone(),
+            self.prefix.clone(),
+            settings,
+        );
+        normalise_runner.run()
+    }
+
+    /// The VCFs freebayes writes: the call VCF, or the per-sample VCFs.
+    fn freebayes_vcfs(&self) -> &[PathBuf] {
+        if self.split_vcf_paths.is_empty() {
+            std::slice::from_ref(&self.call_vcf_path)
+        } else {
+            &self.split_vcf_paths
+        }
+    }
+
+    /// Where `--keep` puts the raw freebayes VCF of each window of `vcf`:
+    /// `chunks/{prefix}.call.{start}-{end}.vcf.gz`, 1-based and inclusive.
+    fn chunk_paths(&self, vcf: &Path) -> Vec<PathBuf> {
+        let stem = call_vcf_sibling(vcf, "call");
+        let stem = stem.file_name().unwrap_or_default().to_string_lossy();
+        self.windows
+            .iter()
+            .map(|window| {
+                self.output_dir
+                    .join("chunks")
+                    .join(format!("{}.{}-{}.vcf.gz", stem, window.start + 1, window.end))
+            })
+            .collect()
+    }
+
+    /// freebayes' stderr for each of its VCFs, kept when it fails.
+    fn freebayes_logs(&self) -> Vec<PathBuf> {
+        self.freebayes_vcfs()
+            .iter()
+            .map(|vcf| call_vcf_sibling(vcf, "freebayes.log"))
+            .collect()
+    }
+
+    /// The files written only to make the outputs: freebayes' logs and,
+    /// with `--keep`, its raw VCFs, the targets BED and indexes made by
+    /// `--create-index`.
+    fn intermediate_files(&self) -> Vec<PathBuf> {
+        let mut files = self.freebayes_logs();
+        if self.keep {
+            files.extend(
+                self.freebayes_vcfs()
+                    .iter()
+                    .map(|vcf| call_vcf_sibling(vcf, "freebayes.raw.vcf.gz")),
+            );
+        }
+        if self.keep {
+            for vcf in self.freebayes_vcfs() {
+                files.extend(self.chunk_paths(vcf));
+            }
+        }
+        if !self.regions.is_empty() {
+            files.push(self.targets_bed_path.clone());
+        }
+        files.extend(self.filtered_copies());
+        files.extend(self.circular_files());
+        if self.circular && self.keep {
+            files.push(call_vcf_sibling(&self.call_vcf_path, "freebayes.shifted.raw.vcf.gz"));
+        }
+        files.extend(self.created_indexes.iter().cloned());
+        if self.output_format != OutputFormat::VcfGz {
+            files.push(self.call_vcf_path.clone());
+        }
+        files
+    }
+
+    /// The `##mity*` lines describing this run, added to the call VCF's
+    /// header. `##mityCommandline` has every effective parameter, so the
+    /// run can be repeated from it.
+    fn set_mity_cmd(&mut self) {
+        let mut args: Vec<String> = vec!["mity".into(), "call".into()];
+        let mut option = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        if let Some(build) = &self.custom_reference_build {
+            option("--reference", build.clone());
+        }
+        option("--custom-reference-fasta", self.reference.display().to_string());
+        if let Some(genome) = &self.genome {
+            option("--custom-reference-genome", genome.display().to_string());
+        }
+        option("--prefix", self.prefix.clone().unwrap_or_default());
+        option("--preset", self.preset.to_string());
+        option("--min-mapping-quality", self.min_mq.to_string());
+        option("--min-base-quality", self.min_bq.to_string());
+        option("--min-alternate-fraction", self.min_af.to_string());
+        option("--min-alternate-count", self.min_ac.to_string());
+        option("--ploidy", self.ploidy.to_string());
+        option("--p", self.p.to_string());
+        if let Some(max_depth) = self.max_depth {
+            option("--max-depth", max_depth.to_string());
+        }
+        option("--min-freebayes-version", self.min_freebayes_version.to_string());
+        option("--output-dir", self.output_dir.display().to_string());
+        if let Some(region) = &self.region {
+            option("--region", region.clone());
+        }
+        for exclusion in &self.exclusions {
+            option("--exclude-region", exclusion.to_region());
+        }
+        if let Some(path) = &self.sample_map_path {
+            option("--sample-map", path.display().to_string());
+        }
+        option("--read-end-exclusion", self.read_filter.end_exclusion.to_string());
+        option("--output-format", self.output_format.extension().to_string());
+        if let Some(path) = &self.numt_bed {
+            option("--numt-bed", path.display().to_string());
+        }
+        for (flag, excluded) in [
+            ("--exclude-duplicates", self.read_filter.duplicates),
+            ("--exclude-secondary", self.read_filter.secondary),
+            ("--exclude-supplementary", self.read_filter.supplementary),
+        ] {
+            args.push(format!("{}={}", flag, excluded));
+        }
+        // min_mq is already lowered, and --auto-mq would lower it again
+        let flags = [
+            (self.coverage_output, "--coverage-output"),
+            (self.error_on_empty, "--error-on-empty"),
+            (self.adaptive_min_ac, "--adaptive-min-ac"),
+            (self.auto_mq && self.auto_mq_note.is_none(), "--auto-mq"),
+            (self.per_sample_filter, "--per-sample-filter"),
+            (self.normalise, "--normalise"),
+            (self.split_samples, "--split-samples"),
+            (self.skip_reference_check, "--skip-reference-check"),
+            (self.report_all_sites, "--report-all-sites"),
+            (self.circular, "--circular"),
+            (self.strict_windows, "--strict-windows"),
+        ];
+        args.extend(flags.iter().filter(|(set, _)| *set).map(|(_, flag)| flag.to_string()));
+        args.extend(self.files.iter().map(|file| file.display().to_string()));
+
+        self.provenance = vec![mity_util::command_line_header("mityCommandline", &args)];
+        if let Some(note) = &self.auto_mq_note {
+            self.provenance.push(format!("##mityAutoMQ=\"{}\"", note));
+        }
+        if let Some(version) = self.freebayes_version {
+            self.provenance.push(format!("##freebayesVersion={}", version));
+        }
+        self.provenance.push(self.read_filter.header_line());
+        self.provenance.push(format!(
+            "##mityTools=\"{}\"",
+            mity_util::tool_provenance(&[Tool::Freebayes, Tool::Tabix])
+        ));
+    }
+}
+
+/// The file named `PREFIX.SUFFIX` next to the call VCF `vcf`, or
+/// `PREFIX.SAMPLE.SUFFIX` for a `--split-samples` VCF, such as where
+/// `--keep` writes freebayes' raw VCF.
+fn call_vcf_sibling(vcf: &Path, suffix: &str) -> PathBuf {
+    let name = vcf
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .replace(".mity.call.vcf.gz", &format!(".{}", suffix));
+    vcf.with_file_name(name)
+}
+
+/// How many windows to split `region_len` bases into, to call at once: one
+/// per thread, no more than `memory` has room for at
+/// [`FREEBAYES_WINDOW_MEMORY`] each, and none shorter than
+/// [`MIN_WINDOW_LEN`].
+fn window_count(threads: usize, memory: Option<u64>, region_len: usize) -> usize {
+    let by_memory = memory.map_or(usize::MAX, |memory| (memory / FREEBAYES_WINDOW_MEMORY) as usize);
+    threads.min(by_memory).min(region_len / MIN_WINDOW_LEN)
+}
+
+/// The error for a failed freebayes, with the last lines of its log.
+fn freebayes_failure(exit_code: Option<i32>, tail: &[String], log_path: &Path) -> Box<dyn Error> {
+    error!("FreeBayes failed; its messages are in {}", log_path.display());
+    let context = format!(
+        "The end of {}:\n{}\n",
+        log_path.display(),
+        tail.iter().map(|line| format!("  {}", line)).collect::<Vec<_>>().join("\n")
+    );
+    anyhow::Error::new(MityError::tool("FreeBayes", exit_code, tail.join("\n").as_bytes()))
+        .context(context)
+        .into()
+}
+
+/// The header line noting that freebayes failed on `window`, so the call
+/// VCF has no calls there.
+fn uncalled_header(window: &Interval) -> String {
+    format!(
+        "##mityUncalled=<ID={},Description=\"FreeBayes failed on this window twice; it has no calls\">",
+        window.to_region()
+    )
+}
+
+/// freebayes' `-b` arguments for `files`.
+fn bam_args(files: &[PathBuf]) -> Vec<OsString> {
+    files
+        .iter()
+        .flat_map(|file| [OsString::from("-b"), file.clone().into_os_string()])
+        .collect()
+}
+
+/// Whether an indexed BAM has a mapped alignment on `contig`.
+fn bam_has_reads_on(bam: &Path, contig: &str) -> Result<bool, Box<dyn Error>> {
+    let mut reader = mity_util::open_indexed_bam(bam).map_err(crate::error::boxed)?;
+    let header = reader.read_header()?;
+    let region = Region::new(contig, ..);
+    for result in reader.query(&header, &region)? {
+        if !result?.flags().is_unmapped() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+
+/// Sort intervals and merge those that overlap or touch, so no position is
+/// called twice.
+fn merge_intervals(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.sort_by(|a, b| (&a.contig, a.start).cmp(&(&b.contig, b.start)));
+    let mut merged: Vec<Interval> = Vec::new();
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if last.contig == interval.contig && interval.start <= last.end => {
+                last.end = last.end.max(interval.end);
+            }
+            _ => merged.push(interval),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, mt_header, reference_read, write_bam, write_records, write_reference};
+    use noodles::sam::alignment::RecordBuf;
+
+    /// A `mity call` of `files` against `reference` into `output_dir`, with
+    /// the command line defaults.
+    fn call(files: Vec<PathBuf>, reference: &Path, output_dir: &Path) -> Call {
+        preset_call(files, reference, output_dir, Preset::ShortRead, None, None)
+    }
+
+    /// [`call`] with `preset`, and `--min-base-quality` and `--p` if given.
+    fn preset_call(
+        files: Vec<PathBuf>,
+        reference: &Path,
+        output_dir: &Path,
+        preset: Preset,
+        min_bq: Option<u32>,
+        p: Option<f32>,
+    ) -> Call {
+        test_util::use_fake_tools();
+        let settings = CallSettings {
+            min_bq,
+            p,
+            preset,
+            progress: false,
+            read_filter: ReadFilter {
+                duplicates: true,
+                secondary: false,
+                supplementary: false,
+                end_exclusion: 0,
+            },
+            ..CallSettings::default()
+        };
+        Call::new(
+            false,
+            files,
+            reference.to_path_buf(),
+            None,
+            output_dir.to_path_buf(),
+            Some("test".to_string()),
+            settings,
+        )
+    }
+
+    /// Records of the call VCF, without the header.
+    fn records(path: &Path) -> Vec<String> {
+        test_util::read_lines(path).into_iter().filter(|line| !line.starts_with('#')).collect()
+    }
+
+    fn header(path: &Path) -> Vec<String> {
+        test_util::read_lines(path).into_iter().filter(|line| line.starts_with('#')).collect()
+    }
+
+    /// Reads of the test mitochondrion covering 1-3000 a few times over.
+    fn reads() -> Vec<RecordBuf> {
+        (0..30).map(|i| reference_read(&format!("r{}", i), 1 + i * 100, 100)).collect()
+    }
+
+    const SNV: &str = "MT\t310\t.\tT\tC\t500\t.\tDP=30;RO=3;AO=27\tGT:DP:RO:AO\t1/1:30:3:27";
+
+    #[cfg(unix)]
+    #[test]
+    fn calls_inputs_under_a_non_utf8_directory() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join(std::ffi::OsStr::from_bytes(b"run-\xff\xfe"));
+        fs::create_dir(&dir).unwrap();
+        let reference = write_reference(&dir, "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&dir.join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, &dir);
+        call.normalise = true;
+        call.genome = Some(test_util::write_genome(&dir, "MT"));
+        let result = call.run().unwrap();
+
+        assert_eq!(result.call_vcf, dir.join("test.mity.call.vcf.gz"));
+        assert_eq!(result.normalised_vcf, Some(dir.join("test.mity.normalise.vcf.gz")));
+        assert_eq!(records(&dir.join("test.mity.normalise.vcf.gz")).len(), 1);
+        assert_eq!(result.samples, ["a"]);
+        assert_eq!(records(&result.call_vcf).len(), 1);
+        assert!(header(&result.call_vcf).iter().any(|line| line.starts_with("##mityCommandline=")));
+    }
+
+    #[test]
+    fn calls_paths_with_spaces_and_shell_metacharacters() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("runs & $HOME; `id`");
+        let output_dir = dir.join("out dir");
+        fs::create_dir_all(&output_dir).unwrap();
+        let reference = write_reference(&dir, "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&dir.join("my sample (repeat 2) 'x'\"y\" | *.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam.clone()], &reference, &output_dir);
+        call.prefix = Some("s1".to_string());
+        call.keep = true;
+        let result = call.run().unwrap();
+
+        assert_eq!(result.call_vcf, output_dir.join("s1.mity.call.vcf.gz"));
+        assert_eq!(records(&result.call_vcf), [SNV]);
+        // freebayes got each path as one argument, as it is
+        let args = freebayes_args_of(&result.call_vcf);
+        assert!(has_args(&args, "-b", bam.to_str().unwrap()), "{:?}", args);
+        assert!(has_args(&args, "-f", reference.to_str().unwrap()), "{:?}", args);
+    }
+
+    #[test]
+    fn a_cram_path_and_its_index_go_to_freebayes_with_the_reference() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        // Not a real CRAM: the stand-in samtools reads a BAM named as one, and
+        // the stand-in freebayes decodes nothing, so this checks how a CRAM
+        // is told apart and handed on, not that one decodes
+        let cram = temp.path().join("a.cram");
+        fs::copy(&bam, &cram).unwrap();
+        fs::write(temp.path().join("a.cram.crai"), "").unwrap();
+        let (bam_dir, cram_dir) = (temp.path().join("bam"), temp.path().join("cram"));
+        fs::create_dir_all(&bam_dir).unwrap();
+        fs::create_dir_all(&cram_dir).unwrap();
+
+        let from_bam = call(vec![bam], &reference, &bam_dir).run().unwrap();
+        let mut from_cram = call(vec![cram.clone()], &reference, &cram_dir);
+        from_cram.keep = true;
+        let from_cram = from_cram.run().unwrap();
+
+        assert_eq!(from_cram.samples, ["a"]);
+        assert_eq!(records(&from_cram.call_vcf), records(&from_bam.call_vcf));
+        let args = freebayes_args_of(&from_cram.call_vcf);
+        assert!(has_args(&args, "-b", cram.to_str().unwrap()), "{:?}", args);
+        assert!(has_args(&args, "-f", reference.to_str().unwrap()), "{:?}", args);
+    }
+
+    #[test]
+    fn a_cram_without_its_index_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let cram = temp.path().join("a.cram");
+        fs::copy(&bam, &cram).unwrap();
+
+        let error = call(vec![cram], &reference, temp.path()).run().unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("a.cram: no index; run samtools index"), "{}", message);
+        assert!(!temp.path().join("test.mity.call.vcf.gz").exists());
+    }
+
+    #[test]
+    fn call_regions_are_checked_against_the_bam() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let call = call(vec![bam], &reference, temp.path());
+
+        let whole = call.parse_call_region("--region", "MT").unwrap();
+        assert_eq!(whole.to_region(), "MT:1-16569");
+        assert_eq!(call.parse_call_region("--region", "MT:100-200").unwrap().to_region(), "MT:100-200");
+
+        let message = |region: &str| call.parse_call_region("--region", region).unwrap_err().to_string();
+        assert_eq!(
+            message("MT:10-5"),
+            "--region: Invalid region MT:10-5: coordinates are 1-based and START <= END"
+        );
+        assert_eq!(
+            message("MT:0-100"),
+            "--region: Invalid region MT:0-100: coordinates are 1-based and START <= END"
+        );
+        assert_eq!(message("MT:1-99999"), "Invalid --region MT:1-99999: MT is only 16569 bp long");
+        assert!(message("chrM").ends_with("has no contig chrM; its mitochondrial contig is MT"));
+        assert!(message("chrM:1-100").ends_with("has no contig chrM; its mitochondrial contig is MT"));
+    }
+
+    #[test]
+    fn an_index_named_without_the_bam_extension_is_used() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        fs::rename(temp.path().join("a.bam.bai"), temp.path().join("a.bai")).unwrap();
+
+        let result = call(vec![bam.clone()], &reference, temp.path()).run().unwrap();
+        assert_eq!(records(&result.call_vcf), [SNV]);
+        // The filtered copy queries the input by its index too
+        let mut filtering = call(vec![bam], &reference, temp.path());
+        filtering.read_filter.end_exclusion = 5;
+        filtering.force = true;
+        assert_eq!(records(&filtering.run().unwrap().call_vcf), [SNV]);
+    }
+
+    #[test]
+    fn a_deleted_index_is_an_error_or_created() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let bai = temp.path().join("a.bam.bai");
+        fs::remove_file(&bai).unwrap();
+
+        let error = call(vec![bam.clone()], &reference, temp.path()).run().unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.contains(&format!("a.bam: no index; run samtools index {} or pass --create-index", bam.display())),
+            "{}",
+            message
+        );
+
+        let mut indexing = call(vec![bam.clone()], &reference, temp.path());
+        indexing.create_index = true;
+        let result = indexing.run().unwrap();
+        assert_eq!(records(&result.call_vcf), [SNV]);
+        let stats: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp.path().join("test.mity.call.stats.json")).unwrap()).unwrap();
+        assert_eq!(stats["metrics"]["created_indexes"], serde_json::json!([bai.display().to_string()]));
+        // It was made for the call, so it goes without --keep
+        assert!(!bai.exists());
+
+        let mut keeping = call(vec![bam], &reference, temp.path());
+        keeping.create_index = true;
+        keeping.keep = true;
+        keeping.force = true;
+        keeping.run().unwrap();
+        assert!(bai.exists());
+    }
+
+    /// The command line in the `##KEY` provenance line of `lines`, without
+    /// the version and date.
+    fn command_line_of(lines: &[String], key: &str) -> String {
+        let line = lines
+            .iter()
+            .find_map(|line| line.strip_prefix(&format!("##{}=\"", key)))
+            .unwrap_or_else(|| panic!("no ##{} in {:?}", key, lines));
+        let (command, rest) = line.split_once("; Version=").unwrap();
+        assert!(rest.starts_with(env!("CARGO_PKG_VERSION")), "{}", rest);
+        assert!(rest.contains("; Date=20"), "{}", rest);
+        command.to_string()
+    }
+
+    #[test]
+    fn every_call_parameter_is_in_the_command_line_header() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam.clone()], &reference, temp.path());
+        call.min_mq = 25;
+        call.min_bq = 21;
+        call.min_af = 0.02;
+        call.min_ac = 5;
+        call.p = 0.01;
+        call.ploidy = 1;
+        call.max_depth = Some(5000);
+        call.region = Some("MT:100-5000".to_string());
+        call.exclude_regions = vec!["MT:300-305".to_string()];
+        call.read_filter.secondary = true;
+        call.read_filter.end_exclusion = 3;
+        call.adaptive_min_ac = true;
+        call.per_sample_filter = true;
+        call.error_on_empty = true;
+        call.strict_windows = true;
+        call.output_format = OutputFormat::Vcf;
+        let result = call.run().unwrap();
+
+        let command = command_line_of(&header(&result.call_vcf), "mityCommandline");
+        for expected in [
+            "mity call ",
+            &format!("--custom-reference-fasta {}", reference.display()),
+            "--prefix test",
+            "--preset short-read",
+            "--min-mapping-quality 25",
+            "--min-base-quality 21",
+            "--min-alternate-fraction 0.02",
+            "--min-alternate-count 5",
+            "--ploidy 1",
+            "--p 0.01",
+            "--max-depth 5000",
+            "--min-freebayes-version 1.3.6",
+            &format!("--output-dir {}", temp.path().display()),
+            "--region MT:100-5000",
+            "--exclude-region MT:300-305",
+            "--read-end-exclusion 3",
+            "--output-format vcf",
+            "--exclude-duplicates=true",
+            "--exclude-secondary=true",
+            "--exclude-supplementary=false",
+            "--error-on-empty",
+            "--adaptive-min-ac",
+            "--per-sample-filter",
+            "--strict-windows",
+        ] {
+            assert!(command.contains(expected), "{:?} is not in {}", expected, command);
+        }
+        assert!(command.ends_with(&bam.display().to_string()), "{}", command);
+    }
+
+    #[test]
+    fn the_call_result_names_outputs_that_exist() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut normalising = call(vec![bam], &reference, temp.path());
+        normalising.normalise = true;
+        normalising.genome = Some(test_util::write_genome(temp.path(), "MT"));
+        let result = normalising.run().unwrap();
+
+        assert_eq!(result.call_vcf, temp.path().join("test.mity.call.vcf.gz"));
+        assert!(result.call_vcf.is_file());
+        assert_eq!(result.format, OutputFormat::VcfGz);
+        let normalised = result.normalised_vcf.unwrap();
+        assert_eq!(normalised, temp.path().join("test.mity.normalise.vcf.gz"));
+        assert!(normalised.is_file());
+        assert_eq!(result.region.as_deref(), Some("MT:1-16569"));
+        assert_eq!(result.prefix, "test");
+        assert_eq!(result.samples, ["a"]);
+        assert_eq!(result.records, SPREAD.len());
+        assert_eq!(records(&result.call_vcf).len(), result.records);
+
+        // In another format, the result names that file
+        let mut plain = call(vec![temp.path().join("a.bam")], &reference, temp.path());
+        plain.output_format = OutputFormat::Vcf;
+        let result = plain.run().unwrap();
+        assert_eq!(result.call_vcf, temp.path().join("test.mity.call.vcf"));
+        assert!(result.call_vcf.is_file());
+        assert!(result.normalised_vcf.is_none());
+    }
+
+    #[test]
+    fn calls_round_trip_through_each_output_format() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let genome = test_util::write_genome(temp.path(), "MT");
+
+        let mut normalised = Vec::new();
+        for format in [OutputFormat::Vcf, OutputFormat::VcfGz, OutputFormat::Bcf] {
+            let output_dir = temp.path().join(format.extension());
+            fs::create_dir(&output_dir).unwrap();
+            let mut formatted = call(vec![bam.clone()], &reference, &output_dir);
+            formatted.output_format = format;
+            formatted.normalise = true;
+            formatted.genome = Some(genome.clone());
+            let result = formatted.run().unwrap();
+
+            assert_eq!(result.call_vcf, output_dir.join(format!("test.mity.call.{}", format.extension())));
+            assert_eq!(mity_util::is_bcf(&result.call_vcf).unwrap(), format == OutputFormat::Bcf);
+            let text = if format == OutputFormat::Bcf {
+                let text = output_dir.join("decoded.vcf");
+                mity_util::bcf_to_vcf(&result.call_vcf, &text).unwrap();
+                text
+            } else {
+                result.call_vcf.clone()
+            };
+            assert_eq!(records(&text), SPREAD, "{}", format.extension());
+            let command = command_line_of(&header(&text), "mityCommandline");
+            assert!(command.contains(&format!("--output-format {}", format.extension())), "{}", command);
+            normalised.push(records(&result.normalised_vcf.unwrap()));
+        }
+        // normalise reads each the same
+        assert_eq!(normalised[0].len(), SPREAD.len());
+        assert_eq!(normalised[0], normalised[1]);
+        assert_eq!(normalised[0], normalised[2]);
+    }
+
+    #[test]
+    fn a_failed_normalise_is_returned_as_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[&SNV.replacen("\t.\t", "\tfail-norm\t", 1)]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut normalising = call(vec![bam], &reference, temp.path());
+        normalising.normalise = true;
+        normalising.genome = Some(test_util::write_genome(temp.path(), "MT"));
+        let message = normalising.run().unwrap_err().to_string();
+        assert!(message.contains("bcftools norm"), "{}", message);
+    }
+
+    #[test]
+    fn an_input_aligned_to_another_mt_length_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let matching = write_bam(&temp.path().join("rcrs.bam"), &mt_header("MT", &["a"]), &reads());
+        // Aligned to hg19's chrM, named as the reference's
+        let hg19_header = mt_header("MT", &["b"]).replace("LN:16569", "LN:16571");
+        let hg19 = write_bam(&temp.path().join("hg19.bam"), &hg19_header, &reads());
+
+        let matched = call(vec![matching], &reference, temp.path()).run().unwrap();
+        assert_eq!(records(&matched.call_vcf), [SNV]);
+
+        let message = call(vec![hg19.clone()], &reference, temp.path()).run().unwrap_err().to_string();
+        assert!(
+            message.starts_with(&format!(
+                "The mitochondrial contig of the reference {} is 16569 bp, but in the BAM/CRAM inputs:\n  {}: MT is 16571 bp\n",
+                reference.display(),
+                hg19.display()
+            )),
+            "{}",
+            message
+        );
+        assert!(message.contains("try --reference hg19"), "{}", message);
+        assert!(message.ends_with("or pass --skip-reference-check for an unusual reference"), "{}", message);
+
+        let mut skipping = call(vec![hg19], &reference, temp.path());
+        skipping.skip_reference_check = true;
+        skipping.force = true;
+        assert_eq!(records(&skipping.run().unwrap().call_vcf), [SNV]);
+    }
+
+    #[test]
+    fn inputs_sharing_an_sm_tag_are_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let first = write_bam(&temp.path().join("first.bam"), &mt_header("MT", &["sample1"]), &reads());
+        let second = write_bam(&temp.path().join("second.bam"), &mt_header("MT", &["sample1"]), &reads());
+        let other = write_bam(&temp.path().join("other.bam"), &mt_header("MT", &["sample2"]), &reads());
+
+        let mut colliding = call(vec![first.clone(), other, second.clone()], &reference, temp.path());
+        let message = colliding.run_checks().unwrap_err().to_string();
+        assert!(
+            message.contains(&format!("\n  SM:sample1: {}, {}\n", first.display(), second.display())),
+            "{}",
+            message
+        );
+        assert!(!message.contains("sample2"), "{}", message);
+    }
+
+    #[test]
+    fn a_variant_across_the_junction_is_called_in_rcrs_coordinates() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let sequence = test_util::mt_sequence();
+        // Deletes 16566-16569 and 1-3: REF is 16565-16569 then 1-3
+        let spanning: String = [&sequence[16564..], &sequence[..3]].concat().into_iter().map(char::from).collect();
+        let alt = &spanning[..1];
+        // Freebayes on the linear contig sees only the end of the deletion
+        let truncated = format!(
+            "MT\t16565\t.\t{}\t{}\t500\t.\tDP=30;RO=3;AO=27\tGT:DP:RO:AO\t1/1:30:3:27",
+            &spanning[..5],
+            alt
+        );
+        // Away from the junction, the first pass stands
+        write_records(&reference, &[SPREAD[1], &truncated]);
+        // 16565 is 8565 on the rotated reference
+        write_records(
+            &temp.path().join("test.mity.call.shifted.fa"),
+            &[&format!("MT\t8565\t.\t{}\t{}\t500\t.\tDP=30;RO=3;AO=27\tGT:DP:RO:AO\t1/1:30:3:27", spanning, alt)],
+        );
+        let mut alignments = reads();
+        alignments.extend((0..5).map(|i| reference_read(&format!("j{}", i), 16370 + i * 25, 100)));
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &alignments);
+
+        let mut circular = call(vec![bam], &reference, temp.path());
+        circular.circular = true;
+        let result = circular.run().unwrap();
+
+        let calls = records(&result.call_vcf);
+        assert_eq!(calls.len(), 2, "{:?}", calls);
+        assert_eq!(calls[0], SPREAD[1]);
+        let junction = VcfRecord::parse(&calls[1]).unwrap();
+        assert_eq!(junction.pos, 16565);
+        assert_eq!(junction.reference, spanning);
+        assert_eq!(junction.alt, alt);
+        assert!(junction.has_info("MT_JUNCTION"));
+        assert!(header(&result.call_vcf).iter().any(|line| line == circular::JUNCTION_HEADER));
+    }
+
+    #[test]
+    fn the_preset_sets_the_thresholds_not_given() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let bam = temp.path().join("a.bam");
+
+        let short = call(vec![bam.clone()], &reference, temp.path());
+        assert_eq!((short.min_mq, short.min_bq, short.min_af, short.min_ac, short.p), (30, 24, 0.01, 4, 0.002));
+        let long = preset_call(vec![bam.clone()], &reference, temp.path(), Preset::LongRead, None, None);
+        assert_eq!((long.min_mq, long.min_bq, long.min_af, long.min_ac, long.p), (30, 10, 0.05, 10, 0.01));
+        // As from the command line or a config file
+        let given = preset_call(vec![bam], &reference, temp.path(), Preset::LongRead, Some(15), Some(0.05));
+        assert_eq!((given.min_bq, given.min_ac, given.p), (15, 10, 0.05));
+    }
+
+    #[test]
+    fn an_input_without_mt_reads_gives_an_empty_vcf_or_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let bam = write_bam(&temp.path().join("exome.bam"), &mt_header("MT", &["a"]), &[]);
+
+        let mut empty = call(vec![bam.clone()], &reference, temp.path());
+        empty.normalise = true;
+        empty.genome = Some(test_util::write_genome(temp.path(), "MT"));
+        let result = empty.run().unwrap();
+        assert_eq!(result.records, 0);
+        assert!(records(&result.call_vcf).is_empty());
+        assert!(header(&result.call_vcf).last().unwrap().starts_with("#CHROM"));
+        let normalised = result.normalised_vcf.unwrap();
+        assert!(records(&normalised).is_empty());
+        assert!(header(&normalised).last().unwrap().starts_with("#CHROM"));
+
+        let mut strict = call(vec![bam.clone()], &reference, temp.path());
+        strict.error_on_empty = true;
+        strict.force = true;
+        let message = strict.run().unwrap_err().to_string();
+        assert_eq!(message, format!("BAM inputs without mitochondrial reads:\n  {}: no reads on MT", bam.display()));
+    }
+
+    #[test]
+    fn sample_columns_follow_the_input_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bams: Vec<PathBuf> = ["charlie", "alpha", "bravo"]
+            .iter()
+            .map(|sample| write_bam(&temp.path().join(format!("{}.bam", sample)), &mt_header("MT", &[sample]), &reads()))
+            .collect();
+
+        let result = call(bams.clone(), &reference, temp.path()).run().unwrap();
+        let columns = header(&result.call_vcf).pop().unwrap();
+        assert!(columns.ends_with("FORMAT\tcharlie\talpha\tbravo"), "{}", columns);
+        assert_eq!(result.samples, ["charlie", "alpha", "bravo"]);
+
+        let mut sorted = call(bams, &reference, temp.path());
+        sorted.sort_inputs = true;
+        sorted.force = true;
+        let result = sorted.run().unwrap();
+        let columns = header(&result.call_vcf).pop().unwrap();
+        assert!(columns.ends_with("FORMAT\talpha\tbravo\tcharlie"), "{}", columns);
+        assert_eq!(result.samples, ["alpha", "bravo", "charlie"]);
+    }
+
+    #[test]
+    fn the_call_vcf_is_bgzf_that_tabix_can_index() {
+        use noodles::vcf;
+
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let result = call(vec![bam], &reference, temp.path()).run().unwrap();
+        let bytes = fs::read(&result.call_vcf).unwrap();
+        assert!(bytes.ends_with(&BGZF_EOF), "no BGZF EOF block");
+
+        let index = vcf::index(&result.call_vcf).unwrap();
+        let mut reader = vcf::io::indexed_reader::Builder::default()
+            .set_index(index)
+            .build_from_path(&result.call_vcf)
+            .unwrap();
+        let vcf_header = reader.read_header().unwrap();
+        let region = "MT:300-5523".parse().unwrap();
+        let positions: Vec<usize> = reader
+            .query(&vcf_header, &region)
+            .unwrap()
+            .map(|record| record.unwrap().variant_start().unwrap().unwrap().get())
+            .collect();
+        assert_eq!(positions, [310, 5523]);
+    }
+
+    #[test]
+    fn freebayes_headers_gain_the_mity_command_line_without_phasing() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        // The stand-in writes ##phasing=none, as freebayes 1.3 does
+        let result = call(vec![bam.clone()], &reference, temp.path()).run().unwrap();
+        let lines = header(&result.call_vcf);
+        assert!(!lines.iter().any(|line| line.starts_with("##phasing")));
+        assert!(lines.iter().any(|line| line == "##freebayesSource=freeBayes v1.3.6"));
+        assert!(lines.iter().any(|line| line == "##freebayesCommandline=\"freebayes\""));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("##mityCommandline=")).count(), 1);
+        assert_eq!(records(&result.call_vcf), [SNV]);
+
+        // A header without it still gets the provenance line
+        let mut other = call(vec![bam], &reference, temp.path());
+        other.set_mity_cmd();
+        let input = [
+            "##fileformat=VCFv4.2",
+            "##source=freeBayes v1.0.2",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta",
+            SNV,
+        ];
+        let mut output = Vec::new();
+        other
+            .filter_vcf_lines(&mut output, input.iter().map(|line| Ok(line.to_string())))
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..2], ["##fileformat=VCFv4.2", "##freebayesSource=freeBayes v1.0.2"]);
+        assert!(lines.iter().any(|line| line.starts_with("##mityCommandline=\"mity call ")));
+        assert_eq!(lines[lines.len() - 2..], [input[2], SNV]);
+    }
+
+    /// The empty block that ends a BGZF file.
+    const BGZF_EOF: [u8; 28] = [
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00, 0x1b, 0x00,
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn split_trio_columns_match_the_combined_vcf() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let trio = ["child", "mother", "father"];
+        let bams: Vec<PathBuf> = trio
+            .iter()
+            .map(|sample| write_bam(&temp.path().join(format!("{}.bam", sample)), &mt_header("MT", &[sample]), &reads()))
+            .collect();
+
+        let mut call = call(bams, &reference, temp.path());
+        call.split_samples = true;
+        let result = call.run().unwrap();
+
+        let combined = records(&result.call_vcf);
+        assert_eq!(combined.len(), SPREAD.len());
+        let columns = header(&result.call_vcf).pop().unwrap();
+        assert!(columns.ends_with("FORMAT\tchild\tmother\tfather"), "{}", columns);
+        for (index, sample) in trio.iter().enumerate() {
+            let split = temp.path().join(format!("test.{}.mity.call.vcf.gz", sample));
+            assert!(header(&split).pop().unwrap().ends_with(&format!("FORMAT\t{}", sample)));
+            let split_records = records(&split);
+            assert_eq!(split_records.len(), combined.len());
+            for (split_record, combined_record) in split_records.iter().zip(&combined) {
+                let split_fields: Vec<&str> = split_record.split('\t').collect();
+                let combined_fields: Vec<&str> = combined_record.split('\t').collect();
+                // The same site and FORMAT, and this sample's column as it is
+                assert_eq!(split_fields[..5], combined_fields[..5]);
+                assert_eq!(split_fields[8], combined_fields[8]);
+                assert_eq!(split_fields[9], combined_fields[9 + index]);
+            }
+        }
+    }
+
+    /// Windows of the test mitochondrion, as `set_windows` would make them
+    /// for three threads.
+    fn three_windows() -> Vec<Interval> {
+        [(0, 5523), (5523, 11046), (11046, 16569)]
+            .iter()
+            .map(|&(start, end)| Interval {
+                contig: "MT".to_string(),
+                start,
+                end,
+            })
+            .collect()
+    }
+
+    /// Records across the test mitochondrion, some in each of
+    /// [`three_windows`].
+    const SPREAD: [&str; 4] = [
+        SNV,
+        "MT\t5523\t.\tA\tG\t300\t.\tDP=30;RO=10;AO=20\tGT:DP:RO:AO\t0/1:30:10:20",
+        "MT\t5524\t.\tC\tT\t300\t.\tDP=30;RO=10;AO=20\tGT:DP:RO:AO\t0/1:30:10:20",
+        "MT\t16000\t.\tG\tA\t300\t.\tDP=30;RO=10;AO=20\tGT:DP:RO:AO\t0/1:30:10:20",
+    ];
+
+    #[test]
+    fn region_starts_and_pos_1_are_called_however_the_region_is_run() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let at = |pos: usize| format!("MT\t{}\t.\tA\tG\t300\t.\tDP=30;RO=10;AO=20\tGT:DP:RO:AO\t0/1:30:10:20", pos);
+        let lines: Vec<String> = [1, 99, 100, 200, 201, 300, 400].into_iter().map(at).collect();
+        write_records(&reference, &lines.iter().map(String::as_str).collect::<Vec<_>>());
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let positions = |region: Option<&str>, windows: Vec<Interval>| -> Vec<usize> {
+            let mut calling = call(vec![bam.clone()], &reference, temp.path());
+            calling.region = region.map(String::from);
+            calling.windows = windows;
+            calling.force = true;
+            let result = calling.run().unwrap();
+            records(&result.call_vcf).iter().map(|line| VcfRecord::parse(line).unwrap().pos as usize).collect()
+        };
+        let window = |start: usize, end: usize| Interval {
+            contig: "MT".to_string(),
+            start,
+            end,
+        };
+
+        assert_eq!(positions(Some("MT:100-200"), Vec::new()), [100, 200]);
+        assert_eq!(positions(Some("MT:100-200,MT:300-400"), Vec::new()), [100, 200, 300, 400]);
+        assert_eq!(positions(Some("MT:100-200"), vec![window(99, 150), window(150, 200)]), [100, 200]);
+        let whole = [1, 99, 100, 200, 201, 300, 400];
+        assert_eq!(positions(None, Vec::new()), whole);
+        assert_eq!(positions(None, three_windows()), whole);
+    }
+
+    #[test]
+    fn windowed_calls_match_a_single_run() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+        let (single_dir, windowed_dir) = (temp.path().join("single"), temp.path().join("windowed"));
+        fs::create_dir_all(&single_dir).unwrap();
+        fs::create_dir_all(&windowed_dir).unwrap();
+
+        let single = call(vec![bam.clone()], &reference, &single_dir).run().unwrap();
+        let mut windowed = call(vec![bam], &reference, &windowed_dir);
+        windowed.windows = three_windows();
+        let windowed = windowed.run().unwrap();
+
+        // Equal but for the command line, which names the output directory
+        let lines = |path: &Path| -> Vec<String> {
+            test_util::read_lines(path)
+                .into_iter()
+                .filter(|line| !line.starts_with("##mityCommandline="))
+                .collect()
+        };
+        assert_eq!(lines(&windowed.call_vcf), lines(&single.call_vcf));
+        assert_eq!(records(&windowed.call_vcf).len(), SPREAD.len());
+
+        // A record two windows report is kept once
+        let mut overlapping = call(vec![temp.path().join("a.bam")], &reference, &windowed_dir);
+        overlapping.windows = three_windows();
+        overlapping.windows[0].end = 5600;
+        overlapping.force = true;
+        let overlapping = overlapping.run().unwrap();
+        assert_eq!(lines(&overlapping.call_vcf), lines(&single.call_vcf));
+    }
+
+    #[test]
+    fn keep_persists_window_chunks() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.keep = true;
+        call.windows = three_windows();
+        let result = call.run().unwrap();
+
+        let chunks = temp.path().join("chunks");
+        let mut concatenated = Vec::new();
+        for name in ["test.call.1-5523.vcf.gz", "test.call.5524-11046.vcf.gz", "test.call.11047-16569.vcf.gz"] {
+            let chunk = chunks.join(name);
+            assert!(chunk.exists(), "{} is missing", chunk.display());
+            concatenated.extend(records(&chunk));
+        }
+        assert_eq!(concatenated, records(&result.call_vcf));
+        assert_eq!(concatenated.len(), SPREAD.len());
+    }
+
+    #[test]
+    fn window_chunks_are_not_kept_without_keep() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &SPREAD);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.windows = three_windows();
+        let result = call.run().unwrap();
+
+        assert!(!temp.path().join("chunks").exists());
+        assert_eq!(records(&result.call_vcf).len(), SPREAD.len());
+    }
+
+    #[test]
+    fn a_failed_freebayes_reports_as_a_tool_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let mut lines = SPREAD.to_vec();
+        lines.push("#fail MT:5523-11046");
+        write_records(&reference, &lines);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.windows = three_windows();
+        call.strict_windows = true;
+        let error = call.run().unwrap_err();
+
+        let report = crate::error::error_json("call", error.as_ref());
+        assert_eq!(report["kind"], "tool");
+        assert_eq!(report["tool"]["name"], "FreeBayes");
+        assert_eq!(report["tool"]["exit_code"], 1);
+        assert!(report["tool"]["stderr_tail"].as_str().unwrap().ends_with("failing on MT:5523-11046"));
+        assert!(report["message"].as_str().unwrap().contains("test.freebayes.log"));
+    }
+
+    #[test]
+    fn a_window_failing_once_is_run_again() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let mut lines = SPREAD.to_vec();
+        lines.push("#flaky MT:5523-11046");
+        write_records(&reference, &lines);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.windows = three_windows();
+        call.run().unwrap();
+
+        let vcf = temp.path().join("test.mity.call.vcf.gz");
+        let positions: Vec<String> = records(&vcf).iter().map(|line| line.split('\t').nth(1).unwrap().to_string()).collect();
+        assert_eq!(positions, ["310", "5523", "5524", "16000"]);
+        assert!(!header(&vcf).iter().any(|line| line.starts_with("##mityUncalled")));
+        let stats = fs::read_to_string(temp.path().join("test.mity.call.stats.json")).unwrap();
+        assert!(!stats.contains("uncalled_intervals"));
+    }
+
+    #[test]
+    fn a_window_failing_twice_is_left_uncalled() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let mut lines = SPREAD.to_vec();
+        lines.push("#fail MT:5523-11046");
+        write_records(&reference, &lines);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.windows = three_windows();
+        call.keep = true;
+        call.run().unwrap();
+
+        let vcf = temp.path().join("test.mity.call.vcf.gz");
+        let positions: Vec<String> = records(&vcf).iter().map(|line| line.split('\t').nth(1).unwrap().to_string()).collect();
+        assert_eq!(positions, ["310", "5523", "16000"]);
+        let uncalled: Vec<String> = header(&vcf).into_iter().filter(|line| line.starts_with("##mityUncalled")).collect();
+        assert_eq!(
+            uncalled,
+            ["##mityUncalled=<ID=MT:5524-11046,Description=\"FreeBayes failed on this window twice; it has no calls\">"]
+        );
+        let stats: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp.path().join("test.mity.call.stats.json")).unwrap()).unwrap();
+        assert_eq!(stats["metrics"]["uncalled_intervals"], serde_json::json!(["MT:5524-11046"]));
+        // Both runs of the window are logged
+        let log = test_util::read_lines(&temp.path().join("test.freebayes.log"));
+        assert_eq!(log.iter().filter(|line| *line == "failing on MT:5523-11046").count(), 2);
+        assert!(log.contains(&"# MT:5524-11046 (retry)".to_string()));
+    }
+
+    #[test]
+    fn every_window_failing_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let mut lines = SPREAD.to_vec();
+        lines.extend(["#fail MT:0-5523", "#fail MT:5523-11046", "#fail MT:11046-16569"]);
+        write_records(&reference, &lines);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut call = call(vec![bam], &reference, temp.path());
+        call.windows = three_windows();
+        let error = call.run().unwrap_err();
+        let report = crate::error::error_json("call", error.as_ref());
+        assert_eq!(report["kind"], "tool");
+        assert!(report["tool"]["stderr_tail"].as_str().unwrap().ends_with("failing on MT:0-5523"));
+    }
+
+    #[test]
+    fn window_count_follows_threads_memory_and_region_length() {
+        let mt = test_util::MT_LENGTH;
+        assert_eq!(window_count(4, None, mt), 4);
+        assert_eq!(window_count(64, None, mt), 16);
+        assert_eq!(window_count(4, None, 2500), 2);
+        // 512 MB a window
+        assert_eq!(window_count(8, Some(4 << 30), mt), 8);
+        assert_eq!(window_count(8, Some(2 << 30), mt), 4);
+        assert_eq!(window_count(8, Some(1536 << 20), mt), 3);
+        assert_eq!(window_count(8, Some(1 << 30), mt), 2);
+        // Too little for two windows leaves a single run
+        assert!(window_count(8, Some(1000 << 20), mt) < 2);
+        assert!(window_count(8, Some(1 << 20), mt) < 2);
+    }
+
+    /// The arguments the stand-in freebayes logged for `vcf`, kept with
+    /// `--keep`.
+    fn freebayes_args_of(vcf: &Path) -> Vec<String> {
+        test_util::read_lines(&call_vcf_sibling(vcf, "freebayes.log"))
+            .into_iter()
+            .filter_map(|line| line.strip_prefix("arg: ").map(String::from))
+            .collect()
+    }
+
+    fn has_args(args: &[String], flag: &str, value: &str) -> bool {
+        args.windows(2).any(|pair| pair[0] == flag && pair[1] == value)
+    }
+
+    #[test]
+    fn max_depth_limits_freebayes_coverage_and_is_recorded() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut capped = call(vec![bam.clone()], &reference, temp.path());
+        capped.max_depth = Some(1000);
+        capped.keep = true;
+        let result = capped.run().unwrap();
+        assert!(has_args(&freebayes_args_of(&result.call_vcf), "--limit-coverage", "1000"));
+        let command = command_line_of(&header(&result.call_vcf), "mityCommandline");
+        assert!(command.contains("--max-depth 1000"), "{}", command);
+
+        let mut uncapped = call(vec![bam], &reference, temp.path());
+        uncapped.keep = true;
+        uncapped.force = true;
+        let result = uncapped.run().unwrap();
+        assert!(!freebayes_args_of(&result.call_vcf).contains(&"--limit-coverage".to_string()));
+        let command = command_line_of(&header(&result.call_vcf), "mityCommandline");
+        assert!(!command.contains("--max-depth"), "{}", command);
+    }
+
+    #[test]
+    fn ploidy_1_calls_haploid_genotypes() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        let haploid = "MT\t310\t.\tT\tC\t500\t.\tDP=30;RO=3;AO=27\tGT:DP:RO:AO\t1:30:3:27";
+        write_records(&reference, &[haploid]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut haploid_call = call(vec![bam], &reference, temp.path());
+        haploid_call.ploidy = 1;
+        haploid_call.keep = true;
+        haploid_call.per_sample_filter = true;
+        let result = haploid_call.run().unwrap();
+        assert!(has_args(&freebayes_args_of(&result.call_vcf), "--ploidy", "1"));
+        assert_eq!(records(&result.call_vcf), [haploid]);
+
+        // A genotype the per-sample filter resets keeps the ploidy
+        let line = "MT\t310\t.\tT\tC\t500\t.\t.\tGT:DP:RO:AO\t1:30:3:27\t1:30:29:1";
+        let mut record = VcfRecord::parse(line).unwrap();
+        assert_eq!(haploid_call.reset_unsupported_genotypes(&mut record), Some(1));
+        assert_eq!(record.sample(0, "GT"), Some("1"));
+        assert_eq!(record.sample(1, "GT"), Some("0"));
+        haploid_call.ploidy = 2;
+        let mut record = VcfRecord::parse(line).unwrap();
+        haploid_call.reset_unsupported_genotypes(&mut record);
+        assert_eq!(record.sample(1, "GT"), Some("0/0"));
+    }
+
+    #[test]
+    fn duplicates_are_left_to_freebayes_to_exclude_or_use() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let bam = write_bam(&temp.path().join("a.bam"), &mt_header("MT", &["a"]), &reads());
+
+        let mut excluding = call(vec![bam.clone()], &reference, temp.path());
+        excluding.keep = true;
+        let result = excluding.run().unwrap();
+        assert!(!freebayes_args_of(&result.call_vcf).contains(&"--use-duplicate-reads".to_string()));
+        let lines = header(&result.call_vcf);
+        assert!(lines.iter().any(|line| line.starts_with("##mityReadFilter=\"duplicates=excluded,")), "{:?}", lines);
+
+        let mut using = call(vec![bam], &reference, temp.path());
+        using.read_filter.duplicates = false;
+        using.keep = true;
+        using.force = true;
+        let result = using.run().unwrap();
+        assert!(freebayes_args_of(&result.call_vcf).contains(&"--use-duplicate-reads".to_string()));
+        let lines = header(&result.call_vcf);
+        assert!(lines.iter().any(|line| line.starts_with("##mityReadFilter=\"duplicates=included,")), "{:?}", lines);
+        // freebayes does this itself, without a filtered copy
+        assert!(!using.needs_filtered_copies());
+    }
+
+    const BOWTIE2_PG: &str = "@PG\tID:bowtie2\tPN:bowtie2\tVN:2.4.1\tCL:bowtie2 -x idx -U r.fq\n";
+
+    #[test]
+    fn auto_mq_rescales_min_mq_for_the_aligner() {
+        let temp = tempfile::tempdir().unwrap();
+        let reference = write_reference(temp.path(), "MT");
+        write_records(&reference, &[SNV]);
+        let sam_header = mt_header("MT", &["a"]) + BOWTIE2_PG;
+        let bam = write_bam(&temp.path().join("a.bam"), &sam_header, &reads());
+
+        let mut auto = call(vec![bam.clone()], &reference, temp.path());
+        auto.keep = true;
+        auto.auto_mq = true;
+        let result = auto.run().unwrap();
+        assert!(has_args(&freebayes_args_of(&result.call_vcf), "--min-mapping-quality", "21"));
+        assert!(header(&result.call_vcf).contains(
+            &"##mityAutoMQ=\"--min-mapping-quality 30 lowered to 21 for bowtie2 (MAPQ up to 42)\"".to_string()
+        ));
+
+        // Without --auto-mq it is only warned about
+        let mut warned = call(vec![bam], &reference, temp.path());
+        warned.keep = true;
+        warned.force = true;
+        let result = warned.run().unwrap();
+        assert!(has_args(&freebayes_args_of(&result.call_vcf), "--min-mapping-quality", "30"));
+        assert!(!header(&result.call_vcf).iter().any(|line| line.starts_with("##mityAutoMQ")));
+    }
 }
+
@@ -1,60 +1,344 @@
-use crate::mity_util::{self, select_reference_fasta, select_reference_genome};
-use std::process::Command;
+use crate::mity_util::{self, select_reference_fasta, select_reference_genome, Tool, ToolVersion};
+use crate::style::{self, Stream};
+use anyhow::{Context, Result};
+use noodles::fasta;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::thread;
 
-pub fn mity_check() {
+const GENOME_OPTIONS: [&str; 4] = ["hs37d5", "hg19", "hg38", "mm10"];
+
+pub fn mity_check(json: bool, min_freebayes_version: ToolVersion) {
+    if json {
+        let report = json!({
+            "tools": Tool::ALL.iter().map(|tool| match mity_util::tool_path(*tool) {
+                Ok(path) => json!({ "tool": tool.name(), "path": path.display().to_string() }),
+                Err(e) => json!({ "tool": tool.name(), "error": e.to_string() }),
+            }).collect::<Vec<_>>(),
+            "freebayes_version": match mity_util::freebayes_version() {
+                Ok(version) => json!({
+                    "version": version.to_string(),
+                    "minimum": min_freebayes_version.to_string(),
+                    "supported": version >= min_freebayes_version,
+                }),
+                Err(e) => json!({ "error": format!("{:#}", e) }),
+            },
+            "threads": available_threads(),
+            "references": GENOME_OPTIONS.iter().map(|genome| reference_json(genome)).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report).expect("JSON values serialise"));
+        return;
+    }
     check_required_commands();
+    check_freebayes_version(min_freebayes_version);
     check_threads();
     check_required_reference_files();
 }
 
 fn check_required_commands() {
-    let required_commands = ["freebayes", "tabix", "gsort"];
-    println!("Checking for required commands...");
-    for command in &required_commands {
-        if !is_command_available(command) {
-            eprintln!(
-                "Error: Command '{}' is not installed or not in PATH.",
-                command
-            );
+    println!("{}", style::heading("Checking for required commands...", Stream::Stdout));
+    for tool in Tool::ALL {
+        match mity_util::tool_path(tool) {
+            Ok(path) => println!("{} {}: {}", style::tick(Stream::Stdout), tool.name(), path.display()),
+            Err(e) => eprintln!("{} {}.", style::cross(Stream::Stderr), e),
         }
     }
-    println!("");
+    println!();
 }
 
-fn is_command_available(command: &str) -> bool {
-    Command::new("which") // Use "where" on Windows
-        .arg(command)
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+fn check_freebayes_version(minimum: ToolVersion) {
+    println!("{}", style::heading("Checking the freebayes version...", Stream::Stdout));
+    match mity_util::freebayes_version() {
+        Ok(version) if version >= minimum => {
+            println!("{} freebayes {} (at least {})", style::tick(Stream::Stdout), version, minimum)
+        }
+        Ok(version) => eprintln!(
+            "{} freebayes {} is older than {}, the oldest mity call supports.",
+            style::cross(Stream::Stderr),
+            version,
+            minimum
+        ),
+        Err(e) => eprintln!("{} {:#}.", style::cross(Stream::Stderr), e),
+    }
+    println!();
 }
 
-fn check_threads() {
-    let num_threads = thread::available_parallelism()
+fn available_threads() -> usize {
+    thread::available_parallelism()
         .map(|n| n.get())
-        .unwrap_or(1);
-    println!("Number of available threads: {}\n", num_threads);
+        .unwrap_or(1)
+}
+
+fn check_threads() {
+    println!("Number of available threads: {}\n", available_threads());
 }
 
 fn check_required_reference_files() {
-    let genome_options = ["hs37d5", "hg19", "hg38", "mm10"];
     let mity_dir = mity_util::get_mity_dir();
     match mity_dir {
         Ok(_) => {
-            println!("Checking for required genome files...");
-            for genome in &genome_options {
+            println!("{}", style::heading("Checking for required genome files...", Stream::Stdout));
+            for genome in &GENOME_OPTIONS {
                 let reference_fasta = select_reference_fasta(genome, None);
                 if reference_fasta.is_err() {
-                    eprintln!("Reference fasta for '{}' is missing.", genome.to_string());
+                    eprintln!("{} Reference fasta for '{}' is missing.", style::cross(Stream::Stderr), genome);
                 }
 
                 let reference_genome = select_reference_genome(genome, None);
                 if reference_genome.is_err() {
-                    eprintln!("Reference genome for '{}' is missing.", genome.to_string());
+                    eprintln!("{} Reference genome for '{}' is missing.", style::cross(Stream::Stderr), genome);
+                }
+
+                if let Ok(fasta) = &reference_fasta {
+                    match check_mt_length(fasta, genome) {
+                        Ok(check) => match check.problem() {
+                            Some(problem) => eprintln!("{} {}: {}", style::cross(Stream::Stderr), genome, problem),
+                            None => println!(
+                                "{} {}: MT contig length is {} bp as expected",
+                                style::tick(Stream::Stdout),
+                                genome,
+                                check.expected
+                            ),
+                        },
+                        Err(e) => eprintln!("{} {}: {:#}", style::cross(Stream::Stderr), genome, e),
+                    }
+                }
+
+                if let (Ok(fasta), Ok(genome_file)) = (reference_fasta, reference_genome) {
+                    match compare_reference_files(&fasta, &genome_file) {
+                        Ok(comparison) if comparison.mismatches.is_empty() => {
+                            println!("{} {}: .fai and .genome agree", style::tick(Stream::Stdout), genome)
+                        }
+                        Ok(comparison) => {
+                            for mismatch in &comparison.mismatches {
+                                eprintln!("{} {}: {}", style::cross(Stream::Stderr), genome, mismatch);
+                            }
+                        }
+                        Err(e) => eprintln!("{} {}: {:#}", style::cross(Stream::Stderr), genome, e),
+                    }
                 }
             }
         }
-        Err(_) => eprintln!("Mity directory not found! Likely an issue with installation."),
+        Err(_) => eprintln!(
+            "{} Mity directory not found! Likely an issue with installation.",
+            style::cross(Stream::Stderr)
+        ),
+    }
+}
+
+fn reference_json(genome: &str) -> Value {
+    let fasta = select_reference_fasta(genome, None);
+    let genome_file = select_reference_genome(genome, None);
+    let comparison = match (&fasta, &genome_file) {
+        (Ok(fasta), Ok(genome_file)) => match compare_reference_files(fasta, genome_file) {
+            Ok(comparison) => comparison.to_json(),
+            Err(e) => json!({ "consistent": false, "error": format!("{:#}", e) }),
+        },
+        _ => Value::Null,
+    };
+    let mt_length = match &fasta {
+        Ok(fasta) => match check_mt_length(fasta, genome) {
+            Ok(check) => check.to_json(),
+            Err(e) => json!({ "ok": false, "error": format!("{:#}", e) }),
+        },
+        Err(_) => Value::Null,
+    };
+    json!({
+        "genome": genome,
+        "fasta": fasta.as_ref().ok().map(|path| path.display().to_string()),
+        "genome_file": genome_file.as_ref().ok().map(|path| path.display().to_string()),
+        "comparison": comparison,
+        "mt_length": mt_length,
+    })
+}
+
+/// Length of the mitochondrion in each build's reference: rCRS for hs37d5
+/// and hg38, the Yoruba sequence of hg19's chrM, and mm10's chrM.
+fn expected_mt_length(genome: &str) -> Option<u64> {
+    match genome {
+        "hs37d5" | "hg38" => Some(16569),
+        "hg19" => Some(16571),
+        "mm10" => Some(16299),
+        _ => None,
+    }
+}
+
+/// The builds whose mitochondrion is `length` bp, to suggest as `--reference`.
+pub fn builds_with_mt_length(length: u64) -> Vec<&'static str> {
+    GENOME_OPTIONS
+        .into_iter()
+        .filter(|genome| expected_mt_length(genome) == Some(length))
+        .collect()
+}
+
+/// Name and length of the MT contig of a reference: from the FASTA's
+/// `.fai`, or from the `.genome` file when the FASTA is not indexed.
+pub fn reference_mt_contig(fasta: &Path, genome: Option<&Path>) -> Result<Option<(String, u64)>> {
+    let fai = fai_path(fasta);
+    let contigs = match genome {
+        Some(genome) if !fai.exists() => read_contig_lengths(genome, 2)?,
+        _ => read_contig_lengths(&fai, 5)?,
+    };
+    Ok(contigs.into_iter().find(|(name, _)| mity_util::is_mt_contig(name)))
+}
+
+/// The MT contig of a reference FASTA's `.fai` against the length expected
+/// for its build. A truncated or wrong-species FASTA shows up here.
+pub struct MtLengthCheck {
+    pub fai: PathBuf,
+    /// Name and length of the MT contig, if the index has one.
+    pub contig: Option<(String, u64)>,
+    pub expected: u64,
+}
+
+impl MtLengthCheck {
+    pub fn problem(&self) -> Option<String> {
+        match &self.contig {
+            None => Some(format!("{} has no MT or chrM contig", self.fai.display())),
+            Some((name, length)) if *length != self.expected => Some(format!(
+                "MT contig {} is {} bp in {} but the build's mitochondrion is {} bp",
+                name,
+                length,
+                self.fai.display(),
+                self.expected
+            )),
+            Some(_) => None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "fai": self.fai.display().to_string(),
+            "contig": self.contig.as_ref().map(|(name, _)| name),
+            "length": self.contig.as_ref().map(|(_, length)| length),
+            "expected": self.expected,
+            "ok": self.problem().is_none(),
+        })
+    }
+}
+
+/// Check the length of the MT contig of `fasta`, found by its aliases, for
+/// the `genome` build. The FASTA is indexed first if it has no `.fai`.
+pub fn check_mt_length(fasta: &Path, genome: &str) -> Result<MtLengthCheck> {
+    let expected = expected_mt_length(genome)
+        .with_context(|| format!("No expected mitochondrial length for {}", genome))?;
+    let fai = fai_path(fasta);
+    if !fai.exists() {
+        let index = fasta::io::index(fasta)
+            .with_context(|| format!("Failed to index {}", fasta.display()))?;
+        let file = File::create(&fai).with_context(|| format!("Failed to create {}", fai.display()))?;
+        fasta::fai::io::Writer::new(file).write_index(&index)?;
+    }
+    let contig = read_contig_lengths(&fai, 5)?
+        .into_iter()
+        .find(|(name, _)| mity_util::is_mt_contig(name));
+    Ok(MtLengthCheck {
+        fai,
+        contig,
+        expected,
+    })
+}
+
+pub fn fai_path(fasta: &Path) -> PathBuf {
+    let mut fai = fasta.as_os_str().to_owned();
+    fai.push(".fai");
+    PathBuf::from(fai)
+}
+
+/// Contig lengths of a reference FASTA index and its `.genome` file, and
+/// where they disagree.
+pub struct ReferenceComparison {
+    pub fai: PathBuf,
+    pub genome: PathBuf,
+    /// Name, `.fai` length and `.genome` length of the MT contig.
+    pub mt_contig: Option<(String, u64, Option<u64>)>,
+    pub mismatches: Vec<String>,
+}
+
+impl ReferenceComparison {
+    fn to_json(&self) -> Value {
+        json!({
+            "fai": self.fai.display().to_string(),
+            "genome": self.genome.display().to_string(),
+            "consistent": self.mismatches.is_empty(),
+            "mt_contig": self.mt_contig.as_ref().map(|(name, fai_length, genome_length)| json!({
+                "name": name,
+                "fai_length": fai_length,
+                "genome_length": genome_length,
+            })),
+            "mismatches": self.mismatches,
+        })
+    }
+}
+
+/// Check that every contig of the FASTA's `.fai` is in the `.genome` file
+/// with the same length. The mity FASTAs only hold the mitochondrion, so
+/// nuclear contigs listed only in the `.genome` file are expected.
+pub fn compare_reference_files(fasta: &Path, genome: &Path) -> Result<ReferenceComparison> {
+    let fai = fai_path(fasta);
+    let fai_contigs = read_contig_lengths(&fai, 5)?;
+    let genome_contigs = read_contig_lengths(genome, 2)?;
+    let genome_length = |name: &str| {
+        genome_contigs
+            .iter()
+            .find(|(contig, _)| contig == name)
+            .map(|(_, length)| *length)
+    };
+
+    let mut mismatches = Vec::new();
+    let mut mt_contig = None;
+    for (name, length) in &fai_contigs {
+        let other = genome_length(name);
+        match other {
+            None => mismatches.push(format!("contig {} ({} bp in {}) is missing from {}", name, length, fai.display(), genome.display())),
+            Some(other) if other != *length => mismatches.push(format!(
+                "contig {} is {} bp in {} but {} bp in {}",
+                name,
+                length,
+                fai.display(),
+                other,
+                genome.display()
+            )),
+            Some(_) => {}
+        }
+        if mity_util::is_mt_contig(name) {
+            mt_contig = Some((name.clone(), *length, other));
+        }
+    }
+    if mt_contig.is_none() {
+        mismatches.push(format!("{} has no MT or chrM contig", fai.display()));
+    }
+
+    Ok(ReferenceComparison {
+        fai,
+        genome: genome.to_path_buf(),
+        mt_contig,
+        mismatches,
+    })
+}
+
+/// `(name, length)` from the first two tab-separated columns of a file with
+/// at least `columns` columns per line.
+fn read_contig_lengths(path: &Path, columns: usize) -> Result<Vec<(String, u64)>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut contigs = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let length = fields.get(1).and_then(|length| length.trim().parse().ok());
+        match length {
+            Some(length) if fields.len() >= columns => contigs.push((fields[0].to_string(), length)),
+            _ => anyhow::bail!(
+                "{}:{}: expected {} tab-separated columns starting with name and length",
+                path.display(),
+                index + 1,
+                columns
+            ),
+        }
     }
+    Ok(contigs)
 }
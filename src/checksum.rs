@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IntoInnerError, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Digest written next to each final output by `--checksums`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "md5" => Some(Algorithm::Md5),
+            "sha256" => Some(Algorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+static ALGORITHM: OnceLock<Algorithm> = OnceLock::new();
+static DIGESTS: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+
+/// Compute `algorithm` digests of every final output written in this run.
+pub fn set_algorithm(algorithm: Algorithm) {
+    let _ = ALGORITHM.set(algorithm);
+}
+
+pub fn algorithm() -> Option<Algorithm> {
+    ALGORITHM.get().copied()
+}
+
+/// The digest recorded for `path` in this run, as `(algorithm, hex digest)`.
+pub fn digest_of(path: &Path) -> Option<(&'static str, String)> {
+    let digest = DIGESTS.get()?.lock().unwrap().get(path)?.clone();
+    Some((algorithm()?.name(), digest))
+}
+
+/// A final output file that is hashed as it is written when `--checksums` is
+/// set. `finish` writes the `{file}.md5` or `{file}.sha256` sidecar.
+pub struct OutputFile {
+    path: PathBuf,
+    file: File,
+    hasher: Option<Hasher>,
+}
+
+impl OutputFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::create_for(path, path)
+    }
+
+    /// Write `tmp_path` but name the sidecar and digest after `path`, for
+    /// atomic writes that rename `tmp_path` to `path` once complete.
+    pub fn create_for(tmp_path: &Path, path: &Path) -> io::Result<Self> {
+        Ok(OutputFile {
+            path: path.to_path_buf(),
+            file: File::create(tmp_path)?,
+            hasher: algorithm().map(Hasher::new),
+        })
+    }
+
+    /// Flush the file and, when hashing, write the sidecar in the
+    /// `digest  filename` format of md5sum and sha256sum.
+    pub fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        let Some(hasher) = self.hasher else {
+            return Ok(());
+        };
+        let digest = hasher.finish();
+        let algorithm = algorithm().expect("hashing implies an algorithm");
+        let mut sidecar = self.path.as_os_str().to_owned();
+        sidecar.push(format!(".{}", algorithm.name()));
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        fs::write(&sidecar, format!("{}  {}\n", digest, file_name))
+            .with_context(|| format!("Failed to write {}", Path::new(&sidecar).display()))?;
+        DIGESTS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .insert(self.path, digest);
+        Ok(())
+    }
+}
+
+/// Flush a buffered `OutputFile` and finish it.
+pub fn finish_buffered(writer: BufWriter<OutputFile>) -> Result<()> {
+    writer.into_inner().map_err(IntoInnerError::into_error)?.finish()
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Incremental MD5 or SHA-256 of an output as it is written.
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// The digest in lowercase hex.
+    fn finish(self) -> String {
+        let digest = match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algorithm: Algorithm, chunks: &[&[u8]]) -> String {
+        let mut hasher = Hasher::new(algorithm);
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        hasher.finish()
+    }
+
+    /// 80 bytes, from the RFC 1321 test suite
+    const DIGITS: &[u8] = b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+    /// 56 bytes, from FIPS 180-4's examples: the length no longer fits in
+    /// the first block's padding
+    const FIPS_TWO_BLOCK: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+    #[test]
+    fn md5_matches_rfc_1321() {
+        assert_eq!(digest(Algorithm::Md5, &[b""]), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(digest(Algorithm::Md5, &[b"abc"]), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest(Algorithm::Md5, &[DIGITS]), "57edf4a22be3c955ac49da2e2107b67a");
+        assert_eq!(digest(Algorithm::Md5, &[FIPS_TWO_BLOCK]), "8215ef0796a20bcaaae116d3876c664a");
+    }
+
+    #[test]
+    fn sha256_matches_fips_180_4() {
+        assert_eq!(
+            digest(Algorithm::Sha256, &[b""]),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(Algorithm::Sha256, &[b"abc"]),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(Algorithm::Sha256, &[FIPS_TWO_BLOCK]),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn digest_does_not_depend_on_how_writes_are_split() {
+        for algorithm in [Algorithm::Md5, Algorithm::Sha256] {
+            let (head, tail) = DIGITS.split_at(61);
+            assert_eq!(digest(algorithm, &[head, b"", tail]), digest(algorithm, &[DIGITS]));
+        }
+    }
+}
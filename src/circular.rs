@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use noodles::bam;
+use noodles::core::{Position, Region};
+use noodles::fasta;
+use noodles::sam::alignment::io::Write as _;
+use noodles::sam::alignment::record::cigar::op::Kind;
+use noodles::sam::alignment::record::cigar::Op;
+use noodles::sam::alignment::RecordBuf;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// How far the second pass of `mity call --circular` rotates the
+/// mitochondrion, putting the 16569/1 junction in the middle of the contig.
+pub const SHIFT: usize = 8000;
+
+/// Calls within this many bases of the junction come from the second pass.
+pub const JUNCTION_WINDOW: usize = 500;
+
+/// Reads starting this far outside the junction window still reach into it.
+const READ_MARGIN: usize = 1000;
+
+/// Bases per line of the rotated FASTA.
+const LINE_WIDTH: usize = 60;
+
+pub const JUNCTION_HEADER: &str = "##INFO=<ID=MT_JUNCTION,Number=0,Type=Flag,Description=\"REF runs past the end of the circular MT contig and continues from position 1\">";
+
+/// The mitochondrial contig rotated left by `shift` bases: base `p` of the
+/// rotated contig is base `p + shift` of the original, wrapping at its end.
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    pub contig: String,
+    pub length: usize,
+    pub shift: usize,
+}
+
+impl Rotation {
+    pub fn new(contig: String, length: usize) -> Self {
+        Rotation {
+            contig,
+            length,
+            shift: SHIFT.min(length / 2),
+        }
+    }
+
+    /// The rotated position of a 1-based original position.
+    pub fn to_shifted(&self, pos: usize) -> usize {
+        (pos - 1 + self.length - self.shift) % self.length + 1
+    }
+
+    /// The original position of a 1-based rotated position.
+    pub fn to_original(&self, pos: usize) -> usize {
+        (pos - 1 + self.shift) % self.length + 1
+    }
+
+    /// Whether a 1-based original position is close enough to the junction
+    /// for the second pass to call it.
+    pub fn near_junction(&self, pos: usize) -> bool {
+        pos > self.length - JUNCTION_WINDOW || pos <= JUNCTION_WINDOW
+    }
+
+    /// The junction window in rotated coordinates, as a freebayes region.
+    pub fn shifted_region(&self) -> String {
+        let junction = self.length - self.shift;
+        format!("{}:{}-{}", self.contig, junction - JUNCTION_WINDOW, junction + JUNCTION_WINDOW)
+    }
+}
+
+/// Write the mitochondrial contig of `reference`, named `reference_contig`
+/// there, rotated and renamed to the contig of the inputs, to `output` with
+/// a `.fai` index.
+pub fn write_shifted_fasta(reference: &Path, reference_contig: &str, rotation: &Rotation, output: &Path) -> Result<()> {
+    let sequence = mity_util::read_fasta_contig(reference, reference_contig)?;
+    if sequence.len() != rotation.length {
+        anyhow::bail!(
+            "{} in {} is {} bp, not the {} bp of the inputs' {}",
+            reference_contig,
+            reference.display(),
+            sequence.len(),
+            rotation.length,
+            rotation.contig
+        );
+    }
+    let mut rotated = sequence[rotation.shift..].to_vec();
+    rotated.extend_from_slice(&sequence[..rotation.shift]);
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writeln!(
+        writer,
+        ">{} {} rotated by {} bp for mity call --circular",
+        rotation.contig, reference_contig, rotation.shift
+    )?;
+    for line in rotated.chunks(LINE_WIDTH) {
+        writer.write_all(line)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    let index = fasta::io::index(output).with_context(|| format!("Failed to index {}", output.display()))?;
+    let fai = crate::check::fai_path(output);
+    let file = File::create(&fai).with_context(|| format!("Failed to create {}", fai.display()))?;
+    fasta::fai::io::Writer::new(file).write_index(&index)?;
+    Ok(())
+}
+
+/// Copy the alignments of `input` around the junction to an indexed BAM at
+/// `output`, moved to the rotated contig. Returns the alignments copied.
+pub fn write_shifted_bam(input: &Path, rotation: &Rotation, output: &Path) -> Result<usize> {
+    let mut reader = mity_util::open_indexed_bam(input)?;
+    let header = reader.read_header()?;
+    let id = header
+        .reference_sequences()
+        .get_index_of(rotation.contig.as_bytes())
+        .with_context(|| format!("{} has no contig {}", input.display(), rotation.contig))?;
+
+    let reach = JUNCTION_WINDOW + READ_MARGIN;
+    let mut records = Vec::new();
+    for (start, end) in [(rotation.length - reach + 1, rotation.length), (1, reach)] {
+        let region = Region::new(rotation.contig.as_str(), Position::try_from(start)?..=Position::try_from(end)?);
+        for result in reader.query(&header, &region)? {
+            let record = RecordBuf::try_from_alignment_record(&header, &result?)?;
+            if record.flags().is_unmapped() || record.reference_sequence_id() != Some(id) {
+                continue;
+            }
+            if let Some(record) = shift_record(record, id, rotation) {
+                records.push(record);
+            }
+        }
+    }
+    // A read can start in one interval and end in the other only by
+    // spanning the whole contig, so none is copied twice
+    records.sort_by_key(|record| record.alignment_start());
+
+    let mut writer = bam::io::Writer::new(File::create(output)?);
+    writer.write_header(&header)?;
+    for record in &records {
+        writer.write_alignment_record(&header, record)?;
+    }
+    writer.try_finish()?;
+    mity_util::index_bam(output)?;
+    Ok(records.len())
+}
+
+/// Move an alignment to the rotated contig. A soft clip that would run off
+/// either end of the linear contig is turned into a match, as on the
+/// circular genome those bases continue across the junction, ungapped.
+/// Alignments that would run off the rotated contig are dropped.
+fn shift_record(mut record: RecordBuf, id: usize, rotation: &Rotation) -> Option<RecordBuf> {
+    let mut start = usize::from(record.alignment_start()?);
+    let ops = record.cigar_mut().as_mut();
+    if let Some(index) = junction_clip(ops, false) {
+        let len = ops[index].len();
+        if len >= start {
+            ops[index] = Op::new(Kind::Match, len);
+            start = start + rotation.length - len;
+        }
+    }
+    let end = start + reference_span(ops) - 1;
+    if let Some(index) = junction_clip(ops, true) {
+        let len = ops[index].len();
+        if end <= rotation.length && end + len > rotation.length {
+            ops[index] = Op::new(Kind::Match, len);
+        }
+    }
+    ops.dedup_by(|next, op| {
+        let merge = next.kind() == op.kind();
+        if merge {
+            *op = Op::new(op.kind(), op.len() + next.len());
+        }
+        merge
+    });
+
+    let shifted = rotation.to_shifted((start - 1) % rotation.length + 1);
+    if shifted + reference_span(ops) - 1 > rotation.length {
+        return None;
+    }
+    *record.alignment_start_mut() = Position::new(shifted);
+    if record.mate_reference_sequence_id() == Some(id) {
+        if let Some(mate) = record.mate_alignment_start() {
+            *record.mate_alignment_start_mut() = Position::new(rotation.to_shifted(usize::from(mate)));
+        }
+    }
+    Some(record)
+}
+
+/// The index of the soft clip at the first or `last` end of an alignment,
+/// inside any hard clip.
+fn junction_clip(ops: &[Op], last: bool) -> Option<usize> {
+    let aligned = |op: &Op| op.kind() != Kind::HardClip;
+    let index = if last {
+        ops.iter().rposition(aligned)?
+    } else {
+        ops.iter().position(aligned)?
+    };
+    (ops[index].kind() == Kind::SoftClip).then_some(index)
+}
+
+/// The reference bases an alignment covers.
+fn reference_span(ops: &[Op]) -> usize {
+    ops.iter()
+        .filter(|op| op.kind().consumes_reference())
+        .map(|op| op.len())
+        .sum()
+}
+
+/// Move a second-pass VCF record back to original coordinates, flagging it
+/// with INFO/MT_JUNCTION when its REF crosses the junction. Returns `None`
+/// for records away from the junction, which the first pass calls.
+pub fn unshift_record(line: &str, rotation: &Rotation) -> Result<Option<VcfRecord>> {
+    let mut record = VcfRecord::parse(line)?;
+    let pos = rotation.to_original(record.pos as usize);
+    if !rotation.near_junction(pos) {
+        return Ok(None);
+    }
+    record.pos = pos as u64;
+    if pos + record.reference.len() - 1 > rotation.length {
+        record.set_info("MT_JUNCTION", None);
+    }
+    Ok(Some(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation() -> Rotation {
+        Rotation::new("MT".to_string(), 16569)
+    }
+
+    #[test]
+    fn rotation_moves_the_junction_to_the_middle() {
+        let rotation = rotation();
+        assert_eq!(rotation.to_shifted(16569), 8569);
+        assert_eq!(rotation.to_shifted(1), 8570);
+        assert_eq!(rotation.to_shifted(8001), 1);
+        for pos in [1, 500, 8000, 8001, 16069, 16569] {
+            assert_eq!(rotation.to_original(rotation.to_shifted(pos)), pos);
+        }
+        assert_eq!(rotation.shifted_region(), "MT:8069-9069");
+    }
+
+    #[test]
+    fn a_deletion_across_the_origin_is_unshifted_and_flagged() {
+        // Deletes 16568-16569 and 1-3: REF is 16567-16569 then 1-3
+        let line = "MT\t8567\t.\tACGTTG\tA\t500\t.\tDP=40;RO=4;AO=36\tGT:DP:RO:AO\t1/1:40:4:36";
+        let record = unshift_record(line, &rotation()).unwrap().unwrap();
+        assert_eq!(record.pos, 16567);
+        assert_eq!(record.reference, "ACGTTG");
+        assert!(record.has_info("MT_JUNCTION"));
+        assert_eq!(record.info("DP"), Some("40"));
+    }
+
+    #[test]
+    fn only_records_near_the_junction_are_unshifted() {
+        let rotation = rotation();
+        // Ends at 16569 without crossing
+        let line = "MT\t8567\t.\tACG\tA\t500\t.\tDP=40\tGT\t1/1";
+        let record = unshift_record(line, &rotation).unwrap().unwrap();
+        assert_eq!(record.pos, 16567);
+        assert!(!record.has_info("MT_JUNCTION"));
+        // Position 31, after the junction
+        let record = unshift_record("MT\t8600\t.\tA\tG\t500\t.\tDP=40\tGT\t1/1", &rotation).unwrap().unwrap();
+        assert_eq!(record.pos, 31);
+        assert!(!record.has_info("MT_JUNCTION"));
+        // Position 16000 is the first pass's
+        assert!(unshift_record("MT\t8000\t.\tA\tG\t500\t.\tDP=40\tGT\t1/1", &rotation).unwrap().is_none());
+    }
+}
@@ -0,0 +1,324 @@
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Lines, Write};
+use std::path::PathBuf;
+
+use crate::annotate::{annotation_values, info_id};
+use crate::error::MityError;
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// One input VCF, read a record at a time.
+struct CohortInput {
+    path: PathBuf,
+    lines: Lines<Box<dyn BufRead>>,
+    /// Column of the first sample of this input in the matrix.
+    first_column: usize,
+    sample_count: usize,
+    next: Option<VcfRecord>,
+}
+
+impl CohortInput {
+    /// Move to the next MT record, checking the input is sorted.
+    fn advance(&mut self) -> Result<(), Box<dyn Error>> {
+        let previous = self.next.take().map(|record| record.pos);
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line)?;
+            if !is_mt(&record.chrom) {
+                continue;
+            }
+            if previous.is_some_and(|pos| record.pos < pos) {
+                return Err(format!(
+                    "{} is not sorted by position at {}:{}; run mity normalise first",
+                    self.path.display(),
+                    record.chrom,
+                    record.pos
+                )
+                .into());
+            }
+            self.next = Some(record);
+            break;
+        }
+        Ok(())
+    }
+}
+
+/// Combines the variants of many mity VCFs into one matrix with a VAF
+/// column per sample, so recurrent artefacts and shared variants stand out.
+///
+/// Inputs are merged by position in one streaming pass, so they must be
+/// sorted, as mity normalise output is. `MT` and `chrM` are treated as the
+/// same contig and written with the naming of the first input.
+pub struct CohortReport {
+    debug: bool,
+    files: Vec<PathBuf>,
+    vcf_file_list: bool,
+    output_dir: PathBuf,
+    prefix: String,
+
+    report_path: PathBuf,
+}
+
+impl CohortReport {
+    pub fn new(
+        debug: bool,
+        files: Vec<PathBuf>,
+        vcf_file_list: bool,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| "cohort".to_string());
+        CohortReport {
+            debug,
+            files,
+            vcf_file_list,
+            report_path: output_dir.join(format!("{}.mity.cohort.tsv", prefix)),
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        let vcfs = self.input_vcfs()?;
+        debug!(
+            "Combining {} VCFs into {} with prefix {}",
+            vcfs.len(),
+            self.output_dir.display(),
+            self.prefix
+        );
+
+        let mut inputs = Vec::with_capacity(vcfs.len());
+        let mut samples: Vec<String> = Vec::new();
+        let mut info_fields: Vec<String> = Vec::new();
+        let mut contig: Option<String> = None;
+        for path in vcfs {
+            let mut lines = mity_util::open_vcf(&path)?.lines();
+            let mut names = None;
+            for line in lines.by_ref() {
+                let line = line?;
+                if let Some(id) = info_id(&line) {
+                    if !info_fields.iter().any(|field| field == id) {
+                        info_fields.push(id.to_string());
+                    }
+                }
+                if line.starts_with("#CHROM") {
+                    names = Some(line.split('\t').skip(9).map(String::from).collect::<Vec<_>>());
+                    break;
+                }
+            }
+            let names = names.ok_or_else(|| format!("{} has no #CHROM header line", path.display()))?;
+            if names.is_empty() {
+                warn!("{} has no samples; its variants are only annotated", path.display());
+            }
+
+            let first_column = samples.len();
+            for name in &names {
+                // Sample names are often reused across runs, e.g. SAMPLE
+                let column = if samples.contains(name) {
+                    format!("{}_{}", mity_util::make_prefix(&path), name)
+                } else {
+                    name.clone()
+                };
+                samples.push(column);
+            }
+            let mut input = CohortInput {
+                path,
+                lines,
+                first_column,
+                sample_count: names.len(),
+                next: None,
+            };
+            input.advance()?;
+            if contig.is_none() {
+                contig = input.next.as_ref().map(|record| record.chrom.clone());
+            }
+            inputs.push(input);
+        }
+        let contig = contig.unwrap_or_else(|| "MT".to_string());
+
+        let mut writer = BufWriter::new(File::create(&self.report_path)?);
+        let mut columns: Vec<String> = ["CHROM", "POS", "REF", "ALT", "RECURRENCE", "FILTERED"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        columns.extend(samples.iter().map(|sample| format!("VAF_{}", sample)));
+        columns.extend(info_fields.iter().cloned());
+        writeln!(writer, "{}", columns.join("\t"))?;
+
+        let mut variants = 0;
+        let mut recurrent = 0;
+        while let Some(pos) = inputs.iter().filter_map(|input| input.next.as_ref()).map(|record| record.pos).min() {
+            // Records at one position can come in any allele order
+            let mut at_pos: BTreeMap<(String, String), Vec<(usize, VcfRecord)>> = BTreeMap::new();
+            for (index, input) in inputs.iter_mut().enumerate() {
+                while input.next.as_ref().is_some_and(|record| record.pos == pos) {
+                    let record = input.next.clone().expect("checked above");
+                    at_pos
+                        .entry((record.reference.clone(), record.alt.clone()))
+                        .or_default()
+                        .push((index, record));
+                    input.advance()?;
+                }
+            }
+
+            for ((reference, alt), records) in at_pos {
+                let mut vafs = vec![".".to_string(); samples.len()];
+                let (mut carriers, mut filtered) = (0, 0);
+                for (index, record) in &records {
+                    let input = &inputs[*index];
+                    for sample in 0..input.sample_count {
+                        let Some(vaf) = record.sample_vaf(sample) else {
+                            continue;
+                        };
+                        vafs[input.first_column + sample] = format!("{:.4}", vaf);
+                        if vaf > 0.0 {
+                            carriers += 1;
+                            if record.filter != "PASS" && record.filter != "." {
+                                filtered += 1;
+                            }
+                        }
+                    }
+                }
+
+                // Annotations are per variant, so the first input that has them wins
+                let annotations = records
+                    .iter()
+                    .map(|(_, record)| annotation_values(record, &info_fields))
+                    .find(|values| values.iter().any(|value| !value.is_empty()))
+                    .unwrap_or_else(|| vec![String::new(); info_fields.len()]);
+
+                let mut row = vec![
+                    contig.clone(),
+                    pos.to_string(),
+                    reference,
+                    alt,
+                    carriers.to_string(),
+                    filtered.to_string(),
+                ];
+                row.extend(vafs);
+                row.extend(annotations);
+                writeln!(writer, "{}", row.join("\t"))?;
+
+                variants += 1;
+                if carriers > 1 {
+                    recurrent += 1;
+                }
+            }
+        }
+        writer.flush()?;
+
+        info!(
+            "{} variants across {} samples, {} in more than one sample",
+            variants,
+            samples.len(),
+            recurrent
+        );
+        info!("Wrote {}", self.report_path.display());
+        Ok(())
+    }
+
+    /// The input VCFs: the files given, the files in any directory given, or
+    /// the files listed in the first input with `--vcf-file-list`.
+    fn input_vcfs(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let files = if self.vcf_file_list {
+            mity_util::read_file_list(&self.files[0])?
+        } else {
+            self.files.clone()
+        };
+
+        let mut vcfs = Vec::new();
+        for file in files {
+            if file.is_dir() {
+                let mut found: Vec<PathBuf> = fs::read_dir(&file)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<_, _>>()?;
+                found.retain(|path| {
+                    let name = path.to_string_lossy();
+                    name.ends_with(".vcf.gz") || name.ends_with(".vcf")
+                });
+                found.sort();
+                if found.is_empty() {
+                    return Err(format!("No VCFs in {}", file.display()).into());
+                }
+                vcfs.extend(found);
+            } else if file.exists() {
+                vcfs.push(file);
+            } else {
+                return Err(MityError::MissingFile(file.clone()).into());
+            }
+        }
+
+        let mut seen = HashSet::new();
+        vcfs.retain(|path| seen.insert(path.clone()));
+        if vcfs.is_empty() {
+            return Err("No input VCFs".into());
+        }
+        Ok(vcfs)
+    }
+}
+
+fn is_mt(contig: &str) -> bool {
+    mity_util::is_mt_contig(contig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "##fileformat=VCFv4.2\n\
+        ##INFO=<ID=GENE,Number=1,Type=String,Description=\"Gene\">\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tSAMPLE\n";
+
+    #[test]
+    fn variants_of_every_vcf_in_a_directory_share_a_row() {
+        let temp = tempfile::tempdir().unwrap();
+        let inputs = temp.path().join("vcfs");
+        fs::create_dir(&inputs).unwrap();
+        fs::write(
+            inputs.join("a.vcf"),
+            format!("{HEADER}chrM\t73\t.\tA\tG\t.\tPASS\t.\tVAF\t1\nchrM\t310\t.\tT\tTC\t.\tSBA\t.\tVAF\t0.3\n"),
+        )
+        .unwrap();
+        fs::write(
+            inputs.join("b.vcf"),
+            format!(
+                "{HEADER}MT\t73\t.\tA\tG\t.\tPASS\tGENE=MT-DLOOP\tVAF\t0.98\n\
+                 1\t100\t.\tA\tG\t.\tPASS\t.\tVAF\t1\n\
+                 MT\t310\t.\tT\tC\t.\tPASS\t.\tVAF\t0.2\n"
+            ),
+        )
+        .unwrap();
+        fs::write(inputs.join("notes.txt"), "not a VCF\n").unwrap();
+
+        CohortReport::new(false, vec![inputs], false, temp.path().to_path_buf(), None).run().unwrap();
+        let report = fs::read_to_string(temp.path().join("cohort.mity.cohort.tsv")).unwrap();
+        // The repeated sample name is told apart by its file; chrM comes from the first input
+        assert_eq!(
+            report.lines().collect::<Vec<_>>(),
+            [
+                "CHROM\tPOS\tREF\tALT\tRECURRENCE\tFILTERED\tVAF_SAMPLE\tVAF_b_SAMPLE\tGENE",
+                "chrM\t73\tA\tG\t2\t0\t1.0000\t0.9800\tMT-DLOOP",
+                "chrM\t310\tT\tC\t1\t0\t.\t0.2000\t",
+                "chrM\t310\tT\tTC\t1\t1\t0.3000\t.\t",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unsorted_input_is_refused() {
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("unsorted.vcf");
+        fs::write(&vcf, format!("{HEADER}MT\t310\t.\tT\tC\t.\tPASS\t.\tVAF\t1\nMT\t73\t.\tA\tG\t.\tPASS\t.\tVAF\t1\n")).unwrap();
+        let err = CohortReport::new(false, vec![vcf], false, temp.path().to_path_buf(), None).run().unwrap_err();
+        assert!(err.to_string().contains("is not sorted by position at MT:73"), "{}", err);
+    }
+}
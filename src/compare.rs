@@ -0,0 +1,195 @@
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Number of largest shifts listed in the summary.
+const TOP_SHIFTS: usize = 5;
+
+/// A variant key as `(chrom, pos, ref, alt)`, ordered by position.
+type VariantKey = (String, u64, String, String);
+
+/// Compares heteroplasmy levels of the variants in two VCFs.
+pub struct Compare {
+    debug: bool,
+    vcfs: [PathBuf; 2],
+    labels: [String; 2],
+    samples: [Option<String>; 2],
+    min_delta: f64,
+    output_dir: PathBuf,
+    prefix: Option<String>,
+}
+
+impl Compare {
+    /// `samples` selects the sample to compare from each VCF, which is
+    /// required when a VCF has more than one sample.
+    pub fn new(
+        debug: bool,
+        vcfs: [PathBuf; 2],
+        labels: Option<[String; 2]>,
+        samples: [Option<String>; 2],
+        min_delta: f64,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+    ) -> Self {
+        let labels = labels.unwrap_or_else(|| {
+            [
+                mity_util::make_prefix(&vcfs[0]),
+                mity_util::make_prefix(&vcfs[1]),
+            ]
+        });
+        Compare {
+            debug,
+            vcfs,
+            labels,
+            samples,
+            min_delta,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+
+        let a = read_vafs(&self.vcfs[0], self.samples[0].as_deref())?;
+        let b = read_vafs(&self.vcfs[1], self.samples[1].as_deref())?;
+
+        let mut keys: Vec<&VariantKey> = a.keys().chain(b.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        let prefix = self
+            .prefix
+            .clone()
+            .unwrap_or_else(|| format!("{}_vs_{}", self.labels[0], self.labels[1]));
+        let tsv_path = self.output_dir.join(format!("{}.mity.compare.tsv", prefix));
+        let mut writer = BufWriter::new(File::create(&tsv_path)?);
+        writeln!(
+            writer,
+            "CHROM\tPOS\tREF\tALT\tVAF_{}\tVAF_{}\tDELTA\tSTATUS",
+            self.labels[0], self.labels[1]
+        )?;
+
+        let (mut shared, mut private_a, mut private_b) = (0, 0, 0);
+        let mut shifts: Vec<(f64, &VariantKey)> = Vec::new();
+        for key in keys {
+            let (vaf_a, vaf_b) = (a.get(key).copied(), b.get(key).copied());
+            let status = match (vaf_a, vaf_b) {
+                (Some(_), Some(_)) => {
+                    shared += 1;
+                    "shared".to_string()
+                }
+                (Some(_), None) => {
+                    private_a += 1;
+                    format!("private_{}", self.labels[0])
+                }
+                _ => {
+                    private_b += 1;
+                    format!("private_{}", self.labels[1])
+                }
+            };
+            // Absent variants count as a VAF of zero
+            let delta = vaf_b.unwrap_or(0.0) - vaf_a.unwrap_or(0.0);
+            shifts.push((delta, key));
+            if delta.abs() < self.min_delta {
+                continue;
+            }
+
+            let format_vaf = |vaf: Option<f64>| vaf.map_or(".".to_string(), |v| format!("{:.4}", v));
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+                key.0,
+                key.1,
+                key.2,
+                key.3,
+                format_vaf(vaf_a),
+                format_vaf(vaf_b),
+                delta,
+                status
+            )?;
+        }
+        writer.flush()?;
+
+        println!("{} vs {}", self.labels[0], self.labels[1]);
+        println!("  shared: {}", shared);
+        println!("  private to {}: {}", self.labels[0], private_a);
+        println!("  private to {}: {}", self.labels[1], private_b);
+        shifts.sort_by(|x, y| y.0.abs().total_cmp(&x.0.abs()));
+        if !shifts.is_empty() {
+            println!("  largest shifts:");
+        }
+        for (delta, key) in shifts.iter().take(TOP_SHIFTS) {
+            println!("    {}:{} {}>{}  {:+.4}", key.0, key.1, key.2, key.3, delta);
+        }
+        info!("Wrote {}", tsv_path.display());
+
+        Ok(())
+    }
+}
+
+/// Index of the sample to use from a `#CHROM` line: the named one, or the
+/// only one when no name is given.
+pub fn select_sample(vcf: &Path, chrom_line: &str, sample: Option<&str>) -> Result<usize, Box<dyn Error>> {
+    let samples: Vec<&str> = chrom_line.split('\t').skip(9).collect();
+    match (sample, samples.len()) {
+        (Some(name), _) => samples.iter().position(|s| *s == name).ok_or_else(|| {
+            format!(
+                "Sample {} not in {} (samples: {})",
+                name,
+                vcf.display(),
+                samples.join(", ")
+            )
+            .into()
+        }),
+        (None, 1) => Ok(0),
+        (None, 0) => Err(format!("{} has no samples", vcf.display()).into()),
+        (None, _) => Err(format!(
+            "{} has several samples ({}); choose one with --sample",
+            vcf.display(),
+            samples.join(", ")
+        )
+        .into()),
+    }
+}
+
+/// VAF of every variant the selected sample carries (VAF above zero).
+fn read_vafs(vcf: &Path, sample: Option<&str>) -> Result<BTreeMap<VariantKey, f64>, Box<dyn Error>> {
+    let reader = mity_util::open_vcf(vcf)?;
+    let mut index: Option<usize> = None;
+    let mut vafs = BTreeMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with("#CHROM") {
+            index = Some(select_sample(vcf, &line, sample)?);
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        let record = VcfRecord::parse(&line)?;
+        let Some(index) = index else {
+            return Err(format!("{} has no #CHROM header line", vcf.display()).into());
+        };
+        match record.sample_vaf(index) {
+            Some(vaf) if vaf > 0.0 => {
+                vafs.insert(
+                    (record.chrom, record.pos, record.reference, record.alt),
+                    vaf,
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(vafs)
+}
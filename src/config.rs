@@ -0,0 +1,407 @@
+use anyhow::{bail, Context, Result};
+use clap::{Arg, ArgAction, Command};
+use log::debug;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file read from the working directory.
+pub const PROJECT_CONFIG: &str = "mity.toml";
+/// Environment variable naming an extra config file, read last.
+pub const CONFIG_ENV_VAR: &str = "MITY_CONFIG";
+/// Section holding the options that apply to every subcommand.
+const GLOBAL_SECTION: &str = "mity";
+/// Subcommands with nothing to configure.
+const SKIPPED_COMMANDS: [&str; 2] = ["config", "help"];
+
+/// Option values from one config file, by section then key.
+struct ConfigFile {
+    path: PathBuf,
+    sections: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Defaults read from config files, which replace the built-in defaults of
+/// the command line. Options given on the command line still win.
+///
+/// Files are read in order, later ones overriding earlier ones: the user
+/// config (`$XDG_CONFIG_HOME/mity/config.toml`, or `~/.config/mity/config.toml`),
+/// then `mity.toml` in the working directory, then the file named by
+/// `MITY_CONFIG`.
+///
+/// Sections are subcommand names, plus `[mity]` for the global options, and
+/// keys are the long option names with `_` for `-`, e.g.
+///
+/// ```toml
+/// [call]
+/// min_alternate_fraction = 0.005
+/// ```
+pub struct Config {
+    files: Vec<ConfigFile>,
+}
+
+impl Config {
+    pub fn discover() -> Result<Self> {
+        let mut paths: Vec<PathBuf> = user_config_path().into_iter().collect();
+        paths.push(PathBuf::from(PROJECT_CONFIG));
+        paths.retain(|path| path.is_file());
+        if let Some(path) = env::var_os(CONFIG_ENV_VAR).map(PathBuf::from) {
+            if !path.is_file() {
+                bail!("{} is set to {}, which does not exist", CONFIG_ENV_VAR, path.display());
+            }
+            paths.push(path);
+        }
+        Config::read(&paths)
+    }
+
+    /// Read the config files at `paths`, later ones overriding earlier ones.
+    pub fn read(paths: &[PathBuf]) -> Result<Self> {
+        let files = paths.iter().map(|path| ConfigFile::read(path)).collect::<Result<Vec<_>>>()?;
+        for file in &files {
+            debug!("Read config from {}", file.path.display());
+        }
+        Ok(Config { files })
+    }
+
+    /// The ids of the options of `section` that the config sets.
+    pub fn configured(&self, cli: &Command, section: &str) -> Result<Vec<String>> {
+        self.values()
+            .filter(|(file_section, ..)| *file_section == section)
+            .map(|(section, key, _, path)| {
+                find_option(cli, section, key).with_context(|| format!("In {}", path.display()))
+            })
+            .collect()
+    }
+
+    /// Replace the defaults of `cli` with the configured values.
+    pub fn apply(&self, mut cli: Command) -> Result<Command> {
+        for (section, key, value, path) in self.values() {
+            let id = find_option(&cli, section, key)
+                .with_context(|| format!("In {}", path.display()))?;
+            // clap keeps defaults as 'static strings; this runs once per process
+            let value: &'static str = Box::leak(value.to_string().into_boxed_str());
+            cli = if section == GLOBAL_SECTION {
+                cli.mut_arg(id, |arg| arg.default_value(value))
+            } else {
+                cli.mut_subcommand(section, |command| command.mut_arg(id, |arg| arg.default_value(value)))
+            };
+        }
+        Ok(cli)
+    }
+
+    /// The effective configuration of `cli` (built-in defaults) as TOML,
+    /// noting which file set each overridden value.
+    pub fn show(&self, cli: &Command) -> Result<String> {
+        let mut set: BTreeMap<(&str, String), (&str, &Path)> = BTreeMap::new();
+        for (section, key, value, path) in self.values() {
+            let id = find_option(cli, section, key)
+                .with_context(|| format!("In {}", path.display()))?;
+            set.insert((section, id), (value, path));
+        }
+
+        let mut text = String::new();
+        if self.files.is_empty() {
+            writeln!(text, "# No config files found; showing the built-in defaults")?;
+        }
+        for file in &self.files {
+            writeln!(text, "# Read {}", file.path.display())?;
+        }
+        for (section, args) in configurable_sections(cli) {
+            writeln!(text, "\n[{}]", section)?;
+            for arg in args {
+                let key = option_key(arg);
+                match set.get(&(section, arg.get_id().to_string())) {
+                    Some((value, path)) => {
+                        writeln!(text, "{} = {}  # from {}", key, toml_value(value), path.display())?
+                    }
+                    None => match default_value(arg) {
+                        Some(value) => writeln!(text, "{} = {}", key, toml_value(&value))?,
+                        None => writeln!(text, "# {} is not set", key)?,
+                    },
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    /// Every configured `(section, key, value, file)`, in precedence order.
+    fn values(&self) -> impl Iterator<Item = (&str, &str, &str, &Path)> {
+        self.files.iter().flat_map(|file| {
+            file.sections.iter().flat_map(move |(section, values)| {
+                values
+                    .iter()
+                    .map(move |(key, value)| (section.as_str(), key.as_str(), value.as_str(), file.path.as_path()))
+            })
+        })
+    }
+}
+
+/// Write a config file with every configurable option of `cli` commented
+/// out at its default, under its help text.
+pub fn write_template(cli: &Command, output: &Path, force: bool) -> Result<()> {
+    if output.exists() && !force {
+        bail!("{} already exists; pass --force to overwrite it", output.display());
+    }
+
+    let mut text = String::from(
+        "# mity configuration.\n\
+         #\n\
+         # Values here replace the built-in defaults; options on the command line\n\
+         # still win. Uncomment and edit the options to change. `mity config show`\n\
+         # prints the configuration in effect.\n",
+    );
+    for (section, args) in configurable_sections(cli) {
+        writeln!(text, "\n[{}]", section)?;
+        for arg in args {
+            if let Some(help) = arg.get_help() {
+                for line in help.to_string().lines() {
+                    writeln!(text, "# {}", line)?;
+                }
+            }
+            let value = default_value(arg).map_or_else(|| "\"\"".to_string(), |value| toml_value(&value));
+            writeln!(text, "# {} = {}", option_key(arg), value)?;
+        }
+    }
+
+    if let Some(parent) = output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(output, text).with_context(|| format!("Failed to write {}", output.display()))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("mity").join("config.toml"))
+}
+
+/// The options that can be configured, by section: named, optional
+/// options that take a value, and on/off flags.
+fn configurable_sections(cli: &Command) -> Vec<(&str, Vec<&Arg>)> {
+    let mut sections = vec![(GLOBAL_SECTION, configurable_args(cli))];
+    for command in cli.get_subcommands() {
+        if !SKIPPED_COMMANDS.contains(&command.get_name()) {
+            sections.push((command.get_name(), configurable_args(command)));
+        }
+    }
+    sections.retain(|(_, args)| !args.is_empty());
+    sections
+}
+
+fn configurable_args(command: &Command) -> Vec<&Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_required_set() && arg.get_long().is_some())
+        .filter(|arg| arg.get_action().takes_values() || matches!(arg.get_action(), ArgAction::SetTrue))
+        .collect()
+}
+
+/// The id of the option a config key names, checked against `cli`.
+fn find_option(cli: &Command, section: &str, key: &str) -> Result<String> {
+    let command = if section == GLOBAL_SECTION {
+        Some(cli)
+    } else {
+        cli.get_subcommands()
+            .find(|command| command.get_name() == section && !SKIPPED_COMMANDS.contains(&section))
+    };
+    let Some(command) = command else {
+        bail!("Unknown section [{}]", section);
+    };
+    configurable_args(command)
+        .into_iter()
+        .find(|arg| option_key(arg) == key)
+        .map(|arg| arg.get_id().to_string())
+        .with_context(|| format!("Unknown option {} in [{}]", key, section))
+}
+
+/// Config key of an option: its long name with `_` for `-`.
+fn option_key(arg: &Arg) -> String {
+    arg.get_long().unwrap_or_default().replace('-', "_")
+}
+
+fn default_value(arg: &Arg) -> Option<String> {
+    // clap only gives flags their `false` default when the command is built
+    if matches!(arg.get_action(), ArgAction::SetTrue) && arg.get_default_values().is_empty() {
+        return Some("false".to_string());
+    }
+    let values: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// A value as TOML: numbers and booleans bare, everything else quoted.
+fn toml_value(value: &str) -> String {
+    let is_number = value.parse::<f64>().is_ok()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+    if is_number || value == "true" || value == "false" {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl ConfigFile {
+    /// Read the subset of TOML that config files need: `[section]` tables of
+    /// `key = value` pairs whose values are strings, numbers or booleans.
+    fn read(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut sections: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        let mut section: Option<String> = None;
+        for (number, line) in contents.lines().enumerate() {
+            let context = || format!("{} line {}", path.display(), number + 1);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = Some(name.trim().to_string());
+                sections.entry(name.trim().to_string()).or_default();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("{}: expected [section] or key = value", context());
+            };
+            let Some(section) = &section else {
+                bail!("{}: {} is not in a [section]", context(), key.trim());
+            };
+            let value = parse_value(value.trim()).with_context(context)?;
+            sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value);
+        }
+        Ok(ConfigFile {
+            path: path.to_path_buf(),
+            sections,
+        })
+    }
+}
+
+/// A line without its `#` comment, leaving `#` inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<String> {
+    if let Some(inner) = value.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        let mut parsed = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                parsed.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => parsed.push('\n'),
+                Some('t') => parsed.push('\t'),
+                Some(c @ ('"' | '\\')) => parsed.push(c),
+                other => bail!("Unsupported escape \\{}", other.map(String::from).unwrap_or_default()),
+            }
+        }
+        return Ok(parsed);
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        return Ok(inner.to_string());
+    }
+    if value == "true" || value == "false" || value.replace('_', "").parse::<f64>().is_ok() {
+        return Ok(value.replace('_', ""));
+    }
+    bail!("Unsupported value {}; use a string, number or boolean", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli() -> Command {
+        Command::new("mity")
+            .arg(Arg::new("debug").long("debug").action(ArgAction::SetTrue))
+            .subcommand(
+                Command::new("call")
+                    .arg(Arg::new("min_alternate_fraction").long("min-alternate-fraction").default_value("0.01"))
+                    .arg(Arg::new("prefix").long("prefix"))
+                    .arg(Arg::new("files").required(true)),
+            )
+    }
+
+    #[test]
+    fn later_files_override_earlier_ones_and_the_command_line_wins() {
+        let temp = tempfile::tempdir().unwrap();
+        let user = temp.path().join("config.toml");
+        fs::write(&user, "[call]\nmin_alternate_fraction = 0.005\nprefix = \"user # not a comment\"\n").unwrap();
+        let project = temp.path().join("mity.toml");
+        fs::write(&project, "# project settings\n[mity]\ndebug = true\n\n[call]\nprefix = 'project'  # wins\n").unwrap();
+        let config = Config::read(&[user.clone(), project.clone()]).unwrap();
+        assert_eq!(config.configured(&cli(), "call").unwrap(), ["min_alternate_fraction", "prefix", "prefix"]);
+
+        let matches = config.apply(cli()).unwrap().get_matches_from(["mity", "call", "a.bam"]);
+        assert!(matches.get_flag("debug"));
+        let call = matches.subcommand_matches("call").unwrap();
+        assert_eq!(call.get_one::<String>("min_alternate_fraction").unwrap(), "0.005");
+        assert_eq!(call.get_one::<String>("prefix").unwrap(), "project");
+
+        let matches = config
+            .apply(cli())
+            .unwrap()
+            .get_matches_from(["mity", "call", "--prefix", "given", "a.bam"]);
+        let call = matches.subcommand_matches("call").unwrap();
+        assert_eq!(call.get_one::<String>("prefix").unwrap(), "given");
+
+        let shown = config.show(&cli()).unwrap();
+        assert!(shown.contains(&format!("min_alternate_fraction = 0.005  # from {}", user.display())), "{}", shown);
+        assert!(shown.contains(&format!("prefix = \"project\"  # from {}", project.display())), "{}", shown);
+    }
+
+    #[test]
+    fn unknown_options_and_malformed_lines_name_the_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("mity.toml");
+        fs::write(&path, "[call]\nfiles = \"a.bam\"\n").unwrap();
+        let err = Config::read(std::slice::from_ref(&path)).unwrap().apply(cli()).err().unwrap();
+        assert_eq!(format!("{:#}", err), format!("In {}: Unknown option files in [call]", path.display()));
+
+        fs::write(&path, "[call]\nprefix = [1, 2]\n").unwrap();
+        let err = Config::read(std::slice::from_ref(&path)).err().unwrap();
+        assert!(format!("{:#}", err).contains("line 2: Unsupported value [1, 2]"), "{:#}", err);
+        fs::write(&path, "prefix = \"x\"\n").unwrap();
+        let err = Config::read(&[path]).err().unwrap();
+        assert!(format!("{:#}", err).contains("prefix is not in a [section]"), "{:#}", err);
+    }
+
+    #[test]
+    fn the_template_lists_every_option_commented_out() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("nested/mity.toml");
+        write_template(&cli(), &path, false).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("[mity]\n# debug = false\n"), "{}", text);
+        assert!(text.contains("[call]\n# min_alternate_fraction = 0.01\n# prefix = \"\"\n"), "{}", text);
+        assert!(write_template(&cli(), &path, false).is_err());
+        write_template(&cli(), &path, true).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+use log::{debug, info, warn};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::compare::select_sample;
+use crate::error::MityError;
+use crate::mity_util;
+use crate::plot::read_bedgraph;
+use crate::vcf_record::VcfRecord;
+
+/// Bases per line of the FASTA output.
+const LINE_WIDTH: usize = 70;
+
+/// A qualifying variant of the sample.
+struct ConsensusVariant {
+    pos: u64,
+    reference: String,
+    alt: String,
+    vaf: f64,
+}
+
+/// Builds a sample's mitochondrial sequence by applying its variants to the
+/// reference.
+///
+/// Variants at or above `min_vaf` are applied, substitutions and indels
+/// alike. A variant overlapping one already applied keeps the reference.
+/// With `iupac`, SNVs between `1 - min_vaf` and `min_vaf` get the ambiguity
+/// code of both alleles instead. With a depth bedgraph, bases below
+/// `min_depth` are masked as `N`.
+pub struct Consensus {
+    debug: bool,
+    vcf: PathBuf,
+    reference_fasta: PathBuf,
+    sample: Option<String>,
+    min_vaf: f64,
+    iupac: bool,
+    depth_bedgraph: Option<PathBuf>,
+    min_depth: f64,
+    output_dir: PathBuf,
+    prefix: String,
+}
+
+impl Consensus {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        reference_fasta: PathBuf,
+        sample: Option<String>,
+        min_vaf: f64,
+        iupac: bool,
+        depth_bedgraph: Option<PathBuf>,
+        min_depth: f64,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&vcf));
+        Consensus {
+            debug,
+            vcf,
+            reference_fasta,
+            sample,
+            min_vaf,
+            iupac,
+            depth_bedgraph,
+            min_depth,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+
+        let (sample, contig, mut variants) = self.read_variants()?;
+        variants.sort_by_key(|variant| variant.pos);
+        let reference = read_mt_sequence(&self.reference_fasta, &contig)?;
+        let depth = match &self.depth_bedgraph {
+            Some(path) => Some(per_base_depth(path, reference.len())?),
+            None => None,
+        };
+        let masked = |index: usize| {
+            depth
+                .as_ref()
+                .is_some_and(|depth| depth.get(index).copied().unwrap_or(0.0) < self.min_depth)
+        };
+
+        let mut sequence: Vec<u8> = Vec::with_capacity(reference.len());
+        let mut cursor = 0;
+        let (mut applied, mut ambiguous, mut skipped) = (0, 0, 0);
+        for variant in &variants {
+            let start = (variant.pos - 1) as usize;
+            let end = start + variant.reference.len();
+            if start < cursor {
+                debug!("{}{}>{} overlaps an applied variant; keeping the reference", variant.pos, variant.reference, variant.alt);
+                skipped += 1;
+                continue;
+            }
+            if reference.get(start..end).is_none_or(|bases| !bases.eq_ignore_ascii_case(variant.reference.as_bytes())) {
+                warn!(
+                    "{}{}>{} does not match the reference {}; skipping",
+                    variant.pos,
+                    variant.reference,
+                    variant.alt,
+                    self.reference_fasta.display()
+                );
+                skipped += 1;
+                continue;
+            }
+
+            let replacement = if variant.vaf >= self.min_vaf {
+                applied += 1;
+                variant.alt.as_bytes().to_vec()
+            } else {
+                match iupac_code(variant.reference.as_bytes()[0], variant.alt.as_bytes()[0]) {
+                    Some(code) if variant.reference.len() == 1 && variant.alt.len() == 1 => {
+                        ambiguous += 1;
+                        vec![code]
+                    }
+                    _ => continue,
+                }
+            };
+            sequence.extend((cursor..start).map(|index| if masked(index) { b'N' } else { reference[index] }));
+            if (start..end.max(start + 1)).any(masked) {
+                sequence.extend(std::iter::repeat_n(b'N', replacement.len()));
+            } else {
+                sequence.extend(replacement);
+            }
+            cursor = end;
+        }
+        sequence.extend((cursor..reference.len()).map(|index| if masked(index) { b'N' } else { reference[index] }));
+        let masked_count = sequence.iter().filter(|base| **base == b'N').count();
+
+        let fasta_path = self
+            .output_dir
+            .join(format!("{}.{}.MT.fa", self.prefix, sample));
+        let mut writer = BufWriter::new(File::create(&fasta_path)?);
+        let mut description = format!(
+            "{} mity consensus of {} from {}; reference {}; {} variants at VAF >= {}",
+            sample,
+            contig,
+            self.vcf.display(),
+            self.reference_fasta.display(),
+            applied,
+            self.min_vaf
+        );
+        if self.iupac {
+            description.push_str(&format!("; {} IUPAC ambiguity codes", ambiguous));
+        }
+        if depth.is_some() {
+            description.push_str(&format!("; {} bases N below depth {}", masked_count, self.min_depth));
+        }
+        writeln!(writer, ">{}", description)?;
+        for line in sequence.chunks(LINE_WIDTH) {
+            writer.write_all(line)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+
+        info!(
+            "{}: applied {} variants, {} ambiguity codes, {} skipped, {} bases masked",
+            sample, applied, ambiguous, skipped, masked_count
+        );
+        info!("Wrote {}", fasta_path.display());
+        Ok(())
+    }
+
+    /// The sample name, MT contig and the PASS variants the sample carries
+    /// at a VAF that changes the sequence.
+    fn read_variants(&self) -> Result<(String, String, Vec<ConsensusVariant>), Box<dyn Error>> {
+        let lowest_vaf = if self.iupac {
+            (1.0 - self.min_vaf).min(self.min_vaf)
+        } else {
+            self.min_vaf
+        };
+
+        let mut index: Option<(usize, String)> = None;
+        let mut contig: Option<String> = mity_util::vcf_get_mt_contig(&self.vcf).ok().map(|(contig, _)| contig);
+        let mut variants = Vec::new();
+        for line in mity_util::open_vcf(&self.vcf)?.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                let selected = select_sample(&self.vcf, &line, self.sample.as_deref())?;
+                let name = line.split('\t').nth(9 + selected).unwrap_or_default().to_string();
+                index = Some((selected, name));
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let record = VcfRecord::parse(&line)?;
+            let Some((index, _)) = &index else {
+                return Err(format!("{} has no #CHROM header line", self.vcf.display()).into());
+            };
+            if record.chrom != "MT" && record.chrom != "chrM" {
+                continue;
+            }
+            contig.get_or_insert_with(|| record.chrom.clone());
+            if record.filter != "PASS" && record.filter != "." {
+                continue;
+            }
+            // Symbolic and multi-allelic records cannot be spelled out
+            if record.alt.contains([',', '<', '*']) {
+                continue;
+            }
+            match record.sample_vaf(*index) {
+                Some(vaf) if vaf > 0.0 && vaf >= lowest_vaf => variants.push(ConsensusVariant {
+                    pos: record.pos,
+                    reference: record.reference,
+                    alt: record.alt,
+                    vaf,
+                }),
+                _ => {}
+            }
+        }
+
+        let (_, sample) = index.ok_or_else(|| format!("{} has no #CHROM header line", self.vcf.display()))?;
+        let contig = contig.ok_or_else(|| format!("No mitochondrial contig in {}", self.vcf.display()))?;
+        Ok((sample, contig, variants))
+    }
+}
+
+/// The MT sequence of a reference FASTA, trying the other naming when the
+/// VCF and FASTA disagree on MT and chrM.
+fn read_mt_sequence(fasta: &Path, contig: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    match mity_util::read_fasta_contig(fasta, contig) {
+        Ok(sequence) => Ok(sequence),
+        Err(e) => {
+            let other = if contig == "MT" { "chrM" } else { "MT" };
+            mity_util::read_fasta_contig(fasta, other).map_err(|_| e.into())
+        }
+    }
+}
+
+fn per_base_depth(bedgraph: &Path, length: usize) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut depth = vec![0.0; length];
+    for (start, end, value) in read_bedgraph(bedgraph)? {
+        for slot in depth.iter_mut().take(end as usize).skip(start as usize) {
+            *slot = value;
+        }
+    }
+    Ok(depth)
+}
+
+/// The IUPAC code covering two bases, if both are A, C, G or T.
+fn iupac_code(a: u8, b: u8) -> Option<u8> {
+    let mut pair = [a.to_ascii_uppercase(), b.to_ascii_uppercase()];
+    pair.sort_unstable();
+    match &pair {
+        b"AG" => Some(b'R'),
+        b"CT" => Some(b'Y'),
+        b"CG" => Some(b'S'),
+        b"AT" => Some(b'W'),
+        b"GT" => Some(b'K'),
+        b"AC" => Some(b'M'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sequence(fasta: &Path) -> (String, String) {
+        let text = fs::read_to_string(fasta).unwrap();
+        let (description, bases) = text.split_once('\n').unwrap();
+        (description.to_string(), bases.replace('\n', ""))
+    }
+
+    #[test]
+    fn the_samples_variants_are_applied_to_the_reference() {
+        let temp = tempfile::tempdir().unwrap();
+        // The FASTA names the contig chrM while the VCF uses MT
+        let reference = temp.path().join("ref.fa");
+        fs::write(&reference, ">chrM\nAAAACCCCGGGGTTTTACGT\n").unwrap();
+        let vcf = temp.path().join("s.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2\n\
+             MT\t2\t.\tA\tG\t.\tPASS\t.\tVAF\t0\t0.9\n\
+             MT\t3\t.\tAA\tA\t.\tPASS\t.\tVAF\t0\t1\n\
+             MT\t4\t.\tA\tT\t.\tPASS\t.\tVAF\t0\t1\n\
+             MT\t6\t.\tC\tT\t.\tPASS\t.\tVAF\t1\t0.4\n\
+             MT\t10\t.\tG\tA\t.\tPASS\t.\tVAF\t1\t0.2\n\
+             MT\t14\t.\tT\tC\t.\tSBA\t.\tVAF\t1\t1\n\
+             MT\t17\t.\tC\tG\t.\tPASS\t.\tVAF\t1\t1\n",
+        )
+        .unwrap();
+        let consensus = |iupac: bool, depth_bedgraph: Option<PathBuf>, prefix: &str| {
+            Consensus::new(
+                false,
+                vcf.clone(),
+                reference.clone(),
+                Some("S2".to_string()),
+                0.7,
+                iupac,
+                depth_bedgraph,
+                10.0,
+                temp.path().to_path_buf(),
+                Some(prefix.to_string()),
+            )
+            .run()
+            .unwrap();
+            sequence(&temp.path().join(format!("{}.S2.MT.fa", prefix)))
+        };
+
+        // 4 overlaps the deletion at 3, and 17 does not match the reference
+        let (description, bases) = consensus(false, None, "plain");
+        assert_eq!(bases, "AGACCCCGGGGTTTTACGT");
+        assert!(description.starts_with(">S2 mity consensus of MT"), "{}", description);
+        assert!(description.ends_with("; 2 variants at VAF >= 0.7"), "{}", description);
+
+        let bedgraph = temp.path().join("S2.bedgraph");
+        fs::write(&bedgraph, "chrM\t0\t18\t50\nchrM\t18\t20\t2\n").unwrap();
+        let (description, bases) = consensus(true, Some(bedgraph), "masked");
+        assert_eq!(bases, "AGACYCCGGGGTTTTACNN");
+        assert!(description.ends_with("; 1 IUPAC ambiguity codes; 2 bases N below depth 10"), "{}", description);
+    }
+
+    #[test]
+    fn iupac_codes_cover_both_bases() {
+        assert_eq!(iupac_code(b'G', b'a'), Some(b'R'));
+        assert_eq!(iupac_code(b'T', b'C'), Some(b'Y'));
+        assert_eq!(iupac_code(b'A', b'A'), None);
+        assert_eq!(iupac_code(b'N', b'A'), None);
+    }
+}
@@ -0,0 +1,274 @@
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::error::MityError;
+use crate::haplogroup::{Classifier, Variant};
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Variants at or above this VAF make up the primary haplotype.
+const PRIMARY_MIN_VAF: f64 = 0.5;
+/// Lower VAFs are not distinguishable from noise.
+const SECONDARY_MIN_VAF: f64 = 0.005;
+/// Consistent secondary alleles needed before estimating a fraction.
+const MIN_SECONDARY_SITES: usize = 3;
+
+/// VAF of each variant a sample carries.
+type SampleVafs = HashMap<Variant, f64>;
+
+/// Estimated share of a second individual's mtDNA in one sample.
+struct Estimate {
+    primary: Option<String>,
+    secondary: Option<String>,
+    fraction: f64,
+    sites: Vec<(Variant, f64)>,
+}
+
+impl Estimate {
+    /// A qualitative reading of the fraction.
+    fn verdict(&self) -> &'static str {
+        if self.sites.len() < MIN_SECONDARY_SITES {
+            "none detected"
+        } else if self.fraction < 0.02 {
+            "trace"
+        } else if self.fraction < 0.05 {
+            "low"
+        } else if self.fraction < 0.15 {
+            "moderate"
+        } else {
+            "high"
+        }
+    }
+
+    fn to_json(&self, sample: &str) -> Value {
+        json!({
+            "sample": sample,
+            "primary_haplogroup": self.primary,
+            "secondary_haplogroup": self.secondary,
+            "contamination_fraction": self.fraction,
+            "verdict": self.verdict(),
+            "sites": self
+                .sites
+                .iter()
+                .map(|((pos, reference, alt), vaf)| json!({ "variant": format!("{}{}>{}", pos, reference, alt), "vaf": vaf }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Estimates contamination from the heteroplasmy profile at
+/// haplogroup-defining sites.
+///
+/// Contaminating mtDNA shows up as low-VAF alleles that together define a
+/// second haplogroup. The sample is modelled as a mixture of two haplotypes:
+/// the primary one is classified from the high-VAF variants, the secondary
+/// from the low-VAF haplogroup-defining alleles outside the primary
+/// haplogroup, and the secondary fraction is the median VAF of those alleles.
+pub struct Contamination {
+    debug: bool,
+    vcf: PathBuf,
+    output_dir: PathBuf,
+    prefix: String,
+}
+
+impl Contamination {
+    pub fn new(debug: bool, vcf: PathBuf, output_dir: PathBuf, prefix: Option<String>) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&vcf));
+        Contamination {
+            debug,
+            vcf,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+
+        let classifier = Classifier::bundled()?;
+        let mut summary = String::new();
+        let mut results = Vec::new();
+        for (sample, vafs) in self.read_sample_vafs()? {
+            let estimate = estimate(&classifier, &vafs);
+            summary.push_str(&format!(
+                "Sample: {}\nPrimary haplogroup: {}\nSecondary haplogroup: {}\nEstimated contamination: {:.4} ({})\nSupporting sites ({}): {}\n\n",
+                sample,
+                estimate.primary.as_deref().unwrap_or("unknown"),
+                estimate.secondary.as_deref().unwrap_or("none"),
+                estimate.fraction,
+                estimate.verdict(),
+                estimate.sites.len(),
+                estimate
+                    .sites
+                    .iter()
+                    .map(|((pos, reference, alt), vaf)| format!("{}{}>{}:{:.4}", pos, reference, alt, vaf))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ));
+            results.push(estimate.to_json(&sample));
+        }
+
+        print!("{}", summary);
+        let text_path = self
+            .output_dir
+            .join(format!("{}.mity.contamination.txt", self.prefix));
+        fs::write(&text_path, &summary)?;
+        let json_path = self
+            .output_dir
+            .join(format!("{}.mity.contamination.json", self.prefix));
+        serde_json::to_writer_pretty(File::create(&json_path)?, &results)?;
+        info!("Wrote {} and {}", text_path.display(), json_path.display());
+
+        Ok(())
+    }
+
+    /// VAF of every PASS variant each sample carries.
+    fn read_sample_vafs(&self) -> Result<Vec<(String, SampleVafs)>, Box<dyn Error>> {
+        let mut samples: Vec<String> = Vec::new();
+        let mut vafs: Vec<SampleVafs> = Vec::new();
+        for line in mity_util::open_vcf(&self.vcf)?.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(String::from).collect();
+                vafs = vec![HashMap::new(); samples.len()];
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line)?;
+            if record.filter != "PASS" && record.filter != "." {
+                continue;
+            }
+            for (sample, sample_vafs) in vafs.iter_mut().enumerate() {
+                if let Some(vaf) = record.sample_vaf(sample).filter(|vaf| *vaf > 0.0) {
+                    sample_vafs.insert((record.pos, record.reference.clone(), record.alt.clone()), vaf);
+                }
+            }
+        }
+        if samples.is_empty() {
+            return Err(format!("{} has no samples", self.vcf.display()).into());
+        }
+        Ok(samples.into_iter().zip(vafs).collect())
+    }
+}
+
+fn estimate(classifier: &Classifier, vafs: &SampleVafs) -> Estimate {
+    let primary_variants: HashSet<Variant> = vafs
+        .iter()
+        .filter(|(_, vaf)| **vaf >= PRIMARY_MIN_VAF)
+        .map(|(variant, _)| variant.clone())
+        .collect();
+    let primary = classifier.classify(&primary_variants);
+    let primary_defining: HashSet<&Variant> = primary
+        .iter()
+        .flat_map(|classification| classification.supporting.iter().chain(&classification.missing))
+        .collect();
+
+    // Low-level alleles the primary haplogroup does not explain
+    let secondary_variants: HashSet<Variant> = vafs
+        .iter()
+        .filter(|(variant, vaf)| {
+            (SECONDARY_MIN_VAF..PRIMARY_MIN_VAF).contains(*vaf) && !primary_defining.contains(variant)
+        })
+        .map(|(variant, _)| variant.clone())
+        .collect();
+    let secondary = classifier.classify(&secondary_variants);
+
+    let mut sites: Vec<(Variant, f64)> = secondary
+        .iter()
+        .flat_map(|classification| &classification.supporting)
+        .map(|variant| (variant.clone(), vafs[variant]))
+        .collect();
+    sites.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut sorted: Vec<f64> = sites.iter().map(|(_, vaf)| *vaf).collect();
+    sorted.sort_by(f64::total_cmp);
+    let fraction = if sites.len() < MIN_SECONDARY_SITES {
+        0.0
+    } else if sorted.len() % 2 == 1 {
+        sorted[sorted.len() / 2]
+    } else {
+        (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
+    };
+
+    Estimate {
+        primary: primary.map(|classification| classification.haplogroup),
+        secondary: secondary
+            .filter(|_| sites.len() >= MIN_SECONDARY_SITES)
+            .map(|classification| classification.haplogroup),
+        fraction,
+        sites,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// A table of H, defined by two variants, and U, defined by three.
+    fn classifier(dir: &Path) -> Classifier {
+        let path = dir.join("haplotypes.vcf");
+        let mut text = String::from("##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+        for (pos, reference, alt, haplogroup) in [
+            (2706, "A", "G", "H"),
+            (7028, "C", "T", "H"),
+            (11467, "A", "G", "U"),
+            (12308, "A", "G", "U"),
+            (12372, "G", "A", "U"),
+        ] {
+            text.push_str(&format!("MT\t{}\t.\t{}\t{}\t.\t.\tphylotree_haplotype={}\n", pos, reference, alt, haplogroup));
+        }
+        fs::write(&path, text).unwrap();
+        Classifier::load(&path).unwrap()
+    }
+
+    fn vafs(variants: &[(u64, &str, &str, f64)]) -> SampleVafs {
+        variants
+            .iter()
+            .map(|(pos, reference, alt, vaf)| ((*pos, reference.to_string(), alt.to_string()), *vaf))
+            .collect()
+    }
+
+    #[test]
+    fn low_level_alleles_of_a_second_haplogroup_give_its_fraction() {
+        let temp = tempfile::tempdir().unwrap();
+        let classifier = classifier(temp.path());
+
+        // 16519 is not haplogroup-defining, so it is not a site
+        let mixed = estimate(
+            &classifier,
+            &vafs(&[
+                (2706, "A", "G", 0.99),
+                (7028, "C", "T", 0.98),
+                (11467, "A", "G", 0.04),
+                (12308, "A", "G", 0.06),
+                (12372, "G", "A", 0.05),
+                (16519, "T", "C", 0.1),
+            ]),
+        );
+        assert_eq!(mixed.primary.as_deref(), Some("H"));
+        assert_eq!(mixed.secondary.as_deref(), Some("U"));
+        assert_eq!(mixed.sites.iter().map(|((pos, ..), _)| *pos).collect::<Vec<_>>(), [11467, 12308, 12372]);
+        assert_eq!(mixed.fraction, 0.05);
+        assert_eq!(mixed.verdict(), "moderate");
+
+        // Two sites are too few to name a contaminant
+        let clean = estimate(
+            &classifier,
+            &vafs(&[(2706, "A", "G", 1.0), (7028, "C", "T", 1.0), (11467, "A", "G", 0.01), (12308, "A", "G", 0.01)]),
+        );
+        assert_eq!((clean.secondary.as_deref(), clean.fraction, clean.verdict()), (None, 0.0, "none detected"));
+    }
+}
@@ -0,0 +1,302 @@
+use log::{debug, info, warn};
+use noodles::bam;
+use serde_json::json;
+use std::error::Error;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::error::MityError;
+use crate::mity_util::{self, Interval};
+use crate::pileup;
+
+/// Estimates relative mtDNA copy number as MT mean depth over autosomal mean
+/// depth.
+pub struct CopyNumber {
+    debug: bool,
+    bam: PathBuf,
+    prefix: Option<String>,
+    regions: Option<PathBuf>,
+    capture: Option<PathBuf>,
+    min_mq: u32,
+    min_bq: u32,
+    output_dir: PathBuf,
+}
+
+impl CopyNumber {
+    const MIN_MQ: u32 = 30;
+    const MIN_BQ: u32 = 24;
+    /// Autosomes sampled when no regions are given.
+    const DEFAULT_CONTIGS: [&'static str; 4] = ["1", "chr1", "20", "chr20"];
+    const WINDOWS_PER_CONTIG: usize = 500;
+    const WINDOW_SIZE: usize = 1000;
+    /// Upper bound on the number of autosomal windows that are piled up.
+    const MAX_WINDOWS: usize = 2000;
+    /// Two-sided 95% normal quantile.
+    const Z_95: f64 = 1.96;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        debug: bool,
+        bam: PathBuf,
+        prefix: Option<String>,
+        regions: Option<PathBuf>,
+        capture: Option<PathBuf>,
+        min_mq: Option<u32>,
+        min_bq: Option<u32>,
+        output_dir: PathBuf,
+    ) -> Self {
+        CopyNumber {
+            debug,
+            bam,
+            prefix,
+            regions,
+            capture,
+            min_mq: min_mq.unwrap_or(Self::MIN_MQ),
+            min_bq: min_bq.unwrap_or(Self::MIN_BQ),
+            output_dir,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.bam.exists() {
+            return Err(MityError::MissingFile(self.bam.clone()).into());
+        }
+
+        let (mt_contig, _) = mity_util::bam_mt_contig(&self.bam)?;
+        let mt_depth = pileup::contig_depth(&self.bam, &mt_contig, self.min_mq, self.min_bq)?;
+        let mt_mean = pileup::DepthSummary::new(&mt_depth).mean;
+
+        let windows = self.autosomal_windows()?;
+        if windows.is_empty() {
+            return Err("No autosomal regions to sample; pass --regions or --capture-bed".into());
+        }
+        info!("Sampling autosomal depth over {} windows", windows.len());
+        let window_means: Vec<f64> =
+            pileup::intervals_depth(&self.bam, &windows, self.min_mq, self.min_bq)?
+                .iter()
+                .filter(|depth| !depth.is_empty())
+                .map(|depth| depth.iter().map(|d| *d as f64).sum::<f64>() / depth.len() as f64)
+                // Windows without any reads are assembly gaps or off-target
+                .filter(|mean| *mean > 0.0)
+                .collect();
+        if window_means.is_empty() {
+            return Err("No reads found in any of the sampled autosomal regions".into());
+        }
+        if window_means.len() < 30 {
+            warn!(
+                "Only {} autosomal windows have coverage; the estimate will be imprecise",
+                window_means.len()
+            );
+        }
+
+        let n = window_means.len() as f64;
+        let autosomal_mean = window_means.iter().sum::<f64>() / n;
+        let variance = if window_means.len() > 1 {
+            window_means
+                .iter()
+                .map(|m| (m - autosomal_mean).powi(2))
+                .sum::<f64>()
+                / (n - 1.0)
+        } else {
+            0.0
+        };
+        let standard_error = (variance / n).sqrt();
+        let autosomal_lower = (autosomal_mean - Self::Z_95 * standard_error).max(f64::EPSILON);
+        let autosomal_upper = autosomal_mean + Self::Z_95 * standard_error;
+
+        let ratio = mt_mean / autosomal_mean;
+        let copy_number = 2.0 * ratio;
+        // A higher autosomal depth gives a lower copy number, and vice versa
+        let copy_number_lower = 2.0 * mt_mean / autosomal_upper;
+        let copy_number_upper = 2.0 * mt_mean / autosomal_lower;
+
+        let prefix = self.prefix.clone().unwrap_or_else(|| {
+            self.bam
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        println!("sample\t{}", prefix);
+        println!("mt_mean_depth\t{:.2}", mt_mean);
+        println!("autosomal_mean_depth\t{:.2}", autosomal_mean);
+        println!("autosomal_windows\t{}", window_means.len());
+        println!("mt_autosomal_ratio\t{:.4}", ratio);
+        println!(
+            "copy_number\t{:.1} (95% CI {:.1}-{:.1})",
+            copy_number, copy_number_lower, copy_number_upper
+        );
+
+        let json_path = self
+            .output_dir
+            .join(format!("{}.mity.copy_number.json", prefix));
+        let report = json!({
+            "sample": prefix,
+            "bam": self.bam.display().to_string(),
+            "mt_contig": mt_contig,
+            "mt_mean_depth": mt_mean,
+            "autosomal_mean_depth": autosomal_mean,
+            "autosomal_windows": window_means.len(),
+            "mt_autosomal_ratio": ratio,
+            "copy_number": copy_number,
+            "copy_number_lower": copy_number_lower,
+            "copy_number_upper": copy_number_upper,
+        });
+        serde_json::to_writer_pretty(File::create(&json_path)?, &report)?;
+        info!("Wrote {}", json_path.display());
+
+        Ok(())
+    }
+
+    /// The autosomal windows to sample: `--regions` if given, otherwise the
+    /// capture BED (for exomes) or evenly spaced windows on chr1 and chr20.
+    /// With both, the regions are restricted to the capture targets.
+    fn autosomal_windows(&self) -> Result<Vec<Interval>, Box<dyn Error>> {
+        let capture = match &self.capture {
+            Some(path) => Some(mity_util::read_bed(path)?),
+            None => None,
+        };
+
+        let windows = match (&self.regions, capture) {
+            (Some(path), Some(capture)) => intersect(&mity_util::read_bed(path)?, &capture),
+            (Some(path), None) => mity_util::read_bed(path)?,
+            (None, Some(capture)) => capture
+                .into_iter()
+                .filter(|interval| is_autosome(&interval.contig))
+                .collect(),
+            (None, None) => self.default_windows()?,
+        };
+
+        Ok(thin(windows, Self::MAX_WINDOWS))
+    }
+
+    fn default_windows(&self) -> Result<Vec<Interval>, Box<dyn Error>> {
+        let mut reader = bam::io::reader::Builder.build_from_path(&self.bam)?;
+        let header = reader.read_header()?;
+
+        let mut windows = Vec::new();
+        for (name, sequence) in header.reference_sequences() {
+            let name = name.to_string();
+            if !Self::DEFAULT_CONTIGS.contains(&name.as_str()) {
+                continue;
+            }
+            let length = sequence.length().get();
+            let step = length / (Self::WINDOWS_PER_CONTIG + 1);
+            if step < Self::WINDOW_SIZE {
+                continue;
+            }
+            for i in 1..=Self::WINDOWS_PER_CONTIG {
+                windows.push(Interval {
+                    contig: name.clone(),
+                    start: i * step,
+                    end: i * step + Self::WINDOW_SIZE,
+                });
+            }
+        }
+        Ok(windows)
+    }
+}
+
+fn is_autosome(contig: &str) -> bool {
+    let name = contig.strip_prefix("chr").unwrap_or(contig);
+    matches!(name.parse::<u32>(), Ok(1..=22))
+}
+
+/// Overlaps between two sets of intervals.
+fn intersect(intervals: &[Interval], targets: &[Interval]) -> Vec<Interval> {
+    let mut overlaps = Vec::new();
+    for interval in intervals {
+        for target in targets.iter().filter(|t| t.contig == interval.contig) {
+            let start = interval.start.max(target.start);
+            let end = interval.end.min(target.end);
+            if start < end {
+                overlaps.push(Interval {
+                    contig: interval.contig.clone(),
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+    overlaps
+}
+
+/// Keep at most `max` intervals, evenly spaced through the list.
+fn thin(intervals: Vec<Interval>, max: usize) -> Vec<Interval> {
+    if intervals.len() <= max {
+        return intervals;
+    }
+    let step = intervals.len() as f64 / max as f64;
+    (0..max)
+        .map(|i| intervals[(i as f64 * step) as usize].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mt_sequence, read, reference_read, write_bam};
+    use noodles::sam::alignment::RecordBuf;
+    use std::fs;
+
+    /// A read of 100 bases on chromosome 1, the second contig.
+    fn autosomal_read(name: &str, start: usize) -> RecordBuf {
+        let mut record = read(name, start, &mt_sequence()[..100]);
+        *record.reference_sequence_id_mut() = Some(1);
+        record
+    }
+
+    #[test]
+    fn copy_number_is_twice_mt_over_autosomal_depth() {
+        let temp = tempfile::tempdir().unwrap();
+        let header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:MT\tLN:16569\n@SQ\tSN:1\tLN:100000\n@RG\tID:a\tSM:a\n";
+        let mut records: Vec<RecordBuf> = (0..20).map(|i| reference_read(&format!("mt{}", i), 1, 100)).collect();
+        // Depth 1 over the first window and 3 over the second
+        records.push(autosomal_read("w1", 1001));
+        records.extend((0..3).map(|i| autosomal_read(&format!("w2.{}", i), 2001)));
+        let bam = write_bam(&temp.path().join("s1.bam"), header, &records);
+        let regions = temp.path().join("regions.bed");
+        // The last window has no reads, so it is taken for a gap
+        fs::write(&regions, "1\t1000\t1100\n1\t2000\t2100\n1\t5000\t5100\n").unwrap();
+
+        CopyNumber::new(false, bam, Some("s1".to_string()), Some(regions), None, None, None, temp.path().to_path_buf())
+            .run()
+            .unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp.path().join("s1.mity.copy_number.json")).unwrap()).unwrap();
+        let mt_mean = 2000.0 / 16569.0;
+        let close = |key: &str, expected: f64| {
+            let value = report[key].as_f64().unwrap();
+            assert!((value - expected).abs() < 1e-9, "{}: {} != {}", key, value, expected);
+        };
+        assert_eq!(report["mt_contig"], "MT");
+        assert_eq!(report["autosomal_windows"], 2);
+        close("mt_mean_depth", mt_mean);
+        close("autosomal_mean_depth", 2.0);
+        close("copy_number", mt_mean);
+        // Window means 1 and 3: a standard error of 1
+        close("copy_number_lower", 2.0 * mt_mean / (2.0 + 1.96));
+        close("copy_number_upper", 2.0 * mt_mean / (2.0 - 1.96));
+    }
+
+    #[test]
+    fn capture_targets_restrict_the_regions_to_autosomes() {
+        let interval = |contig: &str, start: usize, end: usize| Interval {
+            contig: contig.to_string(),
+            start,
+            end,
+        };
+        let regions = [interval("1", 0, 1000), interval("2", 0, 1000)];
+        let targets = [interval("1", 500, 1500), interval("2", 2000, 3000)];
+        assert_eq!(intersect(&regions, &targets), [interval("1", 500, 1000)]);
+
+        assert!(is_autosome("1") && is_autosome("chr22"));
+        assert!(!is_autosome("X") && !is_autosome("chrM") && !is_autosome("23"));
+        let many: Vec<Interval> = (0..10).map(|i| interval("1", i * 10, i * 10 + 5)).collect();
+        assert_eq!(thin(many, 5).iter().map(|i| i.start).collect::<Vec<_>>(), [0, 20, 40, 60, 80]);
+    }
+}
@@ -0,0 +1,169 @@
+use log::{debug, info};
+use noodles::bgzf;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::MityError;
+use crate::mity_util;
+use crate::pileup::{self, DepthSummary};
+
+pub struct Coverage {
+    debug: bool,
+    files: Vec<PathBuf>,
+    min_mq: u32,
+    min_bq: u32,
+    min_depth: u32,
+    output_dir: PathBuf,
+    bam_list: bool,
+}
+
+impl Coverage {
+    const MIN_MQ: u32 = 30;
+    const MIN_BQ: u32 = 24;
+    const MIN_DEPTH: u32 = 100;
+
+    pub fn new(
+        debug: bool,
+        files: Vec<PathBuf>,
+        min_mq: Option<u32>,
+        min_bq: Option<u32>,
+        min_depth: Option<u32>,
+        output_dir: PathBuf,
+        bam_list: bool,
+    ) -> Self {
+        Coverage {
+            debug,
+            files,
+            min_mq: min_mq.unwrap_or(Self::MIN_MQ),
+            min_bq: min_bq.unwrap_or(Self::MIN_BQ),
+            min_depth: min_depth.unwrap_or(Self::MIN_DEPTH),
+            output_dir,
+            bam_list,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+
+        if self.bam_list {
+            if self.files.len() > 1 {
+                return Err("--bam-file-list argument expects only 1 file to be provided.".into());
+            }
+            self.files = mity_util::read_file_list(&self.files[0])?;
+        }
+        for file in &self.files {
+            if !file.exists() {
+                return Err(MityError::MissingFile(file.clone()).into());
+            }
+        }
+
+        for file in &self.files {
+            self.run_sample(file)?;
+        }
+        Ok(())
+    }
+
+    fn run_sample(&self, bam: &Path) -> Result<(), Box<dyn Error>> {
+        let (contig, _) = mity_util::bam_mt_contig(bam)?;
+        info!("Computing depth over {} for {}", contig, bam.display());
+        let depth = pileup::contig_depth(bam, &contig, self.min_mq, self.min_bq)?;
+
+        let prefix = bam
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let bedgraph_path = self
+            .output_dir
+            .join(format!("{}.mity.coverage.bedgraph.gz", prefix));
+        let summary_path = self
+            .output_dir
+            .join(format!("{}.mity.coverage.summary.tsv", prefix));
+        let low_depth_path = self
+            .output_dir
+            .join(format!("{}.mity.low_depth.bed", prefix));
+
+        let mut writer = bgzf::Writer::new(File::create(&bedgraph_path)?);
+        for (start, end, d) in pileup::depth_runs(&depth) {
+            writeln!(writer, "{}\t{}\t{}\t{}", contig, start, end, d)?;
+        }
+        writer.finish()?;
+
+        let summary = DepthSummary::new(&depth);
+        let mut writer = BufWriter::new(File::create(&summary_path)?);
+        writeln!(
+            writer,
+            "sample\tcontig\tmean\tmedian\tmin\tmax\tfraction_100x\tfraction_500x\tfraction_1000x"
+        )?;
+        writeln!(
+            writer,
+            "{}\t{}\t{:.2}\t{:.1}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+            prefix,
+            contig,
+            summary.mean,
+            summary.median,
+            summary.min,
+            summary.max,
+            summary.fraction_100x,
+            summary.fraction_500x,
+            summary.fraction_1000x
+        )?;
+        writer.flush()?;
+
+        let regions = pileup::low_depth_regions(&depth, self.min_depth);
+        let mut writer = BufWriter::new(File::create(&low_depth_path)?);
+        for (start, end) in &regions {
+            writeln!(writer, "{}\t{}\t{}", contig, start, end)?;
+        }
+        writer.flush()?;
+
+        info!(
+            "{}: mean depth {:.1}, median {:.1}, {} region(s) below {}x",
+            prefix,
+            summary.mean,
+            summary.median,
+            regions.len(),
+            self.min_depth
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, mt_header, reference_read, write_bam};
+    use noodles::sam::alignment::record::MappingQuality;
+
+    #[test]
+    fn depth_leaves_out_low_mapping_and_base_qualities() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut records: Vec<_> = (0..3).map(|i| reference_read(&format!("r{}", i), 1, 100)).collect();
+        let mut low_mq = reference_read("low_mq", 51, 100);
+        *low_mq.mapping_quality_mut() = MappingQuality::new(10);
+        records.push(low_mq);
+        // One base of it, 150, below --min-base-quality
+        let mut low_bq = reference_read("low_bq", 101, 100);
+        low_bq.quality_scores_mut().as_mut()[49] = 5;
+        records.push(low_bq);
+        let bam = write_bam(&temp.path().join("s1.bam"), &mt_header("MT", &["s1"]), &records);
+
+        Coverage::new(false, vec![bam], None, None, Some(3), temp.path().to_path_buf(), false)
+            .run()
+            .unwrap();
+
+        let bedgraph = test_util::read_lines(&temp.path().join("s1.mity.coverage.bedgraph.gz"));
+        assert_eq!(
+            bedgraph,
+            ["MT\t0\t100\t3", "MT\t100\t149\t1", "MT\t149\t150\t0", "MT\t150\t200\t1", "MT\t200\t16569\t0"]
+        );
+        let summary = test_util::read_lines(&temp.path().join("s1.mity.coverage.summary.tsv"));
+        assert_eq!(summary[1], "s1\tMT\t0.02\t0.0\t0\t3\t0.0000\t0.0000\t0.0000");
+        let low_depth = test_util::read_lines(&temp.path().join("s1.mity.low_depth.bed"));
+        assert_eq!(low_depth, ["MT\t100\t16569"]);
+    }
+}
@@ -0,0 +1,309 @@
+use log::{debug, info};
+use noodles::bgzf;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::compare::select_sample;
+use crate::error::MityError;
+use crate::mity_util;
+use crate::normalise::noise_pvalue;
+use crate::vcf_record::VcfRecord;
+
+/// Maternal reads are treated as real signal, not noise, below this p-value.
+const MATERNAL_ALPHA: f64 = 0.05;
+
+const DENOVO_HEADER: &str = "##INFO=<ID=DENOVO,Number=1,Type=String,Description=\"Maternal support for the proband variant: de_novo_candidate, inherited or insufficient_maternal_depth\">";
+const DENOVO_P_HEADER: &str = "##INFO=<ID=DENOVO_P,Number=1,Type=Float,Description=\"Probability of at least the maternal alt reads arising from noise at the --p level\">";
+
+/// A variant key as `(chrom, pos, ref, alt)`.
+type VariantKey = (String, u64, String, String);
+
+/// Thresholds for calling a proband variant de novo.
+#[derive(Clone, Debug)]
+pub struct DenovoSettings {
+    /// Proband variants below this VAF are not classified.
+    pub proband_min_vaf: f64,
+    /// A maternal VAF above this makes the variant inherited.
+    pub maternal_max_vaf: f64,
+    /// Mothers with less depth than this at the site cannot rule out inheritance.
+    pub min_maternal_depth: u64,
+    /// Noise level of the binomial model used for QUAL.
+    pub p: f64,
+}
+
+/// Alt read count and depth of one sample at a site.
+#[derive(Clone, Copy)]
+struct Support {
+    ao: u64,
+    dp: u64,
+}
+
+impl Support {
+    /// AO and DP of one sample; AO falls back to VAF x DP for callers that
+    /// only report a fraction.
+    fn of(record: &VcfRecord, sample: usize) -> Option<Support> {
+        let dp = record.sample_f64(sample, "DP")?;
+        let ao = match record.sample_f64(sample, "AO") {
+            Some(ao) => ao,
+            None => (record.sample_vaf(sample)? * dp).round(),
+        };
+        Some(Support {
+            ao: ao.max(0.0) as u64,
+            dp: dp.max(0.0) as u64,
+        })
+    }
+
+    fn vaf(&self) -> f64 {
+        if self.dp == 0 {
+            0.0
+        } else {
+            self.ao as f64 / self.dp as f64
+        }
+    }
+}
+
+/// Looks for low-level heteroplasmies in a proband that the mother's reads
+/// cannot explain.
+///
+/// Each proband variant is tested against the mother's AO/DP at the same
+/// site with the binomial noise model used for QUAL. Maternal alt reads
+/// beyond noise, or a maternal VAF above `maternal_max_vaf`, make it
+/// inherited; otherwise it is a de novo candidate, unless the mother has too
+/// little depth to tell.
+pub struct Denovo {
+    debug: bool,
+    mother_vcf: PathBuf,
+    proband_vcf: PathBuf,
+    samples: [Option<String>; 2],
+    settings: DenovoSettings,
+    output_dir: PathBuf,
+    prefix: String,
+}
+
+impl Denovo {
+    /// `samples` selects the mother and proband sample, which is required
+    /// when a VCF has several, e.g. a joint VCF passed as both files.
+    pub fn new(
+        debug: bool,
+        mother_vcf: PathBuf,
+        proband_vcf: PathBuf,
+        samples: [Option<String>; 2],
+        settings: DenovoSettings,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&proband_vcf));
+        Denovo {
+            debug,
+            mother_vcf,
+            proband_vcf,
+            samples,
+            settings,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        for vcf in [&self.mother_vcf, &self.proband_vcf] {
+            if !vcf.exists() {
+                return Err(MityError::MissingFile(vcf.clone()).into());
+            }
+        }
+        debug!(
+            "Testing {} against mother {} with {:?}",
+            self.proband_vcf.display(),
+            self.mother_vcf.display(),
+            self.settings
+        );
+
+        let mother = read_support(&self.mother_vcf, self.samples[0].as_deref())?;
+
+        let tsv_path = self.output_dir.join(format!("{}.mity.denovo.tsv", self.prefix));
+        let vcf_path = self.output_dir.join(format!("{}.mity.denovo.vcf.gz", self.prefix));
+        let mut tsv = BufWriter::new(File::create(&tsv_path)?);
+        writeln!(
+            tsv,
+            "CHROM\tPOS\tREF\tALT\tPROBAND_AO\tPROBAND_DP\tPROBAND_VAF\tMOTHER_AO\tMOTHER_DP\tMOTHER_VAF\tMOTHER_NOISE_P\tCLASS"
+        )?;
+        let mut vcf = bgzf::Writer::new(File::create(&vcf_path)?);
+
+        let mut index: Option<usize> = None;
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for line in mity_util::open_vcf(&self.proband_vcf)?.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                index = Some(select_sample(&self.proband_vcf, &line, self.samples[1].as_deref())?);
+                writeln!(vcf, "{}", DENOVO_HEADER)?;
+                writeln!(vcf, "{}", DENOVO_P_HEADER)?;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                writeln!(vcf, "{}", line)?;
+                continue;
+            }
+
+            let mut record = VcfRecord::parse(&line)?;
+            let Some(index) = index else {
+                return Err(format!("{} has no #CHROM header line", self.proband_vcf.display()).into());
+            };
+            let Some(proband) = Support::of(&record, index) else {
+                writeln!(vcf, "{}", record)?;
+                continue;
+            };
+            let proband_vaf = record.sample_vaf(index).unwrap_or_else(|| proband.vaf());
+            if proband.ao == 0 || proband_vaf < self.settings.proband_min_vaf {
+                writeln!(vcf, "{}", record)?;
+                continue;
+            }
+
+            let key = (
+                record.chrom.clone(),
+                record.pos,
+                record.reference.clone(),
+                record.alt.clone(),
+            );
+            let maternal = mother.get(&key).copied();
+            let pvalue = maternal
+                .filter(|m| m.dp > 0)
+                .map(|m| noise_pvalue(m.ao, m.dp, self.settings.p));
+            let class = self.classify(maternal, pvalue);
+            *counts.entry(class).or_default() += 1;
+
+            let format_support = |support: Option<Support>| match support {
+                Some(s) => format!("{}\t{}\t{:.4}", s.ao, s.dp, s.vaf()),
+                None => ".\t.\t.".to_string(),
+            };
+            writeln!(
+                tsv,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                key.0,
+                key.1,
+                key.2,
+                key.3,
+                format_support(Some(proband)),
+                format_support(maternal),
+                pvalue.map_or(".".to_string(), |p| format!("{:.4e}", p)),
+                class
+            )?;
+
+            record.set_info("DENOVO", Some(class.to_string()));
+            if let Some(p) = pvalue {
+                record.set_info("DENOVO_P", Some(format!("{:.4e}", p)));
+            }
+            writeln!(vcf, "{}", record)?;
+        }
+        tsv.flush()?;
+        vcf.finish()?;
+        mity_util::tabix(&vcf_path)?;
+
+        for class in ["de_novo_candidate", "inherited", "insufficient_maternal_depth"] {
+            println!("  {}: {}", class, counts.get(class).copied().unwrap_or(0));
+        }
+        info!("Wrote {} and {}", tsv_path.display(), vcf_path.display());
+
+        Ok(())
+    }
+
+    fn classify(&self, maternal: Option<Support>, pvalue: Option<f64>) -> &'static str {
+        match (maternal, pvalue) {
+            (Some(m), Some(p)) if m.dp >= self.settings.min_maternal_depth => {
+                if m.vaf() > self.settings.maternal_max_vaf || p < MATERNAL_ALPHA {
+                    "inherited"
+                } else {
+                    "de_novo_candidate"
+                }
+            }
+            // Clear maternal signal counts even at low depth
+            (Some(m), _) if m.vaf() > self.settings.maternal_max_vaf && m.ao > 0 => "inherited",
+            _ => "insufficient_maternal_depth",
+        }
+    }
+}
+
+/// AO/DP of the selected sample at every variant in the VCF.
+fn read_support(vcf: &Path, sample: Option<&str>) -> Result<HashMap<VariantKey, Support>, Box<dyn Error>> {
+    let mut index: Option<usize> = None;
+    let mut support = HashMap::new();
+    for line in mity_util::open_vcf(vcf)?.lines() {
+        let line = line?;
+        if line.starts_with("#CHROM") {
+            index = Some(select_sample(vcf, &line, sample)?);
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+
+        let record = VcfRecord::parse(&line)?;
+        let Some(index) = index else {
+            return Err(format!("{} has no #CHROM header line", vcf.display()).into());
+        };
+        if let Some(s) = Support::of(&record, index) {
+            support.insert((record.chrom, record.pos, record.reference, record.alt), s);
+        }
+    }
+    Ok(support)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::fs;
+
+    #[test]
+    fn proband_variants_are_classed_by_maternal_support() {
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        // A joint VCF passed as both files, with the samples picked by name
+        let vcf = temp.path().join("family.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n##contig=<ID=MT,length=16569>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tkid\tmum\n\
+             MT\t1000\t.\tA\tG\t.\tPASS\t.\tAO:DP\t20:1000\t0:1000\n\
+             MT\t2000\t.\tA\tG\t.\tPASS\t.\tAO:DP\t20:1000\t10:1000\n\
+             MT\t3000\t.\tA\tG\t.\tPASS\t.\tAO:DP\t20:1000\t0:20\n\
+             MT\t4000\t.\tA\tG\t.\tPASS\t.\tAO:DP\t20:1000\t15:20\n\
+             MT\t5000\t.\tA\tG\t.\tPASS\t.\tAO:DP\t1:1000\t0:1000\n",
+        )
+        .unwrap();
+        let settings = DenovoSettings {
+            proband_min_vaf: 0.01,
+            maternal_max_vaf: 0.5,
+            min_maternal_depth: 100,
+            p: 0.002,
+        };
+        let samples = [Some("mum".to_string()), Some("kid".to_string())];
+        Denovo::new(false, vcf.clone(), vcf, samples, settings, temp.path().to_path_buf(), None).run().unwrap();
+
+        let classes: Vec<(String, String)> = test_util::read_lines(&temp.path().join("family.mity.denovo.tsv"))
+            .iter()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[1].to_string(), fields[11].to_string())
+            })
+            .collect();
+        let expected = [
+            ("1000", "de_novo_candidate"),
+            ("2000", "inherited"),
+            ("3000", "insufficient_maternal_depth"),
+            ("4000", "inherited"),
+        ];
+        assert_eq!(classes, expected.map(|(pos, class)| (pos.to_string(), class.to_string())));
+
+        // Below --proband-min-vaf the record passes through unlabelled
+        let lines = test_util::read_lines(&temp.path().join("family.mity.denovo.vcf.gz"));
+        let record = |pos: &str| lines.iter().find(|line| line.starts_with(&format!("MT\t{}\t", pos))).unwrap();
+        assert!(record("1000").contains("DENOVO=de_novo_candidate;DENOVO_P=1.0000e0"), "{}", record("1000"));
+        assert!(!record("5000").contains("DENOVO"));
+        assert!(lines.contains(&DENOVO_HEADER.to_string()));
+    }
+}
@@ -0,0 +1,242 @@
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::style::{self, Stream, Style};
+
+/// Exit code of a failed command.
+const FAILURE_EXIT_CODE: i32 = 1;
+/// Lines of an external tool's stderr kept in a JSON error report.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Failures that callers of mity need to tell apart, e.g. to decide
+/// whether a run is worth retrying.
+#[derive(Debug)]
+pub enum MityError {
+    /// An input file does not exist.
+    MissingFile(PathBuf),
+    /// An external tool exited unsuccessfully.
+    Tool {
+        tool: String,
+        exit_code: Option<i32>,
+        /// Captured stderr, empty when the tool wrote to ours.
+        stderr: String,
+    },
+}
+
+impl MityError {
+    pub fn tool(tool: &str, exit_code: Option<i32>, stderr: &[u8]) -> Self {
+        MityError::Tool {
+            tool: tool.to_string(),
+            exit_code,
+            stderr: String::from_utf8_lossy(stderr).into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for MityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MityError::MissingFile(path) => write!(f, "Missing file: {}", path.display()),
+            MityError::Tool { tool, exit_code, .. } => write!(f, "{} failed with code {:?}", tool, exit_code),
+        }
+    }
+}
+
+impl Error for MityError {}
+
+/// Box an anyhow error for a `Box<dyn Error>` caller. Boxing hides the type
+/// of the error inside, so a bare `MityError` is taken out first to keep its
+/// kind. Under context it stays in the source chain, with the context kept.
+pub fn boxed(error: anyhow::Error) -> Box<dyn Error> {
+    if error.chain().nth(1).is_some() {
+        return error.into();
+    }
+    match error.downcast::<MityError>() {
+        Ok(error) => Box::new(error),
+        Err(error) => error.into(),
+    }
+}
+
+/// How a failed command reports its error on stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    /// A single-line JSON object, see [`error_json`].
+    Json,
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+pub fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+/// Report the error of a failed `command` in the `--error-format` and exit.
+pub fn exit_with_error(command: &str, error: &(dyn Error + 'static)) -> ! {
+    match ERROR_FORMAT.get().copied().unwrap_or(ErrorFormat::Human) {
+        ErrorFormat::Human => {
+            let mut message = error.to_string();
+            for cause in causes(error) {
+                message.push_str(&format!(": {}", cause));
+            }
+            eprintln!(
+                "{}",
+                style::paint(
+                    format!("Error executing {} command: {}", command, message),
+                    Style::Red,
+                    Stream::Stderr
+                )
+            );
+        }
+        ErrorFormat::Json => eprintln!("{}", error_json(command, error)),
+    }
+    std::process::exit(FAILURE_EXIT_CODE);
+}
+
+/// The JSON error report. Its keys are always present:
+///
+/// - `command`: the subcommand that failed
+/// - `kind`: `missing_file`, `tool`, `io` or `other`
+/// - `message`: the top-level error message
+/// - `causes`: messages of the underlying errors, outermost first
+/// - `paths`: files the error is about, if known
+/// - `tool`: `null`, or `name`, `exit_code` and `stderr_tail` of a failed
+///   external tool
+/// - `exit_code`: the exit code mity exits with
+pub fn error_json(command: &str, error: &(dyn Error + 'static)) -> Value {
+    let mut kind = "other";
+    let mut paths: Vec<String> = Vec::new();
+    let mut tool = Value::Null;
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(mity_error) = error.downcast_ref::<MityError>() {
+            match mity_error {
+                MityError::MissingFile(path) => {
+                    kind = "missing_file";
+                    paths.push(path.display().to_string());
+                }
+                MityError::Tool { tool: name, exit_code, stderr } => {
+                    kind = "tool";
+                    let lines: Vec<&str> = stderr.lines().collect();
+                    let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+                    tool = json!({
+                        "name": name,
+                        "exit_code": exit_code,
+                        "stderr_tail": (!tail.is_empty()).then_some(tail),
+                    });
+                }
+            }
+            break;
+        }
+        if error.downcast_ref::<io::Error>().is_some() {
+            kind = "io";
+            break;
+        }
+        current = error.source();
+    }
+
+    json!({
+        "command": command,
+        "kind": kind,
+        "message": error.to_string(),
+        "causes": causes(error),
+        "paths": paths,
+        "tool": tool,
+        "exit_code": FAILURE_EXIT_CODE,
+    })
+}
+
+fn causes(error: &(dyn Error + 'static)) -> Vec<String> {
+    let mut causes = Vec::new();
+    let mut current = error.source();
+    while let Some(cause) = current {
+        causes.push(cause.to_string());
+        current = cause.source();
+    }
+    causes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mity_util;
+
+    /// The keys of every report, whatever the error.
+    const KEYS: [&str; 7] = ["causes", "command", "exit_code", "kind", "message", "paths", "tool"];
+
+    fn assert_schema(report: &Value) {
+        let keys: Vec<&str> = report.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, KEYS);
+        assert_eq!(report["exit_code"], FAILURE_EXIT_CODE);
+    }
+
+    #[test]
+    fn reports_a_missing_file() {
+        let error = boxed(anyhow::Error::new(MityError::MissingFile(PathBuf::from("/no/such.bam"))));
+        let report = error_json("call", error.as_ref());
+        assert_eq!(report["kind"], "missing_file");
+        assert_eq!(report["message"], "Missing file: /no/such.bam");
+
+
+        // Context is kept over the file it is about
+        let error = boxed(anyhow::Error::new(MityError::MissingFile(PathBuf::from("/no/such.bam"))).context("--bam"));
+        let report = error_json("call", error.as_ref());
+        assert_schema(&report);
+        assert_eq!(report["command"], "call");
+        assert_eq!(report["kind"], "missing_file");
+        assert_eq!(report["message"], "--bam");
+        assert_eq!(report["causes"], json!(["Missing file: /no/such.bam"]));
+        assert_eq!(report["paths"], json!(["/no/such.bam"]));
+        assert_eq!(report["tool"], Value::Null);
+    }
+
+    #[test]
+    fn reports_a_failed_tool_with_the_end_of_its_stderr() {
+        let stderr: String = (1..=30).map(|line| format!("line {}\n", line)).collect();
+        let error = boxed(
+            anyhow::Error::new(MityError::tool("bcftools norm", Some(255), stderr.as_bytes()))
+                .context("Normalising failed"),
+        );
+        let report = error_json("normalise", error.as_ref());
+        assert_schema(&report);
+        assert_eq!(report["kind"], "tool");
+        assert_eq!(report["tool"]["name"], "bcftools norm");
+        assert_eq!(report["tool"]["exit_code"], 255);
+        let tail: Vec<String> = (11..=30).map(|line| format!("line {}", line)).collect();
+        assert_eq!(report["tool"]["stderr_tail"], tail.join("\n"));
+
+        // A tool that wrote to our stderr has no tail, and one killed by a
+        // signal no exit code
+        let error: Box<dyn Error> = Box::new(MityError::tool("gsort", None, b""));
+        let report = error_json("normalise", error.as_ref());
+        assert_eq!(report["tool"], json!({"name": "gsort", "exit_code": null, "stderr_tail": null}));
+    }
+
+    #[test]
+    fn reports_an_io_error() {
+        let error = boxed(mity_util::open_vcf(std::path::Path::new("/no/such.vcf.gz")).err().unwrap());
+        let report = error_json("stats", error.as_ref());
+        assert_schema(&report);
+        assert_eq!(report["kind"], "io");
+        assert_eq!(report["tool"], Value::Null);
+        assert!(!report["causes"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_any_other_error() {
+        let error: Box<dyn Error> = "--sample takes one name, or two separated by a comma".into();
+        let report = error_json("compare", error.as_ref());
+        assert_schema(&report);
+        assert_eq!(report["kind"], "other");
+        assert_eq!(report["message"], "--sample takes one name, or two separated by a comma");
+        assert_eq!(report["causes"], json!([]));
+        assert_eq!(report["paths"], json!([]));
+        // Reports are a single line
+        assert!(!report.to_string().contains('\n'));
+    }
+}
+
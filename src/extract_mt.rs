@@ -0,0 +1,360 @@
+use log::{debug, info};
+use noodles::bam;
+use noodles::core::region::Interval as RegionInterval;
+use noodles::core::Position;
+use noodles::sam::alignment::io::Write as _;
+use noodles::sam::alignment::Record as _;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::error::MityError;
+use crate::mity_util::{self, Interval, Tool};
+use crate::stats::{RunSummary, Timer};
+
+/// Reads written to the MT-only BAM, by why they were kept.
+#[derive(Default)]
+struct ExtractCounts {
+    mt: u64,
+    unmapped_mates: u64,
+    numts: u64,
+}
+
+/// A region to extract, as a reference sequence id and 0-based half-open
+/// coordinates.
+struct Target {
+    id: usize,
+    start: usize,
+    end: usize,
+    is_mt: bool,
+}
+
+/// Subsets a BAM or CRAM to the reads on the mitochondrial contig.
+pub struct ExtractMt {
+    debug: bool,
+    bam: PathBuf,
+    output_dir: PathBuf,
+    prefix: String,
+    include_unmapped_mates: bool,
+    numts: Option<PathBuf>,
+    reference_fasta: Option<PathBuf>,
+
+    output_bam_path: PathBuf,
+}
+
+impl ExtractMt {
+    /// `numts` is a BED of nuclear regions whose reads are extracted
+    /// alongside the MT contig. `reference_fasta` is required for CRAM input.
+    pub fn new(
+        debug: bool,
+        bam: PathBuf,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+        include_unmapped_mates: bool,
+        numts: Option<PathBuf>,
+        reference_fasta: Option<PathBuf>,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| {
+            bam.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        ExtractMt {
+            debug,
+            output_bam_path: output_dir.join(format!("{}.MT.bam", prefix)),
+            bam,
+            output_dir,
+            prefix,
+            include_unmapped_mates,
+            numts,
+            reference_fasta,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.bam.exists() {
+            return Err(MityError::MissingFile(self.bam.clone()).into());
+        }
+
+        let mut summary = RunSummary::new("mity extract-mt");
+
+        let timer = Timer::start();
+        let counts = if mity_util::is_cram(&self.bam) {
+            self.extract_cram()?
+        } else {
+            self.extract_bam()?
+        };
+        summary.record("extraction", timer);
+
+        let timer = Timer::start();
+        mity_util::index_bam(&self.output_bam_path)?;
+        summary.record("indexing", timer);
+
+        info!(
+            "Extracted {} reads: {} on the MT contig, {} unmapped mates, {} in NUMT regions",
+            counts.mt + counts.unmapped_mates + counts.numts,
+            counts.mt,
+            counts.unmapped_mates,
+            counts.numts
+        );
+        summary.set_metric("mt_reads", counts.mt);
+        summary.set_metric("unmapped_mates", counts.unmapped_mates);
+        summary.set_metric("numt_reads", counts.numts);
+        summary.add_output(&self.output_bam_path);
+        summary.log();
+        summary.write_json(
+            &self
+                .output_dir
+                .join(format!("{}.mity.extract-mt.stats.json", self.prefix)),
+        )?;
+
+        Ok(())
+    }
+
+    /// The MT contig plus any NUMT regions, sorted by reference and start
+    /// with overlaps merged so every read is written once and in order.
+    fn targets(&self, header: &noodles::sam::Header) -> Result<Vec<Target>, Box<dyn Error>> {
+        let (contig, length) = mity_util::bam_mt_contig(&self.bam)?;
+        let id_of = |name: &str| header.reference_sequences().get_index_of(name.as_bytes());
+
+        let mut targets = vec![Target {
+            id: id_of(&contig).ok_or_else(|| format!("{} is not in the BAM header", contig))?,
+            start: 0,
+            end: length,
+            is_mt: true,
+        }];
+        if let Some(numts) = &self.numts {
+            for Interval { contig, start, end } in mity_util::read_bed(numts)? {
+                match id_of(&contig) {
+                    Some(id) => targets.push(Target {
+                        id,
+                        start,
+                        end,
+                        is_mt: false,
+                    }),
+                    None => debug!("Skipping NUMT region on {}, which is not in the BAM", contig),
+                }
+            }
+        }
+
+        targets.sort_by_key(|target| (target.id, target.start));
+        let mut merged: Vec<Target> = Vec::new();
+        for target in targets {
+            match merged.last_mut() {
+                Some(last) if last.id == target.id && target.start <= last.end => {
+                    last.end = last.end.max(target.end);
+                    last.is_mt |= target.is_mt;
+                }
+                _ => merged.push(target),
+            }
+        }
+        Ok(merged)
+    }
+
+    fn extract_bam(&self) -> Result<ExtractCounts, Box<dyn Error>> {
+        let mut reader = mity_util::open_indexed_bam(&self.bam).map_err(crate::error::boxed)?;
+        let header = reader.read_header()?;
+        let targets = self.targets(&header)?;
+
+        let mut writer = bam::io::Writer::new(File::create(&self.output_bam_path)?);
+        writer.write_header(&header)?;
+
+        let mut counts = ExtractCounts::default();
+        let mut record = bam::Record::default();
+        for (index, target) in targets.iter().enumerate() {
+            let previous = index.checked_sub(1).map(|i| &targets[i]);
+            let interval = RegionInterval::from(
+                Position::try_from(target.start + 1)?..=Position::try_from(target.end.max(target.start + 1))?,
+            );
+            let chunks = reader.index().query(target.id, interval)?;
+
+            for chunk in chunks {
+                reader.get_mut().seek(chunk.start())?;
+                while reader.get_ref().virtual_position() < chunk.end() {
+                    if reader.read_record(&mut record)? == 0 {
+                        break;
+                    }
+                    let Some(span) = self.keep(&record, target)? else {
+                        continue;
+                    };
+                    // Reads spanning two merged targets were written with the first
+                    if previous.is_some_and(|p| p.id == target.id && span.0 < p.end) {
+                        continue;
+                    }
+                    writer.write_alignment_record(&header, &record)?;
+                    match (target.is_mt, record.flags().is_unmapped()) {
+                        (_, true) => counts.unmapped_mates += 1,
+                        (true, false) => counts.mt += 1,
+                        (false, false) => counts.numts += 1,
+                    }
+                }
+            }
+        }
+        writer.try_finish()?;
+        Ok(counts)
+    }
+
+    /// The 0-based half-open span of a read that belongs in the output for
+    /// `target`, or `None` to leave it out. Unmapped reads placed next to
+    /// their mate are only kept with `--include-unmapped-mates`.
+    fn keep(&self, record: &bam::Record, target: &Target) -> Result<Option<(usize, usize)>, Box<dyn Error>> {
+        if record.reference_sequence_id().transpose()? != Some(target.id) {
+            return Ok(None);
+        }
+        let Some(start) = record.alignment_start().transpose()? else {
+            return Ok(None);
+        };
+        let start = usize::from(start) - 1;
+        let end = if record.flags().is_unmapped() {
+            if !self.include_unmapped_mates {
+                return Ok(None);
+            }
+            start + 1
+        } else {
+            record
+                .alignment_end()
+                .transpose()?
+                .map_or(start + 1, usize::from)
+        };
+        if end <= target.start || start >= target.end {
+            return Ok(None);
+        }
+        Ok(Some((start, end)))
+    }
+
+    /// noodles has no CRAM support here, so CRAM goes through `samtools view`
+    /// and the counts come from reading the result back.
+    fn extract_cram(&self) -> Result<ExtractCounts, Box<dyn Error>> {
+        let reference = self
+            .reference_fasta
+            .as_ref()
+            .ok_or("CRAM input requires --reference")?;
+
+        // samtools resolves the contig names itself, so regions go by name
+        let contig = self.cram_mt_contig(reference)?;
+        let mut regions = vec![contig.clone()];
+        if let Some(numts) = &self.numts {
+            for interval in mity_util::read_bed(numts)? {
+                regions.push(format!("{}:{}-{}", interval.contig, interval.start + 1, interval.end));
+            }
+        }
+
+        let mut command = mity_util::tool_command(Tool::Samtools)?;
+        command
+            .arg("view")
+            .arg("-b")
+            .arg("-M")
+            .arg("--reference")
+            .arg(reference)
+            .arg("-o")
+            .arg(&self.output_bam_path);
+        if !self.include_unmapped_mates {
+            command.arg("-F").arg("4");
+        }
+        command.arg(&self.bam).args(&regions);
+        debug!("{:?}", command);
+
+        let status = command.status()?;
+        if !status.success() {
+            return Err(MityError::tool("samtools view", status.code(), b"").into());
+        }
+
+        let mut reader = bam::io::reader::Builder.build_from_path(&self.output_bam_path)?;
+        let header = reader.read_header()?;
+        let mt_id = header.reference_sequences().get_index_of(contig.as_bytes());
+        let mut counts = ExtractCounts::default();
+        for result in reader.records() {
+            let record = result?;
+            if record.flags().is_unmapped() {
+                counts.unmapped_mates += 1;
+            } else if record.reference_sequence_id().transpose()? == mt_id {
+                counts.mt += 1;
+            } else {
+                counts.numts += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// The MT contig name of a CRAM, taken from `samtools view -H`.
+    fn cram_mt_contig(&self, reference: &Path) -> Result<String, Box<dyn Error>> {
+        let output = mity_util::tool_command(Tool::Samtools)?
+            .arg("view")
+            .arg("-H")
+            .arg("--reference")
+            .arg(reference)
+            .arg(&self.bam)
+            .output()?;
+        if !output.status.success() {
+            return Err(MityError::tool("samtools view -H", output.status.code(), &output.stderr).into());
+        }
+        let header = String::from_utf8_lossy(&output.stdout);
+        let contigs: Vec<&str> = header
+            .lines()
+            .filter(|line| line.starts_with("@SQ"))
+            .filter_map(|line| line.split('\t').find_map(|field| field.strip_prefix("SN:")))
+            .filter(|name| mity_util::is_mt_contig(name))
+            .collect();
+        match contigs.as_slice() {
+            [contig] => Ok(contig.to_string()),
+            _ => Err(format!("Expected exactly one mitochondrial contig, found: {:?}", contigs).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mt_sequence, read, reference_read, write_bam};
+    use noodles::sam::alignment::record::Flags;
+    use noodles::sam::alignment::RecordBuf;
+    use std::fs;
+
+    fn autosomal_read(name: &str, start: usize) -> RecordBuf {
+        let mut record = read(name, start, &mt_sequence()[..100]);
+        *record.reference_sequence_id_mut() = Some(1);
+        record
+    }
+
+    fn extracted_names(path: &Path) -> Vec<String> {
+        let mut reader = bam::io::reader::Builder.build_from_path(path).unwrap();
+        reader.read_header().unwrap();
+        reader
+            .records()
+            .map(|record| String::from_utf8_lossy(record.unwrap().name().unwrap()).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn mt_reads_numt_regions_and_unmapped_mates_are_extracted() {
+        let temp = tempfile::tempdir().unwrap();
+        let header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:MT\tLN:16569\n@SQ\tSN:1\tLN:100000\n@RG\tID:a\tSM:a\n";
+        let mut mate = reference_read("mate", 500, 100);
+        *mate.flags_mut() = Flags::UNMAPPED;
+        let records = [
+            reference_read("mt1", 1, 100),
+            reference_read("mt2", 16000, 100),
+            mate,
+            autosomal_read("numt", 5001),
+            autosomal_read("both_numts", 5101),
+            autosomal_read("nuclear", 9001),
+        ];
+        let bam = write_bam(&temp.path().join("s1.bam"), header, &records);
+        // Overlapping regions are merged, so the read over both is written once
+        let numts = temp.path().join("numts.bed");
+        fs::write(&numts, "1\t5050\t5200\n1\t5000\t5150\n").unwrap();
+
+        let out = temp.path().join("out");
+        fs::create_dir(&out).unwrap();
+        ExtractMt::new(false, bam.clone(), out.clone(), None, false, Some(numts), None).run().unwrap();
+        assert_eq!(extracted_names(&out.join("s1.MT.bam")), ["mt1", "mt2", "numt", "both_numts"]);
+        assert!(out.join("s1.MT.bam.bai").exists());
+
+        ExtractMt::new(false, bam, out.clone(), Some("mates".to_string()), true, None, None).run().unwrap();
+        assert_eq!(extracted_names(&out.join("mates.MT.bam")), ["mt1", "mate", "mt2"]);
+    }
+}
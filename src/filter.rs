@@ -0,0 +1,178 @@
+use log::{debug, info};
+use noodles::bgzf;
+use std::error::Error;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::error::MityError;
+use crate::mity_util;
+use crate::normalise::{FilterSettings, VariantFilter};
+use crate::stats::{RunSummary, Timer};
+
+/// Applies mity's filtering step to a VCF from any caller, without
+/// normalising or sorting it first.
+pub struct Filter {
+    debug: bool,
+    vcf: PathBuf,
+    output_dir: PathBuf,
+    prefix: String,
+    settings: FilterSettings,
+
+    filtered_vcf_path: PathBuf,
+}
+
+impl Filter {
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+        settings: FilterSettings,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&vcf));
+        Filter {
+            debug,
+            filtered_vcf_path: output_dir.join(format!("{}.mity.filter.vcf.gz", prefix)),
+            vcf,
+            output_dir,
+            prefix,
+            settings,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+        debug!("Filtering {} with {:?}", self.vcf.display(), self.settings);
+
+        let mut summary = RunSummary::new("mity filter");
+
+        let timer = Timer::start();
+        let reader = mity_util::open_vcf(&self.vcf)?;
+        let mut writer = bgzf::Writer::new(File::create(&self.filtered_vcf_path)?);
+        let filter_summary = VariantFilter::new(self.settings.clone()).filter_vcf(reader, &mut writer, None, None, None)?;
+        writer.finish()?;
+        summary.record("filtering", timer);
+        summary.set_metric("filters", filter_summary.to_json());
+        filter_summary.log();
+
+        let timer = Timer::start();
+        mity_util::tabix(&self.filtered_vcf_path)?;
+        summary.record("indexing", timer);
+
+        summary.add_output(&self.filtered_vcf_path);
+        summary.log();
+        summary.write_json(
+            &self
+                .output_dir
+                .join(format!("{}.mity.filter.stats.json", self.prefix)),
+        )?;
+        info!("Wrote {}", self.filtered_vcf_path.display());
+
+        Ok(())
+    }
+}
+
+/// Parse `--blacklist` as comma-separated positions or inclusive ranges,
+/// e.g. `302-318,3105-3107`.
+pub fn parse_blacklist(value: &str) -> Result<Vec<u64>, String> {
+    let mut positions = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let parse = |pos: &str| {
+            pos.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid blacklist position: {}", pos))
+        };
+        match item.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("Invalid blacklist range: {}", item));
+                }
+                positions.extend(start..=end);
+            }
+            None => positions.push(parse(item)?),
+        }
+    }
+    Ok(positions)
+}
+
+/// Parse `--sb-range` as `LO,HI`.
+pub fn parse_sb_range(value: &str) -> Result<(f64, f64), String> {
+    let (lo, hi) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Expected LO,HI but got {}", value))?;
+    let parse = |bound: &str| {
+        bound
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid strand bias bound: {}", bound))
+    };
+    let (lo, hi) = (parse(lo)?, parse(hi)?);
+    if !(0.0..=1.0).contains(&lo) || !(0.0..=1.0).contains(&hi) || lo > hi {
+        return Err(format!("Strand bias range must satisfy 0 <= LO <= HI <= 1: {}", value));
+    }
+    Ok((lo, hi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::fs;
+
+    #[test]
+    fn any_callers_vcf_is_filtered_without_being_normalised() {
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("sample.vcf");
+        let info = "DP=500;RO=250;AO=250;SRF=125;SRR=125;SAF=125;SAR=125;QR=9000;MQMR=60";
+        fs::write(
+            &vcf,
+            format!(
+                "##fileformat=VCFv4.2\n##contig=<ID=MT,length=16569>\n\
+                 #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS\n\
+                 MT\t3243\t.\tA\tG\t100\t.\t{info}\tGT:DP:RO:AO\t0/1:500:250:250\n\
+                 MT\t1000\t.\tA\tG\t100\t.\t{info}\tGT:DP:RO:AO\t0/1:500:250:250\n\
+                 MT\t2000\t.\tA\tG,T\t100\t.\t{info}\tGT:DP:RO:AO\t0/1:500:250:250\n"
+            ),
+        )
+        .unwrap();
+
+        let settings = FilterSettings {
+            blacklist: parse_blacklist("3240-3245").unwrap(),
+            ..FilterSettings::default()
+        };
+        Filter::new(false, vcf, temp.path().to_path_buf(), None, settings).run().unwrap();
+
+        // Records keep their order and their multi-allelic ALTs
+        let lines = test_util::read_lines(&temp.path().join("sample.mity.filter.vcf.gz"));
+        let records: Vec<(&str, &str, &str)> = lines
+            .iter()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[1], fields[4], fields[6])
+            })
+            .collect();
+        assert_eq!(records[..2], [("3243", "G", "POS"), ("1000", "G", "PASS")]);
+        assert_eq!(records[2].1, "G,T");
+        assert!(lines.iter().any(|line| line.starts_with("##FILTER=<ID=POS")));
+        assert!(temp.path().join("sample.mity.filter.stats.json").exists());
+    }
+
+    #[test]
+    fn blacklists_and_strand_bias_ranges_are_parsed() {
+        assert_eq!(parse_blacklist("302-304, 310,").unwrap(), [302, 303, 304, 310]);
+        assert!(parse_blacklist("304-302").is_err());
+        assert!(parse_blacklist("3o2").is_err());
+        assert_eq!(parse_sb_range("0.1,0.9").unwrap(), (0.1, 0.9));
+        for invalid in ["0.9,0.1", "0.1", "-0.1,0.9", "0.1,x"] {
+            assert!(parse_sb_range(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
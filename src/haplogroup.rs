@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// A variant as `(position, ref, alt)`.
+pub type Variant = (u64, String, String);
+
+/// Samples with at least this VAF at a site are treated as carrying the
+/// variant when classifying.
+const MIN_VAF: f64 = 0.5;
+
+/// Haplogroup-defining variants loaded from the bundled PhyloTree table.
+///
+/// The table lists each mutation once, against the haplogroup where it
+/// first appears. Ancestry is approximated from the haplogroup names, so the
+/// defining set of `H1a3` also includes the variants of `H1a`, `H1` and `H`.
+pub struct Classifier {
+    variants: HashMap<Variant, String>,
+    haplogroups: HashMap<String, Vec<Variant>>,
+}
+
+/// The best-scoring haplogroup for a set of variants.
+#[derive(Clone, Debug)]
+pub struct Classification {
+    pub haplogroup: String,
+    pub score: f64,
+    pub supporting: Vec<Variant>,
+    pub missing: Vec<Variant>,
+}
+
+impl Classifier {
+    /// The PhyloTree table shipped in the mity assets.
+    pub fn bundled() -> Result<Self> {
+        let path = mity_util::get_mity_dir()?
+            .join("annot_mt")
+            .join("haplotype_data.vcf.gz");
+        Self::load(&path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let reader = mity_util::open_vcf(path)
+            .with_context(|| format!("Failed to open haplogroup table {}", path.display()))?;
+
+        let mut variants = HashMap::new();
+        let mut haplogroups: HashMap<String, Vec<Variant>> = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line)?;
+            let Some(haplogroup) = record.info("phylotree_haplotype") else {
+                continue;
+            };
+            // Some labels carry a stray leading quote from the source table
+            let haplogroup = haplogroup.trim_matches('"').to_string();
+            if haplogroup.is_empty() || haplogroup == "NoLabel" {
+                continue;
+            }
+            let variant = (record.pos, record.reference.clone(), record.alt.clone());
+            haplogroups
+                .entry(haplogroup.clone())
+                .or_default()
+                .push(variant.clone());
+            variants.insert(variant, haplogroup);
+        }
+        debug!(
+            "Loaded {} haplogroup-defining variants for {} haplogroups",
+            variants.len(),
+            haplogroups.len()
+        );
+
+        Ok(Classifier {
+            variants,
+            haplogroups,
+        })
+    }
+
+    /// Whether `variant` defines any haplogroup, i.e. is a common
+    /// polymorphism rather than a private variant.
+    pub fn is_defining(&self, variant: &Variant) -> bool {
+        self.variants.contains_key(variant)
+    }
+
+    /// Variants defining `haplogroup`, including those of its ancestors.
+    fn defining_variants(&self, haplogroup: &str) -> Vec<Variant> {
+        let mut defining = Vec::new();
+        for end in 1..=haplogroup.len() {
+            if !haplogroup.is_char_boundary(end) {
+                continue;
+            }
+            if let Some(variants) = self.haplogroups.get(&haplogroup[..end]) {
+                defining.extend(variants.iter().cloned());
+            }
+        }
+        defining.sort();
+        defining
+    }
+
+    /// Score every haplogroup that shares at least one variant with the
+    /// sample and return the best one. The score is the mean of the fraction
+    /// of defining variants found and the fraction of the sample's known
+    /// variants explained, so both missing and unexplained variants count
+    /// against a candidate.
+    pub fn classify(&self, sample_variants: &HashSet<Variant>) -> Option<Classification> {
+        let known: HashSet<&Variant> = sample_variants
+            .iter()
+            .filter(|variant| self.variants.contains_key(*variant))
+            .collect();
+        if known.is_empty() {
+            return None;
+        }
+
+        let candidates: HashSet<&String> = known
+            .iter()
+            .filter_map(|variant| self.variants.get(*variant))
+            .collect();
+
+        let mut best: Option<Classification> = None;
+        for candidate in candidates {
+            let defining = self.defining_variants(candidate);
+            let (supporting, missing): (Vec<Variant>, Vec<Variant>) = defining
+                .into_iter()
+                .partition(|variant| sample_variants.contains(variant));
+            let hits = supporting.len() as f64;
+            let score = 0.5
+                * (hits / (supporting.len() + missing.len()) as f64 + hits / known.len() as f64);
+
+            let better = match &best {
+                None => true,
+                Some(current) => {
+                    score > current.score
+                        || (score == current.score && candidate.len() > current.haplogroup.len())
+                }
+            };
+            if better {
+                best = Some(Classification {
+                    haplogroup: candidate.clone(),
+                    score,
+                    supporting,
+                    missing,
+                });
+            }
+        }
+        best
+    }
+}
+
+impl Classification {
+    pub fn to_json(&self, sample: &str) -> Value {
+        json!({
+            "sample": sample,
+            "haplogroup": self.haplogroup,
+            "score": self.score,
+            "supporting": self.supporting.iter().map(format_variant).collect::<Vec<_>>(),
+            "missing": self.missing.iter().map(format_variant).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn format_variant(variant: &Variant) -> String {
+    format!("{}{}>{}", variant.0, variant.1, variant.2)
+}
+
+/// Classifies every sample in a VCF into a haplogroup.
+pub struct Haplogroup {
+    debug: bool,
+    vcf: PathBuf,
+    output_dir: PathBuf,
+    prefix: Option<String>,
+}
+
+impl Haplogroup {
+    pub fn new(debug: bool, vcf: PathBuf, output_dir: PathBuf, prefix: Option<String>) -> Self {
+        Haplogroup {
+            debug,
+            vcf,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+
+        let classifier = Classifier::bundled()?;
+        let samples = self.read_sample_variants()?;
+
+        let prefix = self
+            .prefix
+            .clone()
+            .unwrap_or_else(|| mity_util::make_prefix(&self.vcf));
+        let mut summary = String::new();
+        let mut results = Vec::new();
+        for (sample, sample_variants) in &samples {
+            match classifier.classify(sample_variants) {
+                Some(classification) => {
+                    summary.push_str(&format!(
+                        "Sample: {}\nHaplogroup: {} (score {:.3})\nSupporting variants ({}): {}\nMissing defining variants ({}): {}\n\n",
+                        sample,
+                        classification.haplogroup,
+                        classification.score,
+                        classification.supporting.len(),
+                        classification.supporting.iter().map(format_variant).collect::<Vec<_>>().join(" "),
+                        classification.missing.len(),
+                        classification.missing.iter().map(format_variant).collect::<Vec<_>>().join(" "),
+                    ));
+                    results.push(classification.to_json(sample));
+                }
+                None => {
+                    summary.push_str(&format!(
+                        "Sample: {}\nHaplogroup: unknown (no haplogroup-defining variants found)\n\n",
+                        sample
+                    ));
+                    results.push(json!({ "sample": sample, "haplogroup": Value::Null }));
+                }
+            }
+        }
+
+        print!("{}", summary);
+        let text_path = self
+            .output_dir
+            .join(format!("{}.mity.haplogroup.txt", prefix));
+        fs::write(&text_path, &summary)?;
+        let json_path = self
+            .output_dir
+            .join(format!("{}.mity.haplogroup.json", prefix));
+        serde_json::to_writer_pretty(File::create(&json_path)?, &results)?;
+        info!("Wrote {} and {}", text_path.display(), json_path.display());
+
+        Ok(())
+    }
+
+    /// PASS variants carried by each sample: VAF at least `MIN_VAF` when the
+    /// VCF has a VAF field, otherwise a non-reference genotype.
+    fn read_sample_variants(&self) -> Result<Vec<(String, HashSet<Variant>)>> {
+        let reader = mity_util::open_vcf(&self.vcf)?;
+        let mut samples: Vec<String> = Vec::new();
+        let mut variants: Vec<HashSet<Variant>> = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(String::from).collect();
+                variants = vec![HashSet::new(); samples.len()];
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line)?;
+            if record.filter != "PASS" && record.filter != "." {
+                continue;
+            }
+            for (index, alt) in record.alt.split(',').enumerate() {
+                let allele = (index + 1).to_string();
+                let variant = (record.pos, record.reference.clone(), alt.to_string());
+                for (sample, sample_variants) in variants.iter_mut().enumerate() {
+                    let carried = match record.sample_f64(sample, "VAF") {
+                        Some(vaf) => vaf >= MIN_VAF,
+                        None => record
+                            .sample(sample, "GT")
+                            .is_some_and(|gt| gt.split(['/', '|']).any(|a| a == allele)),
+                    };
+                    if carried {
+                        sample_variants.insert(variant.clone());
+                    }
+                }
+            }
+        }
+
+        if samples.is_empty() {
+            anyhow::bail!("{} has no samples", self.vcf.display());
+        }
+        Ok(samples.into_iter().zip(variants).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(pos: u64, reference: &str, alt: &str) -> Variant {
+        (pos, reference.to_string(), alt.to_string())
+    }
+
+    /// A table of H, H1 under it, H1a under that, and U.
+    fn classifier(dir: &Path) -> Classifier {
+        let path = dir.join("haplotypes.vcf");
+        let mut text = String::from("##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+        for (pos, reference, alt, haplogroup) in [
+            (2706, "A", "G", "H"),
+            (7028, "C", "T", "H"),
+            (3010, "G", "A", "H1"),
+            (16162, "A", "G", "H1a"),
+            (11467, "A", "G", "U"),
+            (12308, "A", "G", "\"U"),
+            (73, "A", "G", "NoLabel"),
+        ] {
+            text.push_str(&format!("MT\t{}\t.\t{}\t{}\t.\t.\tphylotree_haplotype={}\n", pos, reference, alt, haplogroup));
+        }
+        fs::write(&path, text).unwrap();
+        Classifier::load(&path).unwrap()
+    }
+
+    #[test]
+    fn the_haplogroup_explaining_most_variants_with_its_ancestors_wins() {
+        let temp = tempfile::tempdir().unwrap();
+        let classifier = classifier(temp.path());
+        assert!(classifier.is_defining(&variant(12308, "A", "G")));
+        assert!(!classifier.is_defining(&variant(73, "A", "G")));
+
+        // H1 has all three of H and H1; H explains only two of them
+        let sample: HashSet<Variant> =
+            [variant(2706, "A", "G"), variant(7028, "C", "T"), variant(3010, "G", "A"), variant(16519, "T", "C")]
+                .into_iter()
+                .collect();
+        let h1 = classifier.classify(&sample).unwrap();
+        assert_eq!(h1.haplogroup, "H1");
+        assert_eq!(h1.score, 1.0);
+        assert_eq!(h1.supporting.len(), 3);
+        assert!(h1.missing.is_empty());
+
+        // Without 7028, H1 misses one of its three and still beats H
+        let sample: HashSet<Variant> = [variant(2706, "A", "G"), variant(3010, "G", "A")].into_iter().collect();
+        let partial = classifier.classify(&sample).unwrap();
+        assert_eq!(partial.haplogroup, "H1");
+        assert_eq!(partial.missing, [variant(7028, "C", "T")]);
+        assert!((partial.score - 0.5 * (2.0 / 3.0 + 1.0)).abs() < 1e-12);
+
+        let private: HashSet<Variant> = [variant(16519, "T", "C")].into_iter().collect();
+        assert!(classifier.classify(&private).is_none());
+    }
+
+    #[test]
+    fn samples_carry_pass_variants_by_vaf_or_genotype() {
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("in.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta\tb\n\
+             MT\t2706\t.\tA\tG\t.\tPASS\t.\tGT:VAF\t1/1:0.98\t0/1:0.2\n\
+             MT\t3010\t.\tG\tA\t.\tLowQual\t.\tGT:VAF\t1/1:0.99\t1/1:0.99\n\
+             MT\t7028\t.\tC\tT,G\t.\t.\t.\tGT\t0/2\t0/1\n",
+        )
+        .unwrap();
+        let samples = Haplogroup::new(false, vcf, temp.path().to_path_buf(), None).read_sample_variants().unwrap();
+        let (a, b) = (&samples[0], &samples[1]);
+        assert_eq!((a.0.as_str(), b.0.as_str()), ("a", "b"));
+        assert_eq!(a.1, [variant(2706, "A", "G"), variant(7028, "C", "G")].into_iter().collect());
+        assert_eq!(b.1, [variant(7028, "C", "T")].into_iter().collect());
+    }
+}
@@ -0,0 +1,272 @@
+use log::{debug, info, warn};
+use noodles::bgzf;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::MityError;
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Length of the hg19 Yoruba chrM (NC_001807), which is not rCRS.
+const YORUBA_LENGTH: usize = 16571;
+
+/// Renames the mitochondrial contig of a VCF between `MT` and `chrM`.
+///
+/// Both names refer to rCRS in GRCh37 and hg38, so only the name changes.
+pub struct Liftover {
+    debug: bool,
+    vcf: PathBuf,
+    to: String,
+    rename_info: bool,
+    output_dir: PathBuf,
+    prefix: String,
+
+    output_vcf_path: PathBuf,
+}
+
+impl Liftover {
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        to: String,
+        rename_info: bool,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+    ) -> Self {
+        let prefix = prefix.unwrap_or_else(|| mity_util::make_prefix(&vcf));
+        Liftover {
+            debug,
+            output_vcf_path: output_dir.join(format!("{}.mity.{}.vcf.gz", prefix, to)),
+            vcf,
+            to,
+            rename_info,
+            output_dir,
+            prefix,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+        debug!(
+            "Renaming the MT contig of {} to {} in {} with prefix {}",
+            self.vcf.display(),
+            self.to,
+            self.output_dir.display(),
+            self.prefix
+        );
+
+        let (header, records) = read_vcf(&self.vcf)?;
+        let from = self.source_contig(&header, &records)?;
+        if from == self.to {
+            info!("{} already uses {}; only rewriting the file", self.vcf.display(), self.to);
+        }
+
+        let contig_order: Vec<String> = header
+            .iter()
+            .filter_map(|line| contig_id(line))
+            .map(|id| if id == from { self.to.clone() } else { id.to_string() })
+            .collect();
+
+        let mut writer = bgzf::Writer::new(File::create(&self.output_vcf_path)?);
+        for line in &header {
+            match contig_id(line) {
+                Some(id) if id == from => {
+                    writeln!(writer, "{}", line.replacen(&format!("ID={}", from), &format!("ID={}", self.to), 1))?
+                }
+                _ => writeln!(writer, "{}", line)?,
+            }
+        }
+
+        let mut renamed = Vec::with_capacity(records.len());
+        let mut renamed_count = 0;
+        for line in &records {
+            let mut record = VcfRecord::parse(line)?;
+            if record.chrom == from {
+                record.chrom = self.to.clone();
+                renamed_count += 1;
+            }
+            if self.rename_info {
+                let (locus_from, locus_to) = (format!("{}:", from), format!("{}:", self.to));
+                for (_, value) in record.info.iter_mut() {
+                    if let Some(value) = value {
+                        *value = value.replace(&locus_from, &locus_to);
+                    }
+                }
+            }
+            renamed.push(record);
+        }
+
+        // The renamed contig can sort differently among the header contigs
+        let rank = |record: &VcfRecord| {
+            (
+                contig_order
+                    .iter()
+                    .position(|id| *id == record.chrom)
+                    .unwrap_or(usize::MAX),
+                record.pos,
+            )
+        };
+        if !renamed.windows(2).all(|pair| rank(&pair[0]) <= rank(&pair[1])) {
+            info!("Re-sorting records into header contig order");
+            renamed.sort_by_key(rank);
+        }
+        for record in &renamed {
+            writeln!(writer, "{}", record)?;
+        }
+        writer.finish()?;
+
+        mity_util::tabix(&self.output_vcf_path)?;
+        info!(
+            "Renamed {} to {} in {} records; wrote {}",
+            from,
+            self.to,
+            renamed_count,
+            self.output_vcf_path.display()
+        );
+        Ok(())
+    }
+
+    /// The contig being renamed. Refuses the hg19 Yoruba chrM, whose
+    /// coordinates differ from rCRS, so renaming it would be silently wrong.
+    fn source_contig(&self, header: &[String], records: &[String]) -> Result<String, Box<dyn Error>> {
+        let declared: Vec<(&str, Option<usize>)> = header
+            .iter()
+            .filter_map(|line| Some((contig_id(line)?, contig_length(line))))
+            .filter(|(id, _)| *id == "MT" || *id == "chrM")
+            .collect();
+
+        let (contig, length) = match declared.as_slice() {
+            [(contig, length)] => (contig.to_string(), *length),
+            [] => {
+                let first = records
+                    .iter()
+                    .map(|line| line.split('\t').next().unwrap_or_default())
+                    .find(|chrom| *chrom == "MT" || *chrom == "chrM")
+                    .ok_or_else(|| format!("No MT or chrM records in {}", self.vcf.display()))?;
+                (first.to_string(), None)
+            }
+            _ => return Err(format!("{} declares both MT and chrM", self.vcf.display()).into()),
+        };
+
+        match length {
+            Some(YORUBA_LENGTH) => Err(format!(
+                "{} is {} bp, the hg19 Yoruba mitochondrial sequence (NC_001807). Its coordinates \
+                 differ from rCRS, so renaming it to {} would misplace variants. Realign to an \
+                 rCRS reference (GRCh37 MT or hg38 chrM) instead",
+                contig, YORUBA_LENGTH, self.to
+            )
+            .into()),
+            Some(_) => Ok(contig),
+            None => {
+                warn!(
+                    "{} has no length in the header, so it cannot be checked against the hg19 \
+                     Yoruba sequence; assuming rCRS",
+                    contig
+                );
+                Ok(contig)
+            }
+        }
+    }
+}
+
+/// Header lines and record lines of a VCF.
+fn read_vcf(vcf: &Path) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let mut header = Vec::new();
+    let mut records = Vec::new();
+    for line in mity_util::open_vcf(vcf)?.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            header.push(line);
+        } else if !line.is_empty() {
+            records.push(line);
+        }
+    }
+    Ok((header, records))
+}
+
+fn contig_id(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("##contig=<")?;
+    rest.trim_end_matches('>')
+        .split(',')
+        .find_map(|field| field.strip_prefix("ID="))
+}
+
+fn contig_length(line: &str) -> Option<usize> {
+    let rest = line.strip_prefix("##contig=<")?;
+    rest.trim_end_matches('>')
+        .split(',')
+        .find_map(|field| field.strip_prefix("length="))?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::fs;
+
+    fn records(path: &Path) -> Vec<String> {
+        test_util::read_lines(path)
+            .into_iter()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split('\t').take(2).collect::<Vec<_>>().join(":"))
+            .collect()
+    }
+
+    #[test]
+    fn mt_is_renamed_and_resorted_into_header_order() {
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("s1.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=248956422>\n##contig=<ID=MT,length=16569>\n\
+             ##contig=<ID=chrY,length=57227415>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+             chr1\t100\t.\tA\tG\t.\tPASS\t.\n\
+             chrY\t50\t.\tA\tG\t.\tPASS\t.\n\
+             MT\t73\t.\tA\tG\t.\tPASS\tLOCUS=MT:73\n",
+        )
+        .unwrap();
+
+        Liftover::new(false, vcf.clone(), "chrM".to_string(), true, temp.path().to_path_buf(), None).run().unwrap();
+        let output = temp.path().join("s1.mity.chrM.vcf.gz");
+        let lines = test_util::read_lines(&output);
+        assert!(lines.contains(&"##contig=<ID=chrM,length=16569>".to_string()));
+        assert_eq!(records(&output), ["chr1:100", "chrM:73", "chrY:50"]);
+        assert!(lines.iter().any(|line| line.ends_with("LOCUS=chrM:73")));
+
+        // Without --rename-info INFO values are left alone
+        Liftover::new(false, vcf, "chrM".to_string(), false, temp.path().to_path_buf(), Some("kept".to_string()))
+            .run()
+            .unwrap();
+        let lines = test_util::read_lines(&temp.path().join("kept.mity.chrM.vcf.gz"));
+        assert!(lines.iter().any(|line| line.ends_with("LOCUS=MT:73")));
+    }
+
+    #[test]
+    fn the_hg19_yoruba_sequence_is_refused() {
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("hg19.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n##contig=<ID=chrM,length=16571>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+             chrM\t73\t.\tA\tG\t.\tPASS\t.\n",
+        )
+        .unwrap();
+        let err = Liftover::new(false, vcf, "MT".to_string(), false, temp.path().to_path_buf(), None)
+            .run()
+            .unwrap_err();
+        assert!(err.to_string().contains("NC_001807"), "{}", err);
+        assert!(!temp.path().join("hg19.mity.MT.vcf.gz").exists());
+    }
+}
@@ -0,0 +1,266 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::mity_util;
+use crate::progress;
+use crate::style::{self, Stream, Style};
+
+/// Environment variable consulted when `--log-filter` is not given.
+pub const LOG_ENV_VAR: &str = "RUST_LOG";
+
+/// Target of stage headings, such as the start of a run summary, which are
+/// shown in bold. They are filtered like the rest of mity's logs.
+pub const HEADING_TARGET: &str = "mity_rs::heading";
+
+/// Filter applied when neither `--log-filter` nor `RUST_LOG` is set.
+const DEFAULT_FILTER: &str = "warn,mity=info";
+
+/// Our crate's module paths start with `mity_rs`, but users think of the tool
+/// as `mity`, so directives may use either.
+const CRATE_ALIAS: (&str, &str) = ("mity", "mity_rs");
+
+/// A single `target=level` (or bare `level`) logging directive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directive {
+    pub target: Option<String>,
+    pub level: LevelFilter,
+}
+
+/// Parsed, env_logger-style list of logging directives, for example
+/// `mity::normalise=trace,mity=info,warn`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFilter {
+    directives: Vec<Directive>,
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut directives = Vec::new();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let directive = match part.split_once('=') {
+                Some((target, level)) => Directive {
+                    target: Some(canonical_target(target.trim())),
+                    level: parse_level(level.trim())?,
+                },
+                None => match LevelFilter::from_str(part) {
+                    Ok(level) => Directive {
+                        target: None,
+                        level,
+                    },
+                    // A bare module name enables everything for it
+                    Err(_) => Directive {
+                        target: Some(canonical_target(part)),
+                        level: LevelFilter::Trace,
+                    },
+                },
+            };
+            directives.push(directive);
+        }
+        Ok(LogFilter { directives })
+    }
+
+    /// Append directives that take precedence over the existing ones.
+    pub fn extend(&mut self, other: LogFilter) {
+        self.directives.extend(other.directives);
+    }
+
+    /// The level that applies to `target`: the directive with the longest
+    /// matching module prefix wins, and later directives win ties.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let mut best: Option<(usize, LevelFilter)> = None;
+        for directive in &self.directives {
+            let length = match &directive.target {
+                None => 0,
+                Some(prefix) if target_matches(target, prefix) => prefix.len(),
+                Some(_) => continue,
+            };
+            if best.is_none_or(|(best_length, _)| length >= best_length) {
+                best = Some((length, directive.level));
+            }
+        }
+        best.map(|(_, level)| level).unwrap_or(LevelFilter::Error)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|d| d.level)
+            .max()
+            .unwrap_or(LevelFilter::Error)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| format!("Invalid log level '{}'", level))
+}
+
+fn canonical_target(target: &str) -> String {
+    let (alias, name) = CRATE_ALIAS;
+    if target == alias {
+        name.to_string()
+    } else if let Some(rest) = target.strip_prefix(&format!("{}::", alias)) {
+        format!("{}::{}", name, rest)
+    } else {
+        target.to_string()
+    }
+}
+
+fn target_matches(target: &str, prefix: &str) -> bool {
+    target == prefix
+        || (target.starts_with(prefix) && target[prefix.len()..].starts_with("::"))
+}
+
+/// Work out the effective filter: `--log-filter` wins, then `RUST_LOG`, then
+/// the default. `--debug` is shorthand for `mity=debug` unless an explicit
+/// `--log-filter` was given.
+pub fn resolve_filter(
+    log_filter: Option<&str>,
+    env_filter: Option<&str>,
+    debug: bool,
+) -> Result<LogFilter, String> {
+    if let Some(spec) = log_filter {
+        return LogFilter::parse(spec);
+    }
+    let mut filter = LogFilter::parse(env_filter.unwrap_or(DEFAULT_FILTER))?;
+    if debug {
+        filter.extend(LogFilter::parse("mity=debug")?);
+    }
+    Ok(filter)
+}
+
+struct MityLogger {
+    filter: LogFilter,
+}
+
+impl Log for MityLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let heading = record.target() == HEADING_TARGET;
+        let target = if heading {
+            record.module_path().unwrap_or(HEADING_TARGET)
+        } else {
+            record.target()
+        };
+        let style = match record.level() {
+            Level::Error => Some(Style::Red),
+            Level::Warn => Some(Style::Yellow),
+            _ if heading => Some(Style::Bold),
+            _ => None,
+        };
+        // Pad before painting, as the escape codes would count towards the width
+        let level = format!("{:<5}", record.level());
+        let (level, message) = match style {
+            Some(style) => (
+                style::paint(level, style, Stream::Stderr),
+                style::paint(record.args(), style, Stream::Stderr),
+            ),
+            None => (level, record.args().to_string()),
+        };
+        progress::suspend(|| {
+            let mut stderr = std::io::stderr().lock();
+            let _ = writeln!(
+                stderr,
+                "{} {} [{}] {}",
+                mity_util::utc_timestamp(),
+                level,
+                target,
+                message
+            );
+        });
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Install the global logger. Logs go to stderr so stdout stays free for
+/// streamed output.
+pub fn init(filter: LogFilter) -> Result<(), log::SetLoggerError> {
+    log::set_max_level(filter.max_level());
+    log::set_boxed_logger(Box::new(MityLogger { filter }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives() {
+        let filter = LogFilter::parse(" mity::normalise=trace, mity=info ,warn,,noodles").unwrap();
+        assert_eq!(
+            filter.directives,
+            [
+                Directive {
+                    target: Some("mity_rs::normalise".to_string()),
+                    level: LevelFilter::Trace,
+                },
+                Directive {
+                    target: Some("mity_rs".to_string()),
+                    level: LevelFilter::Info,
+                },
+                Directive {
+                    target: None,
+                    level: LevelFilter::Warn,
+                },
+                Directive {
+                    target: Some("noodles".to_string()),
+                    level: LevelFilter::Trace,
+                },
+            ]
+        );
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(LogFilter::parse("").unwrap().max_level(), LevelFilter::Error);
+        assert_eq!(LogFilter::parse("mity=loud").unwrap_err(), "Invalid log level 'loud'");
+    }
+
+    #[test]
+    fn longest_matching_target_wins() {
+        let filter = LogFilter::parse("mity=info,warn,mity::call=debug").unwrap();
+        assert_eq!(filter.level_for("mity_rs::call"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("mity_rs::call::windows"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("mity_rs::caller"), LevelFilter::Info);
+        assert_eq!(filter.level_for("mity_rs"), LevelFilter::Info);
+        assert_eq!(filter.level_for("reqwest"), LevelFilter::Warn);
+        // Later directives win ties
+        let filter = LogFilter::parse("mity=info,mity_rs=error").unwrap();
+        assert_eq!(filter.level_for("mity_rs::call"), LevelFilter::Error);
+        assert_eq!(LogFilter::parse("mity=info").unwrap().level_for("reqwest"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn log_filter_beats_rust_log_beats_the_default() {
+        let level = |filter: LogFilter| filter.level_for("mity_rs::call");
+        assert_eq!(level(resolve_filter(None, None, false).unwrap()), LevelFilter::Info);
+        assert_eq!(resolve_filter(None, None, false).unwrap().level_for("noodles"), LevelFilter::Warn);
+        assert_eq!(level(resolve_filter(None, Some("mity=trace"), false).unwrap()), LevelFilter::Trace);
+        assert_eq!(
+            level(resolve_filter(Some("mity=error"), Some("mity=trace"), false).unwrap()),
+            LevelFilter::Error
+        );
+        assert!(resolve_filter(None, Some("mity=loud"), false).is_err());
+        // An explicit --log-filter is used even when RUST_LOG is invalid
+        assert!(resolve_filter(Some("info"), Some("mity=loud"), false).is_ok());
+    }
+
+    #[test]
+    fn debug_raises_mity_unless_log_filter_is_given() {
+        let level = |filter: LogFilter| filter.level_for("mity_rs::call");
+        assert_eq!(level(resolve_filter(None, None, true).unwrap()), LevelFilter::Debug);
+        assert_eq!(level(resolve_filter(None, Some("mity=warn"), true).unwrap()), LevelFilter::Debug);
+        // A more specific RUST_LOG directive still wins over -d
+        assert_eq!(
+            level(resolve_filter(None, Some("mity::call=trace"), true).unwrap()),
+            LevelFilter::Trace
+        );
+        assert_eq!(level(resolve_filter(Some("mity=warn"), None, true).unwrap()), LevelFilter::Warn);
+        assert_eq!(resolve_filter(None, None, true).unwrap().level_for("noodles"), LevelFilter::Warn);
+    }
+}
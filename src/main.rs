@@ -1,22 +1,179 @@
 mod check;
+mod logging;
 mod mity_util;
 
+mod aligner;
+mod annotate;
+mod circular;
 mod call;
+mod checksum;
+mod cohort;
+mod compare;
+mod config;
+mod consensus;
+mod contamination;
+mod haplogroup;
+mod liftover;
+mod copy_number;
+mod coverage;
+mod denovo;
+mod error;
+mod extract_mt;
+mod filter;
 mod normalise;
+mod pedigree;
+mod pileup;
+mod plot;
+mod pon;
+mod preset;
+mod progress;
+mod read_filter;
 mod report;
+mod rg_stats;
 mod merge;
 mod runall;
+mod sample_map;
+mod stats;
+mod style;
+mod validate;
+mod vcf_record;
+mod vcf_stats;
+#[cfg(test)]
+mod test_util;
 
-use call::Call;
-use normalise::Normalise;
+use annotate::{Annotate, AnnotationVcf};
+use call::{Call, CallSettings, OutputFormat};
+use cohort::CohortReport;
+use compare::Compare;
+use config::Config;
+use consensus::Consensus;
+use contamination::Contamination;
+use copy_number::CopyNumber;
+use coverage::Coverage;
+use denovo::{Denovo, DenovoSettings};
+use extract_mt::ExtractMt;
+use filter::Filter;
+use haplogroup::Haplogroup;
+use liftover::Liftover;
+use normalise::{FilterSettings, Normalise, NormaliseSettings};
+use plot::Plot;
+use pon::BuildPon;
+use preset::Preset;
+use read_filter::{NumtFilter, ReadFilter};
+use validate::Validate;
+use vcf_stats::{StatsFormat, VcfStats};
+use clap::parser::ValueSource;
 use clap::{Arg, ArgAction, Command};
+use mity_util::{Tool, ToolVersion};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
-fn handle_call_command(call_matches: &clap::ArgMatches) {
+fn handle_annotate_command(annotate_matches: &clap::ArgMatches) {
+    let debug = annotate_matches.get_flag("debug");
+    let vcf = annotate_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let output_dir = annotate_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = annotate_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+    let with_report = annotate_matches.get_flag("with_report");
+    let split_by_type = annotate_matches.get_flag("split_by_type");
+    let annotation_beds: Vec<(String, PathBuf)> = annotate_matches
+        .get_many::<(String, PathBuf)>("annotation_bed")
+        .map(|beds| beds.cloned().collect())
+        .unwrap_or_default();
+    let pedigree = annotate_matches.get_one::<PathBuf>("pedigree").cloned();
+    let maternal_min_vaf = *annotate_matches
+        .get_one::<f64>("maternal_min_vaf")
+        .expect("Has a default");
+    let strand_table = annotate_matches.get_flag("strand_table");
+    let vcfanno_config = annotate_matches.get_one::<String>("vcfanno_config").map(PathBuf::from);
+    let annotation_vcfs: Vec<AnnotationVcf> = annotate_matches
+        .get_many::<AnnotationVcf>("annotation_vcf")
+        .map(|sources| sources.cloned().collect())
+        .unwrap_or_default();
+    let keep = annotate_matches.get_flag("keep");
+
+    let annotate = Annotate::new(
+        debug,
+        vcf,
+        output_dir,
+        prefix,
+        with_report,
+        split_by_type,
+        annotation_beds,
+        pedigree,
+        maternal_min_vaf,
+        strand_table,
+        vcfanno_config,
+        annotation_vcfs,
+        keep,
+    );
+    match annotate.run() {
+        Ok(()) => {
+            println!("Annotate command completed successfully.");
+        }
+        Err(e) => {
+            error::exit_with_error("annotate", e.as_ref());
+        }
+    }
+}
+
+/// The reference FASTA and genome file: the `--custom-reference-fasta` and
+/// `--custom-reference-genome` when given, otherwise the installed ones for
+/// `--reference`. Exits with an error naming the flag when a custom file is
+/// missing or the custom FASTA has no `.fai`.
+fn select_reference_files(command: &str, matches: &clap::ArgMatches, reference: &str) -> (PathBuf, PathBuf) {
+    let fasta = matches.get_one::<PathBuf>("custom_reference_fasta");
+    let genome = matches.get_one::<PathBuf>("custom_reference_genome");
+    for (flag, path) in [("--custom-reference-fasta", fasta), ("--custom-reference-genome", genome)] {
+        if let Some(path) = path.filter(|path| !path.exists()) {
+            let e: Box<dyn std::error::Error> = anyhow::Error::new(error::MityError::MissingFile(path.clone()))
+                .context(flag)
+                .into();
+            error::exit_with_error(command, e.as_ref());
+        }
+    }
+    if let Some(fasta) = fasta.filter(|fasta| !check::fai_path(fasta).exists()) {
+        let e: Box<dyn std::error::Error> = format!(
+            "--custom-reference-fasta {} has no .fai index; run samtools faidx {}",
+            fasta.display(),
+            fasta.display()
+        )
+        .into();
+        error::exit_with_error(command, e.as_ref());
+    }
+
+    let reference_fasta = mity_util::select_reference_fasta(reference, fasta.map(PathBuf::as_path))
+        .unwrap_or_else(|e| error::exit_with_error(command, error::boxed(e).as_ref()));
+    let reference_genome = mity_util::select_reference_genome(reference, genome.map(PathBuf::as_path))
+        .unwrap_or_else(|e| error::exit_with_error(command, error::boxed(e).as_ref()));
+    (reference_fasta, reference_genome)
+}
+
+/// The value of an option given on the command line or in a config file,
+/// rather than its built-in default. Config values reach clap as defaults,
+/// so `configured` names the options the config sets.
+fn given<T: Clone + Send + Sync + 'static>(matches: &clap::ArgMatches, id: &str, configured: &[String]) -> Option<T> {
+    match matches.value_source(id) {
+        None => return None,
+        Some(ValueSource::DefaultValue) if !configured.iter().any(|option| option == id) => return None,
+        Some(_) => {}
+    }
+    matches.get_one::<T>(id).cloned()
+}
+
+fn handle_call_command(call_matches: &clap::ArgMatches, configured: &[String]) {
     let debug = call_matches.get_flag("debug");
     let files = call_matches
-        .get_many::<String>("files")
+        .get_many::<PathBuf>("files")
         .expect("Required argument")
-        .map(|s| s.to_string())
+        .cloned()
         .collect();
     let reference = call_matches
         .get_one::<String>("reference")
@@ -24,26 +181,115 @@ fn handle_call_command(call_matches: &clap::ArgMatches) {
     let prefix = call_matches
         .get_one::<String>("prefix")
         .map(|s| s.to_string());
-    let min_mq = call_matches.get_one::<u32>("min_mapping_quality").copied();
-    let min_bq = call_matches.get_one::<u32>("min_base_quality").copied();
-    let min_af = call_matches
-        .get_one::<f32>("min_alternate_fraction")
-        .copied();
-    let min_ac = call_matches.get_one::<u32>("min_alternate_count").copied();
-    let p_val = call_matches.get_one::<f32>("p").copied();
+    // The thresholds not given take their values from the preset
+    let preset = call_matches
+        .get_one::<String>("preset")
+        .expect("Has a default")
+        .parse::<Preset>()
+        .expect("Checked by clap");
+    let min_mq = given::<u32>(call_matches, "min_mapping_quality", configured);
+    let min_bq = given::<u32>(call_matches, "min_base_quality", configured);
+    let min_af = given::<f32>(call_matches, "min_alternate_fraction", configured);
+    let min_ac = given::<u32>(call_matches, "min_alternate_count", configured);
+    let p_val = given::<f32>(call_matches, "p", configured);
     let output_dir = call_matches
-        .get_one::<String>("output_dir")
+        .get_one::<PathBuf>("output_dir")
         .expect("Required argument")
-        .to_string();
+        .clone();
     let region = call_matches
         .get_one::<String>("region")
         .map(|s| s.to_string());
+    let exclude_regions: Vec<String> = call_matches
+        .get_many::<String>("exclude_region")
+        .map(|regions| regions.cloned().collect())
+        .unwrap_or_default();
+    let exclude_bed = call_matches.get_one::<PathBuf>("exclude_bed").cloned();
+    let region_bed = call_matches.get_one::<PathBuf>("region_bed").cloned();
+    let create_index = call_matches.get_flag("create_index");
+    let force = call_matches.get_flag("force");
+    let split_samples = call_matches.get_flag("split_samples");
+    let no_progress = call_matches.get_flag("no_progress");
+    let max_depth = call_matches.get_one::<u32>("max_depth").copied();
+    let ploidy = *call_matches.get_one::<u32>("ploidy").expect("Has a default");
+    let read_filter = ReadFilter {
+        duplicates: *call_matches.get_one::<bool>("exclude_duplicates").expect("Has a default"),
+        secondary: *call_matches.get_one::<bool>("exclude_secondary").expect("Has a default"),
+        supplementary: *call_matches.get_one::<bool>("exclude_supplementary").expect("Has a default"),
+        end_exclusion: *call_matches.get_one::<u32>("read_end_exclusion").expect("Has a default"),
+    };
+    let skip_reference_check = call_matches.get_flag("skip_reference_check");
+    let report_all_sites = call_matches.get_flag("report_all_sites");
+    let circular = call_matches.get_flag("circular");
+    let min_freebayes_version = *call_matches
+        .get_one::<ToolVersion>("min_freebayes_version")
+        .expect("Has a default");
     let bam_file_list = call_matches.get_flag("bam_file_list");
     let keep = call_matches.get_flag("keep");
     let normalise = call_matches.get_flag("normalise");
+    let sort_inputs = call_matches.get_flag("sort_inputs");
+    let sample_map = call_matches.get_one::<PathBuf>("sample_map").cloned();
+    let rg_stats = call_matches.get_flag("rg_stats");
+    let coverage_output = call_matches.get_flag("coverage_output");
+    let error_on_empty = call_matches.get_flag("error_on_empty");
+    let strict_windows = call_matches.get_flag("strict_windows");
+    let output_format = call_matches
+        .get_one::<String>("output_format")
+        .and_then(|name| OutputFormat::parse(name))
+        .expect("Checked by clap");
+    let adaptive_min_ac = call_matches.get_flag("adaptive_min_ac");
+    let auto_mq = call_matches.get_flag("auto_mq");
+    let per_sample_filter = call_matches.get_flag("per_sample_filter");
+    let (reference_fasta, reference_genome) = select_reference_files("call", call_matches, reference);
+    let numt_bed = match call_matches.get_one::<PathBuf>("numt_bed") {
+        Some(bed) => Some(bed.clone()),
+        None if call_matches.get_flag("filter_numts") => Some(
+            NumtFilter::bundled_bed(reference)
+                .unwrap_or_else(|e| error::exit_with_error("call", error::boxed(e).as_ref())),
+        ),
+        None => None,
+    };
+    let custom_reference_build = call_matches
+        .contains_id("custom_reference_fasta")
+        .then(|| reference.clone());
 
-    let reference_fasta = mity_util::select_reference_fasta(reference, None).unwrap();
-    let reference_genome = mity_util::select_reference_genome(reference, None).unwrap();
+    let settings = CallSettings {
+        custom_reference_build,
+        min_mq,
+        min_bq,
+        min_af,
+        min_ac,
+        p: p_val,
+        preset,
+        normalise,
+        region,
+        exclude_regions,
+        exclude_bed,
+        region_bed,
+        bam_list: bam_file_list,
+        keep,
+        sort_inputs,
+        sample_map_path: sample_map,
+        rg_stats,
+        adaptive_min_ac,
+        auto_mq,
+        per_sample_filter,
+        create_index,
+        force,
+        split_samples,
+        progress: !no_progress,
+        max_depth,
+        min_freebayes_version,
+        ploidy,
+        read_filter,
+        skip_reference_check,
+        report_all_sites,
+        circular,
+        numt_bed,
+        coverage_output,
+        error_on_empty,
+        output_format,
+        strict_windows,
+    };
 
     // Create the Call struct using the new constructor
     let mut call = Call::new(
@@ -51,28 +297,427 @@ fn handle_call_command(call_matches: &clap::ArgMatches) {
         files,
         reference_fasta,
         Some(reference_genome),
+        output_dir,
         prefix,
+        settings,
+    );
+
+    // TODO: think of better semantics for error handling and logging
+    match call.run() {
+        Ok(result) => {
+            println!("Call command completed successfully.");
+            println!("  prefix:         {}", result.prefix);
+            println!("  region:         {}", result.region.as_deref().unwrap_or("-"));
+            println!("  samples:        {}", result.samples.join(", "));
+            println!(
+                "  call VCF:       {} ({}, {} records)",
+                result.call_vcf.display(),
+                result.format.extension(),
+                result.records
+            );
+            if let Some(normalised_vcf) = &result.normalised_vcf {
+                println!("  normalised VCF: {}", normalised_vcf.display());
+            }
+        }
+        Err(e) => {
+            error::exit_with_error("call", e.as_ref());
+        }
+    }
+}
+
+fn handle_coverage_command(coverage_matches: &clap::ArgMatches) {
+    let debug = coverage_matches.get_flag("debug");
+    let files = coverage_matches
+        .get_many::<PathBuf>("files")
+        .expect("Required argument")
+        .cloned()
+        .collect();
+    let min_mq = coverage_matches.get_one::<u32>("min_mapping_quality").copied();
+    let min_bq = coverage_matches.get_one::<u32>("min_base_quality").copied();
+    let min_depth = coverage_matches.get_one::<u32>("min_depth").copied();
+    let output_dir = coverage_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let bam_file_list = coverage_matches.get_flag("bam_file_list");
+
+    let mut coverage = Coverage::new(
+        debug,
+        files,
         min_mq,
         min_bq,
-        min_af,
-        min_ac,
-        p_val,
-        normalise,
+        min_depth,
         output_dir,
-        region,
         bam_file_list,
-        keep,
     );
 
-    // TODO: think of better semantics for error handling and logging
-    match call.run() {
+    match coverage.run() {
         Ok(()) => {
-            println!("Call command completed successfully.");
+            println!("Coverage command completed successfully.");
         }
         Err(e) => {
-            eprintln!("Error executing call command: {}", e);
-            std::process::exit(1);
+            error::exit_with_error("coverage", e.as_ref());
+        }
+    }
+}
+
+fn handle_cohort_report_command(cohort_matches: &clap::ArgMatches) {
+    let debug = cohort_matches.get_flag("debug");
+    let files: Vec<PathBuf> = cohort_matches
+        .get_many::<PathBuf>("files")
+        .expect("Required argument")
+        .cloned()
+        .collect();
+    let vcf_file_list = cohort_matches.get_flag("vcf_file_list");
+    let output_dir = cohort_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = cohort_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let cohort_report = CohortReport::new(debug, files, vcf_file_list, output_dir, prefix);
+    if let Err(e) = cohort_report.run() {
+        error::exit_with_error("cohort-report", e.as_ref());
+    }
+}
+
+fn handle_build_pon_command(build_pon_matches: &clap::ArgMatches) {
+    let debug = build_pon_matches.get_flag("debug");
+    let files: Vec<PathBuf> = build_pon_matches
+        .get_many::<PathBuf>("files")
+        .expect("Required argument")
+        .cloned()
+        .collect();
+    let output_dir = build_pon_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = build_pon_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let build_pon = BuildPon::new(debug, files, output_dir, prefix);
+    if let Err(e) = build_pon.run() {
+        error::exit_with_error("build-pon", e.as_ref());
+    }
+}
+
+fn handle_compare_command(compare_matches: &clap::ArgMatches) {
+    let debug = compare_matches.get_flag("debug");
+    let vcfs: Vec<PathBuf> = compare_matches
+        .get_many::<PathBuf>("vcfs")
+        .expect("Required argument")
+        .cloned()
+        .collect();
+    let labels = compare_matches
+        .get_many::<String>("labels")
+        .map(|labels| labels.cloned().collect::<Vec<_>>());
+    let samples: Vec<String> = compare_matches
+        .get_many::<String>("sample")
+        .map(|samples| samples.cloned().collect())
+        .unwrap_or_default();
+    let min_delta = *compare_matches.get_one::<f64>("min_delta").expect("Has a default");
+    let output_dir = compare_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = compare_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    // One --sample name applies to both files
+    let samples = match samples.as_slice() {
+        [] => [None, None],
+        [sample] => [Some(sample.clone()), Some(sample.clone())],
+        [a, b] => [Some(a.clone()), Some(b.clone())],
+        _ => {
+            let e: Box<dyn std::error::Error> = "--sample takes one name, or two separated by a comma".into();
+            error::exit_with_error("compare", e.as_ref());
+        }
+    };
+    let labels = match labels.as_deref() {
+        None => None,
+        Some([a, b]) => Some([a.clone(), b.clone()]),
+        Some(_) => {
+            let e: Box<dyn std::error::Error> = "--labels takes exactly two labels separated by a comma".into();
+            error::exit_with_error("compare", e.as_ref());
+        }
+    };
+
+    if compare_matches.get_flag("denovo") {
+        let settings = DenovoSettings {
+            proband_min_vaf: *compare_matches.get_one::<f64>("proband_min_vaf").expect("Has a default"),
+            maternal_max_vaf: *compare_matches.get_one::<f64>("maternal_max_vaf").expect("Has a default"),
+            min_maternal_depth: *compare_matches.get_one::<u64>("min_maternal_depth").expect("Has a default"),
+            p: *compare_matches.get_one::<f32>("p").expect("Has a default") as f64,
+        };
+        let denovo = Denovo::new(
+            debug,
+            vcfs[0].clone(),
+            vcfs[1].clone(),
+            samples,
+            settings,
+            output_dir,
+            prefix,
+        );
+        if let Err(e) = denovo.run() {
+            error::exit_with_error("compare", e.as_ref());
+        }
+        return;
+    }
+
+    let compare = Compare::new(
+        debug,
+        [vcfs[0].clone(), vcfs[1].clone()],
+        labels,
+        samples,
+        min_delta,
+        output_dir,
+        prefix,
+    );
+    if let Err(e) = compare.run() {
+        error::exit_with_error("compare", e.as_ref());
+    }
+}
+
+fn handle_config_command(config_matches: &clap::ArgMatches, config: &Config) {
+    let result = match config_matches.subcommand() {
+        Some(("init", init_matches)) => {
+            let output = init_matches
+                .get_one::<PathBuf>("output")
+                .expect("Has a default");
+            config::write_template(&build_cli(), output, init_matches.get_flag("force"))
+                .map(|()| println!("Wrote {}", output.display()))
+        }
+        Some(("show", _)) => config.show(&build_cli()).map(|text| print!("{}", text)),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        error::exit_with_error("config", e.as_ref());
+    }
+}
+
+fn handle_consensus_command(consensus_matches: &clap::ArgMatches) {
+    let debug = consensus_matches.get_flag("debug");
+    let vcf = consensus_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let reference = consensus_matches
+        .get_one::<String>("reference")
+        .expect("Has a default");
+    let sample = consensus_matches.get_one::<String>("sample").cloned();
+    let min_vaf = *consensus_matches.get_one::<f64>("min_vaf").expect("Has a default");
+    let iupac = consensus_matches.get_flag("iupac");
+    let depth_bedgraph = consensus_matches.get_one::<PathBuf>("depth_bedgraph").cloned();
+    let min_depth = *consensus_matches.get_one::<f64>("min_depth").expect("Has a default");
+    let output_dir = consensus_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = consensus_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let reference_fasta = mity_util::select_reference_fasta(reference, None).unwrap_or_else(|e| {
+        error::exit_with_error("consensus", e.as_ref());
+    });
+
+    let consensus = Consensus::new(
+        debug,
+        vcf,
+        reference_fasta,
+        sample,
+        min_vaf,
+        iupac,
+        depth_bedgraph,
+        min_depth,
+        output_dir,
+        prefix,
+    );
+    if let Err(e) = consensus.run() {
+        error::exit_with_error("consensus", e.as_ref());
+    }
+}
+
+fn handle_contamination_command(contamination_matches: &clap::ArgMatches) {
+    let debug = contamination_matches.get_flag("debug");
+    let vcf = contamination_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let output_dir = contamination_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = contamination_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let contamination = Contamination::new(debug, vcf, output_dir, prefix);
+    if let Err(e) = contamination.run() {
+        error::exit_with_error("contamination", e.as_ref());
+    }
+}
+
+fn handle_copy_number_command(copy_number_matches: &clap::ArgMatches) {
+    let debug = copy_number_matches.get_flag("debug");
+    let bam = copy_number_matches
+        .get_one::<PathBuf>("bam")
+        .expect("Required argument")
+        .clone();
+    let prefix = copy_number_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+    let regions = copy_number_matches.get_one::<PathBuf>("regions").cloned();
+    let capture = copy_number_matches.get_one::<PathBuf>("capture_bed").cloned();
+    let min_mq = copy_number_matches.get_one::<u32>("min_mapping_quality").copied();
+    let min_bq = copy_number_matches.get_one::<u32>("min_base_quality").copied();
+    let output_dir = copy_number_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+
+    let copy_number = CopyNumber::new(
+        debug, bam, prefix, regions, capture, min_mq, min_bq, output_dir,
+    );
+
+    if let Err(e) = copy_number.run() {
+        error::exit_with_error("copy-number", e.as_ref());
+    }
+}
+
+fn handle_extract_mt_command(extract_mt_matches: &clap::ArgMatches) {
+    let debug = extract_mt_matches.get_flag("debug");
+    let bam = extract_mt_matches
+        .get_one::<PathBuf>("bam")
+        .expect("Required argument")
+        .clone();
+    let output_dir = extract_mt_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = extract_mt_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+    let include_unmapped_mates = extract_mt_matches.get_flag("include_unmapped_mates");
+    let numts = extract_mt_matches.get_one::<PathBuf>("include_numts").cloned();
+    let reference = extract_mt_matches
+        .get_one::<String>("reference")
+        .expect("Has a default");
+
+    // Only CRAM needs the reference to decode reads
+    let reference_fasta = if mity_util::is_cram(&bam) {
+        match mity_util::select_reference_fasta(reference, None) {
+            Ok(fasta) => Some(fasta),
+            Err(e) => {
+                error::exit_with_error("extract-mt", e.as_ref());
+            }
         }
+    } else {
+        None
+    };
+
+    let extract_mt = ExtractMt::new(
+        debug,
+        bam,
+        output_dir,
+        prefix,
+        include_unmapped_mates,
+        numts,
+        reference_fasta,
+    );
+    if let Err(e) = extract_mt.run() {
+        error::exit_with_error("extract-mt", e.as_ref());
+    }
+}
+
+fn handle_filter_command(filter_matches: &clap::ArgMatches) {
+    let debug = filter_matches.get_flag("debug");
+    let vcf = filter_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let output_dir = filter_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = filter_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let defaults = FilterSettings::default();
+    let settings = FilterSettings {
+        min_site_dp: filter_matches
+            .get_one::<u32>("min_site_dp")
+            .map_or(defaults.min_site_dp, |dp| *dp as f64),
+        min_sample_dp: filter_matches
+            .get_one::<u32>("min_sample_dp")
+            .map_or(defaults.min_sample_dp, |dp| *dp as f64),
+        sb_range: filter_matches
+            .get_one::<(f64, f64)>("sb_range")
+            .copied()
+            .unwrap_or(defaults.sb_range),
+        blacklist: filter_matches
+            .get_one::<Vec<u64>>("blacklist")
+            .cloned()
+            .unwrap_or(defaults.blacklist),
+        allsamples: filter_matches.get_flag("allsamples"),
+        p: *filter_matches.get_one::<f32>("p").expect("Has a default"),
+        keep_multiallelic: false,
+        ..defaults
+    };
+
+    let filter = Filter::new(debug, vcf, output_dir, prefix, settings);
+    if let Err(e) = filter.run() {
+        error::exit_with_error("filter", e.as_ref());
+    }
+}
+
+fn handle_haplogroup_command(haplogroup_matches: &clap::ArgMatches) {
+    let debug = haplogroup_matches.get_flag("debug");
+    let vcf = haplogroup_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let output_dir = haplogroup_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = haplogroup_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let haplogroup = Haplogroup::new(debug, vcf, output_dir, prefix);
+    if let Err(e) = haplogroup.run() {
+        error::exit_with_error("haplogroup", e.as_ref());
+    }
+}
+
+fn handle_liftover_command(liftover_matches: &clap::ArgMatches) {
+    let debug = liftover_matches.get_flag("debug");
+    let vcf = liftover_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let to = liftover_matches
+        .get_one::<String>("to")
+        .expect("Required argument")
+        .clone();
+    let rename_info = liftover_matches.get_flag("rename_info");
+    let output_dir = liftover_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = liftover_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+
+    let liftover = Liftover::new(debug, vcf, to, rename_info, output_dir, prefix);
+    if let Err(e) = liftover.run() {
+        error::exit_with_error("liftover", e.as_ref());
     }
 }
 
@@ -80,29 +725,55 @@ fn handle_normalise_command(normalise_matches: &clap::ArgMatches) {
     // Extract and parse command-line arguments
     let debug = normalise_matches.get_flag("debug");
     let vcf = normalise_matches
-        .get_one::<String>("vcf")
+        .get_one::<PathBuf>("vcf")
         .expect("Required argument 'vcf' is missing")
-        .to_string();
+        .clone();
     let reference = normalise_matches
         .get_one::<String>("reference")
-        .expect("Required argument 'reference' is missing")
-        .to_string();
+        .expect("Required argument 'reference' is missing");
     let output_dir = normalise_matches
-        .get_one::<String>("output_dir")
+        .get_one::<PathBuf>("output_dir")
         .expect("Required argument 'output_dir' is missing")
-        .to_string();
+        .clone();
     let prefix = normalise_matches
         .get_one::<String>("prefix")
         .map(|s| s.to_string());
     let allsamples = normalise_matches.get_flag("allsamples");
-    let p_val = normalise_matches.get_one::<f32>("p").unwrap().clone();
+    let no_split = normalise_matches.get_flag("no_split");
+    let keep_invalid = normalise_matches.get_flag("keep_invalid");
+    let rejects_vcf = normalise_matches.get_flag("rejects_vcf");
+    let p_val = normalise_matches.get_one::<f32>("p").copied().unwrap();
     let keep = normalise_matches.get_flag("keep");
+    let min_site_dp = normalise_matches.get_one::<u32>("min_site_dp").copied();
+    let min_sample_dp = normalise_matches.get_one::<u32>("min_sample_dp").copied();
+    let panel_of_normals = normalise_matches.get_one::<PathBuf>("panel_of_normals").cloned();
+    let pon_min_freq = normalise_matches.get_one::<f64>("pon_min_freq").copied();
+    let pon_soft = normalise_matches.get_flag("pon_soft");
+    let audit_tsv = normalise_matches.get_flag("audit_tsv");
+    let audit_all = normalise_matches.get_flag("audit_all");
+    let output = normalise_matches.get_one::<PathBuf>("output").cloned();
+    let force = normalise_matches.get_flag("force");
 
     // Select reference files using utility functions
-    let reference_fasta = mity_util::select_reference_fasta(&reference, None)
-        .expect("Failed to select reference FASTA");
-    let reference_genome = mity_util::select_reference_genome(&reference, None)
-        .expect("Failed to select reference genome");
+    let (reference_fasta, reference_genome) = select_reference_files("normalise", normalise_matches, reference);
+
+    let settings = NormaliseSettings {
+        allsamples,
+        no_split,
+        keep_invalid,
+        rejects_vcf,
+        keep,
+        p: p_val,
+        min_site_dp,
+        min_sample_dp,
+        panel_of_normals,
+        pon_min_freq,
+        pon_soft,
+        audit_tsv,
+        audit_all,
+        output,
+        force,
+    };
 
     // Create the Normalise struct using the constructor
     let normalise = Normalise::new(
@@ -112,25 +783,213 @@ fn handle_normalise_command(normalise_matches: &clap::ArgMatches) {
         reference_genome,
         output_dir,
         prefix,
-        allsamples,
-        keep,
-        p_val,
+        settings,
     );
 
     // Execute the normalization process and handle any potential errors
     match normalise.run() {
+        Ok(result) => {
+            // stdout carries the VCF when streaming
+            if let Some(normalised_vcf) = &result.normalised_vcf {
+                println!("Normalisation command completed successfully.");
+                println!("  prefix:         {}", result.prefix);
+                println!(
+                    "  normalised VCF: {} ({} records, {} PASS)",
+                    normalised_vcf.display(),
+                    result.filters.records,
+                    result.filters.passed
+                );
+                for (name, path) in [
+                    ("invalid VCF:   ", &result.invalid_vcf),
+                    ("rejects VCF:   ", &result.rejects_vcf),
+                    ("audit TSV:     ", &result.audit_tsv),
+                ] {
+                    if let Some(path) = path {
+                        println!("  {} {}", name, path.display());
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error::exit_with_error("normalise", e.as_ref());
+        }
+    }
+}
+
+fn handle_plot_command(plot_matches: &clap::ArgMatches) {
+    let debug = plot_matches.get_flag("debug");
+    let vcf = plot_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let bedgraphs = plot_matches
+        .get_many::<PathBuf>("bedgraph")
+        .map(|paths| paths.cloned().collect())
+        .unwrap_or_default();
+    let output_dir = plot_matches
+        .get_one::<PathBuf>("output_dir")
+        .expect("Required argument")
+        .clone();
+    let prefix = plot_matches
+        .get_one::<String>("prefix")
+        .map(|s| s.to_string());
+    let format = plot_matches
+        .get_one::<String>("format")
+        .expect("Has a default")
+        .clone();
+    let width = *plot_matches.get_one::<u32>("width").expect("Has a default");
+    let height = *plot_matches.get_one::<u32>("height").expect("Has a default");
+    let min_vaf = *plot_matches.get_one::<f64>("min_vaf").expect("Has a default");
+    let genome_map = plot_matches.get_flag("genome_map");
+    let map_label_vaf = *plot_matches.get_one::<f64>("map_label_vaf").expect("Has a default");
+
+    let plot = Plot::new(
+        debug, vcf, bedgraphs, output_dir, prefix, format, width, height, min_vaf, genome_map,
+        map_label_vaf,
+    );
+    match plot.run() {
         Ok(()) => {
-            println!("Normalisation command completed successfully.");
+            println!("Plot command completed successfully.");
         }
         Err(e) => {
-            eprintln!("Error executing normalisation command: {}", e);
-            std::process::exit(1);
+            error::exit_with_error("plot", e.as_ref());
         }
     }
 }
 
+fn handle_stats_command(stats_matches: &clap::ArgMatches) {
+    let debug = stats_matches.get_flag("debug");
+    let vcf = stats_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let format = if stats_matches.get_flag("json") {
+        StatsFormat::Json
+    } else if stats_matches.get_flag("tsv") {
+        StatsFormat::Tsv
+    } else {
+        StatsFormat::Text
+    };
 
-fn cli_commands() {
+    let stats = VcfStats::new(debug, vcf, format);
+    if let Err(e) = stats.run() {
+        error::exit_with_error("stats", e.as_ref());
+    }
+}
+
+fn handle_validate_command(validate_matches: &clap::ArgMatches) {
+    let debug = validate_matches.get_flag("debug");
+    let vcf = validate_matches
+        .get_one::<PathBuf>("vcf")
+        .expect("Required argument")
+        .clone();
+    let reference_fasta = validate_matches
+        .get_one::<String>("reference")
+        .map(|reference| {
+            mity_util::select_reference_fasta(reference, None)
+                .unwrap_or_else(|e| error::exit_with_error("validate", error::boxed(e).as_ref()))
+        });
+    let json = validate_matches.get_flag("json");
+
+    let validate = Validate::new(debug, vcf, reference_fasta, json);
+    if let Err(e) = validate.run() {
+        error::exit_with_error("validate", e.as_ref());
+    }
+}
+
+fn init_logging(matches: &clap::ArgMatches) {
+    let debug = matches
+        .subcommand()
+        .and_then(|(_, sub_matches)| sub_matches.try_get_one::<bool>("debug").ok().flatten())
+        .copied()
+        .unwrap_or(false);
+    let env_filter = std::env::var(logging::LOG_ENV_VAR).ok();
+    let filter = logging::resolve_filter(
+        matches.get_one::<String>("log_filter").map(String::as_str),
+        env_filter.as_deref(),
+        debug,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Invalid --log-filter or {}: {}", logging::LOG_ENV_VAR, e);
+        std::process::exit(2);
+    });
+    logging::init(filter).expect("Logger already initialised");
+}
+
+fn set_tool_overrides(matches: &clap::ArgMatches) {
+    let overrides: HashMap<Tool, PathBuf> = Tool::ALL
+        .iter()
+        .filter_map(|tool| {
+            matches
+                .get_one::<PathBuf>(tool.arg_id())
+                .map(|path| (*tool, path.clone()))
+        })
+        .collect();
+    mity_util::set_tool_overrides(overrides);
+}
+
+/// Parse a number, failing with the range it must lie in unless `valid`.
+fn parse_in_range<T: std::str::FromStr + Copy>(value: &str, valid: impl Fn(T) -> bool, range: &str) -> Result<T, String> {
+    let number = value.trim().parse::<T>().map_err(|_| format!("expected a number {}", range))?;
+    if !valid(number) {
+        return Err(format!("must be {}", range));
+    }
+    Ok(number)
+}
+
+/// Parse `--min-alternate-fraction`, which must be in (0, 1].
+fn parse_min_alternate_fraction(value: &str) -> Result<f32, String> {
+    parse_in_range(value, |af: f32| af > 0.0 && af <= 1.0, "greater than 0 and at most 1")
+}
+
+/// Parse `--p`, which must be in (0, 1).
+fn parse_p(value: &str) -> Result<f32, String> {
+    parse_in_range(value, |p: f32| p > 0.0 && p < 1.0, "greater than 0 and less than 1")
+}
+
+/// Parse `--min_vaf`, which must be in [0, 1].
+fn parse_min_vaf(value: &str) -> Result<f64, String> {
+    parse_in_range(value, |vaf: f64| (0.0..=1.0).contains(&vaf), "from 0 to 1")
+}
+
+/// Parse `--max-memory`: a number of bytes, or of `K`, `M`, `G` or `T`
+/// (binary units, an optional trailing `B` allowed).
+fn parse_memory_size(value: &str) -> Result<u64, String> {
+    let upper = value.trim().to_ascii_uppercase();
+    let upper = upper.strip_suffix('B').filter(|rest| !rest.is_empty()).unwrap_or(&upper);
+    let (number, shift) = match upper.char_indices().last() {
+        Some((index, 'K')) => (&upper[..index], 10),
+        Some((index, 'M')) => (&upper[..index], 20),
+        Some((index, 'G')) => (&upper[..index], 30),
+        Some((index, 'T')) => (&upper[..index], 40),
+        _ => (upper, 0),
+    };
+    let invalid = || format!("expected a size such as 4G or 512M, got '{}'", value);
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !number.is_finite() || number <= 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// A `--exclude-KIND[=true|false]` read filter flag.
+fn exclude_reads_arg(id: &'static str, long: &'static str, reads: &str, default: bool) -> Arg {
+    Arg::new(id)
+        .long(long)
+        .value_name("BOOL")
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("true")
+        .default_value(if default { "true" } else { "false" })
+        .value_parser(clap::value_parser!(bool))
+        .help(format!(
+            "Leave {} alignments out of calling; =false keeps them. Default: {}",
+            reads, default
+        ))
+}
+
+/// The full command line, with built-in defaults.
+fn build_cli() -> Command {
     // Reused args
     let debug_arg = Arg::new("debug")
         .short('d')
@@ -144,10 +1003,16 @@ fn cli_commands() {
         .action(ArgAction::SetTrue)
         .help("Keep all intermediate files");
 
+    let force_arg = Arg::new("force")
+        .long("force")
+        .action(ArgAction::SetTrue)
+        .help("Overwrite output files that already exist");
+
     let output_dir_arg = Arg::new("output_dir")
         .long("output-dir")
         .action(ArgAction::Set)
         .value_name("OUTPUT_DIR")
+        .value_parser(clap::value_parser!(PathBuf))
         .default_value(".")
         .help("Output files will be saved in OUTPUT_DIR. Default: '.'");
 
@@ -159,10 +1024,23 @@ fn cli_commands() {
         .default_value("hs37d5")
         .help("Reference genome version to use. Default: hs37d5");
 
+    let custom_reference_fasta_arg = Arg::new("custom_reference_fasta")
+        .long("custom-reference-fasta")
+        .value_name("FASTA")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Use this FASTA, which needs a .fai index, instead of the installed one for --reference. --reference still sets the contig naming and the MT length it is checked against");
+
+    let custom_reference_genome_arg = Arg::new("custom_reference_genome")
+        .long("custom-reference-genome")
+        .value_name("GENOME_FILE")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Use this .genome file of contig lengths instead of the installed one for --reference");
+
     let files_arg = Arg::new("files")
         .action(ArgAction::Append)
+        .value_parser(clap::value_parser!(PathBuf))
         .required(true)
-        .help("BAM / CRAM files to run the analysis on. If --bam-file-list is included, this argument is the file containing the list of BAM/CRAM files");
+        .help("BAM / CRAM files to run the analysis on. If --bam-file-list is included, this argument is the file containing the list of BAM/CRAM files. Sample columns in the output VCF follow this order");
 
     let prefix_arg = Arg::new("prefix")
         .long("prefix")
@@ -171,58 +1049,115 @@ fn cli_commands() {
         .help("Output files will be named with PREFIX");
 
     let vcf_arg = Arg::new("vcf")
-        .help("VCF.GZ file from running mity")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("VCF.GZ file from running mity. Use '-' to read from stdin")
         .required(true);
 
     // Call arguments
     let min_mapping_quality_arg = Arg::new("min_mapping_quality")
         .long("min-mapping-quality")
-        .help("Exclude alignments with a mapping quality less than this value. Default: 30")
+        .help("Exclude alignments with a mapping quality less than this value, at most 254. Default: 30")
         .default_value("30")
-        .value_parser(clap::value_parser!(u32));
+        .value_parser(clap::value_parser!(u32).range(..=254));
 
     let min_base_quality_arg = Arg::new("min_base_quality")
         .long("min-base-quality")
-        .help("Exclude alleles with a base quality less than this value. Default: 24")
+        .help("Exclude alleles with a base quality less than this value, at most 254. Default: 24")
         .default_value("24")
-        .value_parser(clap::value_parser!(u32));
+        .value_parser(clap::value_parser!(u32).range(..=254));
 
     let min_alternate_fraction_arg = Arg::new("min_alternate_fraction")
         .long("min-alternate-fraction")
-        .help("Require at least this fraction of observations supporting an alternate allele. Default: 0.01")
+        .help("Require at least this fraction of observations supporting an alternate allele, greater than 0 and at most 1. Default: 0.01")
         .default_value("0.01")
-        .value_parser(clap::value_parser!(f32));
+        .value_parser(parse_min_alternate_fraction);
 
     let min_alternate_count_arg = Arg::new("min_alternate_count")
         .long("min-alternate-count")
-        .help("Require at least this many observations supporting an alternate allele. Default: 4")
+        .help("Require at least this many observations supporting an alternate allele, at least 1. Default: 4")
         .default_value("4")
-        .value_parser(clap::value_parser!(u32));
+        .value_parser(clap::value_parser!(u32).range(1..));
+
+    let skip_reference_check_arg = Arg::new("skip_reference_check")
+        .long("skip-reference-check")
+        .action(ArgAction::SetTrue)
+        .help("Call even when the mitochondrial contig of the BAM/CRAM inputs is not the length of the reference's, for unusual references");
+
+    let report_all_sites_arg = Arg::new("report_all_sites")
+        .long("report-all-sites")
+        .action(ArgAction::SetTrue)
+        .help("Also write reference blocks, with END and MIN_DP, over the covered positions between variants (freebayes --gvcf), so a position can be shown covered and reference. normalise passes them through unfiltered");
+
+    let circular_arg = Arg::new("circular")
+        .long("circular")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("region")
+        .help("Call the 16569/1 junction of the circular mitochondrion again against the MT rotated by 8 kb, with the reads around it moved there and their soft clips at the contig ends unclipped, and use those calls within 500 bp of the junction. Variants across it get INFO/MT_JUNCTION. BAM inputs only");
+
+    let ploidy_arg = Arg::new("ploidy")
+        .long("ploidy")
+        .value_name("N")
+        .help("Ploidy freebayes calls with, at least 1: 1 for homoplasmy-only calls, more for pooled samples. Default: 2")
+        .default_value("2")
+        .value_parser(clap::value_parser!(u32).range(1..));
+
+    let preset_arg = Arg::new("preset")
+        .long("preset")
+        .value_name("PRESET")
+        .value_parser(Preset::NAMES)
+        .default_value("short-read")
+        .help("Defaults for the sequencing: short-read, or long-read for ONT/PacBio, which sets --min-base-quality 10, --min-alternate-count 10, --min-alternate-fraction 0.05 and --p 0.01 and passes freebayes --min-repeat-entropy 1 --use-best-n-alleles 4. Those mity options given on the command line or in a config file override the preset. Default: short-read");
 
     let call_p_arg = Arg::new("p")
         .long("p")
-        .help("Minimum noise level for calculating QUAL score. Default: 0.002")
+        .help("Minimum noise level for calculating QUAL score, greater than 0 and less than 1. Default: 0.002")
         .default_value("0.002")
-        .value_parser(clap::value_parser!(f32));
+        .value_parser(parse_p);
+
+    let min_site_dp_arg = Arg::new("min_site_dp")
+        .long("min-site-dp")
+        .value_name("DP")
+        .value_parser(clap::value_parser!(u32))
+        .help("Minimum site depth (INFO DP); records below fail SITE_DP. Default: 0, off as in python mity");
+
+    let min_sample_dp_arg = Arg::new("min_sample_dp")
+        .long("min-sample-dp")
+        .value_name("DP")
+        .value_parser(clap::value_parser!(u32))
+        .help("Minimum depth (FORMAT DP) of each sample; records fail DP when every sample is below it, or any with --allsamples. Default: 15");
+
+    let min_freebayes_version_arg = Arg::new("min_freebayes_version")
+        .long("min-freebayes-version")
+        .value_name("VERSION")
+        .value_parser(mity_util::parse_tool_version)
+        .default_value("1.3.6")
+        .help("Refuse to call with a freebayes older than this; 1.2 differs in defaults and some versions crash on CRAM. Default: 1.3.6");
 
     let region_arg = Arg::new("region")
         .long("region")
         .action(ArgAction::Set)
-        .help("Region of MT genome to call variants in. Default: entire MT genome.");
+        .help("Region of MT genome to call variants in, or a comma-separated list of them, e.g. MT:16024-16569,MT:1-576. Default: entire MT genome.");
 
     let bam_file_list_arg = Arg::new("bam_file_list")
         .long("bam-file-list")
         .action(ArgAction::SetTrue)
-        .help("Treat the input file as a text file listing BAM files.");
+        .help("Treat the input file as a text file listing BAM files, one per line. Blank lines and # comments are skipped, globs are expanded and relative paths are read from the list's directory.");
+
+    let sample_map_arg = Arg::new("sample_map")
+        .long("sample-map")
+        .action(ArgAction::Set)
+        .value_name("TSV")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Two-column TSV (old name, new name) used to rename samples in the output. Unmapped samples are left unchanged");
 
     // Report args
     let min_vaf_arg = Arg::new("min_vaf")
         .long("min_vaf")
         .action(ArgAction::Set)
         .value_name("FLOAT")
-        .value_parser(clap::value_parser!(f64))
+        .value_parser(parse_min_vaf)
         .default_value("0")
-        .help("A variant must have at least this VAF to be included in the report. Default: 0.");
+        .help("A variant must have at least this VAF, from 0 to 1, to be included in the report. Default: 0.");
 
     let contig_arg = Arg::new("contig")
         .long("contig")
@@ -250,28 +1185,586 @@ fn cli_commands() {
         .arg(debug_arg.clone())
         .arg(files_arg.clone())
         .arg(reference_arg.clone())
+        .arg(custom_reference_fasta_arg.clone())
+        .arg(custom_reference_genome_arg.clone())
         .arg(prefix_arg.clone())
         .arg(min_mapping_quality_arg.clone())
         .arg(min_base_quality_arg.clone())
         .arg(min_alternate_fraction_arg.clone())
         .arg(min_alternate_count_arg.clone())
+        .arg(preset_arg.clone())
+        .arg(ploidy_arg.clone())
         .arg(call_p_arg.clone())
+        .arg(skip_reference_check_arg.clone())
+        .arg(report_all_sites_arg.clone())
+        .arg(circular_arg.clone().conflicts_with_all(["region_bed", "exclude_region", "exclude_bed", "split_samples"]))
+        .arg(min_freebayes_version_arg.clone())
+        .arg(exclude_reads_arg("exclude_duplicates", "exclude-duplicates", "duplicate-marked", true))
+        .arg(exclude_reads_arg("exclude_secondary", "exclude-secondary", "secondary", false))
+        .arg(exclude_reads_arg("exclude_supplementary", "exclude-supplementary", "supplementary", true))
+        .arg(
+            Arg::new("read_end_exclusion")
+                .long("read-end-exclusion")
+                .value_name("N")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u32))
+                .help("Ignore the first and last N aligned bases of each read, inside any soft clip, by setting their base qualities to 0 in the copies freebayes reads, against end-of-read artifacts. BAM inputs only. Default: 0"),
+        )
+        .arg(
+            Arg::new("filter_numts")
+                .long("filter-numts")
+                .action(ArgAction::SetTrue)
+                .help("Leave out reads whose mate maps to a nuclear contig near a NUMT (nuclear mitochondrial segment) of the reference, or with a mate mapping quality (MQ tag) of 20 or more, as their pair is likely NUMT-derived. Uses the NUMT BED bundled for --reference. BAM inputs only"),
+        )
+        .arg(
+            Arg::new("numt_bed")
+                .long("numt-bed")
+                .value_name("BED_FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Filter NUMT-like reads as --filter-numts does, with the NUMTs in this BED file instead of the bundled ones"),
+        )
+        .arg(
+            Arg::new("max_depth")
+                .long("max-depth")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("Downsample each sample to at most N reads per position, with freebayes --limit-coverage, to keep ultra-deep BAMs fast"),
+        )
         .arg(output_dir_arg.clone())
+        .arg(force_arg.clone())
         .arg(region_arg.clone())
-        .arg(bam_file_list_arg.clone())
-        .arg(keep_arg.clone())
+        .arg(
+            Arg::new("exclude_region")
+                .long("exclude-region")
+                .action(ArgAction::Append)
+                .value_name("REGION")
+                .help("Skip this region, e.g. MT:302-316. Can be given several times"),
+        )
+        .arg(
+            Arg::new("exclude_bed")
+                .long("exclude-bed")
+                .value_name("BED_FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Skip the regions in this BED file"),
+        )
+        .arg(
+            Arg::new("region_bed")
+                .long("region-bed")
+                .value_name("BED_FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Call only in the regions of this BED file, together with any --region"),
+        )
+        .arg(
+            Arg::new("create_index")
+                .long("create-index")
+                .action(ArgAction::SetTrue)
+                .help("Index inputs that have no .bai/.csi (.crai for CRAM) instead of failing. The indexes are removed afterwards unless --keep is given"),
+        )
+        .arg(
+            Arg::new("split_samples")
+                .long("split-samples")
+                .action(ArgAction::SetTrue)
+                .help("Call each BAM/CRAM on its own into PREFIX.SAMPLE.mity.call.vcf.gz, named by its @RG SM tag, and merge those into the combined VCF"),
+        )
+        .arg(
+            Arg::new("no_progress")
+                .long("no-progress")
+                .action(ArgAction::SetTrue)
+                .help("Do not show the running stage. It is a spinner on a terminal and log lines otherwise"),
+        )
+        .arg(bam_file_list_arg.clone())
+        .arg(keep_arg.clone())
+        .arg(sample_map_arg.clone())
         .arg(
             Arg::new("normalise")
                 .long("normalise")
                 .action(ArgAction::SetTrue)
                 .help("Run mity normalise on the resulting VCF"),
+        )
+        .arg(
+            Arg::new("sort_inputs")
+                .long("sort-inputs")
+                .action(ArgAction::SetTrue)
+                .help("Sort the BAM/CRAM files lexicographically instead of keeping the order they were given in"),
+        )
+        .arg(
+            Arg::new("rg_stats")
+                .long("rg-stats")
+                .action(ArgAction::SetTrue)
+                .help("Write per-read-group QC statistics over MT to {prefix}.mity.rg_stats.tsv"),
+        )
+        .arg(
+            Arg::new("output_format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .value_parser(OutputFormat::NAMES)
+                .default_value("vcf.gz")
+                .help("Write the call VCF as PREFIX.mity.call.vcf.gz with a tabix index, as plain PREFIX.mity.call.vcf, or as PREFIX.mity.call.bcf. Plain VCF and BCF are not indexed. Default: vcf.gz"),
+        )
+        .arg(
+            Arg::new("error_on_empty")
+                .long("error-on-empty")
+                .action(ArgAction::SetTrue)
+                .help("Fail when a BAM input has no reads on the mitochondrial contig, e.g. an exome BAM, instead of warning and writing no calls for it"),
+        )
+        .arg(
+            Arg::new("strict_windows")
+                .long("strict-windows")
+                .action(ArgAction::SetTrue)
+                .help("With --threads, fail as soon as FreeBayes fails on a window. Otherwise a failed window is run again, and if it fails twice it is left without calls, listed in the stats JSON and noted in the VCF header"),
+        )
+        .arg(
+            Arg::new("coverage_output")
+                .long("coverage-output")
+                .action(ArgAction::SetTrue)
+                .help("Also write the per-base depth over MT, with the calling mapping and base quality thresholds, to PREFIX.mity.coverage.bedgraph.gz, or PREFIX.SAMPLE.mity.coverage.bedgraph.gz for each of several inputs, and log its mean, median and fraction at 500x or more. BAM inputs only"),
+        )
+        .arg(
+            Arg::new("adaptive_min_ac")
+                .long("adaptive-min-ac")
+                .action(ArgAction::SetTrue)
+                .help("Drop calls whose alternate count is below max(--min-alternate-count, ceil(P x depth)) in every sample, so the minimum count scales with depth"),
+        )
+        .arg(
+            Arg::new("auto_mq")
+                .long("auto-mq")
+                .action(ArgAction::SetTrue)
+                .help("When the @PG lines show an aligner whose MAPQ is not on bwa mem's 0-60 scale (e.g. bwa aln, bowtie2), rescale --min-mapping-quality to it instead of only warning"),
+        )
+        .arg(
+            Arg::new("per_sample_filter")
+                .long("per-sample-filter")
+                .action(ArgAction::SetTrue)
+                .help("Reset the GT of samples with no alternate allele meeting --min-alternate-count and --min-alternate-fraction, keeping AO and DP, and drop records no sample supports"),
         );
 
+    let annotate_command = Command::new("annotate")
+        .about("Annotate any mitochondrial VCF with the mity annotations")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Mitochondrial VCF to annotate. It does not need to come from mity"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone())
+        .arg(
+            Arg::new("with_report")
+                .long("with-report")
+                .action(ArgAction::SetTrue)
+                .help("Also write a tab-separated table of the annotated variants"),
+        )
+        .arg(
+            Arg::new("split_by_type")
+                .long("split-by-type")
+                .action(ArgAction::SetTrue)
+                .requires("with_report")
+                .help("Also write SNVs and indels/MNPs/complex variants to separate tables, with a summary of the count of each type"),
+        )
+        .arg(
+            Arg::new("annotation_bed")
+                .long("annotation-bed")
+                .value_name("NAME=FILE")
+                .action(ArgAction::Append)
+                .value_parser(annotate::parse_annotation_bed)
+                .requires("with_report")
+                .help("Add a report column NAME with the overlapping intervals of a BED: their names, or true/false if it has no name column. Can be repeated"),
+        )
+        .arg(
+            Arg::new("pedigree")
+                .long("pedigree")
+                .value_name("PED_FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Label each proband variant as maternal, de_novo, indeterminate or unknown from the mother's VAF. The VCF must contain both samples"),
+        )
+        .arg(
+            Arg::new("maternal_min_vaf")
+                .long("maternal-min-vaf")
+                .value_name("VAF")
+                .default_value("0.05")
+                .value_parser(clap::value_parser!(f64))
+                .help("Mother's VAF at or above which a variant is maternal. Lower non-zero VAFs are indeterminate. Default: 0.05"),
+        )
+        .arg(
+            Arg::new("strand_table")
+                .long("strand-table")
+                .action(ArgAction::SetTrue)
+                .help("Also write {prefix}.mity.strand_counts.tsv with the forward/reverse ref/alt read counts (SRF, SRR, SAF, SAR), SBR, SBA and FILTER of each variant and sample"),
+        )
+        .arg(vcfanno_config.clone())
+        .arg(
+            Arg::new("annotation_vcf")
+                .long("annotation-vcf")
+                .value_name("FILE:FIELDS[:PREFIX]")
+                .action(ArgAction::Append)
+                .value_parser(annotate::parse_annotation_vcf)
+                .help("Also annotate the comma-separated INFO FIELDS of a tabixed VCF, named PREFIX_FIELD when PREFIX is given. Can be repeated"),
+        )
+        .arg(keep_arg.clone());
+
+    let coverage_command = Command::new("coverage")
+        .about("Compute per-base depth over the mitochondrial contig")
+        .arg(debug_arg.clone())
+        .arg(files_arg.clone())
+        .arg(min_mapping_quality_arg.clone())
+        .arg(min_base_quality_arg.clone())
+        .arg(output_dir_arg.clone())
+        .arg(bam_file_list_arg.clone())
+        .arg(
+            Arg::new("min_depth")
+                .long("min-depth")
+                .value_name("DEPTH")
+                .default_value("100")
+                .value_parser(clap::value_parser!(u32))
+                .help("Report regions with depth below this value in the low-depth BED. Default: 100"),
+        );
+
+    let cohort_report_command = Command::new("cohort-report")
+        .about("Combine the variants of many mity VCFs into one VAF matrix")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("files")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(1..)
+                .required(true)
+                .value_name("VCF")
+                .help("Sorted mity VCFs, or directories of them"),
+        )
+        .arg(
+            Arg::new("vcf_file_list")
+                .long("vcf-file-list")
+                .action(ArgAction::SetTrue)
+                .help("Treat the input file as a text file listing VCF files."),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: cohort"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let build_pon_command = Command::new("build-pon")
+        .about("Build a panel of normals from normalised VCFs of normal samples")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("files")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(1..)
+                .required(true)
+                .value_name("VCF")
+                .help("Normalised mity VCFs of normal samples"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: pon"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let compare_command = Command::new("compare")
+        .about("Compare heteroplasmy levels between two VCFs")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcfs")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(2)
+                .required(true)
+                .value_names(["VCF_A", "VCF_B"])
+                .help("The two normalised VCFs to compare. DELTA is VAF in VCF_B minus VAF in VCF_A"),
+        )
+        .arg(
+            Arg::new("labels")
+                .long("labels")
+                .value_name("A,B")
+                .value_delimiter(',')
+                .help("Names for the two VCFs in the output, e.g. mother,child. Default: derived from the file names"),
+        )
+        .arg(
+            Arg::new("sample")
+                .long("sample")
+                .value_name("NAME[,NAME]")
+                .value_delimiter(',')
+                .help("Sample to compare when a VCF has several. A single name is used for both files"),
+        )
+        .arg(
+            Arg::new("min_delta")
+                .long("min-delta")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0")
+                .help("Only write variants whose VAF changes by at least this much. Default: 0"),
+        )
+        .arg(
+            Arg::new("denovo")
+                .long("denovo")
+                .action(ArgAction::SetTrue)
+                .help("Classify the variants of VCF_B (proband) as de novo candidates, inherited or insufficient maternal depth from the reads of VCF_A (mother)"),
+        )
+        .arg(
+            Arg::new("proband_min_vaf")
+                .long("proband-min-vaf")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.01")
+                .requires("denovo")
+                .help("With --denovo, only classify proband variants at or above this VAF. Default: 0.01"),
+        )
+        .arg(
+            Arg::new("maternal_max_vaf")
+                .long("maternal-max-vaf")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.01")
+                .requires("denovo")
+                .help("With --denovo, a maternal VAF above this makes the variant inherited. Default: 0.01"),
+        )
+        .arg(
+            Arg::new("min_maternal_depth")
+                .long("min-maternal-depth")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100")
+                .requires("denovo")
+                .help("With --denovo, maternal depth needed to call a de novo candidate. Default: 100"),
+        )
+        .arg(call_p_arg.clone())
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: A_vs_B, or the VCF_B name with --denovo"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let consensus_command = Command::new("consensus")
+        .about("Build a sample's mitochondrial FASTA by applying its variants to the reference")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF with the sample's variants, typically the output of mity normalise"),
+        )
+        .arg(reference_arg.clone())
+        .arg(
+            Arg::new("sample")
+                .long("sample")
+                .action(ArgAction::Set)
+                .help("Sample to build the sequence of. Required when the VCF has several"),
+        )
+        .arg(
+            Arg::new("min_vaf")
+                .long("min-vaf")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.7")
+                .help("Apply variants at or above this VAF; lower ones keep the reference base. Default: 0.7"),
+        )
+        .arg(
+            Arg::new("iupac")
+                .long("iupac")
+                .action(ArgAction::SetTrue)
+                .help("Write an IUPAC ambiguity code for SNVs with VAF between 1 - MIN_VAF and MIN_VAF"),
+        )
+        .arg(
+            Arg::new("depth_bedgraph")
+                .long("depth-bedgraph")
+                .value_name("BEDGRAPH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Per-base depth, such as the output of mity coverage, for masking low-depth bases as N"),
+        )
+        .arg(
+            Arg::new("min_depth")
+                .long("min-depth")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("10")
+                .help("With --depth-bedgraph, mask bases below this depth as N. Default: 10"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let contamination_command = Command::new("contamination")
+        .about("Estimate contamination from low-level alleles at haplogroup-defining sites")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF to check, typically the output of mity normalise"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let copy_number_command = Command::new("copy-number")
+        .about("Estimate relative mtDNA copy number from MT and autosomal depth")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("bam")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("BAM file to estimate the copy number of"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: the BAM file name"),
+        )
+        .arg(
+            Arg::new("regions")
+                .long("regions")
+                .value_name("BED")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Autosomal regions to sample depth from. Default: 500 windows each on chr1 and chr20"),
+        )
+        .arg(
+            Arg::new("capture_bed")
+                .long("capture-bed")
+                .value_name("BED")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Capture targets of an exome; autosomal sampling is restricted to these"),
+        )
+        .arg(min_mapping_quality_arg.clone())
+        .arg(min_base_quality_arg.clone())
+        .arg(output_dir_arg.clone());
+
+    let extract_mt_command = Command::new("extract-mt")
+        .about("Subset a BAM/CRAM to the reads on the mitochondrial contig")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("bam")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Indexed BAM or CRAM to extract from"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output is written to PREFIX.MT.bam. Default: the input file name without its extension"),
+        )
+        .arg(output_dir_arg.clone())
+        .arg(
+            Arg::new("include_unmapped_mates")
+                .long("include-unmapped-mates")
+                .action(ArgAction::SetTrue)
+                .help("Also keep unmapped reads whose mate maps to the mitochondrial contig"),
+        )
+        .arg(
+            Arg::new("include_numts")
+                .long("include-numts")
+                .value_name("BED")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Also keep reads overlapping the nuclear NUMT regions in BED"),
+        )
+        .arg(reference_arg.clone());
+
+    let filter_command = Command::new("filter")
+        .about("Apply the mity filters to a VCF from any caller")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF to filter. It is not normalised or sorted first"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone())
+        .arg(min_site_dp_arg.clone())
+        .arg(min_sample_dp_arg.clone().visible_alias("min-dp"))
+        .arg(
+            Arg::new("sb_range")
+                .long("sb-range")
+                .value_name("LO,HI")
+                .value_parser(filter::parse_sb_range)
+                .help("Allowed range of the strand bias ratios SBR and SBA. Default: 0.1,0.9"),
+        )
+        .arg(
+            Arg::new("blacklist")
+                .long("blacklist")
+                .value_name("POSITIONS")
+                .value_parser(filter::parse_blacklist)
+                .help("Comma-separated positions or ranges to flag with the POS filter. Default: 302-318,3105-3107"),
+        )
+        .arg(
+            Arg::new("allsamples")
+                .long("allsamples")
+                .action(ArgAction::SetTrue)
+                .help("PASS requires all samples to pass"),
+        )
+        .arg(call_p_arg.clone());
+
+    let haplogroup_command = Command::new("haplogroup")
+        .about("Classify each sample in a mitochondrial VCF into a haplogroup")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF to classify, typically the output of mity normalise"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone());
+
+    let liftover_command = Command::new("liftover")
+        .about("Rename the mitochondrial contig of a VCF between MT and chrM")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF to rename"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_parser(["MT", "chrM"])
+                .required(true)
+                .help("Contig name to write"),
+        )
+        .arg(
+            Arg::new("rename_info")
+                .long("rename-info")
+                .action(ArgAction::SetTrue)
+                .help("Also rename loci such as MT:3243 embedded in INFO values"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone());
+
     let normalise_command = Command::new("normalise")
         .about("Normalise & filter mitochondrial variants")
         .arg(debug_arg.clone())
         .arg(vcf_arg.clone())
         .arg(output_dir_arg.clone())
+        .arg(force_arg.clone())
         .arg(prefix_arg.clone())
         .arg(
             Arg::new("allsamples")
@@ -279,15 +1772,80 @@ fn cli_commands() {
                 .action(ArgAction::SetTrue)
                 .help("PASS requires all samples to pass"),
         )
+        .arg(
+            Arg::new("no_split")
+                .long("no-split")
+                .action(ArgAction::SetTrue)
+                .help("Keep multi-allelic records intact, with per-allele values comma-separated and each allele's filters in INFO/ALLELE_FILTER"),
+        )
+        .arg(
+            Arg::new("keep_invalid")
+                .long("keep-invalid")
+                .action(ArgAction::SetTrue)
+                .help("Write records left invalid by normalisation (ALT empty or equal to REF, or outside the contig) to {prefix}.mity.normalise.invalid.vcf instead of only dropping them"),
+        )
+        .arg(
+            Arg::new("rejects_vcf")
+                .long("rejects-vcf")
+                .action(ArgAction::SetTrue)
+                .help("Also write every record that failed a filter to {prefix}.mity.rejected.vcf.gz, with INFO/REJECT_REASON naming the blacklist entry or threshold behind each filter"),
+        )
         .arg(keep_arg.clone())
         .arg(call_p_arg.clone())
-        .arg(reference_arg.clone());
+        .arg(min_site_dp_arg.clone())
+        .arg(min_sample_dp_arg.clone())
+        .arg(
+            Arg::new("panel_of_normals")
+                .long("panel-of-normals")
+                .action(ArgAction::Set)
+                .value_name("PON")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Panel of normals from mity build-pon. ALT alleles carried by at least --pon-min-freq of its samples fail PON, and their panel frequency goes in INFO/PON_FREQ"),
+        )
+        .arg(
+            Arg::new("pon_min_freq")
+                .long("pon-min-freq")
+                .value_name("FREQ")
+                .value_parser(clap::value_parser!(f64))
+                .help("Fraction of the panel of normals carrying an allele for it to be flagged. Default: 0.1"),
+        )
+        .arg(
+            Arg::new("pon_soft")
+                .long("pon-soft")
+                .action(ArgAction::SetTrue)
+                .help("Flag panel-of-normals alleles with INFO/PON instead of failing them"),
+        )
+        .arg(
+            Arg::new("audit_tsv")
+                .long("audit-tsv")
+                .action(ArgAction::SetTrue)
+                .help("Write {prefix}.mity.normalise.audit.tsv.gz with a row per filter criterion evaluated on each record: position, allele, criterion, observed value, threshold and result. Criteria that passed are left out unless --audit-all is given"),
+        )
+        .arg(
+            Arg::new("audit_all")
+                .long("audit-all")
+                .action(ArgAction::SetTrue)
+                .requires("audit_tsv")
+                .help("Also write the audit rows of criteria that passed"),
+        )
+        .arg(reference_arg.clone())
+        .arg(custom_reference_fasta_arg.clone())
+        .arg(custom_reference_genome_arg.clone())
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .action(ArgAction::Set)
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the normalised VCF to FILE instead of OUTPUT_DIR. Use '-' to stream bgzipped VCF to stdout without indexing"),
+        );
 
     let report_command = Command::new("report")
         .about("Generate mity report")
         .arg(debug_arg.clone())
         .arg(prefix_arg.clone())
         .arg(output_dir_arg.clone())
+        .arg(force_arg.clone())
         .arg(vcf_arg.clone())
         .arg(keep_arg.clone())
         .arg(contig_arg.clone())
@@ -314,8 +1872,11 @@ fn cli_commands() {
                 .help("nuclear vcf file"),
         )
         .arg(output_dir_arg.clone())
+        .arg(force_arg.clone())
         .arg(prefix_arg.clone())
         .arg(reference_arg.clone())
+        .arg(custom_reference_fasta_arg.clone())
+        .arg(custom_reference_genome_arg.clone())
         .arg(debug_arg.clone())
         .arg(keep_arg.clone());
 
@@ -324,12 +1885,19 @@ fn cli_commands() {
         .arg(debug_arg.clone())
         .arg(files_arg.clone())
         .arg(reference_arg.clone())
+        .arg(custom_reference_fasta_arg.clone())
+        .arg(custom_reference_genome_arg.clone())
         .arg(prefix_arg.clone())
         .arg(min_mapping_quality_arg.clone())
         .arg(min_base_quality_arg.clone())
         .arg(min_alternate_fraction_arg.clone())
         .arg(min_alternate_count_arg.clone())
+        .arg(preset_arg.clone())
+        .arg(ploidy_arg.clone())
         .arg(call_p_arg.clone())
+        .arg(skip_reference_check_arg.clone())
+        .arg(report_all_sites_arg.clone())
+        .arg(circular_arg.clone())
         .arg(output_dir_arg.clone())
         .arg(region_arg.clone())
         .arg(bam_file_list_arg.clone())
@@ -339,46 +1907,389 @@ fn cli_commands() {
         .arg(vcfanno_config.clone())
         .arg(report_config_arg.clone());
 
-    let check_command =
-        Command::new("check").about("Run runtime checks for mity-rs dependencies and features");
+    let plot_command = Command::new("plot")
+        .about("Plot coverage and heteroplasmy along the mitochondrial genome")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Normalised VCF to plot"),
+        )
+        .arg(
+            Arg::new("bedgraph")
+                .long("bedgraph")
+                .action(ArgAction::Append)
+                .value_name("BEDGRAPH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Depth bedgraph from mity coverage to draw as a coverage track. Give one per sample, in VCF sample order"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::Set)
+                .help("Output files will be named with PREFIX. Default: derived from the VCF name"),
+        )
+        .arg(output_dir_arg.clone())
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["svg", "png"])
+                .default_value("svg")
+                .help("Image format. Default: svg"),
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("1600")
+                .help("Image width in pixels. Default: 1600"),
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("600")
+                .help("Image height in pixels. Default: 600"),
+        )
+        .arg(
+            Arg::new("min_vaf")
+                .long("min-vaf")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0")
+                .help("Only draw variants with at least this VAF. Default: 0"),
+        )
+        .arg(
+            Arg::new("genome_map")
+                .long("genome-map")
+                .action(ArgAction::SetTrue)
+                .help("Also draw a circular map of the MT genome with each sample's variants around the genes, to {prefix}.{sample}.mity.map.svg"),
+        )
+        .arg(
+            Arg::new("map_label_vaf")
+                .long("map-label-vaf")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.5")
+                .help("Label the variants of the genome map with at least this VAF. Default: 0.5"),
+        );
+
+    let stats_command = Command::new("stats")
+        .about("Summarise the variants in a mity VCF")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("VCF to summarise. Indexed whole-genome VCFs are restricted to the MT contig"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the summary as JSON"),
+        )
+        .arg(
+            Arg::new("tsv")
+                .long("tsv")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("json")
+                .help("Print the summary as SCOPE/METRIC/VALUE rows"),
+        );
+
+    let validate_command = Command::new("validate")
+        .about("Check that a VCF is well-formed mity output")
+        .arg(debug_arg.clone())
+        .arg(
+            Arg::new("vcf")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("mity VCF to validate"),
+        )
+        .arg(
+            Arg::new("reference")
+                .long("reference")
+                .action(ArgAction::Set)
+                .value_name("GENOME")
+                .value_parser(["hs37d5", "hg19", "hg38", "mm10"])
+                .help("Also check that REF alleles match this reference genome"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the results as JSON"),
+        );
+
+    let check_command = Command::new("check")
+        .about("Run runtime checks for mity-rs dependencies and features")
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the results, including the .fai and .genome comparison of each reference, as JSON"),
+        )
+        .arg(min_freebayes_version_arg.clone());
+
+    let config_command = Command::new("config")
+        .about("Write or show the mity configuration file")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("init")
+                .about("Write a commented template with every configurable option and its default")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .default_value(config::PROJECT_CONFIG)
+                        .help("Where to write the template. Default: mity.toml"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite PATH if it exists"),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Print the effective configuration: built-in defaults merged with the discovered config files"),
+        );
+
+    let tool_path_args = Tool::ALL.map(|tool| {
+        Arg::new(tool.arg_id())
+            .long(tool.long_flag())
+            .action(ArgAction::Set)
+            .value_name("PATH")
+            .value_parser(clap::value_parser!(PathBuf))
+            .global(true)
+            .help(format!(
+                "Path to the {} executable. Overrides {} and PATH",
+                tool.name(),
+                tool.env_var()
+            ))
+    });
 
-    let matches = Command::new("mity-rs")
+    Command::new("mity-rs")
         .version("1.0")
         .about("Mity RS: Mitochondrial variant analysis toolkit in rust")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .args(tool_path_args)
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .action(ArgAction::Set)
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .global(true)
+                .help("Maximum number of threads and subprocesses to run at once, shared by all parallel steps. Default: 1"),
+        )
+        .arg(
+            Arg::new("max_memory")
+                .long("max-memory")
+                .action(ArgAction::Set)
+                .value_name("SIZE")
+                .value_parser(parse_memory_size)
+                .global(true)
+                .help("Memory to plan for, e.g. 4G or 512M. Not enforced, but it limits how many freebayes windows run at once"),
+        )
+        .arg(
+            Arg::new("mito_contig")
+                .long("mito-contig")
+                .visible_alias("mt-contig")
+                .action(ArgAction::Set)
+                .value_name("NAME")
+                .global(true)
+                .help("Name of the mitochondrial contig in the inputs. Default: MT, chrM, chrMT or NC_012920.1, or the only contig of an MT-only BAM"),
+        )
+        .arg(
+            Arg::new("checksums")
+                .long("checksums")
+                .action(ArgAction::Set)
+                .value_name("ALGORITHM")
+                .value_parser(["md5", "sha256"])
+                .global(true)
+                .help("Write a {file}.md5 or {file}.sha256 checksum next to each final output and record the digests in the stats JSON"),
+        )
+        .arg(
+            Arg::new("error_format")
+                .long("error-format")
+                .action(ArgAction::Set)
+                .value_name("FORMAT")
+                .value_parser(["human", "json"])
+                .default_value("human")
+                .global(true)
+                .help("Report a failure as a human-readable message, or as a single-line JSON object on stderr with the error kind, message, paths, failed tool and exit code"),
+        )
+        .arg(
+            Arg::new("no_color")
+                .long("no-color")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help("Never colour console output. Colour is also off when NO_COLOR is set or the output is not a terminal"),
+        )
+        .arg(
+            Arg::new("log_filter")
+                .long("log-filter")
+                .action(ArgAction::Set)
+                .value_name("DIRECTIVES")
+                .global(true)
+                .help("Per-module log levels, e.g. 'mity::normalise=trace,mity=info'. Defaults to RUST_LOG, and --debug is shorthand for 'mity=debug'"),
+        )
         .subcommand(call_command)
+        .subcommand(annotate_command)
+        .subcommand(coverage_command)
+        .subcommand(cohort_report_command)
+        .subcommand(build_pon_command)
+        .subcommand(compare_command)
+        .subcommand(extract_mt_command)
+        .subcommand(filter_command)
+        .subcommand(consensus_command)
+        .subcommand(contamination_command)
+        .subcommand(copy_number_command)
+        .subcommand(haplogroup_command)
+        .subcommand(liftover_command)
         .subcommand(normalise_command)
         .subcommand(report_command)
         .subcommand(merge_command)
         .subcommand(runall_command)
+        .subcommand(plot_command)
+        .subcommand(stats_command)
+        .subcommand(validate_command)
         .subcommand(check_command)
+        .subcommand(config_command)
+}
+
+/// Exit on an error in the config file. It is read before the command line
+/// is parsed, so `--error-format` is looked for in the raw arguments.
+fn exit_with_config_error(e: anyhow::Error) -> ! {
+    let args: Vec<String> = std::env::args().collect();
+    let json = args.iter().any(|arg| arg == "--error-format=json")
+        || args.windows(2).any(|pair| pair[0] == "--error-format" && pair[1] == "json");
+    if json {
+        error::set_error_format(error::ErrorFormat::Json);
+    }
+    error::exit_with_error("config", error::boxed(e.context("Failed to read the config file")).as_ref());
+}
+
+fn cli_commands() {
+    let config = Config::discover().unwrap_or_else(|e| exit_with_config_error(e));
+    let cli = build_cli();
+    // --preset gives way to these as to the command line
+    let configured_call = config.configured(&cli, "call").unwrap_or_else(|e| exit_with_config_error(e));
+    let matches = config
+        .apply(cli)
+        .unwrap_or_else(|e| exit_with_config_error(e))
         .get_matches();
 
+    if matches.get_one::<String>("error_format").map(String::as_str) == Some("json") {
+        error::set_error_format(error::ErrorFormat::Json);
+    }
+    style::init(matches.get_flag("no_color"));
+    init_logging(&matches);
+    set_tool_overrides(&matches);
+    if let Some(contig) = matches.get_one::<String>("mito_contig") {
+        mity_util::set_mito_contig_override(contig.clone());
+    }
+    if let Some(algorithm) = matches.get_one::<String>("checksums") {
+        checksum::set_algorithm(checksum::Algorithm::parse(algorithm).expect("clap restricts the values"));
+    }
+    mity_util::init_thread_budget(*matches.get_one::<usize>("threads").unwrap());
+    if let Some(bytes) = matches.get_one::<u64>("max_memory") {
+        mity_util::init_memory_budget(*bytes);
+    }
+    if let Some((command, command_matches)) = matches.subcommand() {
+        if let Ok(Some(output_dir)) = command_matches.try_get_one::<PathBuf>("output_dir") {
+            if let Err(e) = mity_util::prepare_output_dir(output_dir) {
+                error::exit_with_error(command, error::boxed(e).as_ref());
+            }
+        }
+    }
+
     match matches.subcommand() {
         Some(("call", call_matches)) => {
-            handle_call_command(call_matches);
+            handle_call_command(call_matches, &configured_call);
+        }
+        Some(("annotate", annotate_matches)) => {
+            handle_annotate_command(annotate_matches);
+        }
+        Some(("coverage", coverage_matches)) => {
+            handle_coverage_command(coverage_matches);
+        }
+        Some(("cohort-report", cohort_matches)) => {
+            handle_cohort_report_command(cohort_matches);
+        }
+        Some(("build-pon", build_pon_matches)) => {
+            handle_build_pon_command(build_pon_matches);
+        }
+        Some(("compare", compare_matches)) => {
+            handle_compare_command(compare_matches);
+        }
+        Some(("extract-mt", extract_mt_matches)) => {
+            handle_extract_mt_command(extract_mt_matches);
+        }
+        Some(("filter", filter_matches)) => {
+            handle_filter_command(filter_matches);
+        }
+        Some(("consensus", consensus_matches)) => {
+            handle_consensus_command(consensus_matches);
+        }
+        Some(("contamination", contamination_matches)) => {
+            handle_contamination_command(contamination_matches);
+        }
+        Some(("copy-number", copy_number_matches)) => {
+            handle_copy_number_command(copy_number_matches);
+        }
+        Some(("haplogroup", haplogroup_matches)) => {
+            handle_haplogroup_command(haplogroup_matches);
+        }
+        Some(("liftover", liftover_matches)) => {
+            handle_liftover_command(liftover_matches);
         }
         Some(("normalise", normalise_matches)) => {
             handle_normalise_command(normalise_matches);
         }
-        Some(("report", report_matches)) => {
-            // Handle the 'normalise' subcommand
-            println!("Not implemented yet!");
-            println!("{:?}", report_matches);
+        Some(("report", _)) => {
+            // Reading the VCF, '-' included, comes with the port of report;
+            // until then a pipe into it fails rather than exiting 0 unread
+            let e: Box<dyn std::error::Error> = "mity report is not implemented yet".into();
+            error::exit_with_error("report", e.as_ref());
         }
-        Some(("merge", merge_matches)) => {
-            // Handle the 'normalise' subcommand
-            println!("Not implemented yet!");
-            println!("{:?}", merge_matches);
+        Some(("merge", _)) => {
+            // Its options, the custom reference and --force among them, are
+            // only accepted so far; exiting 0 would pass for a merge
+            let e: Box<dyn std::error::Error> = "mity merge is not implemented yet".into();
+            error::exit_with_error("merge", e.as_ref());
         }
-        Some(("runall", runall_matches)) => {
-            // Handle the 'normalise' subcommand
-            println!("Not implemented yet!");
-            println!("{:?}", runall_matches);
+        Some(("runall", _)) => {
+            // Its options, --ploidy among them, are only accepted so far; a
+            // run that printed them and exited 0 would pass for a real one
+            let e: Box<dyn std::error::Error> =
+                "mity runall is not implemented yet; run mity call --normalise instead".into();
+            error::exit_with_error("runall", e.as_ref());
         }
-        Some(("check", _)) => {
-            check::mity_check();
+        Some(("plot", plot_matches)) => {
+            handle_plot_command(plot_matches);
+        }
+        Some(("stats", stats_matches)) => {
+            handle_stats_command(stats_matches);
+        }
+        Some(("validate", validate_matches)) => {
+            handle_validate_command(validate_matches);
+        }
+        Some(("check", check_matches)) => {
+            check::mity_check(
+                check_matches.get_flag("json"),
+                *check_matches
+                    .get_one::<ToolVersion>("min_freebayes_version")
+                    .expect("Has a default"),
+            );
+        }
+        Some(("config", config_matches)) => {
+            handle_config_command(config_matches, &config);
         }
         _ => unreachable!(),
     }
@@ -387,3 +2298,133 @@ fn cli_commands() {
 fn main() {
     cli_commands();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_memory_sizes() {
+        assert_eq!(parse_memory_size("4G"), Ok(4 << 30));
+        assert_eq!(parse_memory_size("4gb"), Ok(4 << 30));
+        assert_eq!(parse_memory_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_memory_size("1.5G"), Ok(1536 << 20));
+        assert_eq!(parse_memory_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_memory_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_memory_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_memory_size(" 2G "), Ok(2 << 30));
+        for invalid in ["", "G", "B", "-1G", "0", "lots", "4X", "NaNG"] {
+            assert!(parse_memory_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_in_range_checks_the_bounds_it_is_given() {
+        let quality = |value: &str| parse_in_range(value, |q: u32| q <= 254, "at most 254");
+        assert_eq!(quality("0"), Ok(0));
+        assert_eq!(quality("1"), Ok(1));
+        assert_eq!(quality("254"), Ok(254));
+        assert_eq!(quality(" 254 "), Ok(254));
+        assert_eq!(quality("255"), Err("must be at most 254".to_string()));
+        assert_eq!(quality("-1"), Err("expected a number at most 254".to_string()));
+        assert_eq!(quality("ten"), Err("expected a number at most 254".to_string()));
+        assert_eq!(quality(""), Err("expected a number at most 254".to_string()));
+    }
+
+    #[test]
+    fn parses_min_alternate_fraction_in_0_exclusive_to_1() {
+        assert_eq!(parse_min_alternate_fraction("1"), Ok(1.0));
+        assert_eq!(parse_min_alternate_fraction("0.01"), Ok(0.01));
+        assert_eq!(parse_min_alternate_fraction("1e-6"), Ok(1e-6));
+        for invalid in ["0", "0.0", "-0.01", "1.0001", "255", "NaN", "inf", "one"] {
+            assert!(parse_min_alternate_fraction(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(parse_min_alternate_fraction("0"), Err("must be greater than 0 and at most 1".to_string()));
+    }
+
+    #[test]
+    fn parses_p_in_0_to_1_exclusive() {
+        assert_eq!(parse_p("0.002"), Ok(0.002));
+        assert_eq!(parse_p("0.999"), Ok(0.999));
+        for invalid in ["0", "1", "-0.5", "254", "NaN", ""] {
+            assert!(parse_p(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(parse_p("1"), Err("must be greater than 0 and less than 1".to_string()));
+    }
+
+    #[test]
+    fn parses_min_vaf_in_0_to_1_inclusive() {
+        assert_eq!(parse_min_vaf("0"), Ok(0.0));
+        assert_eq!(parse_min_vaf("1"), Ok(1.0));
+        assert_eq!(parse_min_vaf("0.5"), Ok(0.5));
+        for invalid in ["-0.0001", "-1", "1.01", "255", "NaN"] {
+            assert!(parse_min_vaf(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(parse_min_vaf("-1"), Err("must be from 0 to 1".to_string()));
+    }
+
+    #[test]
+    fn exclude_duplicates_defaults_to_true_and_takes_false() {
+        let duplicates = |args: &[&str]| {
+            let mut argv = vec!["mity-rs", "call", "--prefix", "s"];
+            argv.extend(args);
+            argv.push("a.bam");
+            let matches = build_cli().try_get_matches_from(argv).unwrap();
+            let (_, call_matches) = matches.subcommand().unwrap();
+            *call_matches.get_one::<bool>("exclude_duplicates").unwrap()
+        };
+        assert!(duplicates(&[]));
+        assert!(duplicates(&["--exclude-duplicates"]));
+        assert!(duplicates(&["--exclude-duplicates=true"]));
+        assert!(!duplicates(&["--exclude-duplicates=false"]));
+    }
+
+    #[test]
+    fn base_and_mapping_qualities_are_at_most_254() {
+        for flag in ["--min-mapping-quality", "--min-base-quality"] {
+            let parse = |value: &str| {
+                let arg = format!("{}={}", flag, value);
+                build_cli().try_get_matches_from(["mity-rs", "call", "--prefix", "s", &arg, "a.bam"])
+            };
+            for valid in ["0", "1", "254"] {
+                assert!(parse(valid).is_ok(), "{} {}", flag, valid);
+            }
+            for invalid in ["255", "-1", "1.5"] {
+                let error = parse(invalid).unwrap_err();
+                assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation, "{} {}", flag, invalid);
+            }
+        }
+    }
+
+    #[test]
+    fn config_values_and_the_command_line_override_the_preset() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("mity.toml");
+        std::fs::write(&path, "[call]\nmin_base_quality = 15\np = 0.05\n").unwrap();
+        let config = Config::read(&[path]).unwrap();
+        let cli = build_cli();
+        let configured = config.configured(&cli, "call").unwrap();
+        assert_eq!(configured, ["min_base_quality", "p"]);
+
+        let matches = config
+            .apply(cli)
+            .unwrap()
+            .try_get_matches_from(["mity-rs", "call", "--prefix", "s", "--preset", "long-read", "--min-alternate-count", "6", "a.bam"])
+            .unwrap();
+        let (_, call_matches) = matches.subcommand().unwrap();
+        // From the config and the command line
+        assert_eq!(given::<u32>(call_matches, "min_base_quality", &configured), Some(15));
+        assert_eq!(given::<f32>(call_matches, "p", &configured), Some(0.05));
+        assert_eq!(given::<u32>(call_matches, "min_alternate_count", &configured), Some(6));
+        // Left to the preset
+        assert_eq!(given::<u32>(call_matches, "min_mapping_quality", &configured), None);
+        assert_eq!(given::<f32>(call_matches, "min_alternate_fraction", &configured), None);
+
+        let matches = build_cli()
+            .try_get_matches_from(["mity-rs", "call", "--prefix", "s", "--preset", "long-read", "a.bam"])
+            .unwrap();
+        let (_, call_matches) = matches.subcommand().unwrap();
+        // The built-in default is not given
+        assert_eq!(given::<u32>(call_matches, "min_base_quality", &[]), None);
+    }
+}
@@ -1,10 +1,375 @@
+
 use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
 use glob::glob;
-use log::debug;
-use noodles::vcf;
+use log::{debug, info, warn};
+use noodles::core::region::Interval as RegionInterval;
+use noodles::csi::binning_index::{index::reference_sequence::bin::Chunk, Indexer};
+use noodles::sam::alignment::Record as _;
+use noodles::vcf::variant::io::Write as _;
+use noodles::{bam, bcf, fasta, sam, vcf};
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum;
+use crate::error::MityError;
+
+pub mod header;
+
+/// External tools that mity shells out to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tool {
+    Freebayes,
+    Bcftools,
+    Tabix,
+    Gsort,
+    Vcfanno,
+    Samtools,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 6] = [
+        Tool::Freebayes,
+        Tool::Bcftools,
+        Tool::Tabix,
+        Tool::Gsort,
+        Tool::Vcfanno,
+        Tool::Samtools,
+    ];
+
+    /// Name of the executable looked up on PATH.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Freebayes => "freebayes",
+            Tool::Bcftools => "bcftools",
+            Tool::Tabix => "tabix",
+            Tool::Gsort => "gsort",
+            Tool::Vcfanno => "vcfanno",
+            Tool::Samtools => "samtools",
+        }
+    }
+
+    /// Environment variable that overrides the executable location.
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            Tool::Freebayes => "MITY_FREEBAYES",
+            Tool::Bcftools => "MITY_BCFTOOLS",
+            Tool::Tabix => "MITY_TABIX",
+            Tool::Gsort => "MITY_GSORT",
+            Tool::Vcfanno => "MITY_VCFANNO",
+            Tool::Samtools => "MITY_SAMTOOLS",
+        }
+    }
+
+    /// Long name of the command line override, e.g. `freebayes-path`.
+    pub fn long_flag(&self) -> &'static str {
+        match self {
+            Tool::Freebayes => "freebayes-path",
+            Tool::Bcftools => "bcftools-path",
+            Tool::Tabix => "tabix-path",
+            Tool::Gsort => "gsort-path",
+            Tool::Vcfanno => "vcfanno-path",
+            Tool::Samtools => "samtools-path",
+        }
+    }
+
+    /// Id of the `--<tool>-path` command line argument.
+    pub fn arg_id(&self) -> &'static str {
+        match self {
+            Tool::Freebayes => "freebayes_path",
+            Tool::Bcftools => "bcftools_path",
+            Tool::Tabix => "tabix_path",
+            Tool::Gsort => "gsort_path",
+            Tool::Vcfanno => "vcfanno_path",
+            Tool::Samtools => "samtools_path",
+        }
+    }
+}
+
+static TOOL_OVERRIDES: OnceLock<HashMap<Tool, PathBuf>> = OnceLock::new();
+
+/// Register tool paths given on the command line. These take precedence over
+/// the `MITY_*` environment variables. Only the first call has any effect.
+pub fn set_tool_overrides(overrides: HashMap<Tool, PathBuf>) {
+    let _ = TOOL_OVERRIDES.set(overrides);
+}
+
+/// Resolve the absolute path of an external tool.
+///
+/// Lookup order is the command line override, then the tool's `MITY_*`
+/// environment variable, then PATH.
+pub fn tool_path(tool: Tool) -> Result<PathBuf> {
+    if let Some(path) = TOOL_OVERRIDES.get().and_then(|o| o.get(&tool)) {
+        return resolve_explicit_tool(tool, path, "command line");
+    }
+    if let Some(path) = env::var_os(tool.env_var()).filter(|p| !p.is_empty()) {
+        return resolve_explicit_tool(tool, Path::new(&path), tool.env_var());
+    }
+
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path_var) {
+        let candidate = dir.join(tool.name());
+        if candidate.is_file() {
+            let resolved = candidate.canonicalize().unwrap_or(candidate);
+            debug!("Resolved {} from PATH: {}", tool.name(), resolved.display());
+            return Ok(resolved);
+        }
+    }
+    anyhow::bail!(
+        "Command '{}' is not installed or not in PATH (set {} or --{} to override)",
+        tool.name(),
+        tool.env_var(),
+        tool.long_flag()
+    )
+}
+
+fn resolve_explicit_tool(tool: Tool, path: &Path, source: &str) -> Result<PathBuf> {
+    if !path.is_file() {
+        anyhow::bail!(
+            "{} path from {} does not exist: {}",
+            tool.name(),
+            source,
+            path.display()
+        );
+    }
+    let resolved = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {} path {}", tool.name(), path.display()))?;
+    debug!("Resolved {} from {}: {}", tool.name(), source, resolved.display());
+    Ok(resolved)
+}
+
+/// Build a `Command` for an external tool using its resolved path.
+pub fn tool_command(tool: Tool) -> Result<Command> {
+    Ok(Command::new(tool_path(tool)?))
+}
+
+/// Resolved tool paths formatted for provenance headers, e.g.
+/// `freebayes=/usr/bin/freebayes,tabix=/usr/bin/tabix`.
+pub fn tool_provenance(tools: &[Tool]) -> String {
+    tools
+        .iter()
+        .map(|tool| match tool_path(*tool) {
+            Ok(path) => format!("{}={}", tool.name(), path.display()),
+            Err(_) => format!("{}=NA", tool.name()),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A `major.minor.patch` tool version; a missing patch reads as 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ToolVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ToolVersion {
+    /// The first version in `text`, as in the `--version` output of
+    /// freebayes, which has been `version:  v1.3.6`, `version:  v1.2.0-2-g29c4002`
+    /// and `v1.3.1-dirty` with git builds.
+    pub fn parse(text: &str) -> Option<Self> {
+        text.split(|c: char| c.is_whitespace() || c == ',' || c == ':' || c == '=')
+            .filter_map(|word| {
+                let word = word.strip_prefix(['v', 'V']).unwrap_or(word);
+                let version = word.split(['-', '+']).next()?;
+                let mut numbers = version.split('.').map(str::parse::<u32>);
+                let major = numbers.next()?.ok()?;
+                let minor = numbers.next()?.ok()?;
+                let patch = match numbers.next() {
+                    Some(patch) => patch.ok()?,
+                    None => 0,
+                };
+                Some(ToolVersion { major, minor, patch })
+            })
+            .next()
+    }
+}
+
+impl fmt::Display for ToolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Parse a version given on the command line, such as `1.3.6`.
+pub fn parse_tool_version(value: &str) -> std::result::Result<ToolVersion, String> {
+    ToolVersion::parse(value).ok_or_else(|| format!("expected a version such as 1.3.6, got {}", value))
+}
+
+/// The version `freebayes --version` reports.
+pub fn freebayes_version() -> Result<ToolVersion> {
+    let output = Command::new(tool_path(Tool::Freebayes)?)
+        .arg("--version")
+        .output()
+        .context("Failed to run freebayes --version")?;
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    ToolVersion::parse(&text)
+        .with_context(|| format!("No version in the output of freebayes --version: {}", text.trim()))
+}
+
+/// Process-wide budget of concurrently running threads and subprocesses.
+///
+/// Every parallel feature acquires permits before starting work (an external
+/// process counts as the number of threads it is told to use), so combining
+/// features never runs more than `--threads` things at once.
+pub struct ThreadBudget {
+    total: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Permits held from a [`ThreadBudget`], returned when dropped.
+pub struct Permit<'a> {
+    budget: &'a ThreadBudget,
+    count: usize,
+}
+
+impl ThreadBudget {
+    fn new(total: usize) -> Self {
+        let total = total.max(1);
+        ThreadBudget {
+            total,
+            available: Mutex::new(total),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Block until `count` permits (clamped to the budget) are free.
+    pub fn acquire(&self, stage: &str, count: usize) -> Permit<'_> {
+        let count = count.clamp(1, self.total);
+        let mut available = self.available.lock().unwrap();
+        while *available < count {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= count;
+        debug!(
+            "{} acquired {} of {} thread permits ({} free)",
+            stage, count, self.total, *available
+        );
+        Permit {
+            budget: self,
+            count,
+        }
+    }
+
+    /// Split the budget between `tasks` concurrent tasks that each want
+    /// `threads_per_task`. Returns how many tasks may run at once and how
+    /// many threads each should use.
+    // No command splits the budget this way yet
+    #[allow(dead_code)]
+    pub fn allocate(&self, stage: &str, tasks: usize, threads_per_task: usize) -> (usize, usize) {
+        let threads_per_task = threads_per_task.clamp(1, self.total);
+        let concurrent = (self.total / threads_per_task).clamp(1, tasks.max(1));
+        info!(
+            "{}: {} concurrent task(s) x {} thread(s) from a budget of {}",
+            stage, concurrent, threads_per_task, self.total
+        );
+        (concurrent, threads_per_task)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut available = self.budget.available.lock().unwrap();
+        *available += self.count;
+        self.budget.released.notify_all();
+    }
+}
+
+static THREAD_BUDGET: OnceLock<ThreadBudget> = OnceLock::new();
+
+/// Set the global thread budget from `--threads`. Only the first call has
+/// any effect.
+pub fn init_thread_budget(threads: usize) {
+    let _ = THREAD_BUDGET.set(ThreadBudget::new(threads));
+}
+
+/// The global thread budget, a single thread if it was never initialised.
+pub fn thread_budget() -> &'static ThreadBudget {
+    THREAD_BUDGET.get_or_init(|| ThreadBudget::new(1))
+}
+
+static MEMORY_BUDGET: OnceLock<u64> = OnceLock::new();
+
+/// Set the memory hint from `--max-memory`, in bytes. It is not enforced:
+/// steps size their parallelism from it. Only the first call has any effect.
+pub fn init_memory_budget(bytes: u64) {
+    let _ = MEMORY_BUDGET.set(bytes);
+}
+
+/// The `--max-memory` hint in bytes, if one was given.
+pub fn memory_budget() -> Option<u64> {
+    MEMORY_BUDGET.get().copied()
+}
+
+/// `arg` quoted for a POSIX shell, unless it needs no quoting.
+pub fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:,=+@%".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// A `##key` header line with the command that made a VCF, quoted so it
+/// can be re-run, and the mity version and time it ran.
+pub fn command_line_header(key: &str, command: &[String]) -> String {
+    let command: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+    format!(
+        "##{}=\"{}; Version={}; Date={}\"",
+        key,
+        command.join(" ").replace('\\', "\\\\").replace('"', "\\\""),
+        env!("CARGO_PKG_VERSION"),
+        utc_timestamp()
+    )
+}
+
+/// Current UTC time formatted as RFC 3339, e.g. `2024-11-20T03:14:07Z`.
+pub fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
 
 /// Get the directory path of the Mity library.
 pub fn get_mity_dir() -> Result<PathBuf> {
@@ -14,98 +379,499 @@ pub fn get_mity_dir() -> Result<PathBuf> {
 }
 
 /// Generate a tabix index for a bgzipped file.
-pub fn tabix(file: &str) -> Result<()> {
-    let tabix_call = format!("tabix -f {}", file);
-    debug!("{}", tabix_call);
-    Command::new("sh")
-        .arg("-c")
-        .arg(tabix_call)
+pub fn tabix(file: &Path) -> Result<()> {
+    let tabix = tool_path(Tool::Tabix)?;
+    debug!("{} -f {}", tabix.display(), file.display());
+    Command::new(tabix)
+        .arg("-f")
+        .arg(file)
         .status()
         .context("Failed to run tabix command")?;
     Ok(())
 }
 
+/// Find files in the mitylib reference directory whose name matches a
+/// pattern like `hs37d5*.fa`. The directory is escaped so that installs under
+/// unusual paths still match.
+fn find_reference_files(file_pattern: &str) -> Result<Vec<PathBuf>> {
+    let ref_dir = get_mity_dir()?.join("reference");
+    let ref_dir = ref_dir
+        .to_str()
+        .with_context(|| format!("mitylib path is not valid UTF-8: {}", ref_dir.display()))?;
+    let pattern = format!("{}/{}", glob::Pattern::escape(ref_dir), file_pattern);
+    let files = glob(&pattern)?.collect::<Result<Vec<PathBuf>, _>>()?;
+    debug!("{:?}", files);
+    Ok(files)
+}
+
 /// Select the reference genome fasta file.
 pub fn select_reference_fasta(
     reference: &str,
-    custom_reference_fa: Option<&str>,
-) -> Result<String> {
+    custom_reference_fa: Option<&Path>,
+) -> Result<PathBuf> {
     if let Some(custom_path) = custom_reference_fa {
-        if Path::new(custom_path).exists() {
-            return Ok(custom_path.to_string());
+        if custom_path.exists() {
+            return Ok(custom_path.to_path_buf());
         }
     }
-    let ref_dir = get_mity_dir()?.join("reference");
-    let pattern = format!("{}/{}*.fa", ref_dir.display(), reference);
-    let files: Vec<_> = glob(&pattern)?.collect();
-    debug!("{:?}", files);
+    let mut files = find_reference_files(&format!("{}*.fa", reference))?;
     if files.len() != 1 {
         anyhow::bail!(
             "Expected exactly one reference fasta file, found: {:?}",
             files
         );
     }
-    Ok(files[0].as_ref().unwrap().to_str().unwrap().to_string())
+    Ok(files.remove(0))
 }
 
 /// Select the reference genome .genome file.
 pub fn select_reference_genome(
     reference: &str,
-    custom_reference_genome: Option<&str>,
-) -> Result<String> {
+    custom_reference_genome: Option<&Path>,
+) -> Result<PathBuf> {
     if let Some(custom_path) = custom_reference_genome {
-        if Path::new(custom_path).exists() {
-            return Ok(custom_path.to_string());
+        if custom_path.exists() {
+            return Ok(custom_path.to_path_buf());
         }
     }
-    let ref_dir = get_mity_dir()?.join("reference");
-    let pattern = format!("{}/{}.genome", ref_dir.display(), reference);
-    let files: Vec<_> = glob(&pattern)?.collect();
-    debug!("{:?}", files);
+    let mut files = find_reference_files(&format!("{}.genome", reference))?;
     if files.len() != 1 {
         anyhow::bail!(
             "Expected exactly one reference genome file, found: {:?}",
             files
         );
     }
-    Ok(files[0].as_ref().unwrap().to_str().unwrap().to_string())
+    Ok(files.remove(0))
 }
 
 /// Get the mitochondrial contig name and length from a VCF file.
-pub fn vcf_get_mt_contig(vcf_path: &str) -> Result<(String, usize)> {
+pub fn vcf_get_mt_contig(vcf_path: &Path) -> Result<(String, usize)> {
     let mut reader = vcf::io::reader::Builder::default().build_from_path(vcf_path)?;
     let header = reader.read_header()?;
-    let contigs = header.contigs();
-    let mito_contig: Vec<&String> = contigs
-        .keys()
-        .filter(|key| key == &"MT" || key == &"chrM")
+    let contigs: Vec<(String, usize)> = header
+        .contigs()
+        .iter()
+        .map(|(name, contig)| (name.to_string(), contig.length().unwrap_or(0)))
         .collect();
-    if mito_contig.len() != 1 {
+    pick_mt_contig(&contigs, &vcf_path.display().to_string())
+}
+
+/// Create `dir` and any missing parents, and check that files can be
+/// written in it, so a bad `--output-dir` fails before any work is done.
+pub fn prepare_output_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create the output directory {}", dir.display()))?;
+    let probe = dir.join(format!(".mity-write-test-{}", std::process::id()));
+    File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .with_context(|| format!("Cannot write to the output directory {}", dir.display()))?;
+    Ok(())
+}
+
+/// Log the intermediate files a run kept, with `--keep`, or removed.
+pub fn log_intermediate_files(kept: bool, files: &[PathBuf]) {
+    if files.is_empty() {
+        return;
+    }
+    let names: Vec<String> = files.iter().map(|path| path.display().to_string()).collect();
+    if kept {
+        info!("Kept intermediate files: {}", names.join(", "));
+    } else {
+        info!("Removed intermediate files: {}", names.join(", "));
+    }
+}
+
+/// Fail, listing them, if any of `outputs` exist, unless `force` is set.
+pub fn check_overwrite(outputs: &[&Path], force: bool) -> Result<()> {
+    let existing: Vec<String> = outputs
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| format!("  {}", path.display()))
+        .collect();
+    if !force && !existing.is_empty() {
         anyhow::bail!(
-            "Expected exactly one mitochondrial contig, found: {:?}",
-            mito_contig
+            "These outputs already exist; pass --force to overwrite them:\n{}",
+            existing.join("\n")
         );
     }
-    let contig = mito_contig[0].to_string();
-    let length = contigs[contig.as_str()].length().unwrap_or(0);
-    Ok((contig, length))
+    Ok(())
+}
+
+/// The index next to a BAM (`.bai`, `.csi`) or CRAM (`.crai`), if any.
+pub fn bam_index(bam: &Path) -> Option<PathBuf> {
+    let suffixes: &[&str] = if is_cram(bam) { &["crai"] } else { &["bai", "csi"] };
+    let mut index_names = Vec::new();
+    for suffix in suffixes {
+        let mut name = bam.as_os_str().to_owned();
+        name.push(format!(".{}", suffix));
+        index_names.push(PathBuf::from(name));
+    }
+    index_names.push(bam.with_extension(suffixes[0]));
+    index_names.into_iter().find(|path| path.exists())
+}
+
+/// Open a BAM for region queries with the index [`bam_index`] finds, which
+/// may be `sample.bai` as well as `sample.bam.bai`.
+pub fn open_indexed_bam(bam: &Path) -> Result<bam::io::IndexedReader<noodles::bgzf::Reader<File>>> {
+    let index = bam_index(bam).with_context(|| format!("{} has no .bai or .csi index", bam.display()))?;
+    let opened = if index.extension().is_some_and(|extension| extension == "csi") {
+        noodles::csi::read(&index).and_then(|csi| bam::io::indexed_reader::Builder::default().set_index(csi).build_from_path(bam))
+    } else {
+        bam::bai::read(&index).and_then(|bai| bam::io::indexed_reader::Builder::default().set_index(bai).build_from_path(bam))
+    };
+    opened.map_err(|e| anyhow::anyhow!("Failed to open {} with its index {}: {}", bam.display(), index.display(), e))
+}
+
+/// Write a `.bai` index next to a coordinate-sorted BAM.
+pub fn index_bam(bam_path: &Path) -> Result<PathBuf> {
+    let mut reader = bam::io::reader::Builder
+        .build_from_path(bam_path)
+        .with_context(|| format!("Failed to open BAM {}", bam_path.display()))?;
+    let header = reader.read_header()?;
+
+    let mut indexer = Indexer::default();
+    let mut record = bam::Record::default();
+    let mut start = reader.get_ref().virtual_position();
+    while reader.read_record(&mut record)? != 0 {
+        let end = reader.get_ref().virtual_position();
+        let context = match (
+            record.reference_sequence_id().transpose()?,
+            record.alignment_start().transpose()?,
+            record.alignment_end().transpose()?,
+        ) {
+            (Some(id), Some(alignment_start), Some(alignment_end)) => Some((
+                id,
+                alignment_start,
+                alignment_end,
+                !record.flags().is_unmapped(),
+            )),
+            _ => None,
+        };
+        indexer.add_record(context, Chunk::new(start, end))?;
+        start = end;
+    }
+    let index = indexer.build(header.reference_sequences().len());
+
+    let mut index_path = bam_path.as_os_str().to_owned();
+    index_path.push(".bai");
+    let index_path = PathBuf::from(index_path);
+    bam::bai::io::Writer::new(File::create(&index_path)?).write_index(&index)?;
+    Ok(index_path)
+}
+
+/// Write a `.crai` index next to a CRAM with `samtools index`.
+pub fn index_cram(cram_path: &Path) -> Result<PathBuf> {
+    let output = tool_command(Tool::Samtools)?
+        .arg("index")
+        .arg(cram_path)
+        .output()
+        .context("Failed to run samtools index")?;
+    if !output.status.success() {
+        return Err(MityError::tool("samtools index", output.status.code(), &output.stderr).into());
+    }
+    let mut index_path = cram_path.as_os_str().to_owned();
+    index_path.push(".crai");
+    Ok(PathBuf::from(index_path))
 }
 
 /// Get the path to an annotation file.
-pub fn get_annot_file(annotation_file_path: &str) -> Result<String> {
+pub fn get_annot_file(annotation_file_path: &str) -> Result<PathBuf> {
     let mitylib_dir = get_mity_dir()?;
     let path = mitylib_dir.join("annot").join(annotation_file_path);
     if !path.exists() {
         anyhow::bail!("Annotation file not found: {}", path.display());
     }
-    Ok(path.to_string_lossy().into_owned())
+    Ok(path)
+}
+
+/// Name and length of the mitochondrial contig in a BAM or CRAM header,
+/// read through the header cache of [`bam_header_text`].
+pub fn bam_mt_contig(bam: &Path) -> Result<(String, usize)> {
+    let contigs: Vec<(String, usize)> = bam_header_text(bam)?
+        .lines()
+        .filter(|line| line.starts_with("@SQ\t"))
+        .filter_map(|line| {
+            let field = |tag: &str| line.split('\t').find_map(|field| field.strip_prefix(tag));
+            Some((field("SN:")?.to_string(), field("LN:")?.parse().ok()?))
+        })
+        .collect();
+    pick_mt_contig(&contigs, &bam.display().to_string())
+}
+
+/// Names mitochondrial contigs are detected by: GRCh37, UCSC/hg38, some
+/// in-house builds and the rCRS RefSeq accession.
+pub const MT_CONTIG_NAMES: [&str; 4] = ["MT", "chrM", "chrMT", "NC_012920.1"];
+
+/// Contigs listed in an error about a header that has no recognisable
+/// mitochondrial contig; WGS headers can have thousands.
+const LISTED_CONTIGS: usize = 25;
+
+/// Whether `name` is a mitochondrial contig name: one of [`MT_CONTIG_NAMES`]
+/// or the `--mito-contig` override. Files using different namings can be
+/// matched up through this.
+pub fn is_mt_contig(name: &str) -> bool {
+    MT_CONTIG_NAMES.contains(&name) || MITO_CONTIG_OVERRIDE.get().is_some_and(|contig| contig == name)
+}
+
+/// Lengths of the mitochondrial sequences mity knows: rCRS (GRCh37 MT and
+/// hg38 chrM), the hg19 Yoruba chrM and mm10 chrM.
+pub const MITO_LENGTHS: [usize; 3] = [16569, 16571, 16299];
+
+static MITO_CONTIG_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Register the `--mito-contig` name, used instead of detecting the
+/// mitochondrial contig. Only the first call has any effect.
+pub fn set_mito_contig_override(contig: String) {
+    let _ = MITO_CONTIG_OVERRIDE.set(contig);
+}
+
+pub fn mito_contig_override() -> Option<&'static str> {
+    MITO_CONTIG_OVERRIDE.get().map(String::as_str)
+}
+
+/// The mitochondrial contig among `(name, length)` contigs: the
+/// `--mito-contig` override, else the one with a name in
+/// [`MT_CONTIG_NAMES`], else the only contig when its length is a known
+/// mitochondrial length, as in BAMs subset to chrM and renamed.
+fn pick_mt_contig(contigs: &[(String, usize)], source: &str) -> Result<(String, usize)> {
+    let present = || {
+        let mut names: Vec<&str> = contigs.iter().take(LISTED_CONTIGS).map(|(name, _)| name.as_str()).collect();
+        if contigs.len() > LISTED_CONTIGS {
+            names.push("...");
+        }
+        format!("{} has {} contig(s): {}", source, contigs.len(), names.join(", "))
+    };
+    if let Some(name) = MITO_CONTIG_OVERRIDE.get() {
+        return contigs
+            .iter()
+            .find(|(contig, _)| contig == name)
+            .cloned()
+            .with_context(|| format!("--mito-contig {} is not a contig of {}; {}", name, source, present()));
+    }
+
+    let named: Vec<&(String, usize)> = contigs
+        .iter()
+        .filter(|(name, _)| MT_CONTIG_NAMES.contains(&name.as_str()))
+        .collect();
+    match (named.as_slice(), contigs) {
+        ([contig], _) => Ok((*contig).clone()),
+        ([], [(name, length)]) if MITO_LENGTHS.contains(length) => {
+            info!(
+                "Treating {} ({} bp), the only contig of {}, as the mitochondrial contig; \
+                 use --mito-contig if this is wrong",
+                name, length, source
+            );
+            Ok((name.clone(), *length))
+        }
+        ([], _) => anyhow::bail!(
+            "No mitochondrial contig ({}) found; {}. Name it with --mito-contig",
+            MT_CONTIG_NAMES.join(", "),
+            present()
+        ),
+        (named, _) => anyhow::bail!(
+            "Several mitochondrial contigs ({}) found in {}; name the one to use with --mito-contig",
+            named.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "),
+            source
+        ),
+    }
+}
+
+/// Read the sequence of one contig from a FASTA file.
+pub fn read_fasta_contig(fasta_path: &Path, contig: &str) -> Result<Vec<u8>> {
+    let mut reader = fasta::io::reader::Builder
+        .build_from_path(fasta_path)
+        .with_context(|| format!("Failed to open {}", fasta_path.display()))?;
+    for result in reader.records() {
+        let record = result?;
+        if record.name() == contig.as_bytes() {
+            return Ok(record.sequence().as_ref().to_vec());
+        }
+    }
+    anyhow::bail!("Contig {} not found in {}", contig, fasta_path.display())
+}
+
+/// A 0-based, half-open interval on a named contig, as in a BED file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub contig: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Interval {
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Parse a 1-based, inclusive `contig:start-end` region.
+    pub fn parse_region(region: &str) -> Result<Self> {
+        let (contig, range) = region
+            .rsplit_once(':')
+            .with_context(|| format!("Expected CONTIG:START-END but got {}", region))?;
+        let (start, end) = range
+            .replace(',', "")
+            .split_once('-')
+            .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)))
+            .with_context(|| format!("Expected CONTIG:START-END but got {}", region))?;
+        if start == 0 || end < start {
+            anyhow::bail!("Invalid region {}: coordinates are 1-based and START <= END", region);
+        }
+        Ok(Interval {
+            contig: contig.to_string(),
+            start: start - 1,
+            end,
+        })
+    }
+
+    /// The interval as a 1-based, inclusive `contig:start-end` region.
+    pub fn to_region(&self) -> String {
+        format!("{}:{}-{}", self.contig, self.start + 1, self.end)
+    }
+
+    /// The interval as freebayes reads `--region`: `contig:start-end` in
+    /// 0-based, half-open BED coordinates.
+    pub fn to_freebayes_region(&self) -> String {
+        format!("{}:{}-{}", self.contig, self.start, self.end)
+    }
+
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.contig == other.contig && self.start < other.end && other.start < self.end
+    }
+
+    /// The parts of the interval not covered by any of `exclusions`.
+    pub fn subtract(&self, exclusions: &[Interval]) -> Vec<Interval> {
+        let mut cuts: Vec<&Interval> = exclusions.iter().filter(|e| self.overlaps(e)).collect();
+        cuts.sort_by_key(|e| e.start);
+
+        let mut parts = Vec::new();
+        let mut start = self.start;
+        for cut in cuts {
+            if cut.start > start {
+                parts.push(Interval {
+                    contig: self.contig.clone(),
+                    start,
+                    end: cut.start,
+                });
+            }
+            start = start.max(cut.end);
+        }
+        if start < self.end {
+            parts.push(Interval {
+                contig: self.contig.clone(),
+                start,
+                end: self.end,
+            });
+        }
+        parts
+    }
+}
+
+/// Read the first three columns of a BED file. Header, track and comment
+/// lines are skipped.
+pub fn read_bed(path: &Path) -> Result<Vec<Interval>> {
+    Ok(read_named_bed(path)?
+        .into_iter()
+        .map(|(interval, _)| interval)
+        .collect())
+}
+
+/// Read the intervals of a BED file with their name column, if any.
+pub fn read_named_bed(path: &Path) -> Result<Vec<(Interval, Option<String>)>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read BED file {}", path.display()))?;
+
+    let mut intervals = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parse = |field: Option<&&str>| -> Result<usize> {
+            field
+                .and_then(|f| f.trim().parse().ok())
+                .with_context(|| format!("{}:{}: invalid BED line", path.display(), index + 1))
+        };
+        let interval = Interval {
+            contig: fields[0].to_string(),
+            start: parse(fields.get(1))?,
+            end: parse(fields.get(2))?,
+        };
+        if interval.end < interval.start {
+            anyhow::bail!("{}:{}: end is before start", path.display(), index + 1);
+        }
+        let name = fields
+            .get(3)
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty() && *name != ".")
+            .map(String::from);
+        intervals.push((interval, name));
+    }
+    Ok(intervals)
+}
+
+/// Read a list of input files, one per line. Blank lines and `#` comments
+/// are skipped, glob patterns such as `batch1/*.bam` are expanded, and
+/// relative paths are taken from the list's directory. A file listed twice
+/// is kept once, with a warning. Errors name the line they come from.
+pub fn read_file_list(list: &Path) -> Result<Vec<PathBuf>> {
+    let content = fs::read_to_string(list)
+        .with_context(|| format!("Failed to read file list {}", list.display()))?;
+    let dir = list.parent().unwrap_or(Path::new(""));
+    let mut files: Vec<PathBuf> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let location = || format!("{} line {}", list.display(), index + 1);
+        let listed = if line.contains(['*', '?', '[']) {
+            // Escape the directory so only the listed pattern is expanded
+            let pattern = if Path::new(line).is_absolute() || dir.as_os_str().is_empty() {
+                line.to_string()
+            } else {
+                let dir = dir
+                    .to_str()
+                    .with_context(|| format!("File list directory is not valid UTF-8: {}", dir.display()))?;
+                format!("{}/{}", glob::Pattern::escape(dir), line)
+            };
+            let matches = glob(&pattern)
+                .with_context(location)?
+                .collect::<Result<Vec<PathBuf>, _>>()
+                .with_context(location)?;
+            if matches.is_empty() {
+                anyhow::bail!("{}: {} matches no files", location(), line);
+            }
+            matches
+        } else {
+            let path = dir.join(line);
+            if !path.exists() {
+                return Err(anyhow::Error::new(MityError::MissingFile(path)).context(location()));
+            }
+            vec![path]
+        };
+        for file in listed {
+            if files.contains(&file) {
+                warn!("{}: {} is already listed; using it once", location(), file.display());
+            } else {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
 }
 
 /// Make a prefix based on the input VCF path.
-pub fn make_prefix(vcf_path: &str) -> String {
-    Path::new(vcf_path)
+pub fn make_prefix(vcf_path: &Path) -> String {
+    vcf_path
         .file_name()
-        .unwrap()
+        .unwrap_or_default()
         .to_string_lossy()
         .replace(".mity", "")
         .replace(".call", "")
@@ -113,20 +879,588 @@ pub fn make_prefix(vcf_path: &str) -> String {
         .replace(".merge", "")
         .replace(".report", "")
         .replace(".vcf.gz", "")
+        .replace(".bcf", "")
+        .replace(".vcf", "")
+}
+
+/// Open a VCF for line-by-line reading. Gzip/BGZF input is detected from
+/// its magic bytes, and `-` reads from stdin.
+pub fn open_vcf(path: &Path) -> Result<Box<dyn BufRead>> {
+    let inner: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(
+            File::open(path)
+                .with_context(|| format!("Failed to open VCF {}", path.display()))?,
+        )
+    };
+    let mut reader = BufReader::new(inner);
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if is_gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// How a file is compressed, from its magic bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Bgzf,
+    /// Plain gzip, which tabix and indexed readers reject.
+    Gzip,
+    None,
+}
+
+/// Detect BGZF from the `BC` extra subfield its gzip members carry.
+pub fn detect_compression(path: &Path) -> Result<Compression> {
+    let mut header = Vec::with_capacity(16);
+    File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .take(16)
+        .read_to_end(&mut header)?;
+    if !header.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Compression::None);
+    }
+    // FLG.FEXTRA, then a subfield with identifiers 'B' 'C' after XLEN
+    let has_extra = header.get(3).is_some_and(|flags| flags & 0x04 != 0);
+    if has_extra && header.get(12..14) == Some(b"BC".as_slice()) {
+        Ok(Compression::Bgzf)
+    } else {
+        Ok(Compression::Gzip)
+    }
 }
 
-/// Run gsort.
-pub fn gsort(input_path: &str, output_path: &str, genome: &str) -> Result<()> {
-    let gsort_cmd = format!(
-        "gsort {} {} | bgzip -cf > {}",
-        input_path, genome, output_path
+/// Write `src`, plain text or gzip, to `dest` as BGZF.
+pub fn bgzip_to(src: &Path, dest: &Path) -> Result<()> {
+    let mut reader = open_vcf(src)?;
+    let mut writer = noodles::bgzf::Writer::new(
+        File::create(dest).with_context(|| format!("Failed to create {}", dest.display()))?,
     );
-    debug!("{}", gsort_cmd);
-    Command::new("sh")
+    io::copy(&mut reader, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Whether a file is BCF: BGZF whose data starts with the `BCF` magic.
+pub fn is_bcf(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 3];
+    Ok(open_vcf(path)?.read_exact(&mut magic).is_ok() && &magic == b"BCF")
+}
+
+/// Write a VCF, plain or compressed, as BCF to `writer`, returning the
+/// writer once the BCF is complete. BCF stores contigs and keys by their
+/// index in the header, so each must be declared there.
+pub fn write_bcf<W: Write>(vcf_path: &Path, writer: W) -> Result<W> {
+    let mut reader = vcf::io::Reader::new(open_vcf(vcf_path)?);
+    let header = reader.read_header()?;
+    let mut writer = bcf::io::Writer::new(writer);
+    writer.write_header(&header)?;
+    for (index, result) in reader.records().enumerate() {
+        let record = result?;
+        writer
+            .write_variant_record(&header, &record)
+            .with_context(|| format!("Failed to write record {} of {} as BCF", index + 1, vcf_path.display()))?;
+    }
+    Ok(writer.into_inner().finish()?)
+}
+
+/// Write a BCF as BGZF VCF at `dest`.
+pub fn bcf_to_vcf(src: &Path, dest: &Path) -> Result<()> {
+    let mut reader = bcf::io::reader::Builder::default()
+        .build_from_path(src)
+        .with_context(|| format!("Failed to open BCF {}", src.display()))?;
+    let header = reader.read_header()?;
+    let mut writer = vcf::io::Writer::new(noodles::bgzf::Writer::new(
+        File::create(dest).with_context(|| format!("Failed to create {}", dest.display()))?,
+    ));
+    writer.write_header(&header)?;
+    for result in reader.records() {
+        writer.write_variant_record(&header, &result?)?;
+    }
+    writer.into_inner().finish()?;
+    Ok(())
+}
+
+/// Sample names already read in this run, by canonical path.
+static SAMPLE_NAMES: OnceLock<Mutex<HashMap<PathBuf, Vec<String>>>> = OnceLock::new();
+
+/// Look up the sample names of `path` in the cache, reading them with `read`
+/// on a miss. Paths that cannot be canonicalised, such as `-`, are not cached.
+fn cached_sample_names(path: &Path, read: impl FnOnce(&Path) -> Result<Vec<String>>) -> Result<Vec<String>> {
+    let Ok(key) = fs::canonicalize(path) else {
+        return read(path);
+    };
+    let cache = SAMPLE_NAMES.get_or_init(Default::default);
+    if let Some(names) = cache.lock().unwrap().get(&key) {
+        return Ok(names.clone());
+    }
+    let names = read(path)?;
+    cache.lock().unwrap().insert(key, names.clone());
+    Ok(names)
+}
+
+/// Whether `path` is a CRAM, by its `.cram` extension or `CRAM` magic.
+pub fn is_cram(path: &Path) -> bool {
+    if path.extension().is_some_and(|extension| extension == "cram") {
+        return true;
+    }
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == b"CRAM")
+}
+
+/// BAM and CRAM headers already read in this run, by canonical path.
+static BAM_HEADERS: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+
+/// The SAM text header of a BAM or CRAM. CRAM headers are read with
+/// `samtools view -H`. Headers are cached like sample names.
+pub fn bam_header_text(path: &Path) -> Result<String> {
+    let key = fs::canonicalize(path).ok();
+    let cache = BAM_HEADERS.get_or_init(Default::default);
+    if let Some(text) = key.as_ref().and_then(|key| cache.lock().unwrap().get(key).cloned()) {
+        return Ok(text);
+    }
+    let text = if is_cram(path) {
+        let output = tool_command(Tool::Samtools)?
+            .arg("view")
+            .arg("-H")
+            .arg(path)
+            .output()
+            .context("Failed to run samtools view -H")?;
+        if !output.status.success() {
+            let tool = format!("samtools view -H {}", path.display());
+            return Err(MityError::tool(&tool, output.status.code(), &output.stderr).into());
+        }
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    } else {
+        let mut reader = bam::io::reader::Builder
+            .build_from_path(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let header = reader.read_header()?;
+        let mut writer = sam::io::Writer::new(Vec::new());
+        writer.write_header(&header)?;
+        String::from_utf8_lossy(writer.get_ref()).into_owned()
+    };
+    if let Some(key) = key {
+        cache.lock().unwrap().insert(key, text.clone());
+    }
+    Ok(text)
+}
+
+/// The distinct `SM` values of the `@RG` lines of a BAM or CRAM header, in
+/// header order. Read groups without `SM` are skipped, so the result can be
+/// empty.
+pub fn bam_sample_names(path: &Path) -> Result<Vec<String>> {
+    let mut distinct: Vec<String> = Vec::new();
+    for sample in bam_header_text(path)?
+        .lines()
+        .filter(|line| line.starts_with("@RG\t"))
+        .filter_map(|line| line.split('\t').find_map(|field| field.strip_prefix("SM:")))
+    {
+        if !distinct.iter().any(|name| name == sample) {
+            distinct.push(sample.to_string());
+        }
+    }
+    Ok(distinct)
+}
+
+/// The number of records, the lines after the header, of a VCF.
+pub fn count_vcf_records(path: &Path) -> Result<usize> {
+    let mut records = 0;
+    for line in open_vcf(path)?.lines() {
+        let line = line?;
+        if !line.is_empty() && !line.starts_with('#') {
+            records += 1;
+        }
+    }
+    Ok(records)
+}
+
+/// The sample columns of a VCF's `#CHROM` line; empty for a sites-only VCF.
+pub fn vcf_sample_names(path: &Path) -> Result<Vec<String>> {
+    cached_sample_names(path, |path| {
+        for line in open_vcf(path)?.lines() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                return Ok(line.split('\t').skip(9).map(String::from).collect());
+            }
+            if !line.starts_with('#') {
+                break;
+            }
+        }
+        anyhow::bail!("{} has no #CHROM header line", path.display())
+    })
+}
+
+/// The `.tbi` or `.csi` index of a bgzipped VCF, if one exists.
+pub fn vcf_index(vcf: &Path) -> Option<PathBuf> {
+    ["tbi", "csi"]
+        .iter()
+        .map(|extension| {
+            let mut path = vcf.as_os_str().to_owned();
+            path.push(".");
+            path.push(extension);
+            PathBuf::from(path)
+        })
+        .find(|path| path.exists())
+}
+
+/// Call `f` with every record line of `vcf` on `contig`. With an index this
+/// reads only the blocks holding the contig, so it stays fast on
+/// whole-genome VCFs; without one the whole file is scanned.
+pub fn for_each_vcf_line_on(
+    vcf: &Path,
+    contig: &str,
+    mut f: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let on_contig = |line: &str| line.split('\t').next() == Some(contig);
+
+    if vcf != Path::new("-") && vcf_index(vcf).is_some() {
+        let mut reader = vcf::io::indexed_reader::Builder::default()
+            .build_from_path(vcf)
+            .with_context(|| format!("Failed to open indexed VCF {}", vcf.display()))?;
+        let index = reader.index();
+        let id = index
+            .header()
+            .and_then(|header| header.reference_sequence_names().iter().position(|name| name == contig));
+        // The index only names contigs that have records
+        let Some(id) = id else {
+            return Ok(());
+        };
+        let chunks = index.query(id, RegionInterval::from(..))?;
+
+        let bgzf = reader.get_mut();
+        let mut line = String::new();
+        for chunk in chunks {
+            bgzf.seek(chunk.start())?;
+            while bgzf.virtual_position() < chunk.end() {
+                line.clear();
+                if bgzf.read_line(&mut line)? == 0 {
+                    break;
+                }
+                let record = line.trim_end_matches(['\n', '\r']);
+                if on_contig(record) {
+                    f(record)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    for line in open_vcf(vcf)?.lines() {
+        let line = line?;
+        if !line.starts_with('#') && on_contig(&line) {
+            f(&line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run gsort, bgzip the result and index it. An `output_path` of `-` streams
+/// the compressed VCF to stdout and skips indexing; otherwise the output goes
+/// through a `checksum::OutputFile` so `--checksums` can hash it.
+pub fn gsort(input_path: &Path, output_path: &Path, genome: &Path) -> Result<()> {
+    let to_stdout = output_path == Path::new("-");
+    // Paths go in as positional parameters so they are never re-parsed by the
+    // shell: $1 is gsort, $2 the input and $3 the genome.
+    let gsort_cmd = "set -o pipefail && \"$1\" \"$2\" \"$3\" | bgzip -cf";
+    let gsort = tool_path(Tool::Gsort)?;
+    debug!(
+        "{} {} {} > {}",
+        gsort.display(),
+        input_path.display(),
+        genome.display(),
+        output_path.display()
+    );
+    let mut command = Command::new("bash");
+    command
         .arg("-c")
         .arg(gsort_cmd)
-        .status()
-        .context("Failed to run gsort command")?;
-    tabix(output_path)?;
+        .arg("mity")
+        .arg(gsort)
+        .arg(input_path)
+        .arg(genome);
+    let status = if to_stdout {
+        command.status().context("Failed to run gsort command")?
+    } else {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run gsort command")?;
+        let mut output = checksum::OutputFile::create(output_path)
+            .with_context(|| format!("Failed to create {}", output_path.display()))?;
+        io::copy(child.stdout.as_mut().expect("gsort stdout is piped"), &mut output)?;
+        output.finish()?;
+        child.wait()?
+    };
+    if !status.success() {
+        return Err(MityError::tool("gsort", status.code(), b"").into());
+    }
+    if !to_stdout {
+        tabix(output_path)?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, reference_read, write_bam};
+
+    #[test]
+    fn bam_sample_names_are_distinct_in_header_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:MT\tLN:16569\n\
+                      @RG\tID:lane1\tSM:NA12878\n@RG\tID:lane2\tSM:NA12891\n\
+                      @RG\tID:lane3\tSM:NA12878\n@RG\tID:unnamed\tLB:lib\n";
+        let bam = write_bam(&temp.path().join("multi.bam"), header, &[reference_read("r", 1, 50)]);
+        assert_eq!(bam_sample_names(&bam).unwrap(), ["NA12878", "NA12891"]);
+
+        let bam = write_bam(
+            &temp.path().join("none.bam"),
+            "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:MT\tLN:16569\n",
+            &[],
+        );
+        assert!(bam_sample_names(&bam).unwrap().is_empty());
+    }
+
+    #[test]
+    fn regions_are_one_based_and_inclusive() {
+        let interval = Interval::parse_region("MT:1-16569").unwrap();
+        assert_eq!((interval.contig.as_str(), interval.start, interval.end), ("MT", 0, 16569));
+        assert_eq!(interval.to_region(), "MT:1-16569");
+        let interval = Interval::parse_region("MT:1,001-2,000").unwrap();
+        assert_eq!((interval.start, interval.end, interval.len()), (1000, 2000, 1000));
+        // A single position, and a contig name with a colon in it
+        assert_eq!(Interval::parse_region("MT:5-5").unwrap().len(), 1);
+        assert_eq!(Interval::parse_region("HLA-A*01:01:1-10").unwrap().contig, "HLA-A*01:01");
+    }
+
+    #[test]
+    fn malformed_regions_are_errors() {
+        let message = |region: &str| format!("{:#}", Interval::parse_region(region).unwrap_err());
+        assert_eq!(message("MT:10-5"), "Invalid region MT:10-5: coordinates are 1-based and START <= END");
+        assert_eq!(message("MT:0-100"), "Invalid region MT:0-100: coordinates are 1-based and START <= END");
+        for region in ["MT", "MT:100", "MT:a-100", "MT:-100", "MT:1-", "MT:1-2-3"] {
+            assert_eq!(message(region), format!("Expected CONTIG:START-END but got {}", region));
+        }
+    }
+
+    /// A BAM in `dir` named `name` with these `(contig, length)` contigs.
+    fn bam_with_contigs(dir: &Path, name: &str, contigs: &[(&str, usize)]) -> PathBuf {
+        let mut header = "@HD\tVN:1.6\tSO:coordinate\n".to_string();
+        for (contig, length) in contigs {
+            header.push_str(&format!("@SQ\tSN:{}\tLN:{}\n", contig, length));
+        }
+        write_bam(&dir.join(name), &header, &[])
+    }
+
+    #[test]
+    fn mt_contigs_are_detected_by_name() {
+        let temp = tempfile::tempdir().unwrap();
+        let bam = bam_with_contigs(temp.path(), "refseq.bam", &[("NC_000001.11", 248956422), ("NC_012920.1", 16569)]);
+        assert_eq!(bam_mt_contig(&bam).unwrap(), ("NC_012920.1".to_string(), 16569));
+        let bam = bam_with_contigs(temp.path(), "chrmt.bam", &[("chr1", 248956422), ("chrMT", 16569)]);
+        assert_eq!(bam_mt_contig(&bam).unwrap(), ("chrMT".to_string(), 16569));
+        // The only contig, renamed, at a known mitochondrial length
+        let bam = bam_with_contigs(temp.path(), "subset.bam", &[("mito", 16571)]);
+        assert_eq!(bam_mt_contig(&bam).unwrap(), ("mito".to_string(), 16571));
+    }
+
+    #[test]
+    fn mt_and_chrm_together_are_ambiguous() {
+        let temp = tempfile::tempdir().unwrap();
+        let bam = bam_with_contigs(temp.path(), "both.bam", &[("MT", 16569), ("chr1", 248956422), ("chrM", 16571)]);
+        let message = format!("{:#}", bam_mt_contig(&bam).unwrap_err());
+        assert!(message.starts_with("Several mitochondrial contigs (MT, chrM) found in "), "{}", message);
+        assert!(message.ends_with("name the one to use with --mito-contig"), "{}", message);
+    }
+
+    #[test]
+    fn no_mt_contig_lists_the_contigs_present() {
+        let temp = tempfile::tempdir().unwrap();
+        let bam = bam_with_contigs(temp.path(), "nuclear.bam", &[("chr1", 248956422), ("chr2", 242193529)]);
+        let message = format!("{:#}", bam_mt_contig(&bam).unwrap_err());
+        assert!(message.starts_with("No mitochondrial contig (MT, chrM, chrMT, NC_012920.1) found; "), "{}", message);
+        assert!(message.ends_with("has 2 contig(s): chr1, chr2. Name it with --mito-contig"), "{}", message);
+
+        let many: Vec<(String, usize)> = (1..=30).map(|n| (format!("chr{}", n), 1000)).collect();
+        let message = format!("{:#}", pick_mt_contig(&many, "big.bam").unwrap_err());
+        assert!(message.contains("big.bam has 30 contig(s): chr1, chr2, "), "{}", message);
+        assert!(message.contains(", chr25, .... Name it"), "{}", message);
+    }
+
+    #[test]
+    fn output_dirs_are_created_with_their_parents() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("results/run1/calls");
+        prepare_output_dir(&dir).unwrap();
+        assert!(dir.is_dir());
+        // The probe file is gone, and an existing directory is fine
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        prepare_output_dir(&dir).unwrap();
+
+        fs::write(temp.path().join("file"), "").unwrap();
+        let message = format!("{:#}", prepare_output_dir(&temp.path().join("file/run1")).unwrap_err());
+        assert!(message.starts_with("Cannot create the output directory "), "{}", message);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_output_dirs_are_an_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("read-only");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // Permissions do not stop root
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let message = format!("{:#}", prepare_output_dir(&dir).unwrap_err());
+        assert!(message.starts_with(&format!("Cannot write to the output directory {}", dir.display())), "{}", message);
+        let message = format!("{:#}", prepare_output_dir(&dir.join("nested")).unwrap_err());
+        assert!(message.starts_with("Cannot create the output directory "), "{}", message);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn file_lists_skip_comments_and_expand_globs() {
+        let temp = tempfile::tempdir().unwrap();
+        let batch = temp.path().join("batch1");
+        fs::create_dir(&batch).unwrap();
+        for name in ["b.bam", "a.bam", "c.bam", "a.bam.bai"] {
+            fs::write(batch.join(name), "").unwrap();
+        }
+        fs::write(temp.path().join("solo.bam"), "").unwrap();
+        let list = temp.path().join("bams.txt");
+        fs::write(
+            &list,
+            "# trio, then the rest of the batch\n\
+             \n\
+             batch1/c.bam\n\
+             \tsolo.bam  \n\
+             # batch1/missing.bam\n\
+             batch1/*.bam\n\
+             \n",
+        )
+        .unwrap();
+
+        // Relative to the list, in order, each once
+        assert_eq!(
+            read_file_list(&list).unwrap(),
+            [batch.join("c.bam"), temp.path().join("solo.bam"), batch.join("a.bam"), batch.join("b.bam")]
+        );
+    }
+
+    #[test]
+    fn file_list_errors_give_the_line() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("a.bam"), "").unwrap();
+        let list = temp.path().join("bams.txt");
+
+        fs::write(&list, "# samples\na.bam\nmissing.bam\n").unwrap();
+        let error = read_file_list(&list).unwrap_err();
+        assert_eq!(error.to_string(), format!("{} line 3", list.display()));
+        assert!(matches!(error.downcast_ref::<MityError>(), Some(MityError::MissingFile(path)) if path == &temp.path().join("missing.bam")));
+
+        fs::write(&list, "a.bam\n\nbatch2/*.bam\n").unwrap();
+        let message = read_file_list(&list).unwrap_err().to_string();
+        assert_eq!(message, format!("{} line 3: batch2/*.bam matches no files", list.display()));
+
+        let missing = temp.path().join("missing.txt");
+        let message = read_file_list(&missing).unwrap_err().to_string();
+        assert_eq!(message, format!("Failed to read file list {}", missing.display()));
+    }
+
+    fn version(major: u32, minor: u32, patch: u32) -> ToolVersion {
+        ToolVersion { major, minor, patch }
+    }
+
+    #[test]
+    fn tool_versions_are_read_from_version_output() {
+        assert_eq!(ToolVersion::parse("version:  v1.3.6"), Some(version(1, 3, 6)));
+        assert_eq!(ToolVersion::parse("version:  v1.2.0-2-g29c4002"), Some(version(1, 2, 0)));
+        assert_eq!(ToolVersion::parse("v1.3.1-dirty"), Some(version(1, 3, 1)));
+        let bcftools = "bcftools 1.16\nUsing htslib 1.16\nCopyright (C) 2022 Genome Research Ltd.\n\
+                        License Expat: The MIT/Expat license\n";
+        assert_eq!(ToolVersion::parse(bcftools), Some(version(1, 16, 0)));
+        assert_eq!(ToolVersion::parse("tabix (htslib) 1.17+htslib-1.17"), Some(version(1, 17, 0)));
+        for garbage in ["", "freebayes", "version: unknown", "v1", "1.x.3", "Copyright (C) 2022"] {
+            assert_eq!(ToolVersion::parse(garbage), None, "{:?}", garbage);
+        }
+    }
+
+    #[test]
+    fn tool_versions_on_the_command_line() {
+        assert_eq!(parse_tool_version("1.3.6"), Ok(version(1, 3, 6)));
+        assert_eq!(parse_tool_version("v1.3"), Ok(version(1, 3, 0)));
+        assert_eq!(parse_tool_version("latest"), Err("expected a version such as 1.3.6, got latest".to_string()));
+        assert_eq!(version(1, 3, 6).to_string(), "1.3.6");
+        assert!(version(1, 2, 99) < version(1, 3, 0) && version(1, 3, 6) < version(1, 10, 0));
+    }
+
+    #[test]
+    fn freebayes_version_runs_freebayes() {
+        test_util::use_fake_tools();
+        assert_eq!(freebayes_version().unwrap(), version(1, 3, 6));
+    }
+
+    #[test]
+    fn crams_are_told_by_extension_or_magic() {
+        let temp = tempfile::tempdir().unwrap();
+        let bam = write_bam(&temp.path().join("a.bam"), &test_util::mt_header("MT", &["a"]), &[]);
+        assert!(!is_cram(&bam));
+        // Only the extension, as for the stand-in samtools
+        let renamed = temp.path().join("a.cram");
+        fs::copy(&bam, &renamed).unwrap();
+        assert!(is_cram(&renamed));
+        let unnamed = temp.path().join("a.alignments");
+        fs::write(&unnamed, b"CRAM\x03\x01rest of the file").unwrap();
+        assert!(is_cram(&unnamed));
+        // Too short for the magic, or missing
+        fs::write(&unnamed, b"CR").unwrap();
+        assert!(!is_cram(&unnamed));
+        assert!(!is_cram(&temp.path().join("missing.bam")));
+    }
+
+    #[test]
+    fn cram_sample_names_come_from_samtools() {
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let bam = write_bam(
+            &temp.path().join("a.bam"),
+            &test_util::mt_header("MT", &["child", "mother"]),
+            &[reference_read("r", 1, 50)],
+        );
+        let cram = temp.path().join("a.cram");
+        fs::copy(&bam, &cram).unwrap();
+        assert_eq!(bam_sample_names(&cram).unwrap(), ["child", "mother"]);
+    }
+
+    #[test]
+    fn vcf_sample_names_of_sites_only_and_sample_vcfs() {
+        let temp = tempfile::tempdir().unwrap();
+        let sites = temp.path().join("sites.vcf");
+        fs::write(&sites, "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\nMT\t1\t.\tG\tA\t.\t.\t.\n").unwrap();
+        assert!(vcf_sample_names(&sites).unwrap().is_empty());
+
+        let samples = temp.path().join("samples.vcf");
+        fs::write(
+            &samples,
+            "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tb\ta\n",
+        )
+        .unwrap();
+        assert_eq!(vcf_sample_names(&samples).unwrap(), ["b", "a"]);
+        // Cached for the rest of the run
+        fs::write(&samples, "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n").unwrap();
+        assert_eq!(vcf_sample_names(&samples).unwrap(), ["b", "a"]);
+
+        let headless = temp.path().join("headless.vcf");
+        fs::write(&headless, "MT\t1\t.\tG\tA\t.\t.\t.\n").unwrap();
+        assert!(vcf_sample_names(&headless).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{self, Write};
+
+/// The meta-information lines and `#CHROM` line of a VCF, edited as text.
+/// Lines mity does not touch keep their content and order, and repeating
+/// an edit leaves the header as the first one did.
+#[derive(Clone, Debug, Default)]
+pub struct VcfHeader {
+    meta: Vec<String>,
+    columns: String,
+}
+
+impl VcfHeader {
+    /// Read the header from `lines`, stopping after `#CHROM` so the records
+    /// can be read from the same iterator.
+    pub fn read<I>(lines: &mut I) -> io::Result<Self>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let mut header = VcfHeader::default();
+        for line in lines {
+            let line = line?;
+            if line.starts_with("##") {
+                header.meta.push(line);
+            } else if line.starts_with('#') {
+                header.columns = line;
+                break;
+            } else if !line.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "VCF record before the #CHROM header line",
+                ));
+            }
+        }
+        Ok(header)
+    }
+
+    /// The `##` lines, in order.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.meta.iter().map(String::as_str)
+    }
+
+    /// Whether there is a structured line such as `##FORMAT=<ID=AD,...>`
+    /// with this key and ID.
+    pub fn contains(&self, key: &str, id: &str) -> bool {
+        self.meta.iter().any(|line| line_key(line) == Some((key, Some(id))))
+    }
+
+    /// Add a line, replacing the one it redefines: for structured lines the
+    /// line with the same key and ID, otherwise the first with the same key.
+    /// New lines go after the existing ones.
+    pub fn upsert(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        let existing = line_key(&line).and_then(|key| {
+            self.meta
+                .iter()
+                .position(|other| line_key(other) == Some(key))
+        });
+        match existing {
+            Some(index) => self.meta[index] = line,
+            None => self.append_once(line),
+        }
+    }
+
+    /// Add a line unless the header already has it, as for provenance lines
+    /// that can repeat a key.
+    pub fn append_once(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        if !self.meta.contains(&line) {
+            self.meta.push(line);
+        }
+    }
+
+    /// Remove every copy of a line.
+    pub fn remove(&mut self, line: &str) {
+        self.meta.retain(|other| other != line);
+    }
+
+    /// Rename the key of `##from=` lines to `to`, as for tool-specific lines
+    /// like freebayes' `##source` that would clash with later tools.
+    pub fn rename_key(&mut self, from: &str, to: &str) {
+        for line in &mut self.meta {
+            if let Some((key, _)) = line_key(line).filter(|(key, _)| *key == from) {
+                *line = format!("##{}{}", to, &line[2 + key.len()..]);
+            }
+        }
+    }
+
+    /// Rename a `##contig` line's ID. Records are left to the caller.
+    // Kept for merge, which is not ported yet
+    #[allow(dead_code)]
+    pub fn rename_contig(&mut self, from: &str, to: &str) {
+        let prefix = format!("##contig=<ID={}", from);
+        for line in &mut self.meta {
+            if line_key(line) == Some(("contig", Some(from))) {
+                *line = format!("##contig=<ID={}{}", to, &line[prefix.len()..]);
+            }
+        }
+    }
+
+    /// The sample names of the `#CHROM` line.
+    pub fn samples(&self) -> Vec<String> {
+        self.columns.split('\t').skip(9).map(String::from).collect()
+    }
+
+    /// Replace the sample names of the `#CHROM` line.
+    pub fn set_samples(&mut self, samples: Vec<String>) {
+        let mut columns: Vec<String> = self.columns.split('\t').take(9).map(String::from).collect();
+        columns.extend(samples);
+        self.columns = columns.join("\t");
+    }
+
+    /// Write the header, ending with `#CHROM` when the input had one.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for line in &self.meta {
+            writeln!(writer, "{}", line)?;
+        }
+        if !self.columns.is_empty() {
+            writeln!(writer, "{}", self.columns)?;
+        }
+        Ok(())
+    }
+}
+
+/// The key of a `##key=value` line and, for structured lines such as
+/// `##INFO=<ID=DP,...>`, its ID.
+fn line_key(line: &str) -> Option<(&str, Option<&str>)> {
+    let (key, value) = line.strip_prefix("##")?.split_once('=')?;
+    let id = value
+        .strip_prefix('<')
+        .and_then(|fields| fields.split([',', '>']).next()?.strip_prefix("ID="));
+    Some((key, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FREEBAYES_HEADER: &str = "##fileformat=VCFv4.2
+##fileDate=20240101
+##source=freeBayes v1.3.6
+##reference=ref.fa
+##contig=<ID=MT,length=16569>
+##phasing=none
+##commandline=\"freebayes -f ref.fa a.bam\"
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total read depth at the locus\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read Depth\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta
+MT\t1\t.\tG\tA\t50\t.\tDP=10\tGT:DP\t1:10";
+
+    fn parse(text: &str) -> VcfHeader {
+        VcfHeader::read(&mut text.lines().map(|line| Ok(line.to_string()))).unwrap()
+    }
+
+    fn text(header: &VcfHeader) -> String {
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// The edits call makes to freebayes' header.
+    fn edit(header: &mut VcfHeader) {
+        header.rename_key("source", "freebayesSource");
+        header.remove("##phasing=none");
+        header.upsert("##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">");
+        header.upsert("##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allelic depths\">");
+        header.upsert("##mityVersion=0.1.0");
+        header.append_once("##mityCommandline=\"mity call a.bam\"");
+    }
+
+    #[test]
+    fn reading_and_writing_preserves_every_line() {
+        let mut lines = FREEBAYES_HEADER.lines().map(|line| Ok(line.to_string()));
+        let header = VcfHeader::read(&mut lines).unwrap();
+        let expected: Vec<&str> = FREEBAYES_HEADER.lines().take(11).collect();
+        assert_eq!(text(&header), expected.join("\n") + "\n");
+        assert_eq!(header.samples(), ["a"]);
+        // The records stay in the iterator
+        assert_eq!(lines.next().unwrap().unwrap(), "MT\t1\t.\tG\tA\t50\t.\tDP=10\tGT:DP\t1:10");
+    }
+
+    #[test]
+    fn edits_leave_other_lines_in_place() {
+        let mut header = parse(FREEBAYES_HEADER);
+        edit(&mut header);
+        assert_eq!(
+            header.lines().collect::<Vec<_>>(),
+            [
+                "##fileformat=VCFv4.2",
+                "##fileDate=20240101",
+                "##freebayesSource=freeBayes v1.3.6",
+                "##reference=ref.fa",
+                "##contig=<ID=MT,length=16569>",
+                "##commandline=\"freebayes -f ref.fa a.bam\"",
+                "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">",
+                "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+                "##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read Depth\">",
+                "##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allelic depths\">",
+                "##mityVersion=0.1.0",
+                "##mityCommandline=\"mity call a.bam\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_are_idempotent() {
+        let mut once = parse(FREEBAYES_HEADER);
+        edit(&mut once);
+        let mut twice = parse(&text(&once));
+        edit(&mut twice);
+        edit(&mut twice);
+        assert_eq!(text(&twice), text(&once));
+    }
+
+    #[test]
+    fn upsert_replaces_by_key_and_id() {
+        let mut header = parse("##mityVersion=0.0.9\n##FILTER=<ID=DP,Description=\"old\">\n##FILTER=<ID=SBR,Description=\"x\">\n#CHROM");
+        header.upsert("##mityVersion=0.1.0");
+        header.upsert("##FILTER=<ID=DP,Description=\"new\">");
+        header.upsert("##FILTER=<ID=DPX,Description=\"other\">");
+        assert_eq!(
+            header.lines().collect::<Vec<_>>(),
+            [
+                "##mityVersion=0.1.0",
+                "##FILTER=<ID=DP,Description=\"new\">",
+                "##FILTER=<ID=SBR,Description=\"x\">",
+                "##FILTER=<ID=DPX,Description=\"other\">",
+            ]
+        );
+        assert!(header.contains("FILTER", "DP") && !header.contains("INFO", "DP"));
+    }
+
+    #[test]
+    fn append_once_keeps_repeated_keys_but_not_repeated_lines() {
+        let mut header = parse("##mityCommandline=\"mity call a.bam\"\n#CHROM");
+        header.append_once("##mityCommandline=\"mity normalise a.vcf.gz\"");
+        header.append_once("##mityCommandline=\"mity call a.bam\"");
+        header.append_once("##mityCommandline=\"mity normalise a.vcf.gz\"");
+        assert_eq!(
+            header.lines().collect::<Vec<_>>(),
+            ["##mityCommandline=\"mity call a.bam\"", "##mityCommandline=\"mity normalise a.vcf.gz\""]
+        );
+    }
+
+    #[test]
+    fn a_record_before_chrom_is_an_error() {
+        let mut lines = ["##fileformat=VCFv4.2", "MT\t1\t.\tG\tA"].iter().map(|line| Ok(line.to_string()));
+        assert!(VcfHeader::read(&mut lines).is_err());
+    }
+}
@@ -1,37 +1,838 @@
-use log::{debug, info, LevelFilter};
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use noodles::bgzf;
+use serde_json::{json, Value};
+use statrs::distribution::{Binomial, DiscreteCDF};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::fs::{File, remove_file};
-use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::fs::{self, remove_file, File};
+use std::fmt::Display;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::checksum;
+use crate::error::{self, MityError};
+use crate::mity_util::{self, Tool};
+use crate::mity_util::header::VcfHeader;
+use crate::pon::PanelOfNormals;
+use crate::stats::{RunSummary, Timer};
+use crate::vcf_record::VcfRecord;
 
 // Constants
-const P_VAL: f64 = 0.002;
 const SB_RANGE_LO: f64 = 0.1;
 const SB_RANGE_HI: f64 = 0.9;
 const MIN_MQMR: f64 = 30.0;
 const MIN_AQR: f64 = 20.0;
-const MIN_DP: i32 = 15;
+/// Per-sample FORMAT DP needed, as in python mity.
+const MIN_SAMPLE_DP: u32 = 15;
+/// Site-level INFO DP needed; python mity has no site filter, so it is off.
+const MIN_SITE_DP: u32 = 0;
 const BLACKLIST: [i32; 20] = [302, 303, 304, 305, 306, 307, 308, 309, 310, 311, 312, 313, 314, 315, 316, 317, 318, 3105, 3106, 3107];
+const MAX_Q: f64 = 200.0;
+const TIER1_MIN_VAF: f64 = 0.01;
+const TIER1_MIN_AO: f64 = 10.0;
+const P_VAL: f32 = 0.002;
+/// Panel-of-normals frequency at which a variant fails PON.
+const PON_MIN_FREQ: f64 = 0.1;
+/// z for a two-sided 95% interval.
+const Z_95: f64 = 1.959964;
+/// Inputs larger than this that need recompressing get a disk space warning.
+const LARGE_INPUT_BYTES: u64 = 500 * 1024 * 1024;
+const ALLELE_DEPTH_HEADER: &str = "##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Allelic depths for the ref and alt alleles in the order listed: RO,AO\">";
+const REJECT_REASON_HEADER: &str = "##INFO=<ID=REJECT_REASON,Number=.,Type=String,Description=\"What triggered each failed filter, as FILTER:value\">";
+
+/// Thresholds applied by the filtering step.
+#[derive(Clone, Debug)]
+pub struct FilterSettings {
+    pub min_site_dp: f64,
+    pub min_sample_dp: f64,
+    pub sb_range: (f64, f64),
+    pub blacklist: Vec<u64>,
+    pub allsamples: bool,
+    pub p: f32,
+    /// Records may have several ALTs; each allele's outcome is kept in
+    /// INFO/ALLELE_FILTER.
+    pub keep_multiallelic: bool,
+    /// Alleles at `pon_min_freq` or more in this panel fail PON.
+    pub pon: Option<PanelOfNormals>,
+    pub pon_min_freq: f64,
+    /// Flag panel-of-normals alleles with INFO/PON instead of failing them.
+    pub pon_soft: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            min_site_dp: MIN_SITE_DP as f64,
+            min_sample_dp: MIN_SAMPLE_DP as f64,
+            sb_range: (SB_RANGE_LO, SB_RANGE_HI),
+            blacklist: BLACKLIST.iter().map(|pos| *pos as u64).collect(),
+            allsamples: false,
+            p: P_VAL,
+            keep_multiallelic: false,
+            pon: None,
+            pon_min_freq: PON_MIN_FREQ,
+            pon_soft: false,
+        }
+    }
+}
+
+/// Number of records and how many failed each filter.
+#[derive(Clone, Debug, Default)]
+pub struct FilterSummary {
+    pub records: usize,
+    pub passed: usize,
+    pub failed: BTreeMap<String, usize>,
+    /// Records removed as invalid after normalisation.
+    pub invalid: usize,
+    /// gVCF reference blocks, passed through unfiltered.
+    pub reference_blocks: usize,
+}
+
+impl FilterSummary {
+    pub fn log(&self) {
+        info!("Filtered {} variant records, {} PASS", self.records, self.passed);
+        if self.invalid > 0 {
+            info!("Removed {} invalid records", self.invalid);
+        }
+        if self.reference_blocks > 0 {
+            info!("Passed through {} reference blocks", self.reference_blocks);
+        }
+        for (filter, count) in &self.failed {
+            info!("  {:<8} {}", filter, count);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "records": self.records,
+            "passed": self.passed,
+            "failed": self.failed,
+            "invalid": self.invalid,
+            "reference_blocks": self.reference_blocks,
+        })
+    }
+}
+
+/// How a record or allele fared against one filter criterion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Marked with an INFO flag rather than failed, as with `--pon-soft`.
+    Flag,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Flag => "FLAG",
+        }
+    }
+}
+
+/// One filter criterion evaluated on a record. FILTER, ALLELE_FILTER,
+/// REJECT_REASON and the audit log are all built from these.
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    pub criterion: &'static str,
+    /// The ALT allele a per-allele criterion was evaluated on.
+    pub allele: Option<usize>,
+    pub observed: String,
+    pub threshold: String,
+    pub outcome: Outcome,
+    /// What triggered a failure, for INFO/REJECT_REASON.
+    pub reason: Option<String>,
+}
+
+impl Evaluation {
+    /// An evaluation that failed if there is a `reason`.
+    fn new(
+        criterion: &'static str,
+        allele: Option<usize>,
+        observed: impl Display,
+        threshold: impl Display,
+        reason: Option<String>,
+    ) -> Self {
+        Evaluation {
+            criterion,
+            allele,
+            observed: observed.to_string(),
+            threshold: threshold.to_string(),
+            outcome: if reason.is_some() { Outcome::Fail } else { Outcome::Pass },
+            reason,
+        }
+    }
+}
+
+/// Where filter_vcf writes an audit row for each criterion of each record.
+pub struct AuditLog<'a> {
+    pub writer: &'a mut dyn Write,
+    /// Also write the rows of criteria that passed.
+    pub all: bool,
+}
+
+impl AuditLog<'_> {
+    const HEADER: &'static str = "CHROM\tPOS\tREF\tALT\tCRITERION\tOBSERVED\tTHRESHOLD\tRESULT";
+
+    fn write(&mut self, record: &VcfRecord, evaluations: &[Evaluation]) -> io::Result<()> {
+        for evaluation in evaluations {
+            if evaluation.outcome == Outcome::Pass && !self.all {
+                continue;
+            }
+            let alt = match evaluation.allele {
+                Some(allele) => record.alt.split(',').nth(allele).unwrap_or_default(),
+                None => &record.alt,
+            };
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                record.chrom,
+                record.pos,
+                record.reference,
+                alt,
+                evaluation.criterion,
+                evaluation.observed,
+                evaluation.threshold,
+                evaluation.outcome.as_str()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The FILTER, QUAL and VAF/q/tier annotation pass shared by `normalise`
+/// and `filter`.
+pub struct VariantFilter {
+    settings: FilterSettings,
+    /// Lines recording the run, replacing those of an earlier run.
+    provenance: Vec<String>,
+}
+
+impl VariantFilter {
+    pub fn new(settings: FilterSettings) -> Self {
+        VariantFilter {
+            settings,
+            provenance: Vec::new(),
+        }
+    }
+
+    pub fn with_provenance(mut self, provenance: Vec<String>) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Header lines declaring everything the filter adds.
+    pub fn header_lines(&self) -> Vec<String> {
+        let (lo, hi) = self.settings.sb_range;
+        let mut lines = vec![
+            "##INFO=<ID=SBR,Number=1,Type=Float,Description=\"Strand bias of the reference allele: SRF / (SRF + SRR)\">".to_string(),
+            "##INFO=<ID=SBA,Number=A,Type=Float,Description=\"Strand bias of the alternate allele: SAF / (SAF + SAR)\">".to_string(),
+            "##INFO=<ID=AQR,Number=1,Type=Float,Description=\"Mean base quality of the reference allele: QR / RO\">".to_string(),
+            "##INFO=<ID=POS_FILTER,Number=0,Type=Flag,Description=\"Position is in a blacklisted region\">".to_string(),
+            "##INFO=<ID=SBR_FILTER,Number=0,Type=Flag,Description=\"SBR is outside the allowed range\">".to_string(),
+            "##INFO=<ID=SBA_FILTER,Number=0,Type=Flag,Description=\"SBA is outside the allowed range\">".to_string(),
+            "##INFO=<ID=MQMR_FILTER,Number=0,Type=Flag,Description=\"MQMR is below the minimum\">".to_string(),
+            "##INFO=<ID=AQR_FILTER,Number=0,Type=Flag,Description=\"AQR is below the minimum\">".to_string(),
+            "##FORMAT=<ID=VAF,Number=A,Type=Float,Description=\"Variant allele fraction: AO / DP\">".to_string(),
+            "##FORMAT=<ID=VAF_LO,Number=A,Type=Float,Description=\"Lower bound of the 95% Wilson score interval of VAF\">".to_string(),
+            "##FORMAT=<ID=VAF_HI,Number=A,Type=Float,Description=\"Upper bound of the 95% Wilson score interval of VAF\">".to_string(),
+            format!("##FORMAT=<ID=q,Number=A,Type=Float,Description=\"Phred-scaled probability that the alternate reads are noise, capped at {}\">", MAX_Q),
+            format!("##FORMAT=<ID=tier,Number=A,Type=Integer,Description=\"1 if VAF >= {} and AO >= {}, otherwise 2\">", TIER1_MIN_VAF, TIER1_MIN_AO),
+            "##FILTER=<ID=POS,Description=\"Position is in a blacklisted region\">".to_string(),
+            format!("##FILTER=<ID=SBR,Description=\"Reference strand bias outside [{}, {}]\">", lo, hi),
+            format!("##FILTER=<ID=SBA,Description=\"Alternate strand bias outside [{}, {}]\">", lo, hi),
+            format!("##FILTER=<ID=MQMR,Description=\"Mean reference mapping quality below {}\">", MIN_MQMR),
+            format!("##FILTER=<ID=AQR,Description=\"Mean reference base quality below {}\">", MIN_AQR),
+            format!(
+                "##FILTER=<ID=DP,Description=\"Sample depth (FORMAT DP) below {} in {}\">",
+                self.settings.min_sample_dp,
+                if self.settings.allsamples { "any sample" } else { "every sample" }
+            ),
+            format!(
+                "##FILTER=<ID=SITE_DP,Description=\"Site depth (INFO DP) below {}\">",
+                self.settings.min_site_dp
+            ),
+        ];
+        if let Some(pon) = &self.settings.pon {
+            lines.push(format!(
+                "##INFO=<ID=PON_FREQ,Number=A,Type=Float,Description=\"Fraction of the {} samples of the panel of normals carrying each ALT allele\">",
+                pon.samples()
+            ));
+            if self.settings.pon_soft {
+                lines.push(format!(
+                    "##INFO=<ID=PON,Number=0,Type=Flag,Description=\"An ALT allele is in at least {} of the panel of normals\">",
+                    self.settings.pon_min_freq
+                ));
+            } else {
+                lines.push(format!(
+                    "##FILTER=<ID=PON,Description=\"An ALT allele is in at least {} of the panel of normals\">",
+                    self.settings.pon_min_freq
+                ));
+            }
+        }
+        if self.settings.keep_multiallelic {
+            lines.push("##INFO=<ID=ALLELE_FILTER,Number=A,Type=String,Description=\"Filters failed by each ALT allele, separated by |, or PASS\">".to_string());
+        }
+        lines
+    }
+
+    /// Filter every record of `input`, adding the filter's header lines or
+    /// replacing earlier versions of them.
+    ///
+    /// Unless the input already declares FORMAT/AD, records with RO or AO
+    /// also get an AD field made from them (see [`set_allele_depths`]).
+    ///
+    /// Records normalisation left invalid (see [`invalid_reason`]) are
+    /// dropped and counted, or written to `invalid` when given. Records that
+    /// fail a filter are also written to `rejects` when given, with
+    /// INFO/REJECT_REASON naming what triggered each filter. With `audit`,
+    /// each criterion evaluated on a record also gets a row there. gVCF
+    /// reference blocks, from `mity call --report-all-sites`, are written
+    /// through untouched.
+    pub fn filter_vcf(
+        &self,
+        input: Box<dyn BufRead>,
+        output: &mut dyn Write,
+        mut invalid: Option<&mut dyn Write>,
+        mut rejects: Option<&mut dyn Write>,
+        mut audit: Option<AuditLog>,
+    ) -> Result<FilterSummary, Box<dyn Error>> {
+        let mut summary = FilterSummary::default();
+        let mut lines = input.lines();
+        let mut header = VcfHeader::read(&mut lines)?;
+        if let Some(invalid) = invalid.as_deref_mut() {
+            header.write(invalid)?;
+        }
+        let contig_lengths: HashMap<String, u64> = header.lines().filter_map(contig_length).collect();
+        let has_allele_depths = header.contains("FORMAT", "AD");
+        for header_line in self.header_lines() {
+            header.upsert(header_line);
+        }
+        if !has_allele_depths {
+            header.upsert(ALLELE_DEPTH_HEADER);
+        }
+        for line in &self.provenance {
+            header.upsert(line.as_str());
+        }
+        header.write(output)?;
+        if let Some(rejects) = rejects.as_deref_mut() {
+            let mut header = header.clone();
+            header.upsert(REJECT_REASON_HEADER);
+            header.write(rejects)?;
+        }
+        if let Some(audit) = audit.as_mut() {
+            writeln!(audit.writer, "{}", AuditLog::HEADER)?;
+        }
+
+        for line in lines {
+            let line = line?;
+            if !line.is_empty() {
+                let mut record = VcfRecord::parse(&line)?;
+                if record.is_reference_block() {
+                    writeln!(output, "{}", line)?;
+                    summary.reference_blocks += 1;
+                    continue;
+                }
+                if let Some(reason) = invalid_reason(&record, &contig_lengths) {
+                    warn!(
+                        "Removing {}:{} {}>{}: {}",
+                        record.chrom, record.pos, record.reference, record.alt, reason
+                    );
+                    summary.invalid += 1;
+                    if let Some(invalid) = invalid.as_deref_mut() {
+                        writeln!(invalid, "{}", line)?;
+                    }
+                    continue;
+                }
+                let evaluations = self.filter_record(&mut record);
+                let reasons: Vec<&str> = evaluations.iter().filter_map(|evaluation| evaluation.reason.as_deref()).collect();
+                if let Some(audit) = audit.as_mut() {
+                    audit.write(&record, &evaluations)?;
+                }
+                if !has_allele_depths {
+                    set_allele_depths(&mut record);
+                }
+                writeln!(output, "{}", record)?;
+                if let (Some(rejects), false) = (rejects.as_deref_mut(), reasons.is_empty()) {
+                    record.set_info("REJECT_REASON", Some(reasons.join(",")));
+                    writeln!(rejects, "{}", record)?;
+                }
+
+                summary.records += 1;
+                if record.filter == "PASS" {
+                    summary.passed += 1;
+                } else {
+                    for filter in record.filter.split(';') {
+                        *summary.failed.entry(filter.to_string()).or_default() += 1;
+                    }
+                }
+            }
+        }
+        output.flush()?;
+        if let Some(invalid) = invalid {
+            invalid.flush()?;
+        }
+        if let Some(rejects) = rejects {
+            rejects.flush()?;
+        }
+        if let Some(audit) = audit {
+            audit.writer.flush()?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Add the mity INFO/FORMAT annotations to a record and set its FILTER
+    /// and QUAL. Fields that are missing, as in VCFs from other callers, skip
+    /// the annotations and filters that depend on them.
+    ///
+    /// Alternate-allele values (SBA, VAF, q, tier) are computed per ALT and
+    /// written comma-separated. A multi-allelic record fails a filter if any
+    /// allele does, and with `keep_multiallelic` the outcome of each allele
+    /// goes in INFO/ALLELE_FILTER.
+    ///
+    /// Returns every criterion evaluated, with what triggered each failure,
+    /// e.g. `SBR:0.0312_outside_0.1-0.9`, for INFO/REJECT_REASON.
+    fn filter_record(&self, record: &mut VcfRecord) -> Vec<Evaluation> {
+        let alleles = record.alt.split(',').count();
+        let mut evaluations = Vec::new();
+
+        let blacklisted = self.settings.blacklist.contains(&record.pos);
+        if blacklisted {
+            record.set_info("POS_FILTER", None);
+        }
+        evaluations.push(Evaluation::new(
+            "POS",
+            None,
+            record.pos,
+            "blacklist",
+            blacklisted.then(|| format!("POS:blacklisted_{}", record.pos)),
+        ));
+
+        let (sb_lo, sb_hi) = self.settings.sb_range;
+        let sb_range = format!("{}-{}", sb_lo, sb_hi);
+        let info = |key: &str| record.info_f64(key).unwrap_or(0.0);
+        let (srf, srr) = (info("SRF"), info("SRR"));
+        let (ro, qr, mqmr) = (info("RO"), info("QR"), info("MQMR"));
+        let saf = allele_values(record.info("SAF"), alleles);
+        let sar = allele_values(record.info("SAR"), alleles);
+
+        if srf + srr > 0.0 {
+            let sbr = srf / (srf + srr);
+            record.set_info("SBR", Some(format!("{:.4}", sbr)));
+            let outside = !(sb_lo..=sb_hi).contains(&sbr);
+            if outside {
+                record.set_info("SBR_FILTER", None);
+            }
+            evaluations.push(Evaluation::new(
+                "SBR",
+                None,
+                format!("{:.4}", sbr),
+                &sb_range,
+                outside.then(|| format!("SBR:{:.4}_outside_{}", sbr, sb_range)),
+            ));
+        }
+
+        let sba: Vec<Option<f64>> = saf
+            .iter()
+            .zip(&sar)
+            .map(|(saf, sar)| (saf + sar > 0.0).then(|| saf / (saf + sar)))
+            .collect();
+        if sba.iter().any(Option::is_some) {
+            let values: Vec<String> = sba
+                .iter()
+                .map(|sba| sba.map_or_else(|| ".".to_string(), |sba| format!("{:.4}", sba)))
+                .collect();
+            record.set_info("SBA", Some(values.join(",")));
+            let mut any_outside = false;
+            for (allele, sba) in sba.iter().enumerate() {
+                let Some(sba) = sba else {
+                    continue;
+                };
+                let outside = !(sb_lo..=sb_hi).contains(sba);
+                any_outside |= outside;
+                evaluations.push(Evaluation::new(
+                    "SBA",
+                    Some(allele),
+                    format!("{:.4}", sba),
+                    &sb_range,
+                    outside.then(|| format!("SBA:{:.4}_outside_{}", sba, sb_range)),
+                ));
+            }
+            if any_outside {
+                record.set_info("SBA_FILTER", None);
+            }
+        }
+        if let Some(pon) = &self.settings.pon {
+            let frequencies: Vec<Option<f64>> = record
+                .alt
+                .split(',')
+                .map(|alt| pon.frequency(record.pos, &record.reference, alt))
+                .collect();
+            if frequencies.iter().any(Option::is_some) {
+                let values: Vec<String> = frequencies
+                    .iter()
+                    .map(|frequency| format!("{:.4}", frequency.unwrap_or(0.0)))
+                    .collect();
+                record.set_info("PON_FREQ", Some(values.join(",")));
+            }
+            let min_freq = self.settings.pon_min_freq;
+            let soft = self.settings.pon_soft;
+            let mut flagged = false;
+            for (allele, frequency) in frequencies.iter().enumerate() {
+                let in_pon = frequency.is_some_and(|frequency| frequency >= min_freq);
+                let frequency = frequency.unwrap_or(0.0);
+                let mut evaluation = Evaluation::new(
+                    "PON",
+                    Some(allele),
+                    format!("{:.4}", frequency),
+                    min_freq,
+                    (in_pon && !soft).then(|| format!("PON:{:.4}>={}", frequency, min_freq)),
+                );
+                if in_pon && soft {
+                    evaluation.outcome = Outcome::Flag;
+                    flagged = true;
+                }
+                evaluations.push(evaluation);
+            }
+            if flagged {
+                record.set_info("PON", None);
+            }
+        }
+        if ro > 0.0 {
+            let low_mqmr = mqmr < MIN_MQMR;
+            if low_mqmr {
+                record.set_info("MQMR_FILTER", None);
+            }
+            evaluations.push(Evaluation::new(
+                "MQMR",
+                None,
+                mqmr,
+                MIN_MQMR,
+                low_mqmr.then(|| format!("MQMR:{}<{}", mqmr, MIN_MQMR)),
+            ));
+            let aqr = qr / ro;
+            record.set_info("AQR", Some(format!("{:.4}", aqr)));
+            let low_aqr = aqr < MIN_AQR;
+            if low_aqr {
+                record.set_info("AQR_FILTER", None);
+            }
+            evaluations.push(Evaluation::new(
+                "AQR",
+                None,
+                format!("{:.4}", aqr),
+                MIN_AQR,
+                low_aqr.then(|| format!("AQR:{:.4}<{}", aqr, MIN_AQR)),
+            ));
+        }
+
+        if let Some(dp) = record.info_f64("DP") {
+            let min_site_dp = self.settings.min_site_dp;
+            evaluations.push(Evaluation::new(
+                "SITE_DP",
+                None,
+                dp,
+                min_site_dp,
+                (dp < min_site_dp).then(|| format!("SITE_DP:{}<{}", dp, min_site_dp)),
+            ));
+        }
+
+        // Depth is judged per sample, then combined as for PASS: with
+        // allsamples any failing sample fails the record, otherwise only
+        // all of them failing does. That makes the deciding depth the
+        // lowest or the highest one.
+        let mut max_q: Option<f64> = None;
+        let mut depths = Vec::new();
+        for sample in 0..record.samples.len() {
+            let Some(dp) = record.sample_f64(sample, "DP") else {
+                continue;
+            };
+            depths.push(dp);
+
+            let Some(ao) = record.sample(sample, "AO").map(|ao| allele_values(Some(ao), alleles)) else {
+                continue;
+            };
+            let mut values: [Vec<String>; 5] = Default::default();
+            for ao in ao {
+                let vaf = if dp > 0.0 { ao / dp } else { 0.0 };
+                let q = self.qual(ao as u64, dp as u64);
+                let tier = if vaf >= TIER1_MIN_VAF && ao >= TIER1_MIN_AO { 1 } else { 2 };
+                let (vaf_lo, vaf_hi) = wilson_interval(ao, dp);
+                values[0].push(format!("{:.4}", vaf));
+                values[1].push(format!("{:.4}", vaf_lo));
+                values[2].push(format!("{:.4}", vaf_hi));
+                values[3].push(format!("{:.2}", q));
+                values[4].push(tier.to_string());
+                max_q = Some(max_q.map_or(q, |max| max.max(q)));
+            }
+            for (key, values) in ["VAF", "VAF_LO", "VAF_HI", "q", "tier"].iter().zip(values) {
+                record.set_sample(sample, key, values.join(","));
+            }
+        }
+
+        let deciding_dp = if self.settings.allsamples {
+            depths.iter().copied().reduce(f64::min)
+        } else {
+            depths.iter().copied().reduce(f64::max)
+        };
+        if let Some(deciding_dp) = deciding_dp {
+            let min_sample_dp = self.settings.min_sample_dp;
+            let depth_failures = depths.iter().filter(|dp| **dp < min_sample_dp).count();
+            evaluations.push(Evaluation::new(
+                "DP",
+                None,
+                deciding_dp,
+                min_sample_dp,
+                (deciding_dp < min_sample_dp).then(|| {
+                    format!("DP:{}_of_{}_samples<{}", depth_failures, depths.len(), min_sample_dp)
+                }),
+            ));
+        }
+
+        // FILTER and ALLELE_FILTER come from the evaluations alone, so the
+        // audit log cannot disagree with them
+        let failed_in = |allele: Option<usize>| {
+            let mut failed: Vec<&str> = Vec::new();
+            for evaluation in &evaluations {
+                let counts = allele.is_none() || evaluation.allele.is_none() || evaluation.allele == allele;
+                if counts && evaluation.outcome == Outcome::Fail && !failed.contains(&evaluation.criterion) {
+                    failed.push(evaluation.criterion);
+                }
+            }
+            failed
+        };
+        if self.settings.keep_multiallelic && alleles > 1 {
+            let outcomes: Vec<String> = (0..alleles)
+                .map(|allele| {
+                    let filters = failed_in(Some(allele));
+                    if filters.is_empty() {
+                        "PASS".to_string()
+                    } else {
+                        filters.join("|")
+                    }
+                })
+                .collect();
+            record.set_info("ALLELE_FILTER", Some(outcomes.join(",")));
+        }
+
+        // Without AO there is nothing to score, so keep the caller's QUAL
+        if let Some(max_q) = max_q {
+            record.qual = format!("{:.2}", max_q);
+        }
+        let failed = failed_in(None);
+        record.filter = if failed.is_empty() {
+            "PASS".to_string()
+        } else {
+            failed.join(";")
+        };
+        evaluations
+    }
+
+    /// Phred-scaled probability of seeing at least `ao` alternate reads out of
+    /// `dp` if they were all noise at rate `p`.
+    fn qual(&self, ao: u64, dp: u64) -> f64 {
+        if ao == 0 || dp == 0 {
+            return 0.0;
+        }
+        let pval = noise_pvalue(ao, dp, self.settings.p as f64);
+        if pval >= 1.0 {
+            0.0
+        } else if pval <= 0.0 {
+            MAX_Q
+        } else {
+            (-10.0 * pval.log10()).clamp(0.0, MAX_Q)
+        }
+    }
+}
+
+/// Why a record cannot be a variant: an ALT that is empty or equal to REF,
+/// as left-alignment can produce, or a position outside its contig.
+fn invalid_reason(record: &VcfRecord, contig_lengths: &HashMap<String, u64>) -> Option<&'static str> {
+    if record.alt.split(',').any(str::is_empty) {
+        return Some("empty ALT");
+    }
+    if record.alt.split(',').any(|alt| alt.eq_ignore_ascii_case(&record.reference)) {
+        return Some("ALT equals REF");
+    }
+    // REF continues from position 1 across the circular MT junction
+    let end = if record.has_info("MT_JUNCTION") {
+        record.pos
+    } else {
+        record.pos + (record.reference.len() as u64).max(1) - 1
+    };
+    if record.pos == 0 || contig_lengths.get(&record.chrom).is_some_and(|length| end > *length) {
+        return Some("position outside the contig");
+    }
+    None
+}
+
+/// ID and length of a `##contig` header line.
+fn contig_length(line: &str) -> Option<(String, u64)> {
+    let fields = line.strip_prefix("##contig=<")?.strip_suffix('>')?;
+    let mut id = None;
+    let mut length = None;
+    for field in fields.split(',') {
+        match field.split_once('=') {
+            Some(("ID", value)) => id = Some(value.to_string()),
+            Some(("length", value)) => length = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((id?, length?))
+}
+
+/// Set FORMAT/AD to RO followed by AO in each sample, leaving RO and AO as
+/// they are. A split record gets two values and a multi-allelic one kept by
+/// `--no-split` gets one per allele. Samples missing RO or AO, or with an AO
+/// that does not match the ALT alleles, get `.`. Records without either
+/// field are left alone.
+fn set_allele_depths(record: &mut VcfRecord) {
+    if !record.format.iter().any(|key| key == "RO" || key == "AO") {
+        return;
+    }
+    let alleles = record.alt.split(',').count();
+    for sample in 0..record.samples.len() {
+        let depths = match (record.sample(sample, "RO"), record.sample(sample, "AO")) {
+            (Some(ro), Some(ao)) if ao.split(',').count() == alleles => format!("{},{}", ro, ao),
+            _ => ".".to_string(),
+        };
+        record.set_sample(sample, "AD", depths);
+    }
+}
+
+/// The comma-separated values of a Number=A field, one per allele, with 0
+/// for missing or unparseable values.
+fn allele_values(value: Option<&str>, alleles: usize) -> Vec<f64> {
+    let mut values: Vec<f64> = value
+        .unwrap_or_default()
+        .split(',')
+        .map(|value| value.parse().unwrap_or(0.0))
+        .take(alleles)
+        .collect();
+    values.resize(alleles, 0.0);
+    values
+}
+
+/// Probability of seeing at least `ao` alternate reads out of `dp` if they
+/// were all noise at rate `p`.
+pub fn noise_pvalue(ao: u64, dp: u64, p: f64) -> f64 {
+    if ao == 0 {
+        return 1.0;
+    }
+    match Binomial::new(p, dp) {
+        Ok(binomial) => binomial.sf(ao - 1),
+        Err(_) => 1.0,
+    }
+}
+
+/// 95% Wilson score interval of `ao` successes out of `dp` trials. With no
+/// depth nothing is known, so the interval is [0, 1].
+pub fn wilson_interval(ao: f64, dp: f64) -> (f64, f64) {
+    if dp <= 0.0 {
+        return (0.0, 1.0);
+    }
+    let p = (ao / dp).clamp(0.0, 1.0);
+    let z2 = Z_95 * Z_95;
+    let denominator = 1.0 + z2 / dp;
+    let centre = (p + z2 / (2.0 * dp)) / denominator;
+    let half_width = Z_95 * (p * (1.0 - p) / dp + z2 / (4.0 * dp * dp)).sqrt() / denominator;
+    ((centre - half_width).max(0.0), (centre + half_width).min(1.0))
+}
+
+/// What a normalise run produced, for callers that go on to use its
+/// outputs. Optional outputs are `None` when they were not asked for, and
+/// the normalised VCF when it was streamed to stdout.
+#[derive(Clone, Debug)]
+pub struct NormaliseResult {
+    pub normalised_vcf: Option<PathBuf>,
+    pub invalid_vcf: Option<PathBuf>,
+    pub rejects_vcf: Option<PathBuf>,
+    pub audit_tsv: Option<PathBuf>,
+    pub prefix: String,
+    pub filters: FilterSummary,
+}
+
+/// Options of a normalise run beyond its inputs and outputs.
+#[derive(Clone, Debug)]
+pub struct NormaliseSettings {
+    pub allsamples: bool,
+    /// Keep multi-allelic records whole instead of splitting them.
+    pub no_split: bool,
+    pub keep_invalid: bool,
+    pub rejects_vcf: bool,
+    pub keep: bool,
+    pub p: f32,
+    /// The filter's default applies when `None`.
+    pub min_site_dp: Option<u32>,
+    pub min_sample_dp: Option<u32>,
+    pub panel_of_normals: Option<PathBuf>,
+    pub pon_min_freq: Option<f64>,
+    pub pon_soft: bool,
+    pub audit_tsv: bool,
+    pub audit_all: bool,
+    /// Overrides the normalised VCF path; `-` streams it to stdout without
+    /// indexing.
+    pub output: Option<PathBuf>,
+    pub force: bool,
+}
+
+impl Default for NormaliseSettings {
+    fn default() -> Self {
+        NormaliseSettings {
+            allsamples: false,
+            no_split: false,
+            keep_invalid: false,
+            rejects_vcf: false,
+            keep: false,
+            p: P_VAL,
+            min_site_dp: None,
+            min_sample_dp: None,
+            panel_of_normals: None,
+            pon_min_freq: None,
+            pon_soft: false,
+            audit_tsv: false,
+            audit_all: false,
+            output: None,
+            force: false,
+        }
+    }
+}
 
 pub struct Normalise {
     debug: bool,
-    vcf: String,
-    reference_fasta: String,
-    genome: String,
-    output_dir: String,
+    vcf: PathBuf,
+    reference_fasta: PathBuf,
+    genome: PathBuf,
+    output_dir: PathBuf,
     prefix: Option<String>,
-    allsamples: bool,
-    keep: bool,
-    p: f32,
-    
+    settings: NormaliseSettings,
+
     bcftools_norm_path: PathBuf,
+    recompressed_vcf_path: PathBuf,
+    /// The input without its MT junction records, for bcftools norm.
+    junction_free_path: PathBuf,
     filtered_vcf_path: PathBuf,
+    invalid_vcf_path: PathBuf,
+    filtered_rejects_path: PathBuf,
+    rejects_vcf_path: PathBuf,
+    audit_tsv_path: PathBuf,
     normalised_vcf_path: PathBuf,
 }
 
 impl Normalise {
-    pub fn new(debug: bool, vcf: String, reference_fasta: String, genome: String, output_dir: String, prefix: Option<String>, allsamples: bool, keep: bool, p: f32) -> Self {
+    /// `vcf` may be `-` to read from stdin, in which case `prefix` is
+    /// required.
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        reference_fasta: PathBuf,
+        genome: PathBuf,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+        settings: NormaliseSettings,
+    ) -> Self {
         let mut normalise = Normalise {
             debug,
             vcf: vcf.clone(),
@@ -39,79 +840,663 @@ impl Normalise {
             genome,
             output_dir: output_dir.clone(),
             prefix,
-            allsamples,
-            keep,
-            p,
-            
+            settings,
+
             bcftools_norm_path: PathBuf::new(),
+            recompressed_vcf_path: PathBuf::new(),
+            junction_free_path: PathBuf::new(),
             filtered_vcf_path: PathBuf::new(),
+            invalid_vcf_path: PathBuf::new(),
+            filtered_rejects_path: PathBuf::new(),
+            rejects_vcf_path: PathBuf::new(),
+            audit_tsv_path: PathBuf::new(),
             normalised_vcf_path: PathBuf::new(),
         };
         normalise.set_paths();
         normalise
     }
 
-    pub fn run(&self)-> Result<(), Box<dyn Error>> {
+    pub fn run(&self)-> Result<NormaliseResult, Box<dyn Error>> {
         if self.debug {
-            log::set_max_level(LevelFilter::Debug);
             debug!("Entered debug mode.");
-        } else {
-            log::set_max_level(LevelFilter::Info);
         }
 
-        self.run_bcftools_norm();
-        self.run_filtering();
+        if self.prefix.is_none() {
+            return Err("--prefix is required when reading the VCF from stdin".into());
+        }
 
-        // Placeholder for MityUtil::gsort logic
-        // MityUtil::gsort(self.filtered_vcf_path.clone(), self.normalised_vcf_path.clone(), self.genome.clone());
+        let mut outputs: Vec<&Path> = Vec::new();
+        if self.normalised_vcf_path != Path::new("-") {
+            outputs.push(&self.normalised_vcf_path);
+        }
+        for (written, path) in [
+            (self.settings.keep_invalid, &self.invalid_vcf_path),
+            (self.settings.rejects_vcf, &self.rejects_vcf_path),
+            (self.settings.audit_tsv, &self.audit_tsv_path),
+        ] {
+            if written {
+                outputs.push(path);
+            }
+        }
+        mity_util::check_overwrite(&outputs, self.settings.force).map_err(error::boxed)?;
+
+        let mut summary = RunSummary::new("mity normalise");
+
+        let timer = Timer::start();
+        let input = self.bgzf_input()?;
+        let (input, junction_records) = self.set_aside_junction_records(input)?;
+        self.run_bcftools_norm(&input)?;
+        summary.record("bcftools norm", timer);
+
+        let timer = Timer::start();
+        let filter_summary = self.run_filtering(&junction_records)?;
+        summary.record("filtering", timer);
+        summary.set_metric("filters", filter_summary.to_json());
+
+        let timer = Timer::start();
+        mity_util::gsort(&self.filtered_vcf_path, &self.normalised_vcf_path, &self.genome).map_err(error::boxed)?;
+        if self.settings.rejects_vcf {
+            mity_util::gsort(&self.filtered_rejects_path, &self.rejects_vcf_path, &self.genome).map_err(error::boxed)?;
+        }
+        summary.record("sorting", timer);
 
         self.remove_intermediate_files();
 
+        if !self.is_streaming() {
+            summary.add_output(&self.normalised_vcf_path);
+            if self.settings.keep_invalid {
+                summary.add_output(&self.invalid_vcf_path);
+            }
+            if self.settings.rejects_vcf {
+                summary.add_output(&self.rejects_vcf_path);
+            }
+            if self.settings.audit_tsv {
+                summary.add_output(&self.audit_tsv_path);
+            }
+            summary.log();
+        }
+        summary.write_json(&self.output_dir.join(format!(
+            "{}.mity.normalise.stats.json",
+            self.prefix.as_ref().unwrap()
+        )))?;
+
+        Ok(NormaliseResult {
+            normalised_vcf: (!self.is_streaming()).then(|| self.normalised_vcf_path.clone()),
+            invalid_vcf: self.settings.keep_invalid.then(|| self.invalid_vcf_path.clone()),
+            rejects_vcf: self.settings.rejects_vcf.then(|| self.rejects_vcf_path.clone()),
+            audit_tsv: self.settings.audit_tsv.then(|| self.audit_tsv_path.clone()),
+            prefix: self.prefix.clone().unwrap_or_default(),
+            filters: filter_summary,
+        })
+    }
+
+    /// Whether the normalised VCF is written to stdout.
+    pub fn is_streaming(&self) -> bool {
+        self.settings.output.as_deref() == Some(Path::new("-"))
+    }
+
+    /// The VCF to give bcftools: the input itself if it is BGZF or stdin,
+    /// otherwise a BGZF copy of the plain gzip or text input.
+    fn bgzf_input(&self) -> Result<PathBuf, Box<dyn Error>> {
+        if self.vcf == Path::new("-") {
+            return Ok(self.vcf.clone());
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+        if mity_util::is_bcf(&self.vcf).map_err(error::boxed)? {
+            info!("{} is BCF; converting it to {}", self.vcf.display(), self.recompressed_vcf_path.display());
+            mity_util::bcf_to_vcf(&self.vcf, &self.recompressed_vcf_path).map_err(error::boxed)?;
+            return Ok(self.recompressed_vcf_path.clone());
+        }
+        let compression = mity_util::detect_compression(&self.vcf)?;
+        if compression == mity_util::Compression::Bgzf {
+            return Ok(self.vcf.clone());
+        }
+        let size = fs::metadata(&self.vcf)?.len();
+        if size > LARGE_INPUT_BYTES {
+            warn!(
+                "{} is {:.1} GB and not bgzipped, so a copy of about that size is written to {}. \
+                 Extracting MT first, e.g. with bcftools view -r MT, avoids this",
+                self.vcf.display(),
+                size as f64 / (1024.0 * 1024.0 * 1024.0),
+                self.output_dir.display()
+            );
+        }
+        info!(
+            "{} is {}, not BGZF; recompressing it to {}",
+            self.vcf.display(),
+            if compression == mity_util::Compression::Gzip { "plain gzip" } else { "uncompressed" },
+            self.recompressed_vcf_path.display()
+        );
+        mity_util::bgzip_to(&self.vcf, &self.recompressed_vcf_path)?;
+        Ok(self.recompressed_vcf_path.clone())
+    }
+
+    /// Take the records that cross the circular MT junction, from `mity call
+    /// --circular`, out of the input, as bcftools norm cannot match their REF
+    /// to the linear reference. They rejoin the others at filtering. Records
+    /// read from stdin are left in place.
+    fn set_aside_junction_records(&self, input: PathBuf) -> Result<(PathBuf, Vec<String>), Box<dyn Error>> {
+        if input == Path::new("-") {
+            return Ok((input, Vec::new()));
+        }
+        let mut lines = mity_util::open_vcf(&input)?.lines();
+        let header = VcfHeader::read(&mut lines)?;
+        if !header.contains("INFO", "MT_JUNCTION") {
+            return Ok((input, Vec::new()));
+        }
+
+        let mut writer = bgzf::Writer::new(File::create(&self.junction_free_path)?);
+        header.write(&mut writer)?;
+        let mut junction_records = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            if VcfRecord::parse(&line)?.has_info("MT_JUNCTION") {
+                junction_records.push(line);
+            } else {
+                writeln!(writer, "{}", line)?;
+            }
+        }
+        writer.finish()?;
+        info!("Set aside {} records across the MT junction from bcftools norm", junction_records.len());
+        Ok((self.junction_free_path.clone(), junction_records))
+    }
+
+    fn run_bcftools_norm(&self, input: &Path) -> Result<(), Box<dyn Error>> {
+        // Split multi-allelic records, unless --no-split, and left-align
+        // indels. An input of `-` is passed straight through so bcftools
+        // reads our stdin.
+        let mut command = mity_util::tool_command(Tool::Bcftools)?;
+        command.arg("norm").arg("-f").arg(&self.reference_fasta);
+        if !self.settings.no_split {
+            command.arg("-m-both");
+        }
+        command
+            .arg("-O")
+            .arg("z")
+            .arg("-o")
+            .arg(&self.bcftools_norm_path)
+            .arg(input);
+        debug!("{:?}", command);
+
+        let status = command.status()?;
+        if !status.success() {
+            return Err(MityError::tool("bcftools norm", status.code(), b"").into());
+        }
         Ok(())
     }
 
-    fn run_bcftools_norm(&self) {
-        // Placeholder for running bcftools command
-        debug!("Running bcftools norm");
-        // Simulate command: bcftools norm -f {} -m-both {}
-        let output = format!("Simulating bcftools norm on {} with reference {}", self.vcf, self.reference_fasta);
-        let mut file = File::create(&self.bcftools_norm_path).expect("Failed to create bcftools norm file");
-        file.write_all(output.as_bytes()).expect("Failed to write bcftools norm output");
+    /// The `mity normalise` command with every effective setting.
+    fn command_line(&self, settings: &FilterSettings) -> Vec<String> {
+        let mut args: Vec<String> = vec!["mity".into(), "normalise".into()];
+        let mut option = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        option("--custom-reference-fasta", self.reference_fasta.display().to_string());
+        option("--custom-reference-genome", self.genome.display().to_string());
+        if let Some(prefix) = &self.prefix {
+            option("--prefix", prefix.clone());
+        }
+        option("--output-dir", self.output_dir.display().to_string());
+        option("--p", self.settings.p.to_string());
+        option("--min-site-dp", settings.min_site_dp.to_string());
+        option("--min-sample-dp", settings.min_sample_dp.to_string());
+        if let Some(pon) = &self.settings.panel_of_normals {
+            option("--panel-of-normals", pon.display().to_string());
+            option("--pon-min-freq", settings.pon_min_freq.to_string());
+        }
+        if let Some(output) = &self.settings.output {
+            option("--output", output.display().to_string());
+        }
+        let flags = [
+            (self.settings.allsamples, "--allsamples"),
+            (self.settings.no_split, "--no-split"),
+            (self.settings.keep_invalid, "--keep-invalid"),
+            (self.settings.rejects_vcf, "--rejects-vcf"),
+            (self.settings.pon_soft, "--pon-soft"),
+            (self.settings.audit_tsv, "--audit-tsv"),
+            (self.settings.audit_all, "--audit-all"),
+        ];
+        args.extend(flags.iter().filter(|(set, _)| *set).map(|(_, flag)| flag.to_string()));
+        args.push(self.vcf.display().to_string());
+        args
     }
 
-    fn run_filtering(&self) {
-        // Placeholder for filtering logic (e.g., parsing VCF and filtering variants)
+    fn run_filtering(&self, junction_records: &[String]) -> Result<FilterSummary, Box<dyn Error>> {
         debug!("Running filtering");
-        
-        // Simulate file writing logic
-        let mut file = File::create(&self.filtered_vcf_path).expect("Failed to create filtered VCF file");
-        file.write_all(b"Simulated filtered VCF data").expect("Failed to write filtered VCF file");
+
+        let defaults = FilterSettings::default();
+        let settings = FilterSettings {
+            min_site_dp: self.settings.min_site_dp.map_or(defaults.min_site_dp, f64::from),
+            min_sample_dp: self.settings.min_sample_dp.map_or(defaults.min_sample_dp, f64::from),
+            allsamples: self.settings.allsamples,
+            p: self.settings.p,
+            keep_multiallelic: self.settings.no_split,
+            pon: self.settings.panel_of_normals.as_deref().map(PanelOfNormals::load).transpose()?,
+            pon_min_freq: self.settings.pon_min_freq.unwrap_or(defaults.pon_min_freq),
+            pon_soft: self.settings.pon_soft,
+            ..defaults
+        };
+        // Junction records go after the others; the output is sorted later
+        let mut junction_lines = String::new();
+        for line in junction_records {
+            junction_lines.push_str(line);
+            junction_lines.push('\n');
+        }
+        let reader: Box<dyn BufRead> = Box::new(io::Read::chain(
+            mity_util::open_vcf(&self.bcftools_norm_path)?,
+            io::Cursor::new(junction_lines),
+        ));
+        let mut writer = BufWriter::new(File::create(&self.filtered_vcf_path)?);
+        let mut invalid_writer = if self.settings.keep_invalid {
+            Some(BufWriter::new(checksum::OutputFile::create(&self.invalid_vcf_path)?))
+        } else {
+            None
+        };
+        let mut rejects_writer = if self.settings.rejects_vcf {
+            Some(BufWriter::new(File::create(&self.filtered_rejects_path)?))
+        } else {
+            None
+        };
+        // The audit log has a row per criterion per record, so it is
+        // compressed as it is written
+        let mut audit_writer = if self.settings.audit_tsv {
+            Some(bgzf::Writer::new(checksum::OutputFile::create(&self.audit_tsv_path)?))
+        } else {
+            None
+        };
+        let provenance = vec![mity_util::command_line_header(
+            "mityNormaliseCommandline",
+            &self.command_line(&settings),
+        )];
+        let summary = VariantFilter::new(settings).with_provenance(provenance).filter_vcf(
+            reader,
+            &mut writer,
+            invalid_writer.as_mut().map(|writer| writer as &mut dyn Write),
+            rejects_writer.as_mut().map(|writer| writer as &mut dyn Write),
+            audit_writer.as_mut().map(|writer| AuditLog {
+                writer,
+                all: self.settings.audit_all,
+            }),
+        )?;
+        if let Some(audit_writer) = audit_writer {
+            audit_writer.finish()?.finish()?;
+            info!("Wrote the filter audit log to {}", self.audit_tsv_path.display());
+        }
+        if let Some(invalid_writer) = invalid_writer {
+            checksum::finish_buffered(invalid_writer)?;
+            info!("Wrote {} invalid records to {}", summary.invalid, self.invalid_vcf_path.display());
+        }
+        if self.settings.rejects_vcf {
+            info!(
+                "Writing {} filtered records to {}",
+                summary.records - summary.passed,
+                self.rejects_vcf_path.display()
+            );
+        }
+
+        summary.log();
+        Ok(summary)
     }
 
     fn set_paths(&mut self) {
-        if self.prefix.is_none() {
-            self.prefix = Some(self.make_prefix(&self.vcf));
+        if self.prefix.is_none() && self.vcf != Path::new("-") {
+            self.prefix = Some(mity_util::make_prefix(&self.vcf));
         }
-        
-        let prefix = self.prefix.clone().unwrap();
-        self.bcftools_norm_path = PathBuf::from(&self.output_dir).join(format!("{}.bcftools.norm.vcf.gz", prefix));
-        self.filtered_vcf_path = PathBuf::from(&self.vcf.replace(".vcf.gz", ".filtered.vcf"));
-        self.normalised_vcf_path = PathBuf::from(&self.output_dir).join(format!("{}.normalise.vcf.gz", prefix));
-    }
+        let Some(prefix) = self.prefix.clone() else {
+            return;
+        };
 
-    fn make_prefix(&self, vcf: &str) -> String {
-        let parts: Vec<&str> = vcf.split('/').collect();
-        parts.last().unwrap_or(&"default").to_string()
+        self.bcftools_norm_path = self.output_dir.join(format!("{}.mity.bcftools.norm.vcf.gz", prefix));
+        self.recompressed_vcf_path = self.output_dir.join(format!("{}.mity.input.vcf.gz", prefix));
+        self.junction_free_path = self.output_dir.join(format!("{}.mity.junction_free.vcf.gz", prefix));
+        self.filtered_vcf_path = self.output_dir.join(format!("{}.mity.filtered.vcf", prefix));
+        self.invalid_vcf_path = self.output_dir.join(format!("{}.mity.normalise.invalid.vcf", prefix));
+        self.filtered_rejects_path = self.output_dir.join(format!("{}.mity.rejected.vcf", prefix));
+        self.rejects_vcf_path = self.output_dir.join(format!("{}.mity.rejected.vcf.gz", prefix));
+        self.audit_tsv_path = self.output_dir.join(format!("{}.mity.normalise.audit.tsv.gz", prefix));
+        self.normalised_vcf_path = match &self.settings.output {
+            Some(output) => output.clone(),
+            None => self.output_dir.join(format!("{}.mity.normalise.vcf.gz", prefix)),
+        };
     }
 
+    /// Remove the intermediate files unless `--keep`, logging which there
+    /// were. The bcftools norm VCF is the input to the filters as it came
+    /// from bcftools.
     fn remove_intermediate_files(&self) {
-        if !self.keep {
-            if self.filtered_vcf_path.exists() {
-                remove_file(&self.filtered_vcf_path).expect("Failed to remove filtered VCF file");
+        let files: Vec<PathBuf> = [
+            &self.filtered_vcf_path,
+            &self.bcftools_norm_path,
+            &self.recompressed_vcf_path,
+            &self.junction_free_path,
+            &self.filtered_rejects_path,
+        ]
+        .into_iter()
+        .filter(|path| path.exists())
+        .cloned()
+        .collect();
+        if !self.settings.keep {
+            for path in &files {
+                remove_file(path).unwrap_or_else(|e| panic!("Failed to remove {}: {}", path.display(), e));
             }
-            if self.bcftools_norm_path.exists() {
-                remove_file(&self.bcftools_norm_path).expect("Failed to remove bcftools norm file");
+        }
+        mity_util::log_intermediate_files(self.settings.keep, &files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_interval(ao: f64, dp: f64, expected: (f64, f64)) {
+        let (low, high) = wilson_interval(ao, dp);
+        assert!(
+            (low - expected.0).abs() < 1e-4 && (high - expected.1).abs() < 1e-4,
+            "{}/{}: got ({}, {}), expected {:?}",
+            ao,
+            dp,
+            low,
+            high,
+            expected
+        );
+    }
+
+    #[test]
+    fn wilson_interval_matches_known_values() {
+        assert_interval(5.0, 10.0, (0.2366, 0.7634));
+        assert_interval(1.0, 10.0, (0.0179, 0.4042));
+        assert_interval(50.0, 1000.0, (0.0381, 0.0653));
+        assert_interval(0.0, 10.0, (0.0, 0.2775));
+    }
+
+    #[test]
+    fn wilson_interval_with_no_depth_is_uninformative() {
+        assert_eq!(wilson_interval(0.0, 0.0), (0.0, 1.0));
+        assert_eq!(wilson_interval(3.0, 0.0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn wilson_interval_when_every_read_is_alternate() {
+        assert_interval(10.0, 10.0, (0.7225, 1.0));
+        assert_interval(1.0, 1.0, (0.2065, 1.0));
+        let (low, high) = wilson_interval(1000.0, 1000.0);
+        assert!(low > 0.99 && high <= 1.0);
+        // More alternate observations than depth are clamped, not NaN
+        assert_eq!(wilson_interval(12.0, 10.0), wilson_interval(10.0, 10.0));
+    }
+
+    /// Two samples at one site, one 5 deep and the other 500 deep.
+    const SHALLOW_AND_DEEP: &str = "MT\t1000\t.\tA\tG\t100\t.\tDP=505;RO=250;AO=255;SRF=125;SRR=125;SAF=128;SAR=127;QR=9000;MQMR=60\tGT:DP:RO:AO\t0/1:5:2:3\t0/1:500:248:252";
+
+    fn filtered(settings: FilterSettings, line: &str) -> VcfRecord {
+        let mut record = VcfRecord::parse(line).unwrap();
+        VariantFilter::new(settings).filter_record(&mut record);
+        record
+    }
+
+    #[test]
+    fn sample_depth_fails_only_if_every_sample_is_shallow() {
+        let record = filtered(FilterSettings::default(), SHALLOW_AND_DEEP);
+        assert_eq!(record.filter, "PASS");
+
+        let settings = FilterSettings {
+            min_sample_dp: 600.0,
+            ..FilterSettings::default()
+        };
+        assert_eq!(filtered(settings, SHALLOW_AND_DEEP).filter, "DP");
+    }
+
+    #[test]
+    fn sample_depth_fails_if_any_sample_is_shallow_with_allsamples() {
+        let settings = FilterSettings {
+            allsamples: true,
+            ..FilterSettings::default()
+        };
+        assert_eq!(filtered(settings, SHALLOW_AND_DEEP).filter, "DP");
+
+        let settings = FilterSettings {
+            allsamples: true,
+            min_sample_dp: 5.0,
+            ..FilterSettings::default()
+        };
+        assert_eq!(filtered(settings, SHALLOW_AND_DEEP).filter, "PASS");
+    }
+
+    #[test]
+    fn site_depth_uses_info_dp() {
+        // The site total clears a threshold neither sample does alone
+        let settings = FilterSettings {
+            min_site_dp: 505.0,
+            min_sample_dp: 0.0,
+            ..FilterSettings::default()
+        };
+        assert_eq!(filtered(settings, SHALLOW_AND_DEEP).filter, "PASS");
+
+        let settings = FilterSettings {
+            min_site_dp: 506.0,
+            ..FilterSettings::default()
+        };
+        assert_eq!(filtered(settings, SHALLOW_AND_DEEP).filter, "SITE_DP");
+    }
+
+    #[test]
+    fn depth_filter_descriptions_name_the_thresholds() {
+        let settings = FilterSettings {
+            min_site_dp: 100.0,
+            allsamples: true,
+            ..FilterSettings::default()
+        };
+        let lines = VariantFilter::new(settings).header_lines();
+        assert!(lines.contains(&"##FILTER=<ID=DP,Description=\"Sample depth (FORMAT DP) below 15 in any sample\">".to_string()));
+        assert!(lines.contains(&"##FILTER=<ID=SITE_DP,Description=\"Site depth (INFO DP) below 100\">".to_string()));
+    }
+
+    /// freebayes-style records for two samples: a biallelic site, and a
+    /// multi-allelic one the second sample has no reads at.
+    const FREEBAYES_VCF: &str = "##fileformat=VCFv4.2
+##contig=<ID=MT,length=16569>
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total read depth at the locus\">
+##INFO=<ID=RO,Number=1,Type=Integer,Description=\"Reference allele observation count\">
+##INFO=<ID=AO,Number=A,Type=Integer,Description=\"Alternate allele observation count\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read Depth\">
+##FORMAT=<ID=RO,Number=1,Type=Integer,Description=\"Reference allele observation count\">
+##FORMAT=<ID=AO,Number=A,Type=Integer,Description=\"Alternate allele observation count\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta\tb
+MT\t1000\t.\tA\tG\t100\t.\tDP=60;RO=40;AO=20\tGT:DP:RO:AO\t0/1:30:10:20\t0/0:30:30:0
+MT\t2000\t.\tC\tT,G\t100\t.\tDP=30;RO=18;AO=5,7\tGT:DP:RO:AO\t1/2:30:18:5,7\t./.:.:.:.
+";
+
+    #[test]
+    fn allele_depths_read_back_with_a_vcf_parser() {
+        use noodles::vcf;
+        use noodles::vcf::variant::record_buf::samples::sample::value::Array;
+        use noodles::vcf::variant::record_buf::samples::sample::Value as SampleValue;
+
+        let mut output = Vec::new();
+        VariantFilter::new(FilterSettings::default())
+            .filter_vcf(Box::new(FREEBAYES_VCF.as_bytes()), &mut output, None, None, None)
+            .unwrap();
+
+        let mut reader = vcf::io::Reader::new(output.as_slice());
+        let header = reader.read_header().unwrap();
+        let records: Vec<vcf::variant::RecordBuf> = reader.record_bufs(&header).map(Result::unwrap).collect();
+        let allele_depths = |record: &vcf::variant::RecordBuf, sample: usize| {
+            match record.samples().get_index(sample).unwrap().get("AD").unwrap() {
+                Some(SampleValue::Array(Array::Integer(depths))) => Some(depths.clone()),
+                None => None,
+                value => panic!("AD is not integers: {:?}", value),
             }
+        };
+        assert_eq!(allele_depths(&records[0], 0), Some(vec![Some(10), Some(20)]));
+        assert_eq!(allele_depths(&records[0], 1), Some(vec![Some(30), Some(0)]));
+        assert_eq!(allele_depths(&records[1], 0), Some(vec![Some(18), Some(5), Some(7)]));
+        assert_eq!(allele_depths(&records[1], 1), None);
+
+        // RO and AO are left as they were
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains(ALLELE_DEPTH_HEADER));
+        assert!(text.lines().any(|line| line.starts_with("MT\t2000") && line.contains("\t1/2:30:18:5,7:")));
+    }
+
+    #[test]
+    fn allele_depths_of_samples_missing_ro_or_ao_are_missing() {
+        let mut record = VcfRecord::parse("MT\t1000\t.\tA\tG,T\t100\t.\t.\tGT:RO:AO\t0/1:10:20,3\t0/1:10:.\t0/1:10:20").unwrap();
+        set_allele_depths(&mut record);
+        assert_eq!(record.sample(0, "AD"), Some("10,20,3"));
+        assert_eq!(record.sample(1, "AD"), None);
+        // An AO that does not match the ALT alleles
+        assert_eq!(record.sample(2, "AD"), None);
+
+        let mut record = VcfRecord::parse("MT\t1000\t.\tA\tG\t100\t.\t.\tGT:DP\t0/1:30").unwrap();
+        set_allele_depths(&mut record);
+        assert!(!record.format.iter().any(|key| key == "AD"));
+    }
+
+    #[test]
+    fn a_deletion_across_the_origin_skips_bcftools_norm_and_stays_valid() {
+        use crate::test_util;
+
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let reference = test_util::write_reference(temp.path(), "MT");
+        let genome = test_util::write_genome(temp.path(), "MT");
+        // Deletes 16568-16569 and 1-3, as call --circular writes it
+        let sequence = test_util::mt_sequence();
+        let deleted: String = sequence[16566..].iter().chain(&sequence[..3]).map(|&base| base as char).collect();
+        let mut text: String = FREEBAYES_VCF.lines().take(9).map(|line| format!("{}\n", line)).collect();
+        text.push_str(crate::circular::JUNCTION_HEADER);
+        text.push_str("\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta\n");
+        text.push_str("MT\t1000\t.\tA\tG\t100\t.\tDP=30;RO=10;AO=20\tGT:DP:RO:AO\t0/1:30:10:20\n");
+        text.push_str(&format!(
+            "MT\t16567\t.\t{}\t{}\t500\t.\tDP=40;RO=4;AO=36;MT_JUNCTION\tGT:DP:RO:AO\t1/1:40:4:36\n",
+            deleted,
+            &deleted[..1]
+        ));
+        let vcf = temp.path().join("in.vcf");
+        fs::write(&vcf, text).unwrap();
+
+        let settings = NormaliseSettings {
+            keep_invalid: true,
+            keep: true,
+            ..NormaliseSettings::default()
+        };
+        let normalise = Normalise::new(false, vcf, reference, genome, temp.path().to_path_buf(), Some("test".to_string()), settings);
+        let result = normalise.run().unwrap();
+
+        let positions = |path: &Path| -> Vec<u64> {
+            test_util::read_lines(path)
+                .iter()
+                .filter(|line| !line.starts_with('#'))
+                .map(|line| VcfRecord::parse(line).unwrap().pos)
+                .collect()
+        };
+        // bcftools norm never sees it
+        assert_eq!(positions(&temp.path().join("test.mity.junction_free.vcf.gz")), [1000]);
+        // It rejoins the others, whole and not invalid
+        let normalised = result.normalised_vcf.unwrap();
+        assert_eq!(positions(&normalised), [1000, 16567]);
+        let junction = test_util::read_lines(&normalised).into_iter().find(|line| line.starts_with("MT\t16567")).unwrap();
+        let junction = VcfRecord::parse(&junction).unwrap();
+        assert_eq!(junction.reference, deleted);
+        assert!(junction.has_info("MT_JUNCTION"));
+        assert!(positions(&result.invalid_vcf.unwrap()).is_empty());
+    }
+
+    #[test]
+    fn every_normalise_parameter_is_in_the_command_line_header() {
+        use crate::test_util;
+
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let reference = test_util::write_reference(temp.path(), "MT");
+        let genome = test_util::write_genome(temp.path(), "MT");
+        let vcf = temp.path().join("in.vcf");
+        fs::write(&vcf, FREEBAYES_VCF).unwrap();
+
+        let settings = NormaliseSettings {
+            allsamples: true,
+            no_split: true,
+            keep_invalid: true,
+            rejects_vcf: true,
+            p: 0.01,
+            min_site_dp: Some(20),
+            min_sample_dp: Some(10),
+            audit_tsv: true,
+            audit_all: true,
+            ..NormaliseSettings::default()
+        };
+        let normalise = Normalise::new(
+            false, vcf.clone(), reference.clone(), genome.clone(), temp.path().to_path_buf(), Some("n1".to_string()), settings,
+        );
+        let result = normalise.run().unwrap();
+
+        let lines = test_util::read_lines(&result.normalised_vcf.unwrap());
+        let line = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("##mityNormaliseCommandline=\""))
+            .unwrap();
+        let (command, rest) = line.split_once("; Version=").unwrap();
+        assert!(rest.starts_with(env!("CARGO_PKG_VERSION")) && rest.contains("; Date=20"), "{}", rest);
+        for expected in [
+            "mity normalise ",
+            &format!("--custom-reference-fasta {}", reference.display()),
+            &format!("--custom-reference-genome {}", genome.display()),
+            "--prefix n1",
+            &format!("--output-dir {}", temp.path().display()),
+            "--p 0.01",
+            "--min-site-dp 20",
+            "--min-sample-dp 10",
+            "--allsamples",
+            "--no-split",
+            "--keep-invalid",
+            "--rejects-vcf",
+            "--audit-tsv",
+            "--audit-all",
+        ] {
+            assert!(command.contains(expected), "{:?} is not in {}", expected, command);
         }
+        assert!(command.ends_with(&vcf.display().to_string()), "{}", command);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn the_normalise_result_names_outputs_that_exist() {
+        use crate::test_util;
+
+        test_util::use_fake_tools();
+        let temp = tempfile::tempdir().unwrap();
+        let reference = test_util::write_reference(temp.path(), "MT");
+        let genome = test_util::write_genome(temp.path(), "MT");
+        let vcf = temp.path().join("s1.mity.call.vcf");
+        fs::write(&vcf, FREEBAYES_VCF).unwrap();
+
+        let settings = NormaliseSettings {
+            keep_invalid: true,
+            rejects_vcf: true,
+            audit_tsv: true,
+            ..NormaliseSettings::default()
+        };
+        let normalise = Normalise::new(false, vcf, reference, genome, temp.path().to_path_buf(), None, settings);
+        let result = normalise.run().unwrap();
+
+        assert_eq!(result.prefix, "s1");
+        let paths = [
+            (result.normalised_vcf, "s1.mity.normalise.vcf.gz"),
+            (result.invalid_vcf, "s1.mity.normalise.invalid.vcf"),
+            (result.rejects_vcf, "s1.mity.rejected.vcf.gz"),
+            (result.audit_tsv, "s1.mity.normalise.audit.tsv.gz"),
+        ];
+        for (path, name) in paths {
+            let path = path.unwrap_or_else(|| panic!("no {}", name));
+            assert_eq!(path, temp.path().join(name));
+            assert!(path.is_file(), "{} does not exist", path.display());
+        }
+        assert_eq!(result.filters.records, 2);
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use log::warn;
+use std::fs;
+use std::path::Path;
+
+use crate::vcf_record::VcfRecord;
+
+/// A proband and their mother from a PED file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trio {
+    pub proband: String,
+    pub mother: String,
+}
+
+/// Read the mother/child pairs of a PED file (family, individual, father,
+/// mother, sex, phenotype). When any child is marked affected, only
+/// affected children are probands.
+pub fn read_ped(path: &Path) -> Result<Vec<Trio>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read PED file {}", path.display()))?;
+
+    let mut children = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 {
+            anyhow::bail!(
+                "{} line {}: expected 6 columns, found {}",
+                path.display(),
+                number + 1,
+                fields.len()
+            );
+        }
+        if fields[3] != "0" {
+            let trio = Trio {
+                proband: fields[1].to_string(),
+                mother: fields[3].to_string(),
+            };
+            children.push((trio, fields[5] == "2"));
+        }
+    }
+
+    let any_affected = children.iter().any(|(_, affected)| *affected);
+    let trios: Vec<Trio> = children
+        .into_iter()
+        .filter(|(_, affected)| *affected || !any_affected)
+        .map(|(trio, _)| trio)
+        .collect();
+    if trios.is_empty() {
+        anyhow::bail!("{} has no individual with a mother", path.display());
+    }
+    Ok(trios)
+}
+
+/// How a proband's variant relates to the mother.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Inheritance {
+    Maternal,
+    DeNovo,
+    Indeterminate,
+    Unknown,
+}
+
+impl Inheritance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Inheritance::Maternal => "maternal",
+            Inheritance::DeNovo => "de_novo",
+            Inheritance::Indeterminate => "indeterminate",
+            Inheritance::Unknown => "unknown",
+        }
+    }
+}
+
+/// Labels the variants each proband carries from the mother's VAF: present
+/// at or above `maternal_min_vaf` is maternal, absent is de novo and anything
+/// in between is indeterminate. A mother missing from the VCF, or without
+/// depth at the site, gives unknown.
+pub struct InheritanceClassifier {
+    /// Sample indices of each proband and their mother, if in the VCF.
+    pairs: Vec<(usize, Option<usize>)>,
+    maternal_min_vaf: f64,
+}
+
+impl InheritanceClassifier {
+    pub fn new(trios: &[Trio], samples: &[String], maternal_min_vaf: f64) -> Self {
+        let index_of = |name: &str| samples.iter().position(|sample| sample == name);
+        let mut pairs = Vec::new();
+        for trio in trios {
+            let Some(proband) = index_of(&trio.proband) else {
+                warn!("Proband {} is not in the VCF; skipping", trio.proband);
+                continue;
+            };
+            let mother = index_of(&trio.mother);
+            if mother.is_none() {
+                warn!(
+                    "Mother {} of {} is not in the VCF; inheritance will be unknown",
+                    trio.mother, trio.proband
+                );
+            }
+            pairs.push((proband, mother));
+        }
+        InheritanceClassifier {
+            pairs,
+            maternal_min_vaf,
+        }
+    }
+
+    /// Number of probands found in the VCF.
+    pub fn probands(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Inheritance of the variant for each proband that carries it, as
+    /// `(proband sample index, status)`.
+    pub fn classify(&self, record: &VcfRecord) -> Vec<(usize, Inheritance)> {
+        self.pairs
+            .iter()
+            .filter(|(proband, _)| record.sample_vaf(*proband).is_some_and(|vaf| vaf > 0.0))
+            .map(|(proband, mother)| {
+                let status = match mother.and_then(|mother| record.sample_vaf(mother)) {
+                    None => Inheritance::Unknown,
+                    Some(vaf) if vaf >= self.maternal_min_vaf => Inheritance::Maternal,
+                    Some(vaf) if vaf <= 0.0 => Inheritance::DeNovo,
+                    Some(_) => Inheritance::Indeterminate,
+                };
+                (*proband, status)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trio(proband: &str, mother: &str) -> Trio {
+        Trio {
+            proband: proband.to_string(),
+            mother: mother.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_affected_children_are_probands_when_any_is_affected() {
+        let temp = tempfile::tempdir().unwrap();
+        let ped = temp.path().join("family.ped");
+        fs::write(
+            &ped,
+            "# family individual father mother sex phenotype\n\
+             F1 dad 0 0 1 1\nF1 mum 0 0 2 1\nF1 kid dad mum 1 2\nF1 sib dad mum 2 1\n",
+        )
+        .unwrap();
+        assert_eq!(read_ped(&ped).unwrap(), [trio("kid", "mum")]);
+
+        fs::write(&ped, "F1 mum 0 0 2 1\nF1 kid 0 mum 1 1\nF1 sib 0 mum 2 0\n").unwrap();
+        assert_eq!(read_ped(&ped).unwrap(), [trio("kid", "mum"), trio("sib", "mum")]);
+
+        fs::write(&ped, "F1 mum 0 0 2 1\n").unwrap();
+        assert!(read_ped(&ped).unwrap_err().to_string().contains("no individual with a mother"));
+        fs::write(&ped, "F1 kid 0 mum\n").unwrap();
+        assert!(read_ped(&ped).unwrap_err().to_string().contains("line 1: expected 6 columns"));
+    }
+
+    #[test]
+    fn inheritance_follows_the_mothers_vaf() {
+        let samples: Vec<String> = ["kid", "mum", "cousin"].map(String::from).to_vec();
+        let trios = [trio("kid", "mum"), trio("cousin", "aunt"), trio("absent", "mum")];
+        let classifier = InheritanceClassifier::new(&trios, &samples, 0.1);
+        assert_eq!(classifier.probands(), 2);
+
+        let classify = |vafs: &str| {
+            let line = format!("MT\t73\t.\tA\tG\t.\tPASS\t.\tVAF\t{}", vafs.replace(' ', "\t"));
+            classifier.classify(&VcfRecord::parse(&line).unwrap())
+        };
+        assert_eq!(classify("0.5 0.2 0"), [(0, Inheritance::Maternal)]);
+        assert_eq!(classify("0.5 0 0"), [(0, Inheritance::DeNovo)]);
+        assert_eq!(classify("0.5 0.05 0"), [(0, Inheritance::Indeterminate)]);
+        assert_eq!(classify("0.5 . 0.3"), [(0, Inheritance::Unknown), (2, Inheritance::Unknown)]);
+        assert!(classify("0 0.9 0").is_empty(), "only carriers are classified");
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use log::debug;
+use noodles::bam;
+use noodles::core::{Position, Region};
+use noodles::sam::alignment::record::cigar::op::Kind;
+use std::io;
+use std::path::Path;
+
+use crate::mity_util::{self, Interval};
+
+/// Per-base read depth over a single contig, computed the way freebayes sees
+/// the reads: unmapped, secondary, supplementary, duplicate and QC-failed
+/// reads are skipped, as are reads below `min_mq` and bases below `min_bq`.
+/// Deletions do not count towards depth.
+pub fn contig_depth(bam: &Path, contig: &str, min_mq: u32, min_bq: u32) -> Result<Vec<u32>> {
+    let mut reader = bam::io::reader::Builder
+        .build_from_path(bam)
+        .with_context(|| format!("Failed to open {}", bam.display()))?;
+    let header = reader.read_header()?;
+    let length = header
+        .reference_sequences()
+        .get(contig.as_bytes())
+        .map(|sequence| sequence.length().get())
+        .with_context(|| format!("Contig {} not found in {}", contig, bam.display()))?;
+
+    let interval = Interval {
+        contig: contig.to_string(),
+        start: 0,
+        end: length,
+    };
+    let mut depths = intervals_depth(bam, &[interval], min_mq, min_bq)?;
+    Ok(depths.remove(0))
+}
+
+/// Per-base depth over each of `intervals`, with the same read filters as
+/// [`contig_depth`].
+pub fn intervals_depth(
+    bam: &Path,
+    intervals: &[Interval],
+    min_mq: u32,
+    min_bq: u32,
+) -> Result<Vec<Vec<u32>>> {
+    let mut depths: Vec<Vec<u32>> = intervals
+        .iter()
+        .map(|interval| vec![0u32; interval.end.saturating_sub(interval.start)])
+        .collect();
+    for_each_record(bam, intervals, |index, record| {
+        add_record(record, intervals[index].start, &mut depths[index], min_mq, min_bq)
+    })?;
+    Ok(depths)
+}
+
+/// Call `f` with the index of the interval and each alignment on it. An
+/// index next to the BAM is used to jump to each interval; without one the
+/// whole file is scanned once and every alignment on the interval's contig
+/// is passed.
+pub fn for_each_record<F>(bam: &Path, intervals: &[Interval], mut f: F) -> Result<()>
+where
+    F: FnMut(usize, &bam::Record) -> io::Result<()>,
+{
+    if let Ok(mut reader) = mity_util::open_indexed_bam(bam) {
+        let header = reader.read_header()?;
+        for (index, interval) in intervals.iter().enumerate() {
+            if interval.is_empty() {
+                continue;
+            }
+            let region = Region::new(
+                interval.contig.as_str(),
+                Position::try_from(interval.start + 1)?..=Position::try_from(interval.end)?,
+            );
+            let query = reader
+                .query(&header, &region)
+                .with_context(|| format!("Failed to query {} in {}", region, bam.display()))?;
+            for result in query {
+                f(index, &result?)?;
+            }
+        }
+        return Ok(());
+    }
+
+    debug!("No index found for {}, scanning the whole file", bam.display());
+    let mut reader = bam::io::reader::Builder
+        .build_from_path(bam)
+        .with_context(|| format!("Failed to open {}", bam.display()))?;
+    let header = reader.read_header()?;
+    let contig_ids: Vec<Option<usize>> = intervals
+        .iter()
+        .map(|interval| header.reference_sequences().get_index_of(interval.contig.as_bytes()))
+        .collect();
+
+    for result in reader.records() {
+        let record = result?;
+        let Some(id) = record.reference_sequence_id().transpose()? else {
+            continue;
+        };
+        for (index, contig_id) in contig_ids.iter().enumerate() {
+            if *contig_id == Some(id) {
+                f(index, &record)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add one alignment to `depth`, a window starting at 0-based `window_start`.
+/// Bases outside the window are ignored.
+fn add_record(
+    record: &bam::Record,
+    window_start: usize,
+    depth: &mut [u32],
+    min_mq: u32,
+    min_bq: u32,
+) -> io::Result<()> {
+    let flags = record.flags();
+    if flags.is_unmapped()
+        || flags.is_secondary()
+        || flags.is_supplementary()
+        || flags.is_duplicate()
+        || flags.is_qc_fail()
+    {
+        return Ok(());
+    }
+    let mapping_quality = record.mapping_quality().map(|mq| mq.get()).unwrap_or(0);
+    if u32::from(mapping_quality) < min_mq {
+        return Ok(());
+    }
+    let Some(start) = record.alignment_start().transpose()? else {
+        return Ok(());
+    };
+
+    let quality_scores = record.quality_scores();
+    let quality_scores = quality_scores.as_ref();
+    let mut reference_position = usize::from(start) - 1;
+    let mut read_position = 0;
+
+    for op in record.cigar().iter() {
+        let op = op?;
+        match op.kind() {
+            Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
+                for offset in 0..op.len() {
+                    let base_quality = quality_scores
+                        .get(read_position + offset)
+                        .copied()
+                        .unwrap_or(u8::MAX);
+                    if u32::from(base_quality) < min_bq {
+                        continue;
+                    }
+                    let position = reference_position + offset;
+                    if let Some(d) = position
+                        .checked_sub(window_start)
+                        .and_then(|index| depth.get_mut(index))
+                    {
+                        *d += 1;
+                    }
+                }
+                reference_position += op.len();
+                read_position += op.len();
+            }
+            Kind::Insertion | Kind::SoftClip => read_position += op.len(),
+            Kind::Deletion | Kind::Skip => reference_position += op.len(),
+            Kind::HardClip | Kind::Pad => {}
+        }
+    }
+    Ok(())
+}
+
+/// Summary statistics of a per-base depth track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthSummary {
+    pub mean: f64,
+    pub median: f64,
+    pub min: u32,
+    pub max: u32,
+    pub fraction_100x: f64,
+    pub fraction_500x: f64,
+    pub fraction_1000x: f64,
+}
+
+impl DepthSummary {
+    pub fn new(depth: &[u32]) -> Self {
+        if depth.is_empty() {
+            return DepthSummary {
+                mean: 0.0,
+                median: 0.0,
+                min: 0,
+                max: 0,
+                fraction_100x: 0.0,
+                fraction_500x: 0.0,
+                fraction_1000x: 0.0,
+            };
+        }
+
+        let mut sorted = depth.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] as f64 + sorted[n / 2] as f64) / 2.0
+        } else {
+            sorted[n / 2] as f64
+        };
+        let fraction_at_least =
+            |min: u32| depth.iter().filter(|d| **d >= min).count() as f64 / n as f64;
+
+        DepthSummary {
+            mean: depth.iter().map(|d| *d as f64).sum::<f64>() / n as f64,
+            median,
+            min: sorted[0],
+            max: sorted[n - 1],
+            fraction_100x: fraction_at_least(100),
+            fraction_500x: fraction_at_least(500),
+            fraction_1000x: fraction_at_least(1000),
+        }
+    }
+}
+
+/// Runs of equal depth as 0-based, half-open `(start, end, depth)` intervals,
+/// the layout of a bedgraph.
+pub fn depth_runs(depth: &[u32]) -> Vec<(usize, usize, u32)> {
+    let mut runs: Vec<(usize, usize, u32)> = Vec::new();
+    for (position, d) in depth.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.2 == *d => run.1 = position + 1,
+            _ => runs.push((position, position + 1, *d)),
+        }
+    }
+    runs
+}
+
+/// Maximal 0-based, half-open intervals whose depth is below `min_depth`.
+pub fn low_depth_regions(depth: &[u32], min_depth: u32) -> Vec<(usize, usize)> {
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for (position, d) in depth.iter().enumerate() {
+        if *d >= min_depth {
+            continue;
+        }
+        match regions.last_mut() {
+            Some(region) if region.1 == position => region.1 = position + 1,
+            _ => regions.push((position, position + 1)),
+        }
+    }
+    regions
+}
@@ -0,0 +1,588 @@
+use log::{debug, info};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use std::error::Error;
+use std::f64::consts::{FRAC_PI_2, TAU};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Length used for the x axis when the VCF header has no contig length.
+const DEFAULT_MT_LENGTH: u64 = 16569;
+
+/// Colours cycled through for the samples of the overlay figure.
+const SAMPLE_COLOURS: [RGBColor; 6] = [
+    RGBColor(31, 119, 180),
+    RGBColor(255, 127, 14),
+    RGBColor(44, 160, 44),
+    RGBColor(214, 39, 40),
+    RGBColor(148, 103, 189),
+    RGBColor(140, 86, 75),
+];
+const TIER1_COLOUR: RGBColor = RGBColor(27, 120, 55);
+const TIER2_COLOUR: RGBColor = RGBColor(66, 133, 244);
+const FILTERED_COLOUR: RGBColor = RGBColor(170, 170, 170);
+/// Side of the square circular genome map, in pixels.
+const GENOME_MAP_SIZE: u32 = 900;
+/// Spacing of the position scale of the genome map, in bases.
+const GENOME_MAP_SCALE_STEP: u64 = 1000;
+
+/// A bedgraph interval: 0-based start, end and depth.
+pub type DepthInterval = (u64, u64, f64);
+
+/// A variant as drawn for one sample.
+struct PlotVariant {
+    pos: u64,
+    /// `REF>ALT`, for labels.
+    change: String,
+    vaf: f64,
+    passed: bool,
+    tier: Option<u32>,
+}
+
+/// A gene or other feature from the bundled gene map, 0-based half-open.
+pub struct GeneSpan {
+    pub start: u64,
+    pub end: u64,
+    pub name: String,
+    pub biotype: String,
+}
+
+/// Per-sample coverage and heteroplasmy figures of a normalised VCF.
+pub struct Plot {
+    debug: bool,
+    vcf: PathBuf,
+    bedgraphs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    prefix: Option<String>,
+    format: String,
+    width: u32,
+    height: u32,
+    min_vaf: f64,
+    genome_map: bool,
+    map_label_vaf: f64,
+}
+
+impl Plot {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        debug: bool,
+        vcf: PathBuf,
+        bedgraphs: Vec<PathBuf>,
+        output_dir: PathBuf,
+        prefix: Option<String>,
+        format: String,
+        width: u32,
+        height: u32,
+        min_vaf: f64,
+        genome_map: bool,
+        map_label_vaf: f64,
+    ) -> Self {
+        Plot {
+            debug,
+            vcf,
+            bedgraphs,
+            output_dir,
+            prefix,
+            format,
+            width,
+            height,
+            min_vaf,
+            genome_map,
+            map_label_vaf,
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+
+        let (contig, length, samples, variants) = self.read_variants()?;
+        if !self.bedgraphs.is_empty() && self.bedgraphs.len() != samples.len() {
+            return Err(format!(
+                "Got {} bedgraph(s) for {} sample(s); pass one per sample, in VCF sample order",
+                self.bedgraphs.len(),
+                samples.len()
+            )
+            .into());
+        }
+        let genes = load_gene_map(&contig).unwrap_or_else(|e| {
+            info!("No gene map drawn: {}", e);
+            Vec::new()
+        });
+
+        let prefix = self
+            .prefix
+            .clone()
+            .unwrap_or_else(|| mity_util::make_prefix(&self.vcf));
+
+        for (index, sample) in samples.iter().enumerate() {
+            let coverage = match self.bedgraphs.get(index) {
+                Some(path) => Some(read_bedgraph(path)?),
+                None => None,
+            };
+            let path = self.output_dir.join(format!(
+                "{}.{}.mity.plot.{}",
+                prefix, sample, self.format
+            ));
+            let series = [(sample.clone(), &variants[index])];
+            self.draw(&path, sample, length, &series, coverage.as_deref(), &genes)?;
+            info!("Wrote {}", path.display());
+        }
+
+        if samples.len() > 1 {
+            let path = self
+                .output_dir
+                .join(format!("{}.mity.plot.{}", prefix, self.format));
+            let series: Vec<(String, &Vec<PlotVariant>)> =
+                samples.iter().cloned().zip(variants.iter()).collect();
+            self.draw(&path, "all samples", length, &series, None, &genes)?;
+            info!("Wrote {}", path.display());
+        }
+
+        if self.genome_map {
+            for (sample, variants) in samples.iter().zip(&variants) {
+                let path = self
+                    .output_dir
+                    .join(format!("{}.{}.mity.map.svg", prefix, sample));
+                let root = SVGBackend::new(&path, (GENOME_MAP_SIZE, GENOME_MAP_SIZE)).into_drawing_area();
+                draw_genome_map(&root, sample, length, variants, &genes, self.map_label_vaf)?;
+                root.present()?;
+                info!("Wrote {}", path.display());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The VCF's MT contig and length, its samples, and for each sample the
+    /// variants with a VAF of at least `min_vaf`.
+    #[allow(clippy::type_complexity)]
+    fn read_variants(
+        &self,
+    ) -> Result<(String, u64, Vec<String>, Vec<Vec<PlotVariant>>), Box<dyn Error>> {
+        let reader = mity_util::open_vcf(&self.vcf)?;
+        let mut contig: Option<String> = None;
+        let mut length = DEFAULT_MT_LENGTH;
+        let mut samples: Vec<String> = Vec::new();
+        let mut variants: Vec<Vec<PlotVariant>> = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(rest) = line.strip_prefix("##contig=<ID=") {
+                let id = rest.split([',', '>']).next().unwrap_or_default();
+                if id == "MT" || id == "chrM" {
+                    contig = Some(id.to_string());
+                    if let Some(value) = rest.split("length=").nth(1) {
+                        length = value
+                            .split([',', '>'])
+                            .next()
+                            .and_then(|l| l.parse().ok())
+                            .unwrap_or(length);
+                    }
+                }
+                continue;
+            }
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(String::from).collect();
+                variants = samples.iter().map(|_| Vec::new()).collect();
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let record = VcfRecord::parse(&line)?;
+            contig.get_or_insert_with(|| record.chrom.clone());
+            let passed = record.filter == "PASS" || record.filter == ".";
+            for (sample, sample_variants) in variants.iter_mut().enumerate() {
+                let Some(vaf) = record.sample_vaf(sample) else {
+                    continue;
+                };
+                if vaf < self.min_vaf || vaf <= 0.0 {
+                    continue;
+                }
+                sample_variants.push(PlotVariant {
+                    pos: record.pos,
+                    change: format!("{}>{}", record.reference, record.alt),
+                    vaf,
+                    passed,
+                    tier: record
+                        .sample(sample, "tier")
+                        .and_then(|tier| tier.parse().ok()),
+                });
+            }
+        }
+
+        if samples.is_empty() {
+            return Err(format!("{} has no samples to plot", self.vcf.display()).into());
+        }
+        Ok((contig.unwrap_or_else(|| "MT".to_string()), length, samples, variants))
+    }
+
+    fn draw(
+        &self,
+        path: &Path,
+        title: &str,
+        length: u64,
+        series: &[(String, &Vec<PlotVariant>)],
+        coverage: Option<&[DepthInterval]>,
+        genes: &[GeneSpan],
+    ) -> Result<(), Box<dyn Error>> {
+        let size = (self.width, self.height);
+        match self.format.as_str() {
+            "svg" => {
+                let root = SVGBackend::new(path, size).into_drawing_area();
+                draw_figure(&root, title, length, series, coverage, genes)?;
+                root.present()?;
+            }
+            "png" => {
+                let root = BitMapBackend::new(path, size).into_drawing_area();
+                draw_figure(&root, title, length, series, coverage, genes)?;
+                root.present()?;
+            }
+            other => return Err(format!("Unsupported plot format: {}", other).into()),
+        }
+        Ok(())
+    }
+}
+
+/// Draw the coverage track (if any), the variant lollipops and the gene map
+/// stacked on a shared MT position axis.
+fn draw_figure<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    length: u64,
+    series: &[(String, &Vec<PlotVariant>)],
+    coverage: Option<&[DepthInterval]>,
+    genes: &[GeneSpan],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let root = root.titled(title, ("sans-serif", 20))?;
+    let height = root.dim_in_pixel().1 as i32;
+
+    let (upper, gene_area) = root.split_vertically(height - 60);
+    let (coverage_area, variant_area) = match coverage {
+        Some(_) => {
+            let (top, bottom) = upper.split_vertically((height - 60) / 3);
+            (Some(top), bottom)
+        }
+        None => (None, upper),
+    };
+
+    if let (Some(area), Some(coverage)) = (coverage_area, coverage) {
+        let max_depth = coverage
+            .iter()
+            .map(|(_, _, depth)| *depth)
+            .fold(1.0, f64::max);
+        let mut chart = ChartBuilder::on(&area)
+            .margin(5)
+            .x_label_area_size(0)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0u64..length, 0.0..max_depth * 1.05)?;
+        chart.configure_mesh().disable_x_mesh().y_desc("Depth").draw()?;
+        chart.draw_series(AreaSeries::new(
+            coverage
+                .iter()
+                .flat_map(|(start, end, depth)| [(*start, *depth), (*end, *depth)]),
+            0.0,
+            BLUE.mix(0.3),
+        ))?;
+    }
+
+    let mut chart = ChartBuilder::on(&variant_area)
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0u64..length, 0.0..1.05)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_desc("Position")
+        .y_desc("VAF")
+        .draw()?;
+
+    let overlay = series.len() > 1;
+    for (index, (name, variants)) in series.iter().enumerate() {
+        let colour_of = |variant: &PlotVariant| -> RGBColor {
+            if overlay {
+                SAMPLE_COLOURS[index % SAMPLE_COLOURS.len()]
+            } else {
+                variant_colour(variant)
+            }
+        };
+        chart.draw_series(variants.iter().map(|variant| {
+            PathElement::new(
+                vec![(variant.pos, 0.0), (variant.pos, variant.vaf)],
+                colour_of(variant),
+            )
+        }))?;
+        let points = chart.draw_series(variants.iter().map(|variant| {
+            Circle::new((variant.pos, variant.vaf), 3, colour_of(variant).filled())
+        }))?;
+        if overlay {
+            let colour = SAMPLE_COLOURS[index % SAMPLE_COLOURS.len()];
+            points
+                .label(name.as_str())
+                .legend(move |(x, y)| Circle::new((x, y), 4, colour.filled()));
+        }
+    }
+    if overlay {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    let mut genes_chart = ChartBuilder::on(&gene_area)
+        .margin_left(65)
+        .margin_right(5)
+        .build_cartesian_2d(0u64..length, 0.0..1.0)?;
+    for (index, gene) in genes.iter().enumerate() {
+        let colour = biotype_colour(&gene.biotype);
+        // Alternate rows so neighbouring features stay distinguishable
+        let (low, high) = if index % 2 == 0 { (0.5, 0.9) } else { (0.1, 0.5) };
+        genes_chart.draw_series(std::iter::once(Rectangle::new(
+            [(gene.start, low), (gene.end, high)],
+            colour.filled(),
+        )))?;
+        if gene.end - gene.start > length / 60 {
+            genes_chart.draw_series(std::iter::once(Text::new(
+                gene.name.clone(),
+                (gene.start, high),
+                ("sans-serif", 10),
+            )))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw a circular map of the MT genome for one sample: gene arcs, a tick
+/// per variant pointing outwards with its length scaled by VAF, labels for
+/// variants with a VAF of at least `label_vaf`, and `title` in the centre.
+/// Position 1 is at the top and positions increase clockwise.
+fn draw_genome_map<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    length: u64,
+    variants: &[PlotVariant],
+    genes: &[GeneSpan],
+    label_vaf: f64,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let (width, height) = root.dim_in_pixel();
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let size = width.min(height) as f64;
+    let point = |pos: f64, radius: f64| -> (i32, i32) {
+        let angle = TAU * pos / length as f64 - FRAC_PI_2;
+        (
+            (cx + radius * angle.cos()).round() as i32,
+            (cy + radius * angle.sin()).round() as i32,
+        )
+    };
+    let centred = |style: TextStyle<'static>| style.pos(Pos::new(HPos::Center, VPos::Center));
+
+    // Position scale
+    let scale_radius = size * 0.17;
+    root.draw(&Circle::new(point(0.0, 0.0), scale_radius as i32, BLACK.stroke_width(1)))?;
+    for pos in (0..length).step_by(GENOME_MAP_SCALE_STEP as usize) {
+        root.draw(&PathElement::new(
+            vec![point(pos as f64, scale_radius), point(pos as f64, scale_radius - 6.0)],
+            BLACK,
+        ))?;
+        root.draw(&Text::new(
+            format!("{}k", pos / 1000),
+            point(pos as f64, scale_radius - 18.0),
+            centred(("sans-serif", 11).into()),
+        ))?;
+    }
+
+    // Gene arcs, in alternating rings like the rows of the linear map
+    let (gene_inner, gene_middle, gene_outer) = (size * 0.24, size * 0.27, size * 0.30);
+    for (index, gene) in genes.iter().enumerate() {
+        let (inner, outer) = if index % 2 == 0 { (gene_middle, gene_outer) } else { (gene_inner, gene_middle) };
+        let steps = ((gene.end - gene.start) * 360 / length).max(1);
+        let at = |step: u64| gene.start as f64 + (gene.end - gene.start) as f64 * step as f64 / steps as f64;
+        let mut arc: Vec<(i32, i32)> = (0..=steps).map(|step| point(at(step), outer)).collect();
+        arc.extend((0..=steps).rev().map(|step| point(at(step), inner)));
+        root.draw(&Polygon::new(arc, biotype_colour(&gene.biotype).filled()))?;
+        if gene.end - gene.start > length / 60 {
+            root.draw(&Text::new(
+                gene.name.clone(),
+                point((gene.start + gene.end) as f64 / 2.0, gene_inner - 14.0),
+                centred(("sans-serif", 10).into()),
+            ))?;
+        }
+    }
+
+    // Variant ticks and labels
+    let (tick_base, tick_length) = (size * 0.31, size * 0.07);
+    for variant in variants {
+        let tip = tick_base + tick_length * variant.vaf.min(1.0);
+        let colour = variant_colour(variant);
+        root.draw(&PathElement::new(
+            vec![point(variant.pos as f64, tick_base), point(variant.pos as f64, tip)],
+            colour.stroke_width(2),
+        ))?;
+        if variant.vaf >= label_vaf {
+            let (x, _) = point(variant.pos as f64, tip);
+            let anchor = if (x as f64) < cx { HPos::Right } else { HPos::Left };
+            root.draw(&Text::new(
+                format!("m.{}{}", variant.pos, variant.change),
+                point(variant.pos as f64, tip + 8.0),
+                TextStyle::from(("sans-serif", 11).into_font())
+                    .color(&colour)
+                    .pos(Pos::new(anchor, VPos::Center)),
+            ))?;
+        }
+    }
+
+    root.draw(&Text::new(title.to_string(), point(0.0, 0.0), centred(("sans-serif", 24).into())))?;
+
+    let legend = [("Tier 1", TIER1_COLOUR), ("Tier 2", TIER2_COLOUR), ("Filtered", FILTERED_COLOUR)];
+    for (index, (name, colour)) in legend.iter().enumerate() {
+        let y = height as i32 - 20 * (legend.len() - index) as i32;
+        root.draw(&Circle::new((20, y), 5, colour.filled()))?;
+        root.draw(&Text::new(
+            name.to_string(),
+            (32, y),
+            TextStyle::from(("sans-serif", 12).into_font()).pos(Pos::new(HPos::Left, VPos::Center)),
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Colour of a variant of a single sample: grey if filtered, otherwise by tier.
+fn variant_colour(variant: &PlotVariant) -> RGBColor {
+    if !variant.passed {
+        FILTERED_COLOUR
+    } else if variant.tier == Some(1) {
+        TIER1_COLOUR
+    } else {
+        TIER2_COLOUR
+    }
+}
+
+fn biotype_colour(biotype: &str) -> RGBColor {
+    match biotype {
+        "protein_coding" => RGBColor(120, 160, 200),
+        "Mt_tRNA" => RGBColor(230, 170, 90),
+        "Mt_rRNA" => RGBColor(150, 200, 120),
+        _ => RGBColor(200, 200, 200),
+    }
+}
+
+/// Read a bedgraph of `contig start end depth`, such as the output of
+/// `mity coverage`.
+pub fn read_bedgraph(path: &Path) -> Result<Vec<DepthInterval>, Box<dyn Error>> {
+    let reader = mity_util::open_vcf(path)?;
+    let mut intervals = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            return Err(format!("Malformed bedgraph line in {}: {}", path.display(), line).into());
+        }
+        intervals.push((fields[1].parse()?, fields[2].parse()?, fields[3].parse()?));
+    }
+    Ok(intervals)
+}
+
+/// Genes and other features of the bundled annotation, merged from the
+/// per-base BED into one span per feature.
+pub fn load_gene_map(contig: &str) -> Result<Vec<GeneSpan>, Box<dyn Error>> {
+    let file = match contig {
+        "MT" => "annot_mt/gtf_annotations.bed.gz",
+        "chrM" => "annot_chrm/chrm_gtf_annotations.bed.gz",
+        _ => return Err(format!("no bundled gene map for contig {}", contig).into()),
+    };
+    let reader = mity_util::open_vcf(&mity_util::get_mity_dir()?.join(file))?;
+
+    let mut genes: Vec<GeneSpan> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let start: u64 = fields[1].parse()?;
+        let end: u64 = fields[2].parse()?;
+        let name = fields[3].trim_matches('"');
+        let biotype = fields[4].trim_matches('"');
+        match genes.last_mut() {
+            Some(gene) if gene.name == name && gene.end >= start => gene.end = gene.end.max(end),
+            _ => genes.push(GeneSpan {
+                start,
+                end,
+                name: name.to_string(),
+                biotype: biotype.to_string(),
+            }),
+        }
+    }
+    Ok(genes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn plot(dir: &Path, vcf: PathBuf, bedgraphs: Vec<PathBuf>, min_vaf: f64) -> Plot {
+        Plot::new(false, vcf, bedgraphs, dir.to_path_buf(), None, "svg".to_string(), 800, 400, min_vaf, true, 0.5)
+    }
+
+    #[test]
+    fn samples_are_plotted_with_their_variants_over_min_vaf() {
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("trio.normalise.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n##contig=<ID=MT,length=16569>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\tB\n\
+             MT\t73\t.\tA\tG\t.\tPASS\t.\tVAF:tier\t0.9:1\t0.05:.\n\
+             MT\t3243\t.\tA\tG\t.\tLOW_QUAL\t.\tAO:DP\t3:10\t0:10\n",
+        )
+        .unwrap();
+
+        let all = plot(temp.path(), vcf.clone(), Vec::new(), 0.1);
+        let (contig, length, samples, variants) = all.read_variants().unwrap();
+        assert_eq!((contig.as_str(), length), ("MT", 16569));
+        assert_eq!(samples, ["A", "B"]);
+        let a: Vec<(u64, &str, bool, Option<u32>)> = variants[0]
+            .iter()
+            .map(|v| (v.pos, v.change.as_str(), v.passed, v.tier))
+            .collect();
+        assert_eq!(a, [(73, "A>G", true, Some(1)), (3243, "A>G", false, None)]);
+        assert!(variants[1].is_empty(), "B's variants are under min_vaf or absent");
+        assert_eq!(variant_colour(&variants[0][0]), TIER1_COLOUR);
+        assert_eq!(variant_colour(&variants[0][1]), FILTERED_COLOUR);
+
+        // One bedgraph per sample, or none
+        let bedgraph = temp.path().join("A.bedgraph");
+        fs::write(&bedgraph, "track type=bedGraph\nMT\t0\t100\t3\nMT\t100\t16569\t0\n").unwrap();
+        assert_eq!(read_bedgraph(&bedgraph).unwrap(), [(0, 100, 3.0), (100, 16569, 0.0)]);
+        let err = plot(temp.path(), vcf.clone(), vec![bedgraph.clone()], 0.1).run().unwrap_err();
+        assert!(err.to_string().contains("1 bedgraph(s) for 2 sample(s)"), "{}", err);
+
+        all.run().unwrap();
+        for name in ["A.mity.plot.svg", "B.mity.plot.svg", "mity.plot.svg", "A.mity.map.svg", "B.mity.map.svg"] {
+            assert!(temp.path().join(format!("trio.{}", name)).exists(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use log::{debug, info};
+use noodles::bgzf;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::checksum;
+use crate::error::MityError;
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Header line giving the number of normal samples a panel was built from.
+const SAMPLES_HEADER: &str = "##mityPonSamples=";
+
+/// The frequencies of a panel of normals written by `mity build-pon`, keyed
+/// by position and alleles. The panel is MT-only, so MT and chrM records
+/// match each other.
+#[derive(Clone, Debug, Default)]
+pub struct PanelOfNormals {
+    frequencies: HashMap<(u64, String, String), f64>,
+    samples: usize,
+}
+
+impl PanelOfNormals {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Err(MityError::MissingFile(path.to_path_buf()).into());
+        }
+        let mut panel = PanelOfNormals::default();
+        for line in mity_util::open_vcf(path)?.lines() {
+            let line = line?;
+            if let Some(samples) = line.strip_prefix(SAMPLES_HEADER) {
+                panel.samples = samples.trim().parse()?;
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let record = VcfRecord::parse(&line)?;
+            let Some(frequency) = record.info_f64("PON_FREQ") else {
+                return Err(format!(
+                    "{}: {}:{} has no INFO/PON_FREQ; build the panel with mity build-pon",
+                    path.display(),
+                    record.chrom,
+                    record.pos
+                )
+                .into());
+            };
+            panel
+                .frequencies
+                .insert((record.pos, record.reference, record.alt), frequency);
+        }
+        if panel.samples == 0 {
+            return Err(format!(
+                "{} has no {} header line; build the panel with mity build-pon",
+                path.display(),
+                SAMPLES_HEADER.trim_end_matches('=')
+            )
+            .into());
+        }
+        info!(
+            "Loaded {} variants from a panel of {} normals",
+            panel.frequencies.len(),
+            panel.samples
+        );
+        Ok(panel)
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The fraction of the panel's samples carrying a single ALT allele, if
+    /// any do.
+    pub fn frequency(&self, pos: u64, reference: &str, alt: &str) -> Option<f64> {
+        self.frequencies
+            .get(&(pos, reference.to_string(), alt.to_string()))
+            .copied()
+    }
+}
+
+/// Builds a panel of normals from normalised VCFs of normal samples, with
+/// the number of samples carrying each variant and their median VAF.
+pub struct BuildPon {
+    debug: bool,
+    files: Vec<PathBuf>,
+    pon_path: PathBuf,
+}
+
+impl BuildPon {
+    pub fn new(debug: bool, files: Vec<PathBuf>, output_dir: PathBuf, prefix: Option<String>) -> Self {
+        let prefix = prefix.unwrap_or_else(|| "pon".to_string());
+        BuildPon {
+            debug,
+            files,
+            pon_path: output_dir.join(format!("{}.mity.pon.vcf.gz", prefix)),
+        }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+
+        let mut carriers: BTreeMap<(u64, String, String), Vec<f64>> = BTreeMap::new();
+        let mut samples = 0;
+        let mut contig: Option<String> = None;
+        let mut contig_line: Option<String> = None;
+        for path in &self.files {
+            if !path.exists() {
+                return Err(MityError::MissingFile(path.clone()).into());
+            }
+            let mut sample_count = None;
+            for line in mity_util::open_vcf(path)?.lines() {
+                let line = line?;
+                if let Some(rest) = line.strip_prefix("##contig=<ID=") {
+                    let id = rest.split([',', '>']).next().unwrap_or_default();
+                    if contig_line.is_none() && mity_util::is_mt_contig(id) {
+                        contig_line = Some(line.clone());
+                    }
+                    continue;
+                }
+                if line.starts_with("#CHROM") {
+                    sample_count = Some(line.split('\t').skip(9).count());
+                    continue;
+                }
+                if line.starts_with('#') || line.is_empty() {
+                    continue;
+                }
+
+                let record = VcfRecord::parse(&line)?;
+                if !mity_util::is_mt_contig(&record.chrom) {
+                    continue;
+                }
+                contig.get_or_insert_with(|| record.chrom.clone());
+                for (allele, alt) in record.alt.split(',').enumerate() {
+                    for sample in 0..record.samples.len() {
+                        let Some(vaf) = allele_vaf(&record, sample, allele).filter(|vaf| *vaf > 0.0) else {
+                            continue;
+                        };
+                        carriers
+                            .entry((record.pos, record.reference.clone(), alt.to_string()))
+                            .or_default()
+                            .push(vaf);
+                    }
+                }
+            }
+            let sample_count =
+                sample_count.ok_or_else(|| format!("{} has no #CHROM header line", path.display()))?;
+            debug!("{}: {} samples", path.display(), sample_count);
+            samples += sample_count;
+        }
+        if samples == 0 {
+            return Err("The input VCFs have no samples to build a panel of normals from".into());
+        }
+        let contig = contig.unwrap_or_else(|| "MT".to_string());
+
+        let mut writer = bgzf::Writer::new(checksum::OutputFile::create(&self.pon_path)?);
+        writeln!(writer, "##fileformat=VCFv4.2")?;
+        writeln!(writer, "{}{}", SAMPLES_HEADER, samples)?;
+        let files: Vec<String> = self.files.iter().map(|file| file.display().to_string()).collect();
+        writeln!(writer, "##mityCommandline=\"mity build-pon {}\"", files.join(" "))?;
+        if let Some(contig_line) = contig_line {
+            writeln!(writer, "{}", contig_line)?;
+        }
+        writeln!(writer, "##INFO=<ID=PON_COUNT,Number=1,Type=Integer,Description=\"Normal samples carrying the variant\">")?;
+        writeln!(writer, "##INFO=<ID=PON_FREQ,Number=1,Type=Float,Description=\"Fraction of the {} normal samples carrying the variant\">", samples)?;
+        writeln!(writer, "##INFO=<ID=PON_MEDIAN_VAF,Number=1,Type=Float,Description=\"Median VAF in the normal samples carrying the variant\">")?;
+        writeln!(writer, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+        for ((pos, reference, alt), vafs) in carriers.iter_mut() {
+            vafs.sort_by(f64::total_cmp);
+            let middle = vafs.len() / 2;
+            let median = if vafs.len() % 2 == 0 {
+                (vafs[middle - 1] + vafs[middle]) / 2.0
+            } else {
+                vafs[middle]
+            };
+            writeln!(
+                writer,
+                "{}\t{}\t.\t{}\t{}\t.\t.\tPON_COUNT={};PON_FREQ={:.4};PON_MEDIAN_VAF={:.4}",
+                contig,
+                pos,
+                reference,
+                alt,
+                vafs.len(),
+                vafs.len() as f64 / samples as f64,
+                median
+            )?;
+        }
+        writer.finish()?.finish()?;
+        mity_util::tabix(&self.pon_path)?;
+
+        info!(
+            "{} variants from {} normal samples in {} VCFs",
+            carriers.len(),
+            samples,
+            self.files.len()
+        );
+        info!("Wrote {}", self.pon_path.display());
+        Ok(())
+    }
+}
+
+/// VAF of one ALT allele in a sample: the allele's FORMAT/VAF value, or its
+/// AO over DP.
+fn allele_vaf(record: &VcfRecord, sample: usize, allele: usize) -> Option<f64> {
+    let nth = |key: &str| -> Option<f64> { record.sample(sample, key)?.split(',').nth(allele)?.parse().ok() };
+    if let Some(vaf) = nth("VAF") {
+        return Some(vaf);
+    }
+    let ao = nth("AO")?;
+    let dp = record.sample_f64(sample, "DP")?;
+    (dp > 0.0).then(|| ao / dp)
+}
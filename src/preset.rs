@@ -0,0 +1,85 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A set of `mity call` defaults for a kind of sequencing. Options given on
+/// the command line or in a config file override the preset's values one by
+/// one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preset {
+    /// Illumina short reads, the defaults of python mity.
+    #[default]
+    ShortRead,
+    /// ONT and PacBio reads: base qualities are low and errors are mostly
+    /// indels in homopolymers, so it trusts lower-quality bases but wants
+    /// more of them.
+    LongRead,
+}
+
+impl Preset {
+    pub const NAMES: [&'static str; 2] = ["short-read", "long-read"];
+
+    pub fn min_mq(&self) -> u32 {
+        30
+    }
+
+    pub fn min_bq(&self) -> u32 {
+        match self {
+            Preset::ShortRead => 24,
+            Preset::LongRead => 10,
+        }
+    }
+
+    pub fn min_af(&self) -> f32 {
+        match self {
+            Preset::ShortRead => 0.01,
+            Preset::LongRead => 0.05,
+        }
+    }
+
+    pub fn min_ac(&self) -> u32 {
+        match self {
+            Preset::ShortRead => 4,
+            Preset::LongRead => 10,
+        }
+    }
+
+    /// The noise level QUAL is calculated with.
+    pub fn p(&self) -> f32 {
+        match self {
+            Preset::ShortRead => 0.002,
+            Preset::LongRead => 0.01,
+        }
+    }
+
+    /// freebayes options beyond the ones mity sets from its own: for long
+    /// reads, haplotypes are built across interrupted repeats so indels in
+    /// them are called once, and only the best-supported alleles of a site
+    /// are evaluated, as noisy reads show many spurious ones.
+    pub fn freebayes_args(&self) -> &'static [&'static str] {
+        match self {
+            Preset::ShortRead => &[],
+            Preset::LongRead => &["--min-repeat-entropy", "1", "--use-best-n-alleles", "4"],
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Preset::ShortRead => "short-read",
+            Preset::LongRead => "long-read",
+        })
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "short-read" => Ok(Preset::ShortRead),
+            "long-read" => Ok(Preset::LongRead),
+            _ => Err(format!("unknown preset '{}', expected one of {}", s, Preset::NAMES.join(", "))),
+        }
+    }
+}
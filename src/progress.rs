@@ -0,0 +1,118 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How progress is shown: a spinner on a terminal, log lines otherwise.
+enum Mode {
+    Off,
+    Lines { done: u64, total: u64 },
+    Bar(ProgressBar),
+}
+
+static MODE: Mutex<Mode> = Mutex::new(Mode::Off);
+
+const STAGE_TEMPLATE: &str = "{spinner} {msg} ({elapsed})";
+const CHUNK_TEMPLATE: &str = "{spinner} {msg} [{bar:30}] {pos}/{len} ({elapsed})";
+
+/// Shows progress until dropped; see [`start`].
+pub struct Progress;
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let mode = std::mem::replace(&mut *MODE.lock().unwrap(), Mode::Off);
+        if let Mode::Bar(bar) = mode {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Start showing the stages of a run: a spinner when stderr is a terminal,
+/// otherwise a log line per stage, so redirected logs stay plain. Nothing is
+/// shown when `enabled` is false.
+pub fn start(enabled: bool) -> Progress {
+    let mode = if !enabled {
+        Mode::Off
+    } else if io::stderr().is_terminal() {
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+        bar.set_style(style(STAGE_TEMPLATE));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        Mode::Bar(bar)
+    } else {
+        Mode::Lines { done: 0, total: 0 }
+    };
+    *MODE.lock().unwrap() = mode;
+    Progress
+}
+
+/// Show that `name` is running.
+pub fn stage(name: &str) {
+    // Log once the lock is released, as the logger takes it too
+    let log_line = match &mut *MODE.lock().unwrap() {
+        Mode::Off => false,
+        Mode::Lines { .. } => true,
+        Mode::Bar(bar) => {
+            bar.set_style(style(STAGE_TEMPLATE));
+            bar.unset_length();
+            bar.set_position(0);
+            bar.set_message(name.to_string());
+            false
+        }
+    };
+    if log_line {
+        info!("Stage: {}", name);
+    }
+}
+
+/// Count the chunks of the current stage, `total` in all.
+pub fn chunks(total: u64) {
+    match &mut *MODE.lock().unwrap() {
+        Mode::Off => {}
+        Mode::Lines { done, total: all } => {
+            *done = 0;
+            *all = total;
+        }
+        Mode::Bar(bar) => {
+            bar.set_style(style(CHUNK_TEMPLATE));
+            bar.set_length(total);
+            bar.set_position(0);
+        }
+    }
+}
+
+/// Mark the chunk `name` of the current stage done.
+pub fn chunk_done(name: &str) {
+    let count = match &mut *MODE.lock().unwrap() {
+        Mode::Off => None,
+        Mode::Lines { done, total } => {
+            *done += 1;
+            Some((*done, *total))
+        }
+        Mode::Bar(bar) => {
+            bar.inc(1);
+            None
+        }
+    };
+    if let Some((done, total)) = count {
+        info!("Finished {} ({}/{})", name, done, total);
+    }
+}
+
+/// Run `f`, which writes to stderr, with the spinner hidden so the two do
+/// not garble each other.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    // Clone the bar so logging from another thread does not wait on `f`
+    let bar = match &*MODE.lock().unwrap() {
+        Mode::Bar(bar) => Some(bar.clone()),
+        _ => None,
+    };
+    match bar {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).expect("valid progress template")
+}
@@ -0,0 +1,443 @@
+use log::debug;
+use noodles::bam;
+use noodles::core::region::Interval as RegionInterval;
+use noodles::core::Position;
+use noodles::sam::alignment::io::Write as _;
+use noodles::sam::alignment::record::data::field::Tag;
+use noodles::sam::alignment::record::cigar::op::Kind;
+use noodles::sam::alignment::record::cigar::Op;
+use noodles::sam::alignment::record::Flags;
+use noodles::sam::alignment::Record as _;
+use noodles::sam::alignment::RecordBuf;
+use noodles::sam::Header;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::MityError;
+use crate::mity_util::{self, Interval, Tool};
+
+/// How far past a NUMT a mate can map and still come from it: about the
+/// longest insert of a short-read library.
+const NUMT_FLANK: usize = 1000;
+
+/// The mate mapping quality (MQ tag) at or above which a mate on a nuclear
+/// contig places its pair in the nuclear genome.
+const NUMT_MATE_MQ: i64 = 20;
+
+/// The alignments left out of calling, by SAM flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadFilter {
+    pub duplicates: bool,
+    pub secondary: bool,
+    pub supplementary: bool,
+    /// Bases at each end of the aligned part of a read whose base qualities
+    /// are set to 0, so they add no allele observations.
+    pub end_exclusion: u32,
+}
+
+impl ReadFilter {
+    /// The SAM flags of the alignments left out, for `samtools view -F`.
+    pub fn sam_flags(&self) -> Flags {
+        let mut flags = Flags::empty();
+        for (excluded, flag) in [
+            (self.duplicates, Flags::DUPLICATE),
+            (self.secondary, Flags::SECONDARY),
+            (self.supplementary, Flags::SUPPLEMENTARY),
+        ] {
+            if excluded {
+                flags |= flag;
+            }
+        }
+        flags
+    }
+
+    /// Whether the inputs need filtered copies: freebayes leaves out
+    /// duplicates itself, but has no option for the other flags or for
+    /// ignoring read ends.
+    pub fn needs_copies(&self) -> bool {
+        self.secondary || self.supplementary || self.end_exclusion > 0
+    }
+
+    pub fn excludes(&self, flags: Flags) -> bool {
+        flags.intersects(self.sam_flags())
+    }
+
+    /// The header line recording the filter.
+    pub fn header_line(&self) -> String {
+        let state = |excluded: bool| if excluded { "excluded" } else { "included" };
+        format!(
+            "##mityReadFilter=\"duplicates={}, secondary={}, supplementary={} (SAM flags {:#x} left out), read_end_exclusion={}\"",
+            state(self.duplicates),
+            state(self.secondary),
+            state(self.supplementary),
+            self.sam_flags().bits(),
+            self.end_exclusion
+        )
+    }
+
+    /// Set the base qualities of the first and last `end_exclusion` aligned
+    /// bases of a read to 0. Soft-clipped bases are not aligned and count
+    /// for nothing already, so the ends are those of the aligned part.
+    fn mask_read_ends(&self, record: &mut RecordBuf) {
+        let n = self.end_exclusion as usize;
+        let ops = record.cigar().as_ref();
+        let clip = |op: Option<&Op>| match op {
+            Some(op) if op.kind() == Kind::SoftClip => op.len(),
+            _ => 0,
+        };
+        let unclipped = |op: &&Op| op.kind() != Kind::HardClip;
+        let leading = clip(ops.iter().find(unclipped));
+        let trailing = clip(ops.iter().rev().find(unclipped));
+        let scores: &mut Vec<u8> = record.quality_scores_mut().as_mut();
+        let aligned = leading..scores.len().saturating_sub(trailing);
+        if aligned.is_empty() {
+            return;
+        }
+        for (index, score) in scores[aligned.clone()].iter_mut().enumerate() {
+            if index < n || index + n >= aligned.len() {
+                *score = 0;
+            }
+        }
+    }
+}
+
+/// Pairs left out of calling as likely NUMT-derived: a read on the
+/// mitochondrial contig whose mate maps to a nuclear contig, inside or
+/// beside a known NUMT or with a confident mapping quality. A fragment from
+/// the mitochondrion itself has both reads on it.
+#[derive(Clone, Debug)]
+pub struct NumtFilter {
+    pub bed: PathBuf,
+    regions: Vec<Interval>,
+}
+
+impl NumtFilter {
+    pub fn from_bed(bed: &Path) -> anyhow::Result<Self> {
+        Ok(NumtFilter {
+            bed: bed.to_path_buf(),
+            regions: mity_util::read_bed(bed)?,
+        })
+    }
+
+    /// The NUMT BED bundled for a reference build.
+    pub fn bundled_bed(reference: &str) -> anyhow::Result<PathBuf> {
+        mity_util::get_annot_file(&format!("{}.numts.bed", reference))
+            .map_err(|e| anyhow::anyhow!("{}. Give the NUMTs of {} with --numt-bed", e, reference))
+    }
+
+    /// Whether a mate at 0-based `position` of `contig` is in or beside a
+    /// known NUMT.
+    fn near_numt(&self, contig: &str, position: usize) -> bool {
+        self.regions.iter().any(|region| {
+            region.contig == contig
+                && position + NUMT_FLANK >= region.start
+                && position < region.end + NUMT_FLANK
+        })
+    }
+
+    fn excludes(&self, header: &Header, record: &bam::Record, id: usize) -> io::Result<bool> {
+        let flags = record.flags();
+        if !flags.is_segmented() || flags.is_mate_unmapped() {
+            return Ok(false);
+        }
+        let Some(mate_id) = record.mate_reference_sequence_id().transpose()? else {
+            return Ok(false);
+        };
+        if mate_id == id {
+            return Ok(false);
+        }
+        let mate_mq = match record.data().get(&Tag::MATE_MAPPING_QUALITY).transpose()? {
+            Some(value) => value.as_int(),
+            None => None,
+        };
+        if mate_mq.is_some_and(|mq| mq >= NUMT_MATE_MQ) {
+            return Ok(true);
+        }
+        let contig = header
+            .reference_sequences()
+            .get_index(mate_id)
+            .map(|(name, _)| String::from_utf8_lossy(name).into_owned());
+        let position = record.mate_alignment_start().transpose()?.map(|start| usize::from(start) - 1);
+        Ok(match (contig, position) {
+            (Some(contig), Some(position)) => self.near_numt(&contig, position),
+            _ => false,
+        })
+    }
+
+    /// The header line recording the filter and how many reads it left out.
+    pub fn header_line(&self, removed: u64) -> String {
+        format!(
+            "##mityNumtFilter=\"{} reads with a mate on a nuclear contig, near a NUMT of {} or with MQ >= {}, left out\"",
+            removed,
+            self.bed.display(),
+            NUMT_MATE_MQ
+        )
+    }
+}
+
+/// The alignments a filtered copy left out, by reason.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Removed {
+    pub flagged: u64,
+    pub numt: u64,
+}
+
+/// Copy the alignments of a BAM or CRAM in `intervals` that pass `filter`
+/// and are not NUMT-like under `numts` to an indexed BAM at `output`,
+/// returning the numbers left out, which are unknown for CRAM. `reference`
+/// decodes CRAM; NUMT filtering reads BAM only.
+pub fn write_filtered_bam(
+    input: &Path,
+    intervals: &[Interval],
+    filter: ReadFilter,
+    numts: Option<&NumtFilter>,
+    reference: &Path,
+    output: &Path,
+) -> Result<Option<Removed>, Box<dyn Error>> {
+    let removed = if mity_util::is_cram(input) {
+        // noodles has no CRAM support here, so CRAM goes through samtools
+        let mut command = mity_util::tool_command(Tool::Samtools)?;
+        command
+            .arg("view")
+            .arg("-b")
+            .arg("-M")
+            .arg("-F")
+            .arg(filter.sam_flags().bits().to_string())
+            .arg("--reference")
+            .arg(reference)
+            .arg("-o")
+            .arg(output)
+            .arg(input)
+            .args(intervals.iter().map(Interval::to_region));
+        debug!("{:?}", command);
+        let result = command.output()?;
+        if !result.status.success() {
+            return Err(MityError::tool("samtools view", result.status.code(), &result.stderr).into());
+        }
+        None
+    } else {
+        Some(copy_bam(input, intervals, filter, numts, output)?)
+    };
+    mity_util::index_bam(output).map_err(crate::error::boxed)?;
+    Ok(removed)
+}
+
+/// Copy a BAM's alignments in `intervals`, in coordinate order, leaving out the
+/// ones `filter` or `numts` excludes. An alignment in two intervals is
+/// written once.
+fn copy_bam(
+    input: &Path,
+    intervals: &[Interval],
+    filter: ReadFilter,
+    numts: Option<&NumtFilter>,
+    output: &Path,
+) -> Result<Removed, Box<dyn Error>> {
+    let mut reader = mity_util::open_indexed_bam(input).map_err(crate::error::boxed)?;
+    let header = reader.read_header()?;
+    let mut targets = Vec::new();
+    for interval in intervals {
+        let id = header
+            .reference_sequences()
+            .get_index_of(interval.contig.as_bytes())
+            .ok_or_else(|| format!("{} has no contig {}", input.display(), interval.contig))?;
+        targets.push((id, interval));
+    }
+    // Sorted, so the copy stays coordinate-sorted
+    targets.sort_by_key(|(id, interval)| (*id, interval.start));
+
+    let mut writer = bam::io::Writer::new(File::create(output)?);
+    writer.write_header(&header)?;
+    let mut removed = Removed::default();
+    let mut record = bam::Record::default();
+    for (index, (id, interval)) in targets.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|i| targets[i]);
+        let region = RegionInterval::from(Position::try_from(interval.start + 1)?..=Position::try_from(interval.end)?);
+        for chunk in reader.index().query(*id, region)? {
+            reader.get_mut().seek(chunk.start())?;
+            while reader.get_ref().virtual_position() < chunk.end() {
+                if reader.read_record(&mut record)? == 0 {
+                    break;
+                }
+                if record.reference_sequence_id().transpose()? != Some(*id) {
+                    continue;
+                }
+                let Some(start) = record.alignment_start().transpose()? else {
+                    continue;
+                };
+                let start = usize::from(start) - 1;
+                let end = record.alignment_end().transpose()?.map_or(start + 1, usize::from);
+                if end <= interval.start || start >= interval.end {
+                    continue;
+                }
+                // Written with the interval before
+                if previous.is_some_and(|(previous_id, previous)| previous_id == *id && start < previous.end) {
+                    continue;
+                }
+                if filter.excludes(record.flags()) {
+                    removed.flagged += 1;
+                    continue;
+                }
+                if let Some(numts) = numts {
+                    if numts.excludes(&header, &record, *id)? {
+                        removed.numt += 1;
+                        continue;
+                    }
+                }
+                if filter.end_exclusion > 0 {
+                    let mut record = RecordBuf::try_from_alignment_record(&header, &record)?;
+                    filter.mask_read_ends(&mut record);
+                    writer.write_alignment_record(&header, &record)?;
+                } else {
+                    writer.write_alignment_record(&header, &record)?;
+                }
+            }
+        }
+    }
+    writer.try_finish()?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mt_sequence, read, reference_read, write_bam};
+    use std::fs;
+    use noodles::sam::alignment::record_buf::data::field::Value;
+
+    const HEADER: &str = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:MT\tLN:16569\n@SQ\tSN:1\tLN:100000\n@RG\tID:a\tSM:a\n";
+
+    /// The base at 1-based `position` of the test mitochondrion, changed.
+    fn alternate(position: usize) -> u8 {
+        match mt_sequence()[position - 1] {
+            b'A' => b'C',
+            _ => b'A',
+        }
+    }
+
+    /// A read of the test mitochondrion from `start`, `length` bases long,
+    /// with the alternate base at `position`.
+    fn variant_read(name: &str, start: usize, length: usize, position: usize) -> RecordBuf {
+        let mut sequence = mt_sequence()[start - 1..start - 1 + length].to_vec();
+        sequence[position - start] = alternate(position);
+        read(name, start, &sequence)
+    }
+
+    /// `record` as one of a pair, its mate on the nuclear contig at 1-based
+    /// `mate_start` with mapping quality `mate_mq`.
+    fn paired(mut record: RecordBuf, mate_start: usize, mate_mq: i32) -> RecordBuf {
+        *record.flags_mut() = Flags::SEGMENTED;
+        *record.mate_reference_sequence_id_mut() = Some(1);
+        *record.mate_alignment_start_mut() = Some(Position::try_from(mate_start).unwrap());
+        record.data_mut().insert(Tag::MATE_MAPPING_QUALITY, Value::from(mate_mq));
+        record
+    }
+
+    /// Observations of `base` at 1-based `position` with a base quality of
+    /// at least `min_bq`, as freebayes would count them.
+    fn observations(bam: &Path, position: usize, base: u8, min_bq: u8) -> usize {
+        let mut reader = bam::io::reader::Builder.build_from_path(bam).unwrap();
+        let header = reader.read_header().unwrap();
+        let mut count = 0;
+        for result in reader.record_bufs(&header) {
+            let record = result.unwrap();
+            let start = usize::from(record.alignment_start().unwrap());
+            let mut query = 0;
+            let mut reference = start;
+            for op in record.cigar().as_ref() {
+                match op.kind() {
+                    Kind::SoftClip => query += op.len(),
+                    Kind::Match => {
+                        if (reference..reference + op.len()).contains(&position) {
+                            let index = query + position - reference;
+                            let observed = record.sequence().as_ref()[index] == base
+                                && record.quality_scores().as_ref()[index] >= min_bq;
+                            count += usize::from(observed);
+                        }
+                        query += op.len();
+                        reference += op.len();
+                    }
+                    _ => unreachable!("the test reads are clipped and matched only"),
+                }
+            }
+        }
+        count
+    }
+
+    fn whole_mt() -> Vec<Interval> {
+        vec![Interval::parse_region("MT:1-16569").unwrap()]
+    }
+
+    const CALLING: ReadFilter = ReadFilter {
+        duplicates: true,
+        secondary: false,
+        supplementary: false,
+        end_exclusion: 0,
+    };
+
+    #[test]
+    fn numt_pairs_are_left_out_with_the_variant_they_carry() {
+        let temp = tempfile::tempdir().unwrap();
+        let bed = temp.path().join("numts.bed");
+        fs::write(&bed, "1\t5000\t6000\n").unwrap();
+        let numts = NumtFilter::from_bed(&bed).unwrap();
+
+        let mut records: Vec<RecordBuf> = (0..10).map(|i| reference_read(&format!("mt{}", i), 951 + i * 5, 100)).collect();
+        // Mates in the NUMT, or placed confidently elsewhere in the nuclear genome
+        records.extend((0..4).map(|i| paired(variant_read(&format!("numt{}", i), 960 + i * 5, 100, 1000), 5500, 0)));
+        records.extend((0..2).map(|i| paired(variant_read(&format!("nuclear{}", i), 970 + i * 5, 100, 1000), 50000, 30)));
+        // A mate mapped vaguely away from any NUMT says nothing
+        records.push(paired(reference_read("vague", 980, 100), 50000, 3));
+        let input = write_bam(&temp.path().join("in.bam"), HEADER, &records);
+        assert_eq!(observations(&input, 1000, alternate(1000), 24), 6);
+
+        let output = temp.path().join("out.bam");
+        let removed = write_filtered_bam(&input, &whole_mt(), CALLING, Some(&numts), &bed, &output)
+            .unwrap()
+            .unwrap();
+        assert_eq!(removed.numt, 6);
+        assert_eq!(removed.flagged, 0);
+        assert_eq!(observations(&output, 1000, alternate(1000), 24), 0);
+        assert_eq!(observations(&output, 1000, mt_sequence()[999], 24), 11);
+        assert!(numts.header_line(removed.numt).starts_with("##mityNumtFilter=\"6 reads with a mate"));
+    }
+
+    #[test]
+    fn bundled_numt_beds_are_found_for_each_build() {
+        for build in ["hg19", "hg38", "hs37d5"] {
+            let bed = NumtFilter::bundled_bed(build).unwrap();
+            assert!(NumtFilter::from_bed(&bed).is_ok(), "{}", bed.display());
+        }
+        let message = NumtFilter::bundled_bed("mm10").unwrap_err().to_string();
+        assert!(message.ends_with("Give the NUMTs of mm10 with --numt-bed"), "{}", message);
+    }
+
+    #[test]
+    fn an_artifact_at_read_ends_drops_below_min_ac() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut records: Vec<RecordBuf> = (0..10).map(|i| reference_read(&format!("mt{}", i), 941 + i * 5, 100)).collect();
+        // Within 5 aligned bases of a read end, one behind a soft clip
+        records.extend((0..2).map(|i| variant_read(&format!("start{}", i), 998 + i, 100, 1000)));
+        records.extend((0..2).map(|i| variant_read(&format!("end{}", i), 905 - i, 100, 1000)));
+        let mut clipped = variant_read("clipped", 988, 110, 1000);
+        *clipped.alignment_start_mut() = Some(Position::try_from(998).unwrap());
+        *clipped.cigar_mut() = [Op::new(Kind::SoftClip, 10), Op::new(Kind::Match, 100)].into_iter().collect();
+        records.push(clipped);
+        // Mid-read, where it counts either way
+        records.push(variant_read("middle", 950, 100, 1000));
+        let input = write_bam(&temp.path().join("in.bam"), HEADER, &records);
+        assert_eq!(observations(&input, 1000, alternate(1000), 24), 6);
+
+        let output = temp.path().join("out.bam");
+        let filter = ReadFilter { end_exclusion: 5, ..CALLING };
+        write_filtered_bam(&input, &whole_mt(), filter, None, &input, &output).unwrap();
+        // Below the default --min-alternate-count of 4
+        assert_eq!(observations(&output, 1000, alternate(1000), 24), 1);
+        assert_eq!(observations(&output, 1000, mt_sequence()[999], 24), 10);
+        assert!(filter.header_line().ends_with("read_end_exclusion=5\""));
+
+        write_filtered_bam(&input, &whole_mt(), CALLING, None, &input, &output).unwrap();
+        assert_eq!(observations(&output, 1000, alternate(1000), 24), 6);
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use noodles::bam;
+use noodles::sam::alignment::record::data::field::{Tag, Value};
+use noodles::sam::header::record::value::map::read_group::tag::SAMPLE;
+use serde_json::{json, Value as JsonValue};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::mity_util::Interval;
+use crate::pileup;
+
+/// Read group of alignments without an `RG` tag.
+const NO_READ_GROUP: &str = "-";
+
+/// QC statistics of the MT alignments of one read group.
+///
+/// Every primary mapped alignment counts, duplicates and QC failures
+/// included, so the duplicate fraction reflects the library rather than what
+/// freebayes sees. Insert sizes come from the leftmost read of each pair with
+/// both reads mapped.
+#[derive(Default)]
+pub struct ReadGroupStats {
+    pub sample: Option<String>,
+    pub reads: u64,
+    duplicates: u64,
+    mapq_sum: u64,
+    base_quality_sum: u64,
+    bases: u64,
+    insert_sizes: BTreeMap<u32, u64>,
+}
+
+impl ReadGroupStats {
+    fn add_record(&mut self, record: &bam::Record) {
+        let flags = record.flags();
+        if flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary() {
+            return;
+        }
+        self.reads += 1;
+        if flags.is_duplicate() {
+            self.duplicates += 1;
+        }
+        self.mapq_sum += record.mapping_quality().map_or(0, |mq| u64::from(mq.get()));
+        let quality_scores = record.quality_scores();
+        let quality_scores = quality_scores.as_ref();
+        // 0xFF means the qualities are missing
+        if quality_scores.first() != Some(&0xff) {
+            self.base_quality_sum += quality_scores.iter().map(|&q| u64::from(q)).sum::<u64>();
+            self.bases += quality_scores.len() as u64;
+        }
+        let template_length = record.template_length();
+        if flags.is_segmented() && !flags.is_mate_unmapped() && template_length > 0 {
+            *self.insert_sizes.entry(template_length.unsigned_abs()).or_default() += 1;
+        }
+    }
+
+    pub fn duplicate_fraction(&self) -> f64 {
+        ratio(self.duplicates, self.reads)
+    }
+
+    pub fn mean_mapq(&self) -> f64 {
+        ratio(self.mapq_sum, self.reads)
+    }
+
+    pub fn mean_base_quality(&self) -> f64 {
+        ratio(self.base_quality_sum, self.bases)
+    }
+
+    /// Median insert size, or `None` without mapped pairs.
+    pub fn median_insert_size(&self) -> Option<f64> {
+        let pairs: u64 = self.insert_sizes.values().sum();
+        if pairs == 0 {
+            return None;
+        }
+        let nth = |n: u64| {
+            let mut seen = 0;
+            self.insert_sizes
+                .iter()
+                .find(|(_, count)| {
+                    seen += **count;
+                    seen > n
+                })
+                .map_or(0, |(size, _)| *size)
+        };
+        if pairs % 2 == 1 {
+            Some(f64::from(nth(pairs / 2)))
+        } else {
+            Some((f64::from(nth(pairs / 2 - 1)) + f64::from(nth(pairs / 2))) / 2.0)
+        }
+    }
+
+    pub fn to_json(&self, file: &Path, read_group: &str) -> JsonValue {
+        json!({
+            "file": file.display().to_string(),
+            "read_group": read_group,
+            "sample": self.sample,
+            "reads": self.reads,
+            "duplicate_fraction": self.duplicate_fraction(),
+            "mean_mapq": self.mean_mapq(),
+            "mean_base_quality": self.mean_base_quality(),
+            "median_insert_size": self.median_insert_size(),
+        })
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Statistics of each read group of `bam` over `contig`, by read group ID.
+/// Every read group in the header is listed, even without reads on `contig`.
+pub fn read_group_stats(bam: &Path, contig: &str) -> Result<BTreeMap<String, ReadGroupStats>> {
+    let mut reader = bam::io::reader::Builder
+        .build_from_path(bam)
+        .with_context(|| format!("Failed to open {}", bam.display()))?;
+    let header = reader.read_header()?;
+    let length = header
+        .reference_sequences()
+        .get(contig.as_bytes())
+        .map(|sequence| sequence.length().get())
+        .with_context(|| format!("Contig {} not found in {}", contig, bam.display()))?;
+
+    let mut stats: BTreeMap<String, ReadGroupStats> = BTreeMap::new();
+    for (id, read_group) in header.read_groups() {
+        stats.entry(id.to_string()).or_default().sample = read_group
+            .other_fields()
+            .get(&SAMPLE)
+            .map(|sample| sample.to_string());
+    }
+
+    let interval = Interval {
+        contig: contig.to_string(),
+        start: 0,
+        end: length,
+    };
+    pileup::for_each_record(bam, &[interval], |_, record| {
+        let read_group = match record.data().get(&Tag::READ_GROUP).transpose()? {
+            Some(Value::String(id)) => id.to_string(),
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "RG tag is not a string")),
+            None => NO_READ_GROUP.to_string(),
+        };
+        stats.entry(read_group).or_default().add_record(record);
+        Ok(())
+    })?;
+    Ok(stats)
+}
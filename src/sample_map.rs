@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use log::info;
+use noodles::bgzf;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::checksum;
+use crate::mity_util;
+use crate::mity_util::header::VcfHeader;
+
+/// Sample renames read from a two-column TSV of `old<TAB>new`.
+#[derive(Clone, Debug, Default)]
+pub struct SampleMap {
+    entries: Vec<(String, String)>,
+}
+
+impl SampleMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sample map {}", path.display()))?;
+
+        let mut entries: Vec<(String, String)> = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 2 || fields.iter().any(|f| f.is_empty()) {
+                anyhow::bail!(
+                    "{}:{}: expected two tab-separated columns (old name, new name)",
+                    path.display(),
+                    index + 1
+                );
+            }
+            if entries.iter().any(|(old, _)| old == fields[0]) {
+                anyhow::bail!(
+                    "{}:{}: sample '{}' is mapped more than once",
+                    path.display(),
+                    index + 1,
+                    fields[0]
+                );
+            }
+            entries.push((fields[0].to_string(), fields[1].to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        for (_, new) in &entries {
+            if !seen.insert(new) {
+                anyhow::bail!("Sample map {} maps several samples to '{}'", path.display(), new);
+            }
+        }
+
+        Ok(SampleMap { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Rename `samples`, leaving unmapped names unchanged. Every mapped name
+    /// must be present and the result must not contain duplicates.
+    pub fn apply(&self, samples: &[String]) -> Result<Vec<String>> {
+        let missing: Vec<&str> = self
+            .entries
+            .iter()
+            .filter(|(old, _)| !samples.contains(old))
+            .map(|(old, _)| old.as_str())
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Samples in --sample-map are not in the VCF: {} (VCF samples: {})",
+                missing.join(", "),
+                samples.join(", ")
+            );
+        }
+
+        let renamed: Vec<String> = samples
+            .iter()
+            .map(|sample| {
+                self.entries
+                    .iter()
+                    .find(|(old, _)| old == sample)
+                    .map(|(_, new)| new.clone())
+                    .unwrap_or_else(|| sample.clone())
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = renamed.iter().find(|name| !seen.insert(*name)) {
+            anyhow::bail!("Renaming samples would produce duplicate sample '{}'", duplicate);
+        }
+        Ok(renamed)
+    }
+
+    /// The mapping as a `##mitySampleMap` header line.
+    pub fn header_line(&self) -> String {
+        let mapping: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(old, new)| format!("{}={}", old, new))
+            .collect();
+        format!("##mitySampleMap=\"{}\"", mapping.join(","))
+    }
+
+    /// Rewrite the sample columns of a VCF in place, writing BGZF output.
+    pub fn rewrite_vcf(&self, vcf: &Path) -> Result<()> {
+        let tmp_path = vcf.with_extension("samplemap.tmp");
+        let result = self.write_renamed(vcf, &tmp_path);
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+        fs::rename(&tmp_path, vcf)?;
+        Ok(())
+    }
+
+    fn write_renamed(&self, vcf: &Path, tmp_path: &Path) -> Result<()> {
+        let reader = mity_util::open_vcf(vcf)?;
+        let mut writer = bgzf::Writer::new(checksum::OutputFile::create_for(tmp_path, vcf)?);
+        let mut lines = reader.lines();
+        let mut header = VcfHeader::read(&mut lines)?;
+        let renamed = self.apply(&header.samples())?;
+        info!("Renamed samples: {}", renamed.join(", "));
+        header.set_samples(renamed);
+        header.upsert(self.header_line());
+        header.write(&mut writer)?;
+        for line in lines {
+            writeln!(writer, "{}", line?)?;
+        }
+        writer.finish()?.finish()?;
+        Ok(())
+    }
+}
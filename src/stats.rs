@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use log::info;
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::checksum;
+use crate::logging;
+
+/// Measures the wall time of a single stage.
+pub struct Timer {
+    start: Instant,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        Timer {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Per-stage timings, resource usage and outputs of a single command run.
+pub struct RunSummary {
+    command: String,
+    started: Instant,
+    stages: Vec<(String, Duration)>,
+    outputs: Vec<PathBuf>,
+    metrics: Map<String, Value>,
+}
+
+impl RunSummary {
+    pub fn new(command: &str) -> Self {
+        RunSummary {
+            command: command.to_string(),
+            started: Instant::now(),
+            stages: Vec::new(),
+            outputs: Vec::new(),
+            metrics: Map::new(),
+        }
+    }
+
+    /// Record the time elapsed since `timer` was started against `stage`.
+    pub fn record(&mut self, stage: &str, timer: Timer) {
+        self.stages.push((stage.to_string(), timer.elapsed()));
+    }
+
+    /// Register a final output file whose size is reported in the summary.
+    pub fn add_output(&mut self, path: impl Into<PathBuf>) {
+        self.outputs.push(path.into());
+    }
+
+    /// Attach an extra named value to the stats JSON.
+    pub fn set_metric(&mut self, key: &str, value: impl Into<Value>) {
+        self.metrics.insert(key.to_string(), value.into());
+    }
+
+    /// Print the stage table, peak memory and output sizes at info level.
+    pub fn log(&self) {
+        info!(target: logging::HEADING_TARGET, "{} summary:", self.command);
+        for (stage, duration) in &self.stages {
+            info!("  {:<24} {:>10.2}s", stage, duration.as_secs_f64());
+        }
+        info!(
+            "  {:<24} {:>10.2}s",
+            "total",
+            self.started.elapsed().as_secs_f64()
+        );
+
+        let (own, children) = peak_rss_kb();
+        if let Some(kb) = own {
+            info!("  peak RSS (mity):        {:>10.1} MB", kb as f64 / 1024.0);
+        }
+        if let Some(kb) = children {
+            info!("  peak RSS (subprocess):  {:>10.1} MB", kb as f64 / 1024.0);
+        }
+
+        for output in &self.outputs {
+            match fs::metadata(output) {
+                Ok(meta) => info!("  {} ({} bytes)", output.display(), meta.len()),
+                Err(_) => info!("  {} (missing)", output.display()),
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let (own, children) = peak_rss_kb();
+        let stages: Vec<Value> = self
+            .stages
+            .iter()
+            .map(|(stage, duration)| json!({ "stage": stage, "seconds": duration.as_secs_f64() }))
+            .collect();
+        let outputs: Vec<Value> = self
+            .outputs
+            .iter()
+            .map(|output| {
+                let mut entry = json!({
+                    "path": output.display().to_string(),
+                    "bytes": fs::metadata(output).map(|m| m.len()).ok(),
+                });
+                if let Some((algorithm, digest)) = checksum::digest_of(output) {
+                    entry[algorithm] = digest.into();
+                }
+                entry
+            })
+            .collect();
+
+        json!({
+            "command": self.command,
+            "version": env!("CARGO_PKG_VERSION"),
+            "wall_time_seconds": self.started.elapsed().as_secs_f64(),
+            "stages": stages,
+            "peak_rss_kb": { "mity": own, "subprocesses": children },
+            "outputs": outputs,
+            "metrics": self.metrics,
+        })
+    }
+
+    /// Write the summary as pretty-printed JSON.
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create stats file {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &self.to_json())?;
+        Ok(())
+    }
+}
+
+/// Peak resident set size in KB of this process and of its waited-for
+/// children, where the platform exposes it.
+#[cfg(unix)]
+fn peak_rss_kb() -> (Option<u64>, Option<u64>) {
+    fn max_rss(who: libc::c_int) -> Option<u64> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+            return None;
+        }
+        let max_rss = usage.ru_maxrss as u64;
+        // macOS reports bytes, Linux reports kilobytes
+        if cfg!(target_os = "macos") {
+            Some(max_rss / 1024)
+        } else {
+            Some(max_rss)
+        }
+    }
+    (
+        max_rss(libc::RUSAGE_SELF),
+        max_rss(libc::RUSAGE_CHILDREN),
+    )
+}
+
+#[cfg(not(unix))]
+fn peak_rss_kb() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
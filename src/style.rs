@@ -0,0 +1,79 @@
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+/// Environment variable that turns colour off when set to anything but an
+/// empty string, see <https://no-color.org>.
+pub const NO_COLOR_ENV_VAR: &str = "NO_COLOR";
+
+/// The console streams, which are coloured independently: one can be a
+/// terminal while the other is redirected to a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Red,
+    Yellow,
+    Green,
+    Bold,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Red => "31",
+            Style::Yellow => "33",
+            Style::Green => "32",
+            Style::Bold => "1",
+        }
+    }
+}
+
+/// Whether stdout and stderr get ANSI colours.
+static ENABLED: OnceLock<(bool, bool)> = OnceLock::new();
+
+/// Decide whether to colour each stream: only a terminal, and never with
+/// `--no-color` or `NO_COLOR`. Only the first call has any effect.
+pub fn init(no_color: bool) {
+    let allowed = !no_color && std::env::var_os(NO_COLOR_ENV_VAR).is_none_or(|value| value.is_empty());
+    let _ = ENABLED.set((
+        allowed && io::stdout().is_terminal(),
+        allowed && io::stderr().is_terminal(),
+    ));
+}
+
+/// Whether `stream` is coloured; nothing is before [`init`].
+pub fn enabled(stream: Stream) -> bool {
+    let (stdout, stderr) = ENABLED.get().copied().unwrap_or_default();
+    match stream {
+        Stream::Stdout => stdout,
+        Stream::Stderr => stderr,
+    }
+}
+
+/// `text` in `style` if `stream` is coloured, otherwise unchanged.
+pub fn paint(text: impl Display, style: Style, stream: Stream) -> String {
+    if enabled(stream) {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn heading(text: impl Display, stream: Stream) -> String {
+    paint(text, Style::Bold, stream)
+}
+
+/// The mark of a check that passed.
+pub fn tick(stream: Stream) -> String {
+    paint("✓", Style::Green, stream)
+}
+
+/// The mark of a check that failed.
+pub fn cross(stream: Stream) -> String {
+    paint("✗", Style::Red, stream)
+}
@@ -0,0 +1,151 @@
+//! Fixtures for the unit tests: small BAMs and references on a made-up
+//! mitochondrion, and the stand-ins in `testdata/tools` for freebayes,
+//! bcftools and the other tools mity runs.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use noodles::bam;
+use noodles::core::Position;
+use noodles::fasta;
+use noodles::sam::{self, alignment::io::Write as _};
+use noodles::sam::alignment::record::cigar::op::{Kind, Op};
+use noodles::sam::alignment::record::MappingQuality;
+use noodles::sam::alignment::record_buf::{Cigar, QualityScores, Sequence};
+use noodles::sam::alignment::RecordBuf;
+
+use crate::mity_util;
+
+/// Length of the rCRS, and of every test mitochondrion.
+pub const MT_LENGTH: usize = 16569;
+
+/// Point mity at the stand-in tools, through the `MITY_*` variables and
+/// `PATH` (for `bgzip`). Every test that runs a tool calls this first.
+pub fn use_fake_tools() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let tools = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/tools");
+        for (var, tool) in [
+            ("MITY_FREEBAYES", "freebayes"),
+            ("MITY_BCFTOOLS", "bcftools"),
+            ("MITY_TABIX", "tabix"),
+            ("MITY_GSORT", "gsort"),
+            ("MITY_SAMTOOLS", "samtools"),
+        ] {
+            env::set_var(var, tools.join(tool));
+        }
+        let path = env::var_os("PATH").unwrap_or_default();
+        let paths = std::iter::once(tools).chain(env::split_paths(&path));
+        env::set_var("PATH", env::join_paths(paths).expect("PATH entries join"));
+    });
+}
+
+/// The test mitochondrion: [`MT_LENGTH`] bases, the same every time, with
+/// no long repeats.
+pub fn mt_sequence() -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..MT_LENGTH)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            b"ACGT"[(state >> 30) as usize]
+        })
+        .collect()
+}
+
+/// Write `ref.fa` in `dir`, the test mitochondrion as `contig`, with its
+/// `.fai`.
+pub fn write_reference(dir: &Path, contig: &str) -> PathBuf {
+    let path = dir.join("ref.fa");
+    let mut writer = BufWriter::new(File::create(&path).unwrap());
+    writeln!(writer, ">{}", contig).unwrap();
+    for line in mt_sequence().chunks(60) {
+        writer.write_all(line).unwrap();
+        writeln!(writer).unwrap();
+    }
+    writer.flush().unwrap();
+    let index = fasta::io::index(&path).unwrap();
+    let fai = File::create(crate::check::fai_path(&path)).unwrap();
+    fasta::fai::io::Writer::new(fai).write_index(&index).unwrap();
+    path
+}
+
+/// Write `mt.genome` in `dir`, the gsort genome file for the test
+/// mitochondrion as `contig`.
+pub fn write_genome(dir: &Path, contig: &str) -> PathBuf {
+    let path = dir.join("mt.genome");
+    fs::write(&path, format!("{}\t{}\n", contig, MT_LENGTH)).unwrap();
+    path
+}
+
+/// The records the stand-in freebayes reports against `reference`: VCF
+/// lines up to one sample column, which it repeats for every sample.
+pub fn write_records(reference: &Path, lines: &[&str]) {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs::write(reference.with_extension("records"), text).unwrap();
+}
+
+/// A coordinate-sorted SAM header for one `contig` of [`MT_LENGTH`], with a
+/// read group per sample named after it.
+pub fn mt_header(contig: &str, samples: &[&str]) -> String {
+    let mut text = format!("@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:{}\tLN:{}\n", contig, MT_LENGTH);
+    for sample in samples {
+        text.push_str(&format!("@RG\tID:{}\tSM:{}\n", sample, sample));
+    }
+    text
+}
+
+/// A forward-strand read on the first contig, matching all of `sequence`
+/// from the 1-based `start`, with base qualities of 30 and a mapping
+/// quality of 60.
+pub fn read(name: &str, start: usize, sequence: &[u8]) -> RecordBuf {
+    RecordBuf::builder()
+        .set_name(name)
+        .set_flags(sam::alignment::record::Flags::empty())
+        .set_reference_sequence_id(0)
+        .set_alignment_start(Position::try_from(start).unwrap())
+        .set_mapping_quality(MappingQuality::new(60).unwrap())
+        .set_cigar(Cigar::from(vec![Op::new(Kind::Match, sequence.len())]))
+        .set_sequence(Sequence::from(sequence.to_vec()))
+        .set_quality_scores(QualityScores::from(vec![30; sequence.len()]))
+        .build()
+}
+
+/// A read of the test mitochondrion itself, `length` bases from `start`.
+pub fn reference_read(name: &str, start: usize, length: usize) -> RecordBuf {
+    read(name, start, &mt_sequence()[start - 1..start - 1 + length])
+}
+
+/// Write `records`, in coordinate order, to a BAM with the SAM header
+/// `header`, and index it.
+pub fn write_bam(path: &Path, header: &str, records: &[RecordBuf]) -> PathBuf {
+    let header: sam::Header = header.parse().unwrap();
+    let mut records = records.to_vec();
+    records.sort_by_key(|record| (record.reference_sequence_id(), record.alignment_start()));
+    let mut writer = bam::io::Writer::new(File::create(path).unwrap());
+    writer.write_header(&header).unwrap();
+    for record in &records {
+        writer.write_alignment_record(&header, record).unwrap();
+    }
+    writer.try_finish().unwrap();
+    mity_util::index_bam(path).unwrap();
+    path.to_path_buf()
+}
+
+/// The lines of a text file, gunzipped if need be.
+pub fn read_lines(path: &Path) -> Vec<String> {
+    let bytes = fs::read(path).unwrap();
+    let text = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut text = String::new();
+        flate2::read::MultiGzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    } else {
+        String::from_utf8(bytes).unwrap()
+    };
+    text.lines().map(String::from).collect()
+}
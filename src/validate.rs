@@ -0,0 +1,295 @@
+use log::debug;
+use noodles::bgzf;
+use serde_json::json;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::error::MityError;
+use crate::mity_util;
+use crate::normalise::{FilterSettings, VariantFilter};
+use crate::vcf_record::VcfRecord;
+
+/// The empty block that terminates every BGZF file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+    0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Only the first few problems of each check are listed.
+const MAX_DETAILS: usize = 10;
+
+/// Outcome of a single validation check.
+struct Check {
+    name: &'static str,
+    details: Vec<String>,
+    problems: usize,
+}
+
+impl Check {
+    fn new(name: &'static str) -> Self {
+        Check {
+            name,
+            details: Vec::new(),
+            problems: 0,
+        }
+    }
+
+    fn fail(&mut self, detail: String) {
+        self.problems += 1;
+        if self.details.len() < MAX_DETAILS {
+            self.details.push(detail);
+        }
+    }
+
+    fn passed(&self) -> bool {
+        self.problems == 0
+    }
+}
+
+/// Checks that a VCF is structurally sound mity output.
+pub struct Validate {
+    debug: bool,
+    vcf: PathBuf,
+    reference_fasta: Option<PathBuf>,
+    json: bool,
+}
+
+impl Validate {
+    pub fn new(debug: bool, vcf: PathBuf, reference_fasta: Option<PathBuf>, json: bool) -> Self {
+        Validate {
+            debug,
+            vcf,
+            reference_fasta,
+            json,
+        }
+    }
+
+    /// Run every check and print the report. Returns an error if any check
+    /// failed.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+
+        let mut integrity = Check::new("bgzip integrity");
+        let mut index = Check::new("index");
+        let mut provenance = Check::new("mity provenance");
+        let mut declarations = Check::new("mity header declarations");
+        let mut sorting = Check::new("sorted on a single MT contig");
+        let mut undeclared = Check::new("all used fields declared");
+        let mut reference = Check::new("REF matches reference");
+
+        self.check_eof(&mut integrity)?;
+        self.check_index(&mut index)?;
+
+        let mut header = Vec::new();
+        let mut contig: Option<String> = None;
+        let mut last_pos = 0;
+        let mut reference_sequence: Option<Vec<u8>> = None;
+        let mut declared: HashSet<(String, String)> = HashSet::new();
+
+        let reader = bgzf::Reader::new(File::open(&self.vcf)?);
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    integrity.fail(format!("Failed to decompress: {}", e));
+                    break;
+                }
+            };
+            if line.starts_with("##") {
+                if let Some(id) = declared_id(&line) {
+                    declared.insert(id);
+                }
+                header.push(line);
+                continue;
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let record = match VcfRecord::parse(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    integrity.fail(format!("line {}: {}", line_number + 1, e));
+                    continue;
+                }
+            };
+
+            match &contig {
+                None => {
+                    if record.chrom != "MT" && record.chrom != "chrM" {
+                        sorting.fail(format!("{} is not a mitochondrial contig", record.chrom));
+                    }
+                    contig = Some(record.chrom.clone());
+                    if let Some(fasta) = &self.reference_fasta {
+                        match mity_util::read_fasta_contig(fasta, &record.chrom) {
+                            Ok(sequence) => reference_sequence = Some(sequence),
+                            Err(e) => reference.fail(e.to_string()),
+                        }
+                    }
+                }
+                Some(contig) if *contig != record.chrom => {
+                    sorting.fail(format!(
+                        "{}:{} is on a second contig (first was {})",
+                        record.chrom, record.pos, contig
+                    ));
+                }
+                Some(_) => {}
+            }
+            if record.pos < last_pos {
+                sorting.fail(format!(
+                    "{}:{} comes after position {}",
+                    record.chrom, record.pos, last_pos
+                ));
+            }
+            last_pos = record.pos;
+
+            for (key, _) in &record.info {
+                if !declared.contains(&("INFO".to_string(), key.clone())) {
+                    undeclared.fail(format!("{}:{}: INFO/{}", record.chrom, record.pos, key));
+                }
+            }
+            for key in &record.format {
+                if !declared.contains(&("FORMAT".to_string(), key.clone())) {
+                    undeclared.fail(format!("{}:{}: FORMAT/{}", record.chrom, record.pos, key));
+                }
+            }
+            for filter in record.filter.split(';') {
+                if filter != "PASS"
+                    && filter != "."
+                    && !declared.contains(&("FILTER".to_string(), filter.to_string()))
+                {
+                    undeclared.fail(format!("{}:{}: FILTER/{}", record.chrom, record.pos, filter));
+                }
+            }
+
+            if let Some(sequence) = &reference_sequence {
+                let start = record.pos as usize - 1;
+                let expected = sequence
+                    .get(start..start + record.reference.len())
+                    .map(|bases| String::from_utf8_lossy(bases).to_uppercase());
+                if expected.as_deref() != Some(record.reference.to_uppercase().as_str()) {
+                    reference.fail(format!(
+                        "{}:{}: REF {} but reference has {}",
+                        record.chrom,
+                        record.pos,
+                        record.reference,
+                        expected.unwrap_or_else(|| "nothing".to_string())
+                    ));
+                }
+            }
+        }
+
+        if !header.iter().any(|line| line.starts_with("##mityCommandline=")) {
+            provenance.fail("No ##mityCommandline header line".to_string());
+        }
+        let filter_lines = VariantFilter::new(FilterSettings::default()).header_lines();
+        if self.is_normalised(&header, &filter_lines) {
+            // Compare by ID as the descriptions carry the filter thresholds
+            for line in &filter_lines {
+                let (kind, id) = declared_id(line).unwrap_or_default();
+                if !declared.contains(&(kind.clone(), id.clone())) {
+                    declarations.fail(format!("{}/{} is not declared", kind, id));
+                }
+            }
+        }
+
+        let mut checks = vec![integrity, index, provenance, declarations, sorting, undeclared];
+        if self.reference_fasta.is_some() {
+            checks.push(reference);
+        }
+        let failed = checks.iter().filter(|check| !check.passed()).count();
+
+        if self.json {
+            let report = json!({
+                "file": self.vcf.display().to_string(),
+                "passed": failed == 0,
+                "checks": checks.iter().map(|check| json!({
+                    "name": check.name,
+                    "passed": check.passed(),
+                    "problems": check.problems,
+                    "details": check.details,
+                })).collect::<Vec<_>>(),
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("Validating {}", self.vcf.display());
+            for check in &checks {
+                println!(
+                    "  [{}] {}",
+                    if check.passed() { "PASS" } else { "FAIL" },
+                    check.name
+                );
+                for detail in &check.details {
+                    println!("      {}", detail);
+                }
+                if check.problems > check.details.len() {
+                    println!("      ... and {} more", check.problems - check.details.len());
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(format!("{} of {} checks failed", failed, checks.len()).into());
+        }
+        if !self.json {
+            println!("All {} checks passed", checks.len());
+        }
+        Ok(())
+    }
+
+    fn check_eof(&self, check: &mut Check) -> Result<(), Box<dyn Error>> {
+        let mut file = File::open(&self.vcf)?;
+        let length = file.metadata()?.len();
+        if length < BGZF_EOF.len() as u64 {
+            check.fail("File is too short to be BGZF".to_string());
+            return Ok(());
+        }
+        file.seek(SeekFrom::End(-(BGZF_EOF.len() as i64)))?;
+        let mut tail = [0u8; 28];
+        file.read_exact(&mut tail)?;
+        if tail != BGZF_EOF {
+            check.fail("Missing BGZF end-of-file marker (truncated, or not bgzipped)".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, check: &mut Check) -> Result<(), Box<dyn Error>> {
+        let Some(index) = mity_util::vcf_index(&self.vcf) else {
+            check.fail("No .tbi or .csi index".to_string());
+            return Ok(());
+        };
+        let vcf_modified = fs::metadata(&self.vcf)?.modified()?;
+        let index_modified = fs::metadata(&index)?.modified()?;
+        if index_modified < vcf_modified {
+            check.fail(format!("{} is older than the VCF", index.display()));
+        }
+        Ok(())
+    }
+
+    /// Normalised and filtered VCFs must declare everything added by the
+    /// filtering step.
+    fn is_normalised(&self, header: &[String], filter_lines: &[String]) -> bool {
+        let name = self.vcf.to_string_lossy();
+        name.contains(".normalise.")
+            || name.contains(".mity.filter.")
+            || filter_lines.iter().any(|line| header.contains(line))
+    }
+}
+
+/// The `(INFO|FORMAT|FILTER, ID)` declared by a meta-information line.
+fn declared_id(line: &str) -> Option<(String, String)> {
+    let (kind, rest) = line.strip_prefix("##")?.split_once("=<ID=")?;
+    if !matches!(kind, "INFO" | "FORMAT" | "FILTER") {
+        return None;
+    }
+    let id = rest.split([',', '>']).next()?;
+    Some((kind.to_string(), id.to_string()))
+}
@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use std::fmt;
+
+/// A VCF data line split into its columns.
+///
+/// Only the fields mity rewrites (INFO, FILTER, QUAL and the per-sample
+/// values) are parsed further; everything else is carried through verbatim.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcfRecord {
+    pub chrom: String,
+    pub pos: u64,
+    pub id: String,
+    pub reference: String,
+    pub alt: String,
+    pub qual: String,
+    pub filter: String,
+    pub info: Vec<(String, Option<String>)>,
+    pub format: Vec<String>,
+    pub samples: Vec<Vec<String>>,
+}
+
+impl VcfRecord {
+    pub fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.trim_end_matches(['\n', '\r']).split('\t').collect();
+        if fields.len() < 8 {
+            anyhow::bail!("Malformed VCF record with {} columns: {}", fields.len(), line);
+        }
+
+        let info = if fields[7] == "." {
+            Vec::new()
+        } else {
+            fields[7]
+                .split(';')
+                .map(|entry| match entry.split_once('=') {
+                    Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                    None => (entry.to_string(), None),
+                })
+                .collect()
+        };
+
+        let format = fields
+            .get(8)
+            .map(|f| f.split(':').map(String::from).collect())
+            .unwrap_or_default();
+        let samples = fields
+            .iter()
+            .skip(9)
+            .map(|s| s.split(':').map(String::from).collect())
+            .collect();
+
+        Ok(VcfRecord {
+            chrom: fields[0].to_string(),
+            pos: fields[1]
+                .parse()
+                .with_context(|| format!("Invalid POS '{}'", fields[1]))?,
+            id: fields[2].to_string(),
+            reference: fields[3].to_string(),
+            alt: fields[4].to_string(),
+            qual: fields[5].to_string(),
+            filter: fields[6].to_string(),
+            info,
+            format,
+            samples,
+        })
+    }
+
+    pub fn info(&self, key: &str) -> Option<&str> {
+        self.info
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    /// Whether the INFO column has `key`, as a flag or with a value.
+    pub fn has_info(&self, key: &str) -> bool {
+        self.info.iter().any(|(k, _)| k == key)
+    }
+
+    /// Parse the first value of a numeric INFO field.
+    pub fn info_f64(&self, key: &str) -> Option<f64> {
+        self.info(key)?.split(',').next()?.parse().ok()
+    }
+
+    /// Insert or replace an INFO entry. A `None` value writes a flag.
+    pub fn set_info(&mut self, key: &str, value: Option<String>) {
+        match self.info.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.info.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn sample(&self, sample: usize, key: &str) -> Option<&str> {
+        let index = self.format.iter().position(|k| k == key)?;
+        self.samples
+            .get(sample)?
+            .get(index)
+            .map(String::as_str)
+            .filter(|v| *v != ".")
+    }
+
+    /// Whether the record is a gVCF reference block: a symbolic `<*>` or
+    /// `<NON_REF>` ALT alone, covering POS to INFO/END.
+    pub fn is_reference_block(&self) -> bool {
+        matches!(self.alt.as_str(), "<*>" | "<NON_REF>") && self.info("END").is_some()
+    }
+
+    /// The last position the record covers: INFO/END when given, otherwise
+    /// the last base of REF.
+    pub fn end(&self) -> u64 {
+        self.info("END")
+            .and_then(|end| end.parse().ok())
+            .unwrap_or(self.pos + (self.reference.len() as u64).max(1) - 1)
+    }
+
+    /// Parse the first value of a numeric FORMAT field for one sample.
+    pub fn sample_f64(&self, sample: usize, key: &str) -> Option<f64> {
+        self.sample(sample, key)?.split(',').next()?.parse().ok()
+    }
+
+    /// Variant allele fraction of one sample: FORMAT/VAF when present,
+    /// otherwise AO / DP.
+    pub fn sample_vaf(&self, sample: usize) -> Option<f64> {
+        if let Some(vaf) = self.sample_f64(sample, "VAF") {
+            return Some(vaf);
+        }
+        let ao = self.sample_f64(sample, "AO")?;
+        let dp = self.sample_f64(sample, "DP")?;
+        (dp > 0.0).then(|| ao / dp)
+    }
+
+    /// Insert or replace a FORMAT value for one sample, adding the key to the
+    /// FORMAT column (with `.` for the other samples) if it is not there yet.
+    pub fn set_sample(&mut self, sample: usize, key: &str, value: String) {
+        let index = match self.format.iter().position(|k| k == key) {
+            Some(index) => index,
+            None => {
+                self.format.push(key.to_string());
+                self.format.len() - 1
+            }
+        };
+        for values in &mut self.samples {
+            while values.len() <= index {
+                values.push(".".to_string());
+            }
+        }
+        if let Some(values) = self.samples.get_mut(sample) {
+            values[index] = value;
+        }
+    }
+}
+
+/// The kind of change a record's alleles make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VariantType {
+    Snv,
+    Insertion,
+    Deletion,
+    Mnp,
+    Complex,
+}
+
+impl VariantType {
+    pub const ALL: [VariantType; 5] = [
+        VariantType::Snv,
+        VariantType::Insertion,
+        VariantType::Deletion,
+        VariantType::Mnp,
+        VariantType::Complex,
+    ];
+
+    /// Classify one ALT against REF. Indels are expected left-aligned with a
+    /// shared leading base, as after normalisation.
+    pub fn classify(reference: &str, alt: &str) -> Self {
+        match (reference.len(), alt.len()) {
+            (1, 1) => VariantType::Snv,
+            (r, a) if r == a => VariantType::Mnp,
+            (r, a) if r < a && alt.starts_with(reference) => VariantType::Insertion,
+            (r, a) if r > a && reference.starts_with(alt) => VariantType::Deletion,
+            _ => VariantType::Complex,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VariantType::Snv => "SNV",
+            VariantType::Insertion => "ins",
+            VariantType::Deletion => "del",
+            VariantType::Mnp => "MNP",
+            VariantType::Complex => "complex",
+        }
+    }
+}
+
+impl VcfRecord {
+    /// The type of the record's alleles; multi-allelic records whose
+    /// alleles differ in type are complex.
+    pub fn variant_type(&self) -> VariantType {
+        let mut types = self
+            .alt
+            .split(',')
+            .map(|alt| VariantType::classify(&self.reference, alt));
+        let first = types.next().unwrap_or(VariantType::Complex);
+        if types.all(|other| other == first) {
+            first
+        } else {
+            VariantType::Complex
+        }
+    }
+}
+
+impl fmt::Display for VcfRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let info = if self.info.is_empty() {
+            ".".to_string()
+        } else {
+            self.info
+                .iter()
+                .map(|(k, v)| match v {
+                    Some(v) => format!("{}={}", k, v),
+                    None => k.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join(";")
+        };
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.chrom, self.pos, self.id, self.reference, self.alt, self.qual, self.filter, info
+        )?;
+        if !self.format.is_empty() {
+            write!(f, "\t{}", self.format.join(":"))?;
+            for sample in &self.samples {
+                write!(f, "\t{}", sample.join(":"))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use log::debug;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::error::MityError;
+use crate::mity_util;
+use crate::vcf_record::VcfRecord;
+
+/// Samples at or above this VAF are counted as homoplasmic.
+const HOMOPLASMIC_VAF: f64 = 0.95;
+
+/// How `mity stats` prints its numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+    Tsv,
+}
+
+/// Counts for one sample over the PASS variants it carries.
+#[derive(Default)]
+struct SampleStats {
+    homoplasmic: usize,
+    heteroplasmic: usize,
+    vafs: Vec<f64>,
+}
+
+/// Summarises the variants of a mity VCF in one streaming pass.
+pub struct VcfStats {
+    debug: bool,
+    vcf: PathBuf,
+    format: StatsFormat,
+}
+
+impl VcfStats {
+    pub fn new(debug: bool, vcf: PathBuf, format: StatsFormat) -> Self {
+        VcfStats { debug, vcf, format }
+    }
+
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if self.debug {
+            debug!("Entered debug mode.");
+        }
+        if !self.vcf.exists() {
+            return Err(MityError::MissingFile(self.vcf.clone()).into());
+        }
+
+        let report = self.report()?;
+        match self.format {
+            StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            StatsFormat::Tsv => print_tsv(&report),
+            StatsFormat::Text => print_text(&report),
+        }
+        Ok(())
+    }
+
+    /// Counts over the whole VCF and per sample, as printed by every format.
+    fn report(&self) -> Result<Value, Box<dyn Error>> {
+        let samples = mity_util::vcf_sample_names(&self.vcf)?;
+        let contig = mt_contig(&self.vcf)?;
+        debug!("Summarising {} on {}", self.vcf.display(), contig);
+
+        let mut total = 0;
+        let mut passed = 0;
+        let mut filters: BTreeMap<String, usize> = BTreeMap::new();
+        let mut types: BTreeMap<&'static str, usize> = BTreeMap::new();
+        let (mut transitions, mut transversions) = (0, 0);
+        let mut per_sample: Vec<SampleStats> = samples.iter().map(|_| SampleStats::default()).collect();
+
+        mity_util::for_each_vcf_line_on(&self.vcf, &contig, |line| {
+            let record = VcfRecord::parse(line)?;
+            total += 1;
+
+            let pass = record.filter == "PASS" || record.filter == ".";
+            if pass {
+                passed += 1;
+            } else {
+                for filter in record.filter.split(';') {
+                    *filters.entry(filter.to_string()).or_default() += 1;
+                }
+            }
+
+            for alt in record.alt.split(',') {
+                let kind = variant_type(&record.reference, alt);
+                *types.entry(kind).or_default() += 1;
+                if kind == "SNV" {
+                    if is_transition(&record.reference, alt) {
+                        transitions += 1;
+                    } else {
+                        transversions += 1;
+                    }
+                }
+            }
+
+            if pass {
+                for (index, stats) in per_sample.iter_mut().enumerate() {
+                    match record.sample_vaf(index) {
+                        Some(vaf) if vaf >= HOMOPLASMIC_VAF => {
+                            stats.homoplasmic += 1;
+                            stats.vafs.push(vaf);
+                        }
+                        Some(vaf) if vaf > 0.0 => {
+                            stats.heteroplasmic += 1;
+                            stats.vafs.push(vaf);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        let ts_tv = if transversions > 0 {
+            Some(transitions as f64 / transversions as f64)
+        } else {
+            None
+        };
+        let sample_values: Vec<Value> = samples
+            .iter()
+            .zip(per_sample.iter_mut())
+            .map(|(name, stats)| {
+                stats.vafs.sort_by(f64::total_cmp);
+                json!({
+                    "sample": name,
+                    "homoplasmic": stats.homoplasmic,
+                    "heteroplasmic": stats.heteroplasmic,
+                    "vaf_quartiles": quartiles(&stats.vafs),
+                })
+            })
+            .collect();
+        Ok(json!({
+            "file": self.vcf.display().to_string(),
+            "contig": contig,
+            "variants": total,
+            "passed": passed,
+            "filters": filters,
+            "types": types,
+            "transitions": transitions,
+            "transversions": transversions,
+            "ts_tv": ts_tv,
+            "samples": sample_values,
+        }))
+    }
+}
+
+/// The mitochondrial contig, from the header or else the first MT record.
+fn mt_contig(vcf: &Path) -> Result<String, Box<dyn Error>> {
+    if let Ok((contig, _)) = mity_util::vcf_get_mt_contig(vcf) {
+        return Ok(contig);
+    }
+    for line in mity_util::open_vcf(vcf)?.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let chrom = line.split('\t').next().unwrap_or_default();
+        if chrom == "MT" || chrom == "chrM" {
+            return Ok(chrom.to_string());
+        }
+    }
+    Err(format!("No mitochondrial contig in {}", vcf.display()).into())
+}
+
+fn variant_type(reference: &str, alt: &str) -> &'static str {
+    if alt.starts_with('<') || alt == "*" || alt == "." {
+        "other"
+    } else if reference.len() != alt.len() {
+        "indel"
+    } else if reference.len() == 1 {
+        "SNV"
+    } else {
+        "MNP"
+    }
+}
+
+/// A<->G and C<->T substitutions.
+fn is_transition(reference: &str, alt: &str) -> bool {
+    matches!(
+        (reference.to_ascii_uppercase().as_str(), alt.to_ascii_uppercase().as_str()),
+        ("A", "G") | ("G", "A") | ("C", "T") | ("T", "C")
+    )
+}
+
+/// First quartile, median and third quartile of sorted values, linearly
+/// interpolated. Empty when there are no values.
+fn quartiles(sorted: &[f64]) -> Vec<f64> {
+    if sorted.is_empty() {
+        return Vec::new();
+    }
+    [0.25, 0.5, 0.75]
+        .iter()
+        .map(|q| {
+            let rank = q * (sorted.len() - 1) as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+        })
+        .collect()
+}
+
+fn format_quartiles(value: &Value) -> String {
+    match value.as_array() {
+        Some(q) if q.len() == 3 => format!(
+            "{:.4} / {:.4} / {:.4}",
+            q[0].as_f64().unwrap_or_default(),
+            q[1].as_f64().unwrap_or_default(),
+            q[2].as_f64().unwrap_or_default()
+        ),
+        _ => "-".to_string(),
+    }
+}
+
+fn format_ts_tv(value: &Value) -> String {
+    value.as_f64().map_or("-".to_string(), |ratio| format!("{:.3}", ratio))
+}
+
+fn print_text(report: &Value) {
+    println!("{} ({})", report["file"].as_str().unwrap_or_default(), report["contig"].as_str().unwrap_or_default());
+    println!("  {:<24}{}", "variants", report["variants"]);
+    println!("  {:<24}{}", "PASS", report["passed"]);
+    for (filter, count) in report["filters"].as_object().into_iter().flatten() {
+        println!("  {:<24}{}", format!("FILTER {}", filter), count);
+    }
+    for (kind, count) in report["types"].as_object().into_iter().flatten() {
+        println!("  {:<24}{}", kind, count);
+    }
+    println!("  {:<24}{}", "Ts/Tv", format_ts_tv(&report["ts_tv"]));
+
+    let samples = report["samples"].as_array().cloned().unwrap_or_default();
+    if samples.is_empty() {
+        return;
+    }
+    let width = samples
+        .iter()
+        .filter_map(|sample| sample["sample"].as_str())
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+        .max("SAMPLE".len());
+    println!();
+    println!(
+        "  {:<width$}  {:>11}  {:>13}  VAF Q1 / median / Q3 (PASS)",
+        "SAMPLE", "homoplasmic", "heteroplasmic"
+    );
+    for sample in &samples {
+        println!(
+            "  {:<width$}  {:>11}  {:>13}  {}",
+            sample["sample"].as_str().unwrap_or_default(),
+            sample["homoplasmic"].as_u64().unwrap_or_default(),
+            sample["heteroplasmic"].as_u64().unwrap_or_default(),
+            format_quartiles(&sample["vaf_quartiles"])
+        );
+    }
+}
+
+/// One `SCOPE METRIC VALUE` row per number, where SCOPE is `all` or a
+/// sample name.
+fn print_tsv(report: &Value) {
+    println!("SCOPE\tMETRIC\tVALUE");
+    println!("all\tvariants\t{}", report["variants"]);
+    println!("all\tpassed\t{}", report["passed"]);
+    for (filter, count) in report["filters"].as_object().into_iter().flatten() {
+        println!("all\tfilter_{}\t{}", filter, count);
+    }
+    for (kind, count) in report["types"].as_object().into_iter().flatten() {
+        println!("all\t{}\t{}", kind, count);
+    }
+    println!("all\ttransitions\t{}", report["transitions"]);
+    println!("all\ttransversions\t{}", report["transversions"]);
+    println!("all\tts_tv\t{}", format_ts_tv(&report["ts_tv"]));
+    for sample in report["samples"].as_array().into_iter().flatten() {
+        let name = sample["sample"].as_str().unwrap_or_default();
+        println!("{}\thomoplasmic\t{}", name, sample["homoplasmic"]);
+        println!("{}\theteroplasmic\t{}", name, sample["heteroplasmic"]);
+        let quartiles: Vec<f64> = sample["vaf_quartiles"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_f64)
+            .collect();
+        for (label, value) in ["vaf_q1", "vaf_median", "vaf_q3"].iter().zip(quartiles) {
+            println!("{}\t{}\t{:.4}", name, label, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn variants_are_counted_by_filter_type_and_sample() {
+        let temp = tempfile::tempdir().unwrap();
+        let vcf = temp.path().join("trio.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n##contig=<ID=MT,length=16569>\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\tB\n\
+             MT\t73\t.\tA\tG\t.\tPASS\t.\tVAF\t1.0\t0.2\n\
+             MT\t310\t.\tT\tTC\t.\tPASS\t.\tVAF\t0.4\t0\n\
+             MT\t3243\t.\tA\tC\t.\tSBA;DP\t.\tVAF\t0.9\t0.9\n\
+             MT\t16519\t.\tTC\tCT\t.\t.\t.\tAO:DP\t3:4\t.:.\n",
+        )
+        .unwrap();
+
+        let report = VcfStats::new(false, vcf, StatsFormat::Json).report().unwrap();
+        assert_eq!(report["contig"], "MT");
+        assert_eq!((report["variants"].as_u64(), report["passed"].as_u64()), (Some(4), Some(3)));
+        assert_eq!(report["filters"], json!({"DP": 1, "SBA": 1}));
+        assert_eq!(report["types"], json!({"MNP": 1, "SNV": 2, "indel": 1}));
+        assert_eq!((report["transitions"].as_u64(), report["transversions"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(report["ts_tv"], 1.0);
+
+        // The filtered 3243 counts for neither sample; AO/DP stands in for VAF
+        let a = &report["samples"][0];
+        assert_eq!((a["homoplasmic"].as_u64(), a["heteroplasmic"].as_u64()), (Some(1), Some(2)));
+        assert_eq!(a["vaf_quartiles"], json!([0.575, 0.75, 0.875]));
+        let b = &report["samples"][1];
+        assert_eq!((b["homoplasmic"].as_u64(), b["heteroplasmic"].as_u64()), (Some(0), Some(1)));
+        assert_eq!(b["vaf_quartiles"], json!([0.2, 0.2, 0.2]));
+    }
+}